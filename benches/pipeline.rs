@@ -0,0 +1,306 @@
+// Benchmarks over synthetic PGNs for the stages a conversion goes through:
+// decompression, parsing/visitor dispatch (including comment scanning), and
+// CSV writing, and for whole conversions through the library's pipeline. Run
+// with `cargo bench`.
+
+use std::{fmt::Write as _, fs, hint::black_box, io, process};
+
+use bzip2::{read::MultiBzDecoder, write::BzEncoder};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use pgn2csv::{
+    comments::{Clock, Eval},
+    headers::Rating,
+    testing, GameProcessor, Pgn2Csv,
+};
+use pgn_reader::{BufferedReader, RawComment, RawHeader, SanPlus, Skip, Visitor};
+use serde::Serialize;
+
+const GAMES: usize = 500;
+const PLIES: usize = 80;
+
+#[derive(Clone, Copy)]
+enum Kind {
+    HeadersOnly,
+    Clocks,
+    Evals,
+    Variations,
+}
+
+impl Kind {
+    const ALL: [Kind; 4] = [
+        Kind::HeadersOnly,
+        Kind::Clocks,
+        Kind::Evals,
+        Kind::Variations,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Kind::HeadersOnly => "headers-only",
+            Kind::Clocks => "clocks",
+            Kind::Evals => "evals",
+            Kind::Variations => "variations",
+        }
+    }
+}
+
+fn synthetic(kind: Kind) -> Vec<u8> {
+    const MOVES: [&str; 4] = ["Nf3", "Nf6", "Ng1", "Ng8"];
+    let mut pgn = String::new();
+    for game in 0..GAMES {
+        writeln!(pgn, "[Event \"Rated Blitz game\"]").unwrap();
+        writeln!(pgn, "[Site \"https://lichess.org/{game:08}\"]").unwrap();
+        writeln!(pgn, "[White \"white{game}\"]").unwrap();
+        writeln!(pgn, "[Black \"black{game}\"]").unwrap();
+        writeln!(pgn, "[Result \"1/2-1/2\"]").unwrap();
+        writeln!(pgn, "[UTCDate \"2023.01.01\"]").unwrap();
+        writeln!(pgn, "[WhiteElo \"{}\"]", 1500 + game % 500).unwrap();
+        writeln!(pgn, "[BlackElo \"{}\"]", 1500 + game % 300).unwrap();
+        writeln!(pgn, "[TimeControl \"180+0\"]").unwrap();
+        writeln!(pgn, "[Termination \"Normal\"]\n").unwrap();
+        let plies = if let Kind::HeadersOnly = kind {
+            2
+        } else {
+            PLIES
+        };
+        for ply in 0..plies {
+            if ply % 2 == 0 {
+                write!(pgn, "{}. ", ply / 2 + 1).unwrap();
+            }
+            write!(pgn, "{} ", MOVES[ply % 4]).unwrap();
+            let seconds = 180 - ply / 2;
+            let clock = format!("[%clk 0:{:02}:{:02}]", seconds / 60, seconds % 60);
+            match kind {
+                Kind::HeadersOnly => {}
+                Kind::Clocks => write!(pgn, "{{ {clock} }} ").unwrap(),
+                Kind::Evals => write!(pgn, "{{ [%eval 0.{ply}] {clock} }} ").unwrap(),
+                Kind::Variations => {
+                    if ply % 10 == 9 {
+                        write!(
+                            pgn,
+                            "( {0}... Ng8 ( {0}... Nh6 ( {0}... Na6 ) ) ) ",
+                            ply / 2 + 1
+                        )
+                        .unwrap();
+                    }
+                }
+            }
+        }
+        writeln!(pgn, "1/2-1/2\n").unwrap();
+    }
+    pgn.into_bytes()
+}
+
+#[derive(Default)]
+struct Clocks {
+    ratings: u32,
+    seconds: u64,
+}
+
+impl Visitor for Clocks {
+    type Result = ();
+
+    fn header(&mut self, key: &[u8], value: RawHeader<'_>) {
+        if key == b"WhiteElo" && Rating::try_from(value).is_ok() {
+            self.ratings += 1;
+        }
+    }
+
+    fn comment(&mut self, comment: RawComment<'_>) {
+        if let Ok(clock) = Clock::try_from(comment) {
+            self.seconds += u64::from(clock.total_seconds());
+        }
+    }
+
+    fn begin_variation(&mut self) -> Skip {
+        Skip(true)
+    }
+
+    fn end_game(&mut self) {}
+}
+
+fn read_all(reader: impl io::Read) -> Clocks {
+    let mut visitor = Clocks::default();
+    BufferedReader::new(reader).read_all(&mut visitor).unwrap();
+    visitor
+}
+
+fn parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    for kind in Kind::ALL {
+        let pgn = synthetic(kind);
+        group.throughput(Throughput::Bytes(pgn.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(kind.name()), &pgn, |b, pgn| {
+            b.iter(|| read_all(black_box(pgn.as_slice())));
+        });
+    }
+    group.finish();
+}
+
+fn comments(c: &mut Criterion) {
+    let comment = b" [%eval 0.17] [%clk 0:02:59] ";
+    c.bench_function("clock_from_comment", |b| {
+        b.iter(|| Clock::try_from(RawComment(black_box(comment.as_slice()))).unwrap());
+    });
+}
+
+fn decompress(c: &mut Criterion) {
+    let pgn = synthetic(Kind::Clocks);
+    let zst = zstd::stream::encode_all(pgn.as_slice(), 3).unwrap();
+    let mut bz2 = BzEncoder::new(Vec::new(), bzip2::Compression::fast());
+    io::Write::write_all(&mut bz2, &pgn).unwrap();
+    let bz2 = bz2.finish().unwrap();
+
+    let mut group = c.benchmark_group("decompress");
+    group.throughput(Throughput::Bytes(pgn.len() as u64));
+    group.bench_function("zstd", |b| {
+        b.iter(|| read_all(zstd::stream::read::Decoder::new(black_box(zst.as_slice())).unwrap()));
+    });
+    group.bench_function("bzip2", |b| {
+        b.iter(|| read_all(MultiBzDecoder::new(black_box(bz2.as_slice()))));
+    });
+    group.finish();
+}
+
+#[derive(Default, Serialize)]
+struct Row {
+    white: String,
+    black: String,
+    white_rating: u16,
+    black_rating: u16,
+    result: u8,
+    tournament: bool,
+}
+
+fn csv_writing(c: &mut Criterion) {
+    let rows: Vec<Row> = (0..10_000u16)
+        .map(|i| Row {
+            white: format!("white{i}"),
+            black: format!("black{i}"),
+            white_rating: 1500 + i % 500,
+            black_rating: 1500 + i % 300,
+            result: (i % 3) as u8,
+            tournament: i % 2 == 0,
+        })
+        .collect();
+    c.bench_function("csv_write", |b| {
+        b.iter(|| {
+            let mut writer = csv::Writer::from_writer(io::sink());
+            for row in &rows {
+                writer.serialize(row).unwrap();
+            }
+            writer.flush().unwrap();
+        });
+    });
+}
+
+/// A processor like the binaries', which reads ratings, clocks, and evals.
+#[derive(Default)]
+struct Processor {
+    row: GameRow,
+}
+
+#[derive(Default, Serialize)]
+struct GameRow {
+    white_elo: Rating,
+    plies: u16,
+    clock_seconds: u32,
+    evals: u16,
+}
+
+impl Visitor for Processor {
+    type Result = ();
+
+    fn begin_game(&mut self) {
+        self.row = GameRow::default();
+    }
+
+    fn header(&mut self, key: &[u8], value: RawHeader<'_>) {
+        if key == b"WhiteElo" {
+            self.row.white_elo = Rating::try_from(value).unwrap_or_default();
+        }
+    }
+
+    fn san(&mut self, _san: SanPlus) {
+        self.row.plies += 1;
+    }
+
+    fn comment(&mut self, comment: RawComment<'_>) {
+        if let Ok(clock) = Clock::try_from(comment) {
+            self.row.clock_seconds += clock.total_seconds();
+        }
+        if Eval::try_from(comment).is_ok() {
+            self.row.evals += 1;
+        }
+    }
+
+    fn begin_variation(&mut self) -> Skip {
+        Skip(true)
+    }
+
+    fn end_game(&mut self) {}
+}
+
+impl GameProcessor for Processor {
+    type Row = GameRow;
+
+    fn row(&mut self) -> GameRow {
+        std::mem::take(&mut self.row)
+    }
+}
+
+/// Games read through the pipeline's own visitor, with a processor, as a run
+/// reads them, but from memory.
+fn games(c: &mut Criterion) {
+    let mut group = c.benchmark_group("games");
+    for kind in Kind::ALL {
+        let pgn = String::from_utf8(synthetic(kind)).unwrap();
+        group.throughput(Throughput::Bytes(pgn.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(kind.name()), &pgn, |b, pgn| {
+            b.iter(|| testing::process_games::<Processor>(black_box(pgn)));
+        });
+    }
+    group.finish();
+}
+
+/// Whole runs, from a zstd PGN on disk to a CSV, for the end-to-end effect of
+/// changes to any stage.
+fn runs(c: &mut Criterion) {
+    let dir = std::env::temp_dir().join(format!("pgn2csv-bench-{}", process::id()));
+    let mut group = c.benchmark_group("run");
+    group.sample_size(10);
+    for kind in [Kind::Clocks, Kind::Evals] {
+        let pgn = synthetic(kind);
+        let pgn_dir = dir.join(kind.name());
+        fs::create_dir_all(&pgn_dir).unwrap();
+        let zst = zstd::stream::encode_all(pgn.as_slice(), 3).unwrap();
+        fs::write(pgn_dir.join("games.pgn.zst"), zst).unwrap();
+        group.throughput(Throughput::Bytes(pgn.len() as u64));
+        group.bench_function(kind.name(), |b| {
+            b.iter(|| {
+                Pgn2Csv::builder()
+                    .pgn_dir(&pgn_dir)
+                    .csv_dir(dir.join(format!("{}-csvs", kind.name())))
+                    .serial(true)
+                    .args(["--quiet"])
+                    .build()
+                    .unwrap()
+                    .run::<Processor>()
+                    .unwrap();
+            });
+        });
+    }
+    group.finish();
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+criterion_group!(
+    benches,
+    parse,
+    comments,
+    decompress,
+    csv_writing,
+    games,
+    runs
+);
+criterion_main!(benches);
@@ -0,0 +1,147 @@
+//! A processor to start a new dataset from, which `pgn2csv new` copies. Add
+//! the columns you want to `Row`, set them from the headers in `header` (the
+//! types in `pgn2csv::headers` parse lichess's), and skip the games you don't
+//! want; the golden test at the end checks the rows of a small PGN.
+
+use pgn2csv::{
+    headers::{PgnResult, Rating, TimeControl},
+    pgn2csv, GameProcessor,
+};
+
+use std::{env, mem};
+
+use anyhow::Result;
+use pgn_reader::{RawHeader, Skip, Visitor};
+use serde::Serialize;
+
+#[derive(Default, Serialize)]
+struct Row {
+    white_elo: Rating,
+    black_elo: Rating,
+    result: PgnResult,
+    estimated_duration_seconds: u32,
+}
+
+#[derive(Default)]
+struct Scratch {
+    skip_game: bool,
+}
+
+impl Scratch {
+    fn reset(&mut self) {
+        self.skip_game = false;
+    }
+}
+
+#[derive(Default)]
+struct Processor {
+    row: Row,
+    scratch: Scratch,
+}
+
+impl GameProcessor for Processor {
+    type Row = Row;
+
+    fn skip(&self) -> bool {
+        self.scratch.skip_game
+    }
+
+    fn row(&mut self) -> Row {
+        mem::take(&mut self.row)
+    }
+
+    fn description() -> &'static str {
+        "Ratings, result, and expected duration of rated games."
+    }
+
+    fn filters() -> Vec<&'static str> {
+        vec![
+            "the event is rated",
+            "both ratings, the result, and the time control are known",
+        ]
+    }
+}
+
+impl Visitor for Processor {
+    type Result = ();
+
+    fn begin_game(&mut self) {
+        self.scratch.reset();
+    }
+
+    fn header(&mut self, key: &[u8], value: RawHeader<'_>) {
+        if self.skip() {
+            return;
+        }
+
+        match key {
+            b"Event" if !value.as_bytes().starts_with(b"Rated ") => {
+                self.scratch.skip_game = true;
+            }
+            b"WhiteElo" => match Rating::try_from(value) {
+                Ok(rating) => self.row.white_elo = rating,
+                Err(_) => self.scratch.skip_game = true,
+            },
+            b"BlackElo" => match Rating::try_from(value) {
+                Ok(rating) => self.row.black_elo = rating,
+                Err(_) => self.scratch.skip_game = true,
+            },
+            b"Result" => match PgnResult::try_from(value) {
+                Ok(PgnResult::Other) | Err(_) => self.scratch.skip_game = true,
+                Ok(result) => self.row.result = result,
+            },
+            b"TimeControl" => match TimeControl::try_from(value) {
+                Ok(time_control) => {
+                    self.row.estimated_duration_seconds = time_control.estimated_duration_seconds();
+                }
+                Err(_) => self.scratch.skip_game = true,
+            },
+            _ => (),
+        }
+    }
+
+    fn end_headers(&mut self) -> Skip {
+        // the moves aren't needed, so skip parsing them
+        Skip(true)
+    }
+
+    fn begin_variation(&mut self) -> Skip {
+        Skip(true)
+    }
+
+    fn end_game(&mut self) {}
+}
+
+fn main() -> Result<()> {
+    env::set_var("RUST_BACKTRACE", "1");
+    pgn2csv::<Processor>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extracts_rated_games() {
+        let pgn = "\
+[Event \"Rated Blitz game\"]
+[Result \"1-0\"]
+[WhiteElo \"1800\"]
+[BlackElo \"1700\"]
+[TimeControl \"180+2\"]
+
+1. e4 e5 1-0
+
+[Event \"Casual Blitz game\"]
+[Result \"0-1\"]
+[WhiteElo \"1800\"]
+[BlackElo \"1700\"]
+[TimeControl \"180+2\"]
+
+1. e4 e5 0-1
+";
+        pgn2csv::assert_rows!(Processor, pgn, ["1800,1700,1.0,260"]);
+        pgn2csv::testing::assert_deterministic::<Processor>(pgn);
+    }
+}
@@ -0,0 +1,604 @@
+//! Reusable checks over the moves of a game, or over a run of games, for
+//! processors to build on.
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::comments::{Clock, Eval};
+
+/// The slack allowed by default when comparing clocks, since lichess rounds
+/// clocks to whole seconds.
+pub const DEFAULT_TOLERANCE: u32 = 1;
+
+/// The advantage in pawns that counts as decisive, as on lichess, where an
+/// eval of +3 or more is shown as winning.
+pub const DECISIVE_ADVANTAGE: f32 = 3.0;
+
+/// The pawns that a forced mate counts as when evals are compared as numbers
+/// (see `Eval::pawns`).
+pub const MATE_PAWNS: f32 = 100.0;
+
+/// Flags games in which a player's clock goes up between their moves by more
+/// than the increment (plus a tolerance), which happens when a game was
+/// adjourned or its clocks were adjusted, e.g. by a moderator or an opponent
+/// giving time. Feed it the clock after every move, in order.
+///
+/// In arenas, a player who berserked gets no increment, so their clock going
+/// up by the increment is a violation too. `berserk_aware` tells berserks from
+/// the players' first clocks (see `BerserkDetector`).
+#[derive(Clone, Debug)]
+pub struct ClockMonotonicity {
+    increment: u32,
+    tolerance: u32,
+    berserks: Option<BerserkDetector>,
+    previous: [Option<u32>; 2],
+    plies: usize,
+    violations: u32,
+}
+
+impl Default for ClockMonotonicity {
+    fn default() -> Self {
+        ClockMonotonicity::new(0, DEFAULT_TOLERANCE)
+    }
+}
+
+impl ClockMonotonicity {
+    #[must_use]
+    pub fn new(increment: u32, tolerance: u32) -> Self {
+        ClockMonotonicity {
+            increment,
+            tolerance,
+            berserks: None,
+            previous: [None; 2],
+            plies: 0,
+            violations: 0,
+        }
+    }
+
+    /// Takes away the increment of players who berserked, given the initial
+    /// time of the time control in seconds.
+    #[must_use]
+    pub fn berserk_aware(mut self, initial_time: u32) -> Self {
+        self.berserks = Some(BerserkDetector::new(initial_time, self.increment));
+        self
+    }
+
+    /// Records the clock in seconds of the player who just moved, returning
+    /// whether it is consistent with that player's previous clock.
+    pub fn push(&mut self, seconds: u32) -> bool {
+        let white = self.plies.is_multiple_of(2);
+        let increment = match &mut self.berserks {
+            Some(berserks) => {
+                berserks.push(white, seconds * 10);
+                berserks.increment(white)
+            }
+            None => self.increment,
+        };
+        let previous = &mut self.previous[self.plies % 2];
+        self.plies += 1;
+        let consistent =
+            previous.is_none_or(|previous| seconds <= previous + increment + self.tolerance);
+        *previous = Some(seconds);
+        if !consistent {
+            self.violations += 1;
+        }
+        consistent
+    }
+
+    /// The number of moves after which a clock went up too much.
+    #[must_use]
+    pub fn violations(&self) -> u32 {
+        self.violations
+    }
+}
+
+/// Whether a player berserked in an arena game, given the initial time of the
+/// time control in seconds and the player's clock after their first move,
+/// which lichess doesn't run the clock for. Berserking halves the initial
+/// time, so in UltraBullet the clock is in tenths of a second, e.g.
+/// `0:00:07.5` for `15+0`. A clock above half the initial time is taken as
+/// time spent on the move, for sources that do run the clock.
+#[must_use]
+pub fn berserked(initial_time: u32, first_clock: &Clock) -> bool {
+    first_clock.total_tenths() <= initial_time * 10 / 2
+}
+
+/// Tells whether each player of an arena game berserked from their clock
+/// after their first move (see `berserked`), and with it the time control
+/// they actually played: a player who berserked started with half the initial
+/// time and gets no increment, which naive move time math would otherwise
+/// count as time spent.
+#[derive(Clone, Debug, Default)]
+pub struct BerserkDetector {
+    initial_time: u32,
+    increment: u32,
+    berserked: [Option<bool>; 2],
+}
+
+impl BerserkDetector {
+    /// Detects berserks in games of the given initial time and increment, in
+    /// seconds.
+    #[must_use]
+    pub fn new(initial_time: u32, increment: u32) -> Self {
+        BerserkDetector {
+            initial_time,
+            increment,
+            berserked: [None; 2],
+        }
+    }
+
+    /// Records the clock in tenths of a second of white (or else black)
+    /// after one of their moves, of which only the first tells whether they
+    /// berserked. Returns whether they did.
+    pub fn push(&mut self, white: bool, tenths: u32) -> bool {
+        let initial_tenths = self.initial_time * 10;
+        *self.berserked[usize::from(!white)].get_or_insert(tenths <= initial_tenths / 2)
+    }
+
+    /// Whether white (or else black) berserked, if their first clock is known.
+    #[must_use]
+    pub fn berserked(&self, white: bool) -> Option<bool> {
+        self.berserked[usize::from(!white)]
+    }
+
+    /// The initial time of white (or else black) in tenths of a second.
+    #[must_use]
+    pub fn initial_tenths(&self, white: bool) -> u32 {
+        match self.berserked(white) {
+            Some(true) => self.initial_time * 10 / 2,
+            _ => self.initial_time * 10,
+        }
+    }
+
+    /// The increment of white (or else black) in seconds.
+    #[must_use]
+    pub fn increment(&self, white: bool) -> u32 {
+        match self.berserked(white) {
+            Some(true) => 0,
+            _ => self.increment,
+        }
+    }
+
+    /// The time white (or else black) spent on their first move in tenths of
+    /// a second (see `move_time_tenths`), from the time control they played.
+    pub fn first_move_tenths(&mut self, white: bool, after: &Clock) -> u32 {
+        self.push(white, after.total_tenths());
+        move_time_tenths(self.initial_tenths(white), after, self.increment(white))
+    }
+}
+
+/// The time a player spent on a move in tenths of a second, from their clock
+/// before and after it in tenths of a second and the increment in seconds,
+/// which the clock after the move already includes. Clocks rounded to whole
+/// seconds can make very short moves come out as 0.
+#[must_use]
+pub fn move_time_tenths(before: u32, after: &Clock, increment: u32) -> u32 {
+    (before + increment * 10).saturating_sub(after.total_tenths())
+}
+
+/// Whether a player who lost on time had a decisive advantage (see
+/// `DECISIVE_ADVANTAGE`) by the last eval of the game, which makes for an
+/// "unfair" flag.
+#[must_use]
+pub fn flagged_while_winning(flagged_white: bool, last_eval: &Eval) -> bool {
+    last_eval.favors(flagged_white, DECISIVE_ADVANTAGE)
+}
+
+/// Measures how much the evals of a game swing, for comeback studies. Feed it
+/// the eval after every move that has one, in order. Evals are compared in
+/// pawns, with forced mates as `MATE_PAWNS`.
+#[derive(Clone, Debug, Default)]
+pub struct EvalSwings {
+    previous: Option<f32>,
+    max_swing: f32,
+    // the side ahead by the last eval that wasn't 0, true for white
+    leader: Option<bool>,
+    lead_changes: u32,
+    // the lowest and highest evals
+    range: Option<(f32, f32)>,
+}
+
+impl EvalSwings {
+    pub fn push(&mut self, eval: &Eval) {
+        let pawns = eval.pawns(MATE_PAWNS);
+        if let Some(previous) = self.previous {
+            self.max_swing = self.max_swing.max((pawns - previous).abs());
+        }
+        self.previous = Some(pawns);
+        if pawns != 0.0 {
+            let leader = pawns > 0.0;
+            if self.leader.is_some_and(|previous| previous != leader) {
+                self.lead_changes += 1;
+            }
+            self.leader = Some(leader);
+        }
+        self.range = Some(match self.range {
+            Some((low, high)) => (low.min(pawns), high.max(pawns)),
+            None => (pawns, pawns),
+        });
+    }
+
+    /// Whether any eval was pushed.
+    #[must_use]
+    pub fn has_evals(&self) -> bool {
+        self.range.is_some()
+    }
+
+    /// The largest change in pawns between consecutive evals.
+    #[must_use]
+    pub fn max_swing(&self) -> f32 {
+        self.max_swing
+    }
+
+    /// The number of times the side ahead changed, ignoring evals of exactly
+    /// 0.
+    #[must_use]
+    pub fn lead_changes(&self) -> u32 {
+        self.lead_changes
+    }
+
+    /// Whether white (or else black) was ever at least `pawns` behind, e.g.
+    /// to find games that the winner came back from a lost position in.
+    #[must_use]
+    pub fn was_lost(&self, white: bool, pawns: f32) -> bool {
+        self.range.is_some_and(
+            |(low, high)| {
+                if white {
+                    low <= -pawns
+                } else {
+                    high >= pawns
+                }
+            },
+        )
+    }
+}
+
+/// The centipawns that evals are capped at, and that forced mates count as,
+/// when centipawn losses are computed, as on lichess.
+pub const CENTIPAWN_CEILING: i32 = 1000;
+
+/// The eval of the starting position that lichess takes the first move's
+/// loss from, in centipawns.
+pub const INITIAL_CENTIPAWNS: i32 = 15;
+
+/// Computes each player's centipawn loss as lichess does: a move's loss is
+/// how much the eval after it fell from the eval before it, from the mover's
+/// view, with evals capped at `CENTIPAWN_CEILING` and forced mates counted as
+/// the ceiling, and gains counting as 0. The first move's loss is from
+/// `INITIAL_CENTIPAWNS`. Feed it the eval after every move that has one, with
+/// the move's ply; moves whose eval before or after is missing are left out.
+#[derive(Clone, Debug)]
+pub struct CentipawnLoss {
+    // the ply and centipawns of the last eval
+    previous: Option<(u32, i32)>,
+    last_loss: Option<u32>,
+    // the sum of the losses and the number of moves, for white and black
+    totals: [(u64, u32); 2],
+}
+
+impl Default for CentipawnLoss {
+    fn default() -> Self {
+        CentipawnLoss {
+            previous: Some((0, INITIAL_CENTIPAWNS)),
+            last_loss: None,
+            totals: [(0, 0); 2],
+        }
+    }
+}
+
+impl CentipawnLoss {
+    pub fn push(&mut self, ply: u32, eval: &Eval) {
+        let centipawns = match *eval {
+            Eval::Pawns(pawns) => {
+                ((pawns * 100.0).round() as i32).clamp(-CENTIPAWN_CEILING, CENTIPAWN_CEILING)
+            }
+            Eval::Mate(moves) if moves > 0 => CENTIPAWN_CEILING,
+            Eval::Mate(_) => -CENTIPAWN_CEILING,
+        };
+        self.last_loss = match self.previous {
+            Some((previous_ply, previous)) if previous_ply + 1 == ply => {
+                let white = ply % 2 == 1;
+                let fall = if white {
+                    previous - centipawns
+                } else {
+                    centipawns - previous
+                };
+                let loss = fall.max(0).unsigned_abs();
+                let (sum, moves) = &mut self.totals[usize::from(!white)];
+                *sum += u64::from(loss);
+                *moves += 1;
+                Some(loss)
+            }
+            _ => None,
+        };
+        self.previous = Some((ply, centipawns));
+    }
+
+    /// The loss of the move whose eval was pushed last, if the eval before it
+    /// is known, for per-move outputs.
+    #[must_use]
+    pub fn last_loss(&self) -> Option<u32> {
+        self.last_loss
+    }
+
+    /// White's (or else black's) average centipawn loss, rounded down like
+    /// lichess's, or None if none of their moves had a loss.
+    #[must_use]
+    pub fn average(&self, white: bool) -> Option<u32> {
+        let (sum, moves) = self.totals[usize::from(!white)];
+        (moves > 0).then(|| (sum / u64::from(moves)) as u32)
+    }
+}
+
+/// How one player was given an advantage before a game started.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub enum Odds {
+    #[default]
+    None,
+    /// The players started with different clocks, e.g. from berserking.
+    Time,
+    /// The game started from a position in which one player has less
+    /// material, given by its FEN header.
+    Material,
+    TimeAndMaterial,
+}
+
+impl Odds {
+    /// Labels a game from the material of each side in its starting position
+    /// (see `headers::Fen::material`), if it has a FEN header, and whether the
+    /// players' clocks after their first moves differ.
+    #[must_use]
+    pub fn classify(material: Option<(u32, u32)>, clocks_differ: bool) -> Self {
+        let material_odds = material.is_some_and(|(white, black)| white != black);
+        match (clocks_differ, material_odds) {
+            (false, false) => Odds::None,
+            (true, false) => Odds::Time,
+            (false, true) => Odds::Material,
+            (true, true) => Odds::TimeAndMaterial,
+        }
+    }
+}
+
+/// Splits the games between each pair of players into sessions of rematches,
+/// in which each game started at most `max_gap_seconds` after the one before.
+/// Feed it every game ordered by pair and then by start time, as
+/// `GameProcessor::sessions` does.
+#[derive(Clone, Debug)]
+pub struct SessionTracker {
+    max_gap_seconds: i64,
+    previous: Option<(Vec<u8>, i64)>,
+    sessions: u64,
+    rematches: u32,
+}
+
+impl SessionTracker {
+    #[must_use]
+    pub fn new(max_gap_seconds: i64) -> Self {
+        SessionTracker {
+            max_gap_seconds,
+            previous: None,
+            sessions: 0,
+            rematches: 0,
+        }
+    }
+
+    /// Records a game between `pair` starting at `timestamp` in seconds,
+    /// returning the number of its session, counting from 1, and how many
+    /// games of the session came before it.
+    pub fn push(&mut self, pair: &[u8], timestamp: i64) -> (u64, u32) {
+        let rematch = self.previous.as_ref().is_some_and(|(previous, time)| {
+            previous == pair && timestamp - time <= self.max_gap_seconds
+        });
+        if rematch {
+            self.rematches += 1;
+        } else {
+            self.sessions += 1;
+            self.rematches = 0;
+        }
+        match &mut self.previous {
+            Some((previous, time)) if previous == pair => *time = timestamp,
+            _ => self.previous = Some((pair.to_vec(), timestamp)),
+        }
+        (self.sessions, self.rematches)
+    }
+}
+
+/// How many seconds apart the games of a swiss round can start. Lichess pairs
+/// a whole round at once, so its games start within seconds of each other,
+/// while rounds are at least a game's length apart.
+pub const ROUND_START_SLACK: i64 = 60;
+
+/// Infers the round of each game of a swiss tournament from when it started,
+/// since lichess doesn't record rounds in its PGNs. Games of a tournament
+/// that started within `ROUND_START_SLACK` seconds of a round's first game
+/// are of that round; any other starts a new one. Rounds are numbered from 1
+/// in the order they are first seen, which is the order they were played if
+/// the games are fed in start order, as in lichess's dumps.
+#[derive(Clone, Debug, Default)]
+pub struct SwissRounds {
+    // the start of the first game of each round, by tournament
+    starts: HashMap<String, Vec<i64>>,
+}
+
+impl SwissRounds {
+    /// Records a game of `tournament_id` starting at `timestamp` in seconds,
+    /// returning the number of its round.
+    pub fn push(&mut self, tournament_id: &str, timestamp: i64) -> u32 {
+        if !self.starts.contains_key(tournament_id) {
+            self.starts.insert(tournament_id.to_owned(), Vec::new());
+        }
+        let starts = self
+            .starts
+            .get_mut(tournament_id)
+            .expect("was just inserted");
+        let round = starts
+            .iter()
+            .position(|start| (timestamp - start).abs() <= ROUND_START_SLACK);
+        match round {
+            Some(round) => round as u32 + 1,
+            None => {
+                starts.push(timestamp);
+                starts.len() as u32
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_clocks_that_go_up() {
+        let mut clocks = ClockMonotonicity::new(2, DEFAULT_TOLERANCE);
+        // white and black start with different times, then gain their increment
+        assert!(clocks.push(180));
+        assert!(clocks.push(90));
+        assert!(clocks.push(181));
+        assert!(clocks.push(92));
+        // white is given 15 seconds
+        assert!(!clocks.push(196));
+        assert_eq!(clocks.violations(), 1);
+        // the same start in a 3+2 arena, where black berserked and gets no
+        // increment
+        let mut clocks = ClockMonotonicity::new(2, DEFAULT_TOLERANCE).berserk_aware(180);
+        for seconds in [180, 90, 181] {
+            assert!(clocks.push(seconds));
+        }
+        assert!(!clocks.push(92));
+    }
+
+    #[test]
+    fn classifies_odds() {
+        use crate::headers::Fen;
+        use pgn_reader::RawHeader;
+
+        // white plays without the queen's knight
+        let fen = Fen::try_from(RawHeader(
+            b"r1bqkbnr/pppppppp/8/8/8/8/PPPPPPPP/R1BQKBNR w KQkq - 0 1".as_slice(),
+        ))
+        .unwrap();
+        assert_eq!(fen.material(), (36, 36));
+        let fen = Fen::try_from(RawHeader(
+            b"rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/R1BQKBNR w KQkq - 0 1".as_slice(),
+        ))
+        .unwrap();
+        assert_eq!(fen.material(), (36, 39));
+        assert_eq!(Odds::classify(Some(fen.material()), false), Odds::Material);
+        assert_eq!(Odds::classify(None, true), Odds::Time);
+        assert_eq!(Odds::classify(Some((39, 39)), false), Odds::None);
+    }
+
+    #[test]
+    fn splits_sessions() {
+        let mut sessions = SessionTracker::new(600);
+        assert_eq!(sessions.push(b"a,b", 0), (1, 0));
+        assert_eq!(sessions.push(b"a,b", 500), (1, 1));
+        // gaps are between consecutive games, not from the session's start
+        assert_eq!(sessions.push(b"a,b", 1000), (1, 2));
+        assert_eq!(sessions.push(b"a,b", 5000), (2, 0));
+        assert_eq!(sessions.push(b"a,c", 5100), (3, 0));
+    }
+
+    #[test]
+    fn infers_swiss_rounds() {
+        let mut rounds = SwissRounds::default();
+        assert_eq!(rounds.push("abc", 1000), 1);
+        assert_eq!(rounds.push("abc", 1002), 1);
+        assert_eq!(rounds.push("xyz", 1002), 1);
+        assert_eq!(rounds.push("abc", 1400), 2);
+        // a late game of the first round, e.g. from a player who joined late
+        assert_eq!(rounds.push("abc", 1030), 1);
+        assert_eq!(rounds.push("abc", 1800), 3);
+    }
+
+    #[test]
+    fn measures_eval_swings() {
+        let mut swings = EvalSwings::default();
+        for eval in [
+            Eval::Pawns(0.3),
+            Eval::Pawns(-0.5),
+            Eval::Pawns(0.0),
+            Eval::Pawns(-4.0),
+            Eval::Mate(2),
+        ] {
+            swings.push(&eval);
+        }
+        assert_eq!(swings.lead_changes(), 2);
+        assert_eq!(swings.max_swing(), 104.0);
+        assert!(swings.was_lost(true, DECISIVE_ADVANTAGE));
+        assert!(swings.was_lost(false, DECISIVE_ADVANTAGE));
+        assert!(!EvalSwings::default().was_lost(true, 0.0));
+    }
+
+    #[test]
+    fn computes_centipawn_loss() {
+        let mut loss = CentipawnLoss::default();
+        // white blunders a piece, black misses it, and white is mated
+        for (ply, eval) in [
+            (1, Eval::Pawns(0.2)),
+            (2, Eval::Pawns(3.0)),
+            (3, Eval::Pawns(0.5)),
+            (5, Eval::Pawns(0.5)),
+            (6, Eval::Mate(-1)),
+        ] {
+            loss.push(ply, &eval);
+        }
+        assert_eq!(loss.last_loss(), Some(0));
+        // 0 after the first move, 250 after the third, and the fifth has no
+        // eval before it
+        assert_eq!(loss.average(true), Some(125));
+        // 280 after the second move, and none for mating
+        assert_eq!(loss.average(false), Some(140));
+        assert_eq!(CentipawnLoss::default().average(true), None);
+    }
+
+    #[test]
+    fn detects_berserk_in_ultrabullet() {
+        let clock = |tenths: u8| Clock {
+            seconds: tenths / 10,
+            tenths: tenths % 10,
+            ..Clock::default()
+        };
+        assert!(berserked(15, &clock(75)));
+        assert!(!berserked(15, &clock(150)));
+        // 1.2 seconds on the first move of 15+1
+        assert_eq!(move_time_tenths(150, &clock(148), 1), 12);
+        assert_eq!(move_time_tenths(150, &clock(150), 0), 0);
+        let mut berserks = BerserkDetector::new(15, 1);
+        assert_eq!(berserks.first_move_tenths(true, &clock(158)), 2);
+        // black's berserk isn't counted as time spent
+        assert_eq!(berserks.first_move_tenths(false, &clock(75)), 0);
+        assert_eq!(berserks.berserked(false), Some(true));
+        assert_eq!(berserks.increment(false), 0);
+    }
+
+    #[test]
+    fn detects_berserks_from_first_clocks() {
+        let mut berserks = BerserkDetector::new(180, 2);
+        assert_eq!(berserks.berserked(true), None);
+        assert_eq!(berserks.initial_tenths(true), 1800);
+        assert_eq!(berserks.increment(true), 2);
+        // exactly half the initial time is a berserk
+        assert!(berserks.push(true, 900));
+        assert!(!berserks.push(false, 901));
+        // later clocks don't change it
+        assert!(berserks.push(true, 1800));
+        assert!(!berserks.push(false, 300));
+        assert_eq!(berserks.berserked(true), Some(true));
+        assert_eq!(berserks.berserked(false), Some(false));
+        assert_eq!(berserks.initial_tenths(true), 900);
+        assert_eq!(berserks.initial_tenths(false), 1800);
+        assert_eq!(berserks.increment(true), 0);
+        assert_eq!(berserks.increment(false), 2);
+        // white berserked, so gaining the increment is a violation, while
+        // black gains it as usual
+        let mut clocks = ClockMonotonicity::new(2, 0).berserk_aware(180);
+        for seconds in [90, 180, 88, 181] {
+            assert!(clocks.push(seconds));
+        }
+        assert!(!clocks.push(90));
+        assert_eq!(clocks.violations(), 1);
+    }
+}
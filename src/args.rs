@@ -0,0 +1,1054 @@
+use std::{
+    env,
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Context, Error, Result};
+use clap::{
+    error::ErrorKind, parser::ValueSource, Arg, ArgMatches, Command, CommandFactory,
+    FromArgMatches, Parser,
+};
+use ignore::overrides::{Override, OverrideBuilder};
+use toml::{Table, Value};
+
+use crate::{
+    assertions::{Assertion, Assertions},
+    boolean::BoolEncoding,
+    columns::{LibraryColumn, MoveSeparator, MovesFormat},
+    comments::ClockUnit,
+    encoding::Encodings,
+    headers::{ResultEncoding, TerminationEncoding},
+    log::{Log, LogFormat, Verbosity},
+    normalize::Normalization,
+    output::Format,
+    partition::Partition,
+    run::EXTENSIONS,
+    sink::SinkFactory,
+    text::TextEncoding,
+    GameProcessor,
+};
+
+/// The path that stands for stdin as the PGN, and stdout as the output.
+pub(crate) const STDIO: &str = "-";
+
+/// The configuration file read from the working directory, if there is one
+/// and `--config` isn't given.
+const CONFIG: &str = "pgn2csv.toml";
+
+/// What is done with games that ended before their first move.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum AbortedGames {
+    /// They are counted, and the count logged for each PGN.
+    Count,
+    /// They are also written, even if the processor skips them.
+    Keep,
+}
+
+impl FromStr for AbortedGames {
+    type Err = Error;
+
+    fn from_str(aborted: &str) -> Result<Self> {
+        match aborted {
+            "count" => Ok(AbortedGames::Count),
+            "keep" => Ok(AbortedGames::Keep),
+            _ => bail!("expected count or keep for aborted games, not {aborted}"),
+        }
+    }
+}
+
+/// What is done with PGNs whose output already exists, e.g. when a run that
+/// stopped is started again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum OnExisting {
+    /// They are converted again, replacing the output.
+    Overwrite,
+    /// They are left out of the run.
+    Skip,
+    /// The run fails before converting anything.
+    Error,
+}
+
+impl FromStr for OnExisting {
+    type Err = Error;
+
+    fn from_str(policy: &str) -> Result<Self> {
+        match policy {
+            "overwrite" => Ok(OnExisting::Overwrite),
+            "skip" => Ok(OnExisting::Skip),
+            "error" => Ok(OnExisting::Error),
+            _ => bail!("expected overwrite, skip, or error for existing outputs, not {policy}"),
+        }
+    }
+}
+
+/// What is done when a PGN can't be converted, e.g. because its archive is
+/// corrupt.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ErrorPolicy {
+    /// The run stops.
+    Abort,
+    /// The PGN is left out, and the run goes on.
+    SkipFile,
+    /// Games that can't be read are left out, and so are PGNs that fail
+    /// otherwise.
+    SkipGame,
+}
+
+impl FromStr for ErrorPolicy {
+    type Err = Error;
+
+    fn from_str(policy: &str) -> Result<Self> {
+        match policy {
+            "abort" => Ok(ErrorPolicy::Abort),
+            "skip-file" => Ok(ErrorPolicy::SkipFile),
+            "skip-game" => Ok(ErrorPolicy::SkipGame),
+            _ => bail!("expected abort, skip-file, or skip-game for errors, not {policy}"),
+        }
+    }
+}
+
+/// A `--format-for` rule, which writes the outputs of some PGNs in another
+/// format than `--format`: `GLOB=FORMAT` for the PGNs whose path matches the
+/// glob, as for `--glob`, and `<SIZE=FORMAT` or `>SIZE=FORMAT` for those
+/// smaller or larger than the size.
+#[derive(Clone)]
+pub(crate) struct FormatRule {
+    pgns: PgnMatch,
+    format: Format,
+}
+
+#[derive(Clone)]
+enum PgnMatch {
+    Glob(Override),
+    Smaller(u64),
+    Larger(u64),
+}
+
+impl FormatRule {
+    /// Whether the rule applies to the PGN at `path`, relative to the pgn dir,
+    /// which is `size` bytes large if that is known.
+    fn matches(&self, path: &Path, size: Option<u64>) -> bool {
+        match &self.pgns {
+            PgnMatch::Glob(glob) => glob.matched(path, false).is_whitelist(),
+            PgnMatch::Smaller(max) => size.is_some_and(|size| size < *max),
+            PgnMatch::Larger(min) => size.is_some_and(|size| size > *min),
+        }
+    }
+}
+
+impl FromStr for FormatRule {
+    type Err = Error;
+
+    fn from_str(rule: &str) -> Result<Self> {
+        let Some((pgns, format)) = rule.rsplit_once('=') else {
+            bail!("expected a rule like '*.zst=parquet' or '>1G=parquet', not {rule}");
+        };
+        let format: Format = format.parse()?;
+        let shared = match format {
+            #[cfg(feature = "duckdb")]
+            Format::DuckDb => true,
+            #[cfg(feature = "parquet")]
+            Format::HuggingFace => true,
+            _ => false,
+        };
+        if shared {
+            bail!("DuckDB and Hugging Face outputs are shared by every PGN, so they can only be given with --format");
+        }
+        let pgns = if let Some(size) = pgns.strip_prefix('<') {
+            PgnMatch::Smaller(parse_size(size)? as u64)
+        } else if let Some(size) = pgns.strip_prefix('>') {
+            PgnMatch::Larger(parse_size(size)? as u64)
+        } else {
+            let mut glob = OverrideBuilder::new("");
+            glob.add(pgns)?;
+            PgnMatch::Glob(glob.build()?)
+        };
+        Ok(FormatRule { pgns, format })
+    }
+}
+
+/// The command line arguments understood by every binary built on this crate.
+pub(crate) struct Args {
+    pub(crate) pgn_dir: PathBuf,
+    /// PGN files given one by one, rather than found in the PGN directory.
+    pub(crate) pgn_files: Vec<PathBuf>,
+    pub(crate) csv_dir: PathBuf,
+    /// Globs that override which files in the PGN directory are converted.
+    pub(crate) globs: Vec<String>,
+    /// The file that the output of the only PGN is written to, instead of one
+    /// in the CSV directory; `-` for stdout.
+    pub(crate) output: Option<PathBuf>,
+    /// The CSV that the rows of every PGN are written to together, instead
+    /// of one per PGN; `-` for stdout.
+    pub(crate) merge: Option<PathBuf>,
+    /// What the rows of every PGN are partitioned by, over the processor's
+    /// own partition.
+    pub(crate) partition_by: Option<Partition>,
+    pub(crate) text_encoding: TextEncoding,
+    pub(crate) result_encoding: Option<ResultEncoding>,
+    pub(crate) termination_encoding: Option<TerminationEncoding>,
+    pub(crate) bool_encoding: BoolEncoding,
+    pub(crate) clock_unit: Option<ClockUnit>,
+    pub(crate) profile: Option<PathBuf>,
+    pub(crate) sort_by: Option<Vec<String>>,
+    pub(crate) max_memory: usize,
+    pub(crate) dedup: bool,
+    pub(crate) stats: bool,
+    pub(crate) assertions: Option<Assertions>,
+    pub(crate) format: Format,
+    pub(crate) format_for: Vec<FormatRule>,
+    pub(crate) game_counts: Option<String>,
+    pub(crate) heartbeat: Option<Duration>,
+    pub(crate) log: Log,
+    pub(crate) describe: bool,
+    pub(crate) preview: Option<usize>,
+    pub(crate) limit: Option<u64>,
+    pub(crate) scan: bool,
+    pub(crate) yes: bool,
+    pub(crate) dry_run: bool,
+    pub(crate) schema_comment: bool,
+    pub(crate) columns: Vec<LibraryColumn>,
+    #[cfg(feature = "openings")]
+    pub(crate) openings: Vec<PathBuf>,
+    pub(crate) rating_stats: Option<PathBuf>,
+    pub(crate) write_rating_stats: Option<PathBuf>,
+    pub(crate) tournaments: bool,
+    pub(crate) header_rows: bool,
+    pub(crate) game_uid: bool,
+    pub(crate) session_gap: Option<Duration>,
+    pub(crate) normalization: Normalization,
+    pub(crate) tolerant: bool,
+    pub(crate) max_plies: Option<u32>,
+    pub(crate) max_game_bytes: Option<usize>,
+    pub(crate) threads: Option<usize>,
+    pub(crate) serial: bool,
+    pub(crate) force: bool,
+    pub(crate) on_existing: OnExisting,
+    pub(crate) resume: bool,
+    pub(crate) fallback_dir: Option<PathBuf>,
+    pub(crate) errors: ErrorPolicy,
+    pub(crate) mirror_colors: bool,
+    pub(crate) recursive: bool,
+    pub(crate) max_depth: Option<usize>,
+    pub(crate) batch: Option<usize>,
+    pub(crate) aborted: Option<AbortedGames>,
+    pub(crate) dump_skipped: Option<usize>,
+    #[cfg(feature = "download")]
+    pub(crate) downloads: Vec<String>,
+    #[cfg(feature = "download")]
+    pub(crate) checksums: Option<String>,
+    #[cfg(feature = "download")]
+    pub(crate) streams: Vec<String>,
+    #[cfg(feature = "download")]
+    pub(crate) cache_dir: Option<PathBuf>,
+    #[cfg(feature = "download")]
+    pub(crate) cache_size: u64,
+    #[cfg(feature = "download")]
+    pub(crate) requests_per_hour: Option<usize>,
+    #[cfg(feature = "download")]
+    pub(crate) max_connections: Option<usize>,
+    /// Where rows are written instead of in `format`, if the run was built
+    /// with one.
+    pub(crate) sink: Option<Arc<SinkFactory>>,
+}
+
+/// The command line understood by every binary built on this crate, which
+/// `Args::resolve` checks and resolves into `Args`.
+#[derive(Parser)]
+#[command(version)]
+pub(crate) struct Cli {
+    /// A directory of PGNs, a single PGN, or - for stdin, then where outputs
+    /// are written (default: the pgn dir); or several PGN files, then
+    /// optionally where their outputs are written
+    #[arg(value_name = "PATH")]
+    pub(crate) pgns: Vec<PathBuf>,
+    /// The pgn dir, as a flag rather than the first argument
+    #[arg(long, value_name = "DIR", conflicts_with = "pgns")]
+    pgn_dir: Option<PathBuf>,
+    /// Read a PGN from stdin, compressed or not, like giving - as the pgn dir
+    #[arg(long, conflicts_with_all = ["pgns", "pgn_dir"])]
+    stdin: bool,
+    /// The csv dir, as a flag rather than the last argument
+    #[arg(long, value_name = "DIR")]
+    pub(crate) csv_dir: Option<PathBuf>,
+    /// Read options from this TOML file (default: pgn2csv.toml, if there is
+    /// one)
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+    /// Also convert the PGNs in subdirectories of the pgn dir
+    #[arg(long)]
+    pub(crate) recursive: bool,
+    /// With --recursive, go at most n levels of subdirectories down
+    #[arg(long, value_name = "N", requires = "recursive")]
+    pub(crate) max_depth: Option<usize>,
+    /// Only convert files matching the glob, or skip them with a leading !,
+    /// e.g. '2024-*.pgn.zst' (repeatable)
+    #[arg(long = "glob", value_name = "GLOB")]
+    pub(crate) globs: Vec<String>,
+    /// Write the output of the only PGN here, - for stdout
+    #[arg(long, value_name = "FILE")]
+    pub(crate) output: Option<PathBuf>,
+    /// Write the rows of every PGN to this one CSV, - for stdout
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["output", "sort_by", "format_for", "resume"]
+    )]
+    merge: Option<PathBuf>,
+    /// Write the rows of every PGN to a CSV per month, speed, or value of a
+    /// header, e.g. Event
+    #[arg(long, value_name = "KEY")]
+    partition_by: Option<Partition>,
+    /// Write csv, jsonl, or a format enabled by a feature
+    #[arg(long, value_name = "FORMAT", default_value = "csv")]
+    format: Format,
+    /// Write the PGNs matching a glob, or <SIZE or >SIZE, in another format,
+    /// e.g. '>1G=parquet' (repeatable, the first match wins)
+    #[arg(long, value_name = "RULE=FORMAT")]
+    format_for: Vec<FormatRule>,
+    /// Sort each CSV by the given columns
+    #[arg(long, value_name = "COL,...", value_delimiter = ',')]
+    sort_by: Option<Vec<String>>,
+    /// Memory for sorting each CSV, e.g. 512M
+    #[arg(long, value_name = "SIZE", default_value = "256M", value_parser = parse_size)]
+    max_memory: usize,
+    /// Drop rows identical to one already written
+    #[arg(long)]
+    dedup: bool,
+    /// Write free text plain, or as json strings
+    #[arg(long, value_name = "ENC", default_value = "plain")]
+    text_encoding: TextEncoding,
+    /// Write results as white-score, half-points, signed, or categorical
+    /// (default: the binary's own)
+    #[arg(long, value_name = "ENC")]
+    result_encoding: Option<ResultEncoding>,
+    /// Write booleans as true-false, 1-0, or t-f
+    #[arg(long, value_name = "ENC", default_value = "true-false")]
+    bool_encoding: BoolEncoding,
+    /// Write clocks as whole seconds, deciseconds, or milliseconds
+    #[arg(long, value_name = "UNIT")]
+    clock_unit: Option<ClockUnit>,
+    /// Write terminations as codes or names (default: the binary's own)
+    #[arg(long, value_name = "ENC")]
+    termination_encoding: Option<TerminationEncoding>,
+    /// Add derived columns, e.g. estimated_duration_seconds
+    #[arg(long, value_name = "COL,...", value_delimiter = ',')]
+    library_columns: Vec<LibraryColumn>,
+    /// The offset of Date and Time headers, for started_at
+    #[arg(
+        long,
+        value_name = "+HH:MM",
+        default_value = "+00:00",
+        value_parser = parse_utc_offset,
+        allow_hyphen_values = true
+    )]
+    utc_offset: i32,
+    /// Write at most n plies to moves_san
+    #[arg(long, value_name = "N", value_parser = positive::<u32>)]
+    moves_max_plies: Option<u32>,
+    /// Leave move numbers out of moves_san
+    #[arg(long)]
+    moves_strip_numbers: bool,
+    /// Leave + and # out of moves_san
+    #[arg(long)]
+    moves_strip_checks: bool,
+    /// Separate moves_san tokens by space or comma
+    #[arg(long, value_name = "SEP", default_value = "space")]
+    moves_separator: MoveSeparator,
+    /// Write a table of the tournaments seen to tournaments.csv
+    #[arg(long)]
+    tournaments: bool,
+    /// Write every header of every game to <output>.headers.csv
+    #[arg(long)]
+    header_rows: bool,
+    /// Leave out the game_uid column that identifies each game
+    #[arg(long)]
+    no_game_uid: bool,
+    /// The longest break within a session of rematches
+    #[arg(long, value_name = "MINUTES")]
+    session_gap: Option<u64>,
+    /// Count games without moves, or also keep their rows
+    #[arg(long, value_name = "count|keep")]
+    aborted: Option<AbortedGames>,
+    /// Write the first N skipped games of each output to <output>.skipped.pgn
+    #[arg(long, value_name = "N")]
+    dump_skipped: Option<usize>,
+    /// Write column statistics to manifest.json
+    #[arg(long)]
+    stats: bool,
+    /// Check every row, e.g. "white_elo between 600 and 3500" (repeatable)
+    #[arg(long = "assert", value_name = "ASSERTION")]
+    assertions: Vec<Assertion>,
+    /// Drop rows that fail an assertion
+    #[arg(long)]
+    reject: bool,
+    /// The most PGNs to convert at once (default: one per core)
+    #[arg(long, value_name = "N", value_parser = positive::<usize>, conflicts_with = "serial")]
+    pub(crate) threads: Option<usize>,
+    /// Convert one PGN at a time, on the main thread
+    #[arg(long)]
+    pub(crate) serial: bool,
+    /// Write to the csv dir even if another run has it locked
+    #[arg(long)]
+    force: bool,
+    /// Convert PGNs whose output exists again, skip them, or stop
+    #[arg(long, value_name = "overwrite|skip|error", default_value = "overwrite")]
+    on_existing: OnExisting,
+    /// Leave out the PGNs that the last runs into the csv dir converted
+    #[arg(long)]
+    resume: bool,
+    /// Write the remaining outputs here if the csv dir turns out to be
+    /// read-only during the run
+    #[arg(long, value_name = "DIR")]
+    fallback_dir: Option<PathBuf>,
+    /// Stop the run when a PGN fails, skip the PGN, or also skip games that
+    /// can't be read
+    #[arg(
+        long,
+        value_name = "abort|skip-file|skip-game",
+        default_value = "abort"
+    )]
+    errors: ErrorPolicy,
+    /// Also write each row with the colors swapped, with a mirrored column
+    #[arg(long)]
+    mirror_colors: bool,
+    /// Convert PGNs smaller than size together, e.g. 64M
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    batch: Option<usize>,
+    /// Write per-file stage timings as a Chrome trace
+    #[arg(long, value_name = "TRACE.JSON")]
+    profile: Option<PathBuf>,
+    /// Print the columns and filters of this binary's rows
+    #[arg(long)]
+    describe: bool,
+    /// Print the first n rows of the first PGN as a table, without writing
+    /// anything
+    #[arg(long, value_name = "N", value_parser = positive::<usize>)]
+    preview: Option<usize>,
+    /// Stop reading each PGN once it has written n rows, to try out a
+    /// processor on the first games
+    #[arg(long, value_name = "N", value_parser = positive::<u64>)]
+    limit: Option<u64>,
+    /// Read the headers of every PGN first, to estimate the rows, and ask
+    /// before converting them
+    #[arg(long)]
+    scan: bool,
+    /// Convert after --scan without asking
+    #[arg(long, requires = "scan")]
+    yes: bool,
+    /// List the PGNs found, their compression, and their outputs, without
+    /// converting them
+    #[arg(long)]
+    dry_run: bool,
+    /// Start each CSV with a # schema_version comment line
+    #[arg(long)]
+    schema_comment: bool,
+    /// Log text, or json for one object per event
+    #[arg(long, value_name = "FORMAT", default_value = "text")]
+    log_format: LogFormat,
+    /// Only log warnings, without progress bars
+    #[arg(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Also log each file converted, or with -vv each file started
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// Log each file's progress, flagging stalled files
+    #[arg(long, value_name = "SECONDS", value_parser = positive::<u64>)]
+    heartbeat: Option<u64>,
+    /// Expected games per PGN, for per-file progress bars
+    #[arg(long, value_name = "FILE")]
+    game_counts: Option<String>,
+    /// Fix all line endings and BOMs, bom, or none
+    #[arg(long = "normalize", value_name = "MODE", default_value = "all")]
+    normalization: Normalization,
+    /// Split games crammed onto one line or missing blank lines
+    #[arg(long)]
+    tolerant: bool,
+    /// Skip games with more moves than this, variations included
+    #[arg(long, value_name = "N", value_parser = positive::<u32>)]
+    max_plies: Option<u32>,
+    /// Skip games larger than this without parsing them, e.g. 1M
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    max_game_bytes: Option<usize>,
+    /// A book of openings for the opening columns (repeatable)
+    #[cfg(feature = "openings")]
+    #[arg(long, value_name = "TSV")]
+    openings: Vec<PathBuf>,
+    /// Rating statistics for the rating z-score columns
+    #[arg(long, value_name = "CSV")]
+    rating_stats: Option<PathBuf>,
+    /// Write the mean and spread of ratings per month and speed, then exit
+    #[arg(long, value_name = "CSV", conflicts_with = "rating_stats")]
+    write_rating_stats: Option<PathBuf>,
+    /// Download a PGN into the pgn dir first (repeatable)
+    #[cfg(feature = "download")]
+    #[arg(long = "download", value_name = "URL")]
+    downloads: Vec<String>,
+    /// The sha256sums list to verify downloads against
+    #[cfg(feature = "download")]
+    #[arg(long, value_name = "URL")]
+    checksums: Option<String>,
+    /// Convert a remote PGN as it is read, without saving it (repeatable)
+    #[cfg(feature = "download")]
+    #[arg(long = "stream", value_name = "URL")]
+    streams: Vec<String>,
+    /// Keep streamed PGNs here for later runs
+    #[cfg(feature = "download")]
+    #[arg(long, value_name = "DIR")]
+    cache_dir: Option<PathBuf>,
+    /// The most to keep in the cache
+    #[cfg(feature = "download")]
+    #[arg(long, value_name = "SIZE", default_value = "64G", value_parser = parse_size)]
+    cache_size: usize,
+    /// The most HTTP requests to send in any hour
+    #[cfg(feature = "download")]
+    #[arg(long, value_name = "N", value_parser = positive::<usize>)]
+    requests_per_hour: Option<usize>,
+    /// The most HTTP requests to have open at once
+    #[cfg(feature = "download")]
+    #[arg(long, value_name = "N", value_parser = positive::<usize>)]
+    max_connections: Option<usize>,
+}
+
+impl Args {
+    /// Parses the process arguments, with `about` as the description in
+    /// `--help`, printing usage and exiting if they are malformed.
+    pub(crate) fn parse(about: &'static str) -> Args {
+        let mut command = Cli::command().about(about);
+        let config = Path::new(CONFIG);
+        let cli = Cli::parse_with_config(
+            &mut command,
+            env::args_os().collect(),
+            config.is_file().then_some(config),
+        )
+        .unwrap_or_else(|e| match e.downcast::<clap::Error>() {
+            Ok(e) => e.exit(),
+            Err(e) => command
+                .error(ErrorKind::InvalidValue, format!("{e:#}"))
+                .exit(),
+        });
+        Args::resolve(cli, false)
+            .unwrap_or_else(|e| command.error(ErrorKind::ArgumentConflict, e).exit())
+    }
+
+    /// Checks options that clap can't check on its own and fills in the
+    /// defaults that depend on other options. Without a PGN directory or PGN
+    /// files, only `--describe` or `sources` given from code can be converted.
+    pub(crate) fn resolve(cli: Cli, sources: bool) -> Result<Args> {
+        let mut paths = cli.pgns;
+        // the last of several paths is where outputs are written, unless it is
+        // a PGN too
+        let csv_path = match paths.last() {
+            Some(last) if paths.len() > 1 && !is_pgn_file(last) => paths.pop(),
+            _ => None,
+        };
+        let csv_dir = csv_path.or(cli.csv_dir);
+        let mut pgn_files = Vec::new();
+        let (pgn_dir, csv_dir) = if paths.len() > 1 {
+            for path in &paths {
+                if !path.is_file() {
+                    bail!(
+                        "{} isn't a PGN file; give one pgn dir, or PGN files",
+                        path.display()
+                    );
+                }
+            }
+            let csv_dir = match csv_dir {
+                Some(csv_dir) => csv_dir,
+                None => common_dir(&paths)?,
+            };
+            pgn_files = paths;
+            (PathBuf::new(), Some(csv_dir))
+        } else {
+            let stdin = cli.stdin.then(|| PathBuf::from(STDIO));
+            match (paths.pop().or(cli.pgn_dir).or(stdin), csv_dir) {
+                (Some(pgn_dir), csv_dir) => (pgn_dir, csv_dir),
+                // describing the processor doesn't read any PGNs
+                (None, None) if cli.describe => (PathBuf::new(), None),
+                (None, csv_dir) if sources => (PathBuf::new(), csv_dir),
+                _ => bail!("a pgn dir is required"),
+            }
+        };
+        let moves_format = MovesFormat {
+            max_plies: cli.moves_max_plies,
+            numbers: !cli.moves_strip_numbers,
+            checks: !cli.moves_strip_checks,
+            separator: cli.moves_separator,
+        };
+        let mut columns = cli.library_columns;
+        for column in &mut columns {
+            match column {
+                LibraryColumn::StartedAt { local_offset } => *local_offset = cli.utc_offset,
+                LibraryColumn::MovesSan(format) => *format = moves_format,
+                _ => {}
+            }
+        }
+        let only_csv = cli.format == Format::Csv
+            && cli.format_for.iter().all(|rule| rule.format == Format::Csv);
+        if cli.sort_by.is_some() && !only_csv {
+            bail!("--sort-by only works with CSV output");
+        }
+        // PGNs read from stdin are written to stdout, unless told otherwise
+        let mut output = cli.output;
+        if pgn_dir == Path::new(STDIO) && csv_dir.is_none() && output.is_none() {
+            output = Some(PathBuf::from(STDIO));
+        }
+        let to_stdout = output.as_deref() == Some(Path::new(STDIO));
+        let streams = matches!(cli.format, Format::Csv | Format::Jsonl);
+        if to_stdout && !cli.format_for.is_empty() {
+            bail!("--format-for needs output files, not stdout, which takes --format");
+        }
+        if to_stdout && (!streams || cli.sort_by.is_some()) {
+            bail!("only unsorted CSV or JSONL output can be written to stdout");
+        }
+        if cli.merge.is_some() && cli.format != Format::Csv {
+            bail!("--merge writes a single CSV, so it only works with CSV output");
+        }
+        if to_stdout && cli.header_rows {
+            bail!("--header-rows needs an output file, not stdout");
+        }
+        if cli.scan && pgn_dir == Path::new(STDIO) {
+            bail!("--scan reads PGNs twice, so it can't read one from stdin");
+        }
+        #[cfg(feature = "download")]
+        if cli.scan && !cli.streams.is_empty() {
+            bail!("--scan reads PGNs twice, so it can't read streamed ones");
+        }
+        if to_stdout && cli.dump_skipped.is_some() {
+            bail!("--dump-skipped needs an output file, not stdout");
+        }
+        #[cfg(feature = "duckdb")]
+        if cli.format == Format::DuckDb && cli.on_existing != OnExisting::Overwrite {
+            bail!("--on-existing doesn't work with DuckDB, whose outputs share a database");
+        }
+        Ok(Args {
+            csv_dir: csv_dir.unwrap_or_else(|| default_csv_dir(&pgn_dir, output.as_deref())),
+            pgn_files,
+            output,
+            merge: cli.merge,
+            partition_by: cli.partition_by,
+            text_encoding: cli.text_encoding,
+            result_encoding: cli.result_encoding,
+            termination_encoding: cli.termination_encoding,
+            bool_encoding: cli.bool_encoding,
+            clock_unit: cli.clock_unit,
+            pgn_dir,
+            globs: cli.globs,
+            profile: cli.profile,
+            sort_by: cli.sort_by,
+            max_memory: cli.max_memory,
+            dedup: cli.dedup,
+            stats: cli.stats,
+            format: cli.format,
+            format_for: cli.format_for,
+            game_counts: cli.game_counts,
+            heartbeat: cli.heartbeat.map(Duration::from_secs),
+            log: Log::new(cli.log_format, Verbosity::new(cli.quiet, cli.verbose)),
+            describe: cli.describe,
+            preview: cli.preview,
+            limit: cli.limit,
+            scan: cli.scan,
+            yes: cli.yes,
+            dry_run: cli.dry_run,
+            schema_comment: cli.schema_comment,
+            columns,
+            #[cfg(feature = "openings")]
+            openings: cli.openings,
+            rating_stats: cli.rating_stats,
+            write_rating_stats: cli.write_rating_stats,
+            tournaments: cli.tournaments,
+            header_rows: cli.header_rows,
+            game_uid: !cli.no_game_uid,
+            session_gap: cli
+                .session_gap
+                .map(|minutes| Duration::from_secs(minutes * 60)),
+            normalization: cli.normalization,
+            tolerant: cli.tolerant,
+            max_plies: cli.max_plies,
+            max_game_bytes: cli.max_game_bytes,
+            threads: cli.threads,
+            serial: cli.serial,
+            force: cli.force,
+            on_existing: cli.on_existing,
+            resume: cli.resume,
+            fallback_dir: cli.fallback_dir,
+            errors: cli.errors,
+            mirror_colors: cli.mirror_colors,
+            recursive: cli.recursive,
+            max_depth: cli.max_depth,
+            batch: cli.batch,
+            aborted: cli.aborted,
+            dump_skipped: cli.dump_skipped,
+            #[cfg(feature = "download")]
+            downloads: cli.downloads,
+            #[cfg(feature = "download")]
+            checksums: cli.checksums,
+            #[cfg(feature = "download")]
+            streams: cli.streams,
+            #[cfg(feature = "download")]
+            cache_dir: cli.cache_dir,
+            #[cfg(feature = "download")]
+            cache_size: cli.cache_size as u64,
+            #[cfg(feature = "download")]
+            requests_per_hour: cli.requests_per_hour,
+            #[cfg(feature = "download")]
+            max_connections: cli.max_connections,
+            assertions: (!cli.assertions.is_empty()).then_some(Assertions {
+                assertions: cli.assertions,
+                reject: cli.reject,
+            }),
+            sink: None,
+        })
+    }
+}
+
+impl Args {
+    /// The format of the output of the PGN at `path`, relative to the pgn
+    /// dir, which is `size` bytes large if that is known: that of the first
+    /// `--format-for` rule that matches it, or else `--format`.
+    pub(crate) fn format_of(&self, path: &Path, size: Option<u64>) -> Format {
+        self.format_for
+            .iter()
+            .find(|rule| rule.matches(path, size))
+            .map_or(self.format, |rule| rule.format)
+    }
+
+    /// Whether every output is a CSV.
+    pub(crate) fn only_csv(&self) -> bool {
+        self.format == Format::Csv
+            && self
+                .format_for
+                .iter()
+                .all(|rule| rule.format == Format::Csv)
+    }
+
+    /// The encodings that the run writes values in: those given, or else
+    /// `P`'s own.
+    pub(crate) fn encodings<P: GameProcessor>(&self) -> Encodings {
+        Encodings {
+            result: self.result_encoding.unwrap_or_else(P::result_encoding),
+            termination: self
+                .termination_encoding
+                .unwrap_or_else(P::termination_encoding),
+            text: self.text_encoding,
+            bool: self.bool_encoding,
+            clock_unit: self.clock_unit,
+        }
+    }
+}
+
+impl Cli {
+    /// Parses `args`, the first of which names the binary, along with the
+    /// options in the configuration file given with `--config`, or else in
+    /// `config`. Options given in `args` override the file's.
+    pub(crate) fn parse_with_config(
+        command: &mut Command,
+        mut args: Vec<OsString>,
+        config: Option<&Path>,
+    ) -> Result<Cli> {
+        let matches = command.try_get_matches_from_mut(&args)?;
+        let config = matches
+            .get_one::<PathBuf>("config")
+            .map(PathBuf::as_path)
+            .or(config);
+        let matches = match config {
+            Some(path) => {
+                let bin = args.first().map(Path::new).and_then(Path::file_stem);
+                let bin = bin.unwrap_or_default().to_string_lossy().into_owned();
+                let options = config_args(command, &matches, path, &bin)?;
+                args.splice(1..1, options);
+                command.try_get_matches_from_mut(args)?
+            }
+            None => matches,
+        };
+        Ok(Cli::from_arg_matches(&matches)?)
+    }
+}
+
+/// The options in the configuration file at `path`, as arguments to parse
+/// before the command line's. Top-level keys are options for every binary,
+/// and the keys of a `[processors.<bin>]` table options for that binary only,
+/// which override them. Options given on the command line, in `matches`, are
+/// left out, so that they override both.
+fn config_args(
+    command: &Command,
+    matches: &ArgMatches,
+    path: &Path,
+    bin: &str,
+) -> Result<Vec<OsString>> {
+    let text = fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+    let mut options: Table = text
+        .parse()
+        .with_context(|| format!("parsing {}", path.display()))?;
+    match options.remove("processors") {
+        Some(Value::Table(mut processors)) => match processors.remove(bin) {
+            Some(Value::Table(processor)) => options.extend(processor),
+            Some(_) => bail!("processors.{bin} in {} must be a table", path.display()),
+            None => {}
+        },
+        Some(_) => bail!("processors in {} must be a table", path.display()),
+        None => {}
+    }
+    let mut args = Vec::new();
+    for (key, value) in options {
+        let long = key.replace('_', "-");
+        let arg = command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(&long) && long != "config")
+            .ok_or_else(|| anyhow!("unknown option {key} in {}", path.display()))?;
+        if given(matches, arg) {
+            continue;
+        }
+        // lists are given as the option repeated
+        let values = match value {
+            Value::Array(values) => values,
+            value => vec![value],
+        };
+        for value in values {
+            let arg = match (value, arg.get_action().takes_values()) {
+                (Value::Boolean(true), false) => format!("--{long}"),
+                (Value::Boolean(false), false) => continue,
+                (Value::String(s), true) => format!("--{long}={s}"),
+                (Value::Integer(n), true) => format!("--{long}={n}"),
+                (Value::Float(x), true) => format!("--{long}={x}"),
+                (_, false) => bail!("expected true or false for {key} in {}", path.display()),
+                _ => bail!(
+                    "expected a string or number for {key} in {}",
+                    path.display()
+                ),
+            };
+            args.push(arg.into());
+        }
+    }
+    Ok(args)
+}
+
+/// Whether the command line gave `arg`, or for the pgn dir, the paths that
+/// stand for it.
+fn given(matches: &ArgMatches, arg: &Arg) -> bool {
+    let ids = match arg.get_id().as_str() {
+        "pgn_dir" => vec!["pgns", "pgn_dir"],
+        id => vec![id],
+    };
+    ids.into_iter()
+        .any(|id| matches.value_source(id) == Some(ValueSource::CommandLine))
+}
+
+/// Parses a number greater than zero.
+fn positive<T: FromStr + Default + PartialOrd>(n: &str) -> Result<T> {
+    match n.parse() {
+        Ok(n) if n > T::default() => Ok(n),
+        _ => bail!("expected a positive number, not {n}"),
+    }
+}
+
+/// Whether a path given on the command line is a PGN rather than a directory:
+/// an existing file, or one named like a PGN.
+fn is_pgn_file(path: &Path) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.is_file() || EXTENSIONS.iter().any(|ext| name.ends_with(ext))
+}
+
+/// The directory that the PGN files `paths` are all in, where their outputs
+/// are written unless a CSV directory is given.
+fn common_dir(paths: &[PathBuf]) -> Result<PathBuf> {
+    let mut dirs = paths
+        .iter()
+        .map(|path| path.parent().unwrap_or(Path::new("")));
+    let first = dirs.next().unwrap_or(Path::new(""));
+    if !dirs.all(|dir| dir == first) {
+        bail!("the PGNs are in different directories, so a csv dir is needed");
+    }
+    Ok(match first.as_os_str().is_empty() {
+        true => PathBuf::from("."),
+        false => first.to_path_buf(),
+    })
+}
+
+/// Where files other than the outputs of PGNs are written, such as the
+/// manifest, when no CSV directory is given: next to the output if there is
+/// only one, and otherwise in the PGN directory, or next to the PGN if it is a
+/// file.
+fn default_csv_dir(pgn_dir: &Path, output: Option<&Path>) -> PathBuf {
+    let dir = match output {
+        Some(output) => output.parent(),
+        None if pgn_dir.is_file() || pgn_dir == Path::new(STDIO) => pgn_dir.parent(),
+        None => Some(pgn_dir),
+    };
+    match dir {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+/// Parses a size in bytes with an optional `K`, `M`, or `G` suffix.
+fn parse_size(size: &str) -> Result<usize> {
+    let (digits, shift) = match size.as_bytes().last().copied().unwrap_or_default() {
+        b'K' | b'k' => (&size[..size.len() - 1], 10),
+        b'M' | b'm' => (&size[..size.len() - 1], 20),
+        b'G' | b'g' => (&size[..size.len() - 1], 30),
+        _ => (size, 0),
+    };
+    digits
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_mul(1 << shift))
+        .ok_or_else(|| anyhow!("expected a size like 512M, not {size}"))
+}
+
+/// Parses a UTC offset like `+05:30` or `-08:00` into seconds.
+fn parse_utc_offset(offset: &str) -> Result<i32> {
+    utc_offset_seconds(offset)
+        .ok_or_else(|| anyhow!("expected an offset like +05:30, not {offset}"))
+}
+
+fn utc_offset_seconds(offset: &str) -> Option<i32> {
+    let (sign, offset) = match offset.as_bytes().first()? {
+        b'+' => (1, &offset[1..]),
+        b'-' => (-1, &offset[1..]),
+        _ => return None,
+    };
+    let (hours, minutes) = offset.split_once(':')?;
+    let (hours, minutes) = (hours.parse::<i32>().ok()?, minutes.parse::<i32>().ok()?);
+    (hours <= 14 && minutes < 60).then_some(sign * (hours * 3600 + minutes * 60))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::iter;
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("512").ok(), Some(512));
+        assert_eq!(parse_size("4k").ok(), Some(4096));
+        assert_eq!(parse_size("256M").ok(), Some(256 << 20));
+        assert_eq!(parse_size("2G").ok(), Some(2 << 30));
+        assert_eq!(parse_size("G").ok(), None);
+        assert_eq!(parse_size("1.5G").ok(), None);
+        assert_eq!(parse_utc_offset("+05:30").ok(), Some(19_800));
+        assert_eq!(parse_utc_offset("-08:00").ok(), Some(-28_800));
+        assert_eq!(parse_utc_offset("08:00").ok(), None);
+    }
+
+    #[test]
+    fn format_rules() {
+        let rule = |rule: &str| rule.parse::<FormatRule>().unwrap();
+        let (small, old) = (rule("<1M=jsonl"), rule("2013/*.pgn=jsonl"));
+        assert!(small.matches(Path::new("a.pgn"), Some(1000)));
+        assert!(!small.matches(Path::new("a.pgn"), None));
+        assert!(!rule(">1M=jsonl").matches(Path::new("a.pgn"), Some(1000)));
+        assert!(old.matches(Path::new("2013/a.pgn"), None));
+        assert!(!old.matches(Path::new("2014/a.pgn"), None));
+        assert!("*.pgn".parse::<FormatRule>().is_err());
+        assert!("*.pgn=tsv".parse::<FormatRule>().is_err());
+    }
+
+    #[test]
+    fn cli() {
+        Cli::command().debug_assert();
+        let cli = Cli::try_parse_from(["blitz", "pgns", "--sort-by", "a,b", "--threads", "4"]);
+        let cli = cli.unwrap();
+        assert_eq!(cli.sort_by, Some(vec!["a".to_owned(), "b".to_owned()]));
+        assert_eq!(cli.threads, Some(4));
+        assert!(Cli::try_parse_from(["blitz", "pgns", "--threads", "4", "--serial"]).is_err());
+        assert!(Cli::try_parse_from(["blitz", "pgns", "--max-depth", "2"]).is_err());
+        assert!(Cli::try_parse_from(["blitz", "pgns", "--stdin"]).is_err());
+        let cli = Cli::try_parse_from([
+            "blitz",
+            "pgns",
+            "--library-columns",
+            "started_at",
+            "--utc-offset",
+            "-05:00",
+        ]);
+        let args = Args::resolve(cli.unwrap(), false).unwrap();
+        assert!(matches!(
+            args.columns[..],
+            [LibraryColumn::StartedAt {
+                local_offset: -18_000
+            }]
+        ));
+    }
+
+    #[test]
+    fn stdio() {
+        let resolve = |args: &[&str]| {
+            let args = iter::once("blitz").chain(args.iter().copied());
+            Args::resolve(Cli::try_parse_from(args).unwrap(), false)
+        };
+        // PGNs from stdin are written to stdout, unless there is an output
+        let args = resolve(&["-"]).unwrap();
+        assert_eq!(args.output.as_deref(), Some(Path::new(STDIO)));
+        let args = resolve(&["--stdin"]).unwrap();
+        assert_eq!(args.output.as_deref(), Some(Path::new(STDIO)));
+        assert_eq!(resolve(&["-", "csvs"]).unwrap().output, None);
+        let args = resolve(&["-", "--output", "games.csv"]).unwrap();
+        assert_eq!(args.output.as_deref(), Some(Path::new("games.csv")));
+        assert!(resolve(&["-", "--format", "jsonl"]).is_ok());
+        assert!(resolve(&["pgns", "--output", "-"]).is_ok());
+        // stdout takes a single unsorted CSV or JSONL stream
+        assert!(resolve(&["-", "--format-for", "*.pgn=jsonl"]).is_err());
+        assert!(resolve(&["-", "--sort-by", "a"]).is_err());
+        assert!(resolve(&["-", "--header-rows"]).is_err());
+        assert!(resolve(&["-", "--scan"]).is_err());
+        assert!(resolve(&["pgns", "--output", "-", "--dump-skipped", "10"]).is_err());
+    }
+
+    #[test]
+    fn pgn_files() {
+        let dir = env::temp_dir().join(format!("pgn2csv-files-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (a, b) = (dir.join("a.pgn"), dir.join("b.pgn.zst"));
+        fs::write(&a, "").unwrap();
+        fs::write(&b, "").unwrap();
+        let resolve = |paths: &[&Path]| {
+            let args = iter::once(Path::new("blitz")).chain(paths.iter().copied());
+            Args::resolve(Cli::try_parse_from(args).unwrap(), false)
+        };
+        let args = resolve(&[&a, &b]).unwrap();
+        assert_eq!(args.pgn_files, [a.clone(), b.clone()]);
+        assert_eq!(args.csv_dir, dir);
+        let args = resolve(&[&a, &b, Path::new("csvs")]).unwrap();
+        assert_eq!(args.pgn_files.len(), 2);
+        assert_eq!(args.csv_dir, Path::new("csvs"));
+        // a single PGN is still the pgn dir, and two paths a pgn and csv dir
+        let args = resolve(&[&a, Path::new("csvs")]).unwrap();
+        assert_eq!((args.pgn_dir, args.pgn_files.len()), (a.clone(), 0));
+        assert!(resolve(&[&a, &dir.join("missing.pgn")]).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn config() {
+        let path = env::temp_dir().join(format!("pgn2csv-{}.toml", std::process::id()));
+        let toml = "pgn_dir = \"pgns\"\nformat = \"jsonl\"\nglob = [\"a*\", \"b*\"]\n\
+                    recursive = true\n[processors.blitz]\nformat = \"csv\"\nthreads = 2\n";
+        fs::write(&path, toml).unwrap();
+        let parse = |args: &[&str]| {
+            let args = args.iter().map(OsString::from).collect();
+            Cli::parse_with_config(&mut Cli::command(), args, Some(&path))
+        };
+        let cli = parse(&["blitz"]).unwrap();
+        assert_eq!(cli.pgn_dir.as_deref(), Some(Path::new("pgns")));
+        assert!(cli.format == Format::Csv);
+        assert_eq!(cli.globs, ["a*", "b*"]);
+        assert!(cli.recursive);
+        assert_eq!(cli.threads, Some(2));
+        // the command line overrides the file
+        let cli = parse(&["rapid", "other", "--glob", "c*"]).unwrap();
+        assert_eq!(cli.pgns, [Path::new("other")]);
+        assert_eq!(cli.pgn_dir, None);
+        assert!(cli.format == Format::Jsonl);
+        assert_eq!(cli.globs, ["c*"]);
+        fs::write(&path, "colour = true\n").unwrap();
+        assert!(parse(&["blitz"]).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
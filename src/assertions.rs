@@ -0,0 +1,185 @@
+use std::{fmt, str::FromStr};
+
+use anyhow::{anyhow, bail, Error, Result};
+use serde_json::Value;
+
+use crate::{
+    log::{Event, Log},
+    record::{is_null, Record},
+};
+
+// violations of each assertion logged per file before they are only counted
+const LOGGED: u64 = 10;
+
+#[derive(Clone)]
+enum Check {
+    Between(f64, f64),
+    In(Vec<String>),
+    NotNull,
+}
+
+/// A check on the values of one output column, such as
+/// `white_elo between 600 and 3500`, `result in {0,1,2}`, or
+/// `termination not null`. Only `not null` rejects missing values.
+#[derive(Clone)]
+pub(crate) struct Assertion {
+    column: String,
+    check: Check,
+    text: String,
+}
+
+impl FromStr for Assertion {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<Self> {
+        let malformed = || anyhow!("malformed assertion: {text}");
+        let (column, check) = text.trim().split_once(' ').ok_or_else(malformed)?;
+        let check = check.trim();
+        let check = if let Some(range) = check.strip_prefix("between ") {
+            let (low, high) = range.split_once(" and ").ok_or_else(malformed)?;
+            let low = low.trim().parse().map_err(|_| malformed())?;
+            let high = high.trim().parse().map_err(|_| malformed())?;
+            Check::Between(low, high)
+        } else if let Some(set) = check.strip_prefix("in ") {
+            let set = set
+                .trim()
+                .strip_prefix('{')
+                .and_then(|set| set.strip_suffix('}'))
+                .ok_or_else(malformed)?;
+            Check::In(set.split(',').map(|v| v.trim().to_owned()).collect())
+        } else if check == "not null" {
+            Check::NotNull
+        } else {
+            return Err(malformed());
+        };
+        Ok(Assertion {
+            column: column.to_owned(),
+            check,
+            text: text.trim().to_owned(),
+        })
+    }
+}
+
+impl fmt::Display for Assertion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl Assertion {
+    fn holds(&self, value: &Value) -> bool {
+        if is_null(value) {
+            return !matches!(self.check, Check::NotNull);
+        }
+        match &self.check {
+            Check::Between(low, high) => {
+                let number = match value {
+                    Value::Number(n) => n.as_f64(),
+                    Value::String(s) => s.parse().ok(),
+                    _ => None,
+                };
+                number.is_some_and(|n| (*low..=*high).contains(&n))
+            }
+            Check::In(set) => {
+                let text = match value {
+                    Value::String(s) => s.clone(),
+                    _ => value.to_string(),
+                };
+                set.contains(&text)
+            }
+            Check::NotNull => true,
+        }
+    }
+}
+
+/// The assertions of a run, and whether rows that violate them are dropped or
+/// only logged.
+pub(crate) struct Assertions {
+    pub(crate) assertions: Vec<Assertion>,
+    pub(crate) reject: bool,
+}
+
+/// Checks the rows of one output against the run's assertions.
+pub(crate) struct FileChecks<'a> {
+    assertions: &'a Assertions,
+    log: &'a Log,
+    name: String,
+    rows: u64,
+    violations: Vec<u64>,
+}
+
+impl<'a> FileChecks<'a> {
+    pub(crate) fn new(assertions: &'a Assertions, log: &'a Log, name: String) -> Self {
+        FileChecks {
+            violations: vec![0; assertions.assertions.len()],
+            assertions,
+            log,
+            name,
+            rows: 0,
+        }
+    }
+
+    /// Returns whether the row should be written.
+    pub(crate) fn check(&mut self, record: &Record) -> Result<bool> {
+        self.rows += 1;
+        let mut ok = true;
+        for (assertion, violations) in self
+            .assertions
+            .assertions
+            .iter()
+            .zip(self.violations.iter_mut())
+        {
+            let Some(value) = record.get(&assertion.column) else {
+                bail!("no column named {} to check", assertion.column);
+            };
+            if assertion.holds(value) {
+                continue;
+            }
+            ok = false;
+            *violations += 1;
+            if *violations <= LOGGED {
+                self.log.emit(&Event::Violation {
+                    file: &self.name,
+                    row: self.rows,
+                    assertion: assertion.to_string(),
+                    value,
+                });
+            }
+        }
+        Ok(ok || !self.assertions.reject)
+    }
+
+    /// Logs how often each assertion was violated in total, if some
+    /// violations weren't logged individually.
+    pub(crate) fn finish(&self) {
+        for (assertion, &violations) in self.assertions.assertions.iter().zip(&self.violations) {
+            if violations > LOGGED {
+                self.log.emit(&Event::Violations {
+                    file: &self.name,
+                    assertion: assertion.to_string(),
+                    violations,
+                    rows: self.rows,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_checks() {
+        let elo: Assertion = "white_elo between 600 and 3500".parse().unwrap();
+        assert!(elo.holds(&Value::from(1500)));
+        assert!(!elo.holds(&Value::from(5000)));
+        assert!(elo.holds(&Value::Null));
+        let result: Assertion = "result in {0, 1, 2}".parse().unwrap();
+        assert!(result.holds(&Value::from(2)));
+        assert!(!result.holds(&Value::from("3")));
+        let present: Assertion = "termination not null".parse().unwrap();
+        assert!(!present.holds(&Value::from("")));
+        assert!("white_elo above 600".parse::<Assertion>().is_err());
+    }
+}
@@ -0,0 +1,147 @@
+// Count lichess games by time control class, UTC day of the week (0 for
+// Monday to 6 for Sunday), and UTC hour, with the average rating of the games
+// played in each, for activity patterns over a dump.
+
+use pgn2csv::{
+    group::GroupBy,
+    headers::{Matchup, PgnDate, Rating, Speed, TimeControl},
+    pgn2csv, GameProcessor,
+};
+
+use std::mem;
+
+use anyhow::Result;
+use bstr::ByteSlice;
+use pgn_reader::{RawHeader, Skip, Visitor};
+use serde::Serialize;
+
+#[derive(Default, Serialize)]
+struct Row {
+    speed: Speed,
+    weekday: u8,
+    hour: u8,
+    average_rating: Option<u16>,
+}
+
+#[derive(Default)]
+struct Scratch {
+    white_rating: Option<Rating>,
+    black_rating: Option<Rating>,
+    has_date: bool,
+    has_time: bool,
+    skip_game: bool,
+}
+
+impl Scratch {
+    fn reset(&mut self) {
+        *self = Scratch::default();
+    }
+}
+
+#[derive(Default)]
+struct Processor {
+    row: Row,
+    scratch: Scratch,
+}
+
+impl GameProcessor for Processor {
+    type Row = Row;
+
+    fn skip(&self) -> bool {
+        self.scratch.skip_game
+    }
+
+    fn row(&mut self) -> Row {
+        mem::take(&mut self.row)
+    }
+
+    fn description() -> &'static str {
+        "Games and their average rating per time control class, UTC weekday, and UTC hour."
+    }
+
+    fn filters() -> Vec<&'static str> {
+        vec![
+            "the UTC date and time are known",
+            "the time control is known",
+        ]
+    }
+
+    fn group_by() -> Option<GroupBy> {
+        Some(
+            GroupBy::new(&["speed", "weekday", "hour"])
+                .count("games")
+                .mean("average_rating", "average_rating"),
+        )
+    }
+}
+
+impl Visitor for Processor {
+    type Result = ();
+
+    fn begin_game(&mut self) {
+        self.scratch.reset();
+    }
+
+    fn header(&mut self, key: &[u8], value: RawHeader<'_>) {
+        if self.skip() {
+            return;
+        }
+
+        match key {
+            b"UTCDate" => match PgnDate::try_from(value)
+                .ok()
+                .and_then(|date| date.weekday())
+            {
+                Some(weekday) => {
+                    self.row.weekday = weekday;
+                    self.scratch.has_date = true;
+                }
+                None => self.scratch.skip_game = true,
+            },
+            b"UTCTime" => match value
+                .as_bytes()
+                .get(..2)
+                .and_then(|hour| hour.to_str().ok()?.parse().ok())
+            {
+                Some(hour) if hour < 24 => {
+                    self.row.hour = hour;
+                    self.scratch.has_time = true;
+                }
+                _ => self.scratch.skip_game = true,
+            },
+            b"TimeControl" if value.as_bytes() == b"-" => {
+                self.row.speed = Speed::Correspondence;
+            }
+            b"TimeControl" => match TimeControl::try_from(value) {
+                Ok(tc) => self.row.speed = Speed::from(&tc),
+                Err(_) => self.scratch.skip_game = true,
+            },
+            b"WhiteElo" => self.scratch.white_rating = Rating::try_from(value).ok(),
+            b"BlackElo" => self.scratch.black_rating = Rating::try_from(value).ok(),
+            _ => (),
+        }
+    }
+
+    fn end_headers(&mut self) -> Skip {
+        if !self.scratch.has_date || !self.scratch.has_time || self.row.speed == Speed::Unknown {
+            self.scratch.skip_game = true;
+        }
+        if let (Some(white), Some(black)) = (&self.scratch.white_rating, &self.scratch.black_rating)
+        {
+            self.row.average_rating = Some(Matchup::new(white, black).average_rating);
+        }
+        // only the headers are needed
+        Skip(true)
+    }
+
+    fn begin_variation(&mut self) -> Skip {
+        Skip(true)
+    }
+
+    fn end_game(&mut self) {}
+}
+
+fn main() -> Result<()> {
+    pgn2csv::<Processor>()?;
+    Ok(())
+}
@@ -2,15 +2,15 @@
 // least one player berserked.
 
 use pgn2csv::{
+    analyzers::berserked,
     comments::Clock,
-    headers::{PgnResult, Rating, Termination, TimeControl},
+    headers::{Event, EventKind, PgnResult, Rating, ResultEncoding, Termination, TimeControl},
     pgn2csv, GameProcessor,
 };
 
-use std::{env, mem};
+use std::mem;
 
 use anyhow::Result;
-use bstr::ByteSlice;
 use pgn_reader::{RawComment, RawHeader, Skip, Visitor};
 use serde::Serialize;
 
@@ -20,8 +20,8 @@ struct Row {
     black_rating: Rating,
     time: u32,
     berserk: u8,
-    result: u8,
-    termination: u8,
+    result: PgnResult,
+    termination: Termination,
 }
 
 #[derive(Default)]
@@ -71,6 +71,25 @@ impl GameProcessor for Processor {
     fn row(&mut self) -> Row {
         mem::take(&mut self.row)
     }
+
+    fn result_encoding() -> ResultEncoding {
+        ResultEncoding::HalfPoints
+    }
+
+    fn description() -> &'static str {
+        "Which players berserked in 1+0 and 3+0 arena tournament games."
+    }
+
+    fn filters() -> Vec<&'static str> {
+        vec![
+            "the game was played in an arena tournament",
+            "the time control is 1+0 or 3+0",
+            "both ratings are known",
+            "the game ended normally or on time",
+            "the result is decisive or a draw",
+            "both players made a move with a clock comment",
+        ]
+    }
 }
 
 impl Visitor for Processor {
@@ -102,11 +121,11 @@ impl Visitor for Processor {
                     self.scratch.skip_game = true;
                 }
             },
-            b"Event" => {
-                // we only want arena games (is there a way to disclude swiss?)
-                if !value.as_bytes().contains_str("tournament") {
-                    self.scratch.skip_game = true;
-                }
+            // we only want arena games, not swiss ones
+            b"Event"
+                if !Event::try_from(value).is_ok_and(|event| event.kind == EventKind::Arena) =>
+            {
+                self.scratch.skip_game = true;
             }
             b"TimeControl" => match TimeControl::try_from(value) {
                 Ok(tc) => {
@@ -121,25 +140,14 @@ impl Visitor for Processor {
                 }
             },
             b"Termination" => match Termination::try_from(value) {
-                Ok(termination) => match termination {
-                    Termination::Normal => self.row.termination = 0,
-                    Termination::TimeForfeit => self.row.termination = 1,
-                    _ => self.scratch.skip_game = true,
-                },
-                Err(_) => {
-                    self.scratch.skip_game = true;
+                Ok(termination @ (Termination::Normal | Termination::TimeForfeit)) => {
+                    self.row.termination = termination;
                 }
+                _ => self.scratch.skip_game = true,
             },
             b"Result" => match PgnResult::try_from(value) {
-                Ok(result) => match result {
-                    PgnResult::WhiteWin => self.row.result = 2,
-                    PgnResult::Draw => self.row.result = 1,
-                    PgnResult::BlackWin => self.row.result = 0,
-                    PgnResult::Other => self.scratch.skip_game = true,
-                },
-                Err(_) => {
-                    self.scratch.skip_game = true;
-                }
+                Ok(PgnResult::Other) | Err(_) => self.scratch.skip_game = true,
+                Ok(result) => self.row.result = result,
             },
             _ => (),
         }
@@ -162,17 +170,12 @@ impl Visitor for Processor {
 
         match Clock::try_from(comment) {
             Ok(clock) => {
-                let t = clock.total_seconds();
                 if self.scratch.moves_with_clk == 0 {
                     // white's first move
-                    if self.row.time > t {
-                        self.scratch.white_berserked = true;
-                    }
+                    self.scratch.white_berserked = berserked(self.row.time, &clock);
                 } else if self.scratch.moves_with_clk == 1 {
                     // black's first move
-                    if self.row.time > t {
-                        self.scratch.black_berserked = true;
-                    }
+                    self.scratch.black_berserked = berserked(self.row.time, &clock);
                 }
                 self.scratch.moves_with_clk += 1;
             }
@@ -202,7 +205,6 @@ impl Visitor for Processor {
 }
 
 fn main() -> Result<()> {
-    env::set_var("RUST_BACKTRACE", "1");
     pgn2csv::<Processor>()?;
     Ok(())
 }
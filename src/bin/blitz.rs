@@ -1,19 +1,19 @@
 use pgn2csv::{
-    headers::{PgnResult, Rating, RatingDiff},
+    headers::{PgnResult, Rating, RatingDiff, ResultEncoding},
     pgn2csv, GameProcessor,
 };
 
-use std::{env, mem};
+use std::mem;
 
 use anyhow::Result;
 use pgn_reader::{RawHeader, Skip, Visitor};
 use serde::Serialize;
 
-#[derive(Default, Serialize)]
+#[derive(Clone, Default, Serialize)]
 struct Row {
     white: String,
     black: String,
-    result: i8,
+    result: PgnResult,
     utc_date: String,
     utc_time: String,
     white_elo: Rating,
@@ -49,6 +49,39 @@ impl GameProcessor for Processor {
     fn row(&mut self) -> Row {
         mem::take(&mut self.row)
     }
+
+    fn mirror(row: &Row) -> Option<Row> {
+        Some(Row {
+            white: row.black.clone(),
+            black: row.white.clone(),
+            result: row.result.swapped(),
+            white_elo: row.black_elo,
+            black_elo: row.white_elo,
+            white_rating_diff: row.black_rating_diff,
+            black_rating_diff: row.white_rating_diff,
+            ..row.clone()
+        })
+    }
+
+    fn needs_moves() -> bool {
+        false
+    }
+
+    fn result_encoding() -> ResultEncoding {
+        ResultEncoding::Signed
+    }
+
+    fn description() -> &'static str {
+        "Players, result, date, and ratings of rated blitz games."
+    }
+
+    fn filters() -> Vec<&'static str> {
+        vec![
+            "the event is \"Rated Blitz game\"",
+            "the result is decisive or a draw",
+            "both ratings and rating changes are known",
+        ]
+    }
 }
 
 impl Visitor for Processor {
@@ -64,10 +97,8 @@ impl Visitor for Processor {
         }
 
         match key {
-            b"Event" => {
-                if value.as_bytes() != b"Rated Blitz game" {
-                    self.scratch.skip_game = true;
-                }
+            b"Event" if value.as_bytes() != b"Rated Blitz game" => {
+                self.scratch.skip_game = true;
             }
             b"White" =>  {
                     self.row.white = String::from_utf8_lossy(value.as_bytes()).into_owned();
@@ -76,15 +107,8 @@ impl Visitor for Processor {
                     self.row.black = String::from_utf8_lossy(value.as_bytes()).into_owned();
             },
             b"Result" => match PgnResult::try_from(value) {
-                Ok(result) => match result {
-                    PgnResult::WhiteWin => self.row.result = 1,
-                    PgnResult::Draw => self.row.result = 0,
-                    PgnResult::BlackWin => self.row.result = -1,
-                    PgnResult::Other => self.scratch.skip_game = true,
-                },
-                Err(_) => {
-                    self.scratch.skip_game = true;
-                }
+                Ok(PgnResult::Other) | Err(_) => self.scratch.skip_game = true,
+                Ok(result) => self.row.result = result,
             },
             b"UTCDate" =>  {
                     self.row.utc_date = String::from_utf8_lossy(value.as_bytes()).into_owned();
@@ -144,7 +168,6 @@ impl Visitor for Processor {
 }
 
 fn main() -> Result<()> {
-    env::set_var("RUST_BACKTRACE", "1");
     pgn2csv::<Processor>()?;
     Ok(())
 }
@@ -0,0 +1,143 @@
+// Get one row per pair of lichess players who met in a dump, with the number
+// of games they played, the first player's score against the second, and
+// their average ratings in those games. Players are ordered by name, so each
+// pair appears once.
+
+use pgn2csv::{
+    group::GroupBy,
+    headers::{PgnResult, Rating},
+    pgn2csv, GameProcessor,
+};
+
+use std::mem;
+
+use anyhow::Result;
+use pgn_reader::{RawHeader, Skip, Visitor};
+use serde::Serialize;
+
+#[derive(Default, Serialize)]
+struct Row {
+    player_a: String,
+    player_b: String,
+    score_a: f32,
+    rating_a: Option<Rating>,
+    rating_b: Option<Rating>,
+}
+
+#[derive(Default)]
+struct Scratch {
+    white: String,
+    black: String,
+    white_rating: Option<Rating>,
+    black_rating: Option<Rating>,
+    // white's score
+    score: f32,
+    skip_game: bool,
+}
+
+impl Scratch {
+    fn reset(&mut self) {
+        *self = Scratch::default();
+    }
+}
+
+#[derive(Default)]
+struct Processor {
+    row: Row,
+    scratch: Scratch,
+}
+
+impl GameProcessor for Processor {
+    type Row = Row;
+
+    fn skip(&self) -> bool {
+        self.scratch.skip_game
+    }
+
+    fn row(&mut self) -> Row {
+        mem::take(&mut self.row)
+    }
+
+    fn description() -> &'static str {
+        "Games, score, and average ratings of every pair of players who met."
+    }
+
+    fn filters() -> Vec<&'static str> {
+        vec!["both players are known", "the result is decisive or a draw"]
+    }
+
+    fn group_by() -> Option<GroupBy> {
+        Some(
+            GroupBy::new(&["player_a", "player_b"])
+                .count("games")
+                .sum("score_a", "score_a")
+                .mean("rating_a", "average_rating_a")
+                .mean("rating_b", "average_rating_b"),
+        )
+    }
+}
+
+impl Visitor for Processor {
+    type Result = ();
+
+    fn begin_game(&mut self) {
+        self.scratch.reset();
+    }
+
+    fn header(&mut self, key: &[u8], value: RawHeader<'_>) {
+        if self.skip() {
+            return;
+        }
+
+        match key {
+            b"White" => self.scratch.white = String::from_utf8_lossy(value.as_bytes()).into_owned(),
+            b"Black" => self.scratch.black = String::from_utf8_lossy(value.as_bytes()).into_owned(),
+            b"WhiteElo" => self.scratch.white_rating = Rating::try_from(value).ok(),
+            b"BlackElo" => self.scratch.black_rating = Rating::try_from(value).ok(),
+            b"Result" => match PgnResult::try_from(value) {
+                Ok(PgnResult::WhiteWin) => self.scratch.score = 1.0,
+                Ok(PgnResult::Draw) => self.scratch.score = 0.5,
+                Ok(PgnResult::BlackWin) => self.scratch.score = 0.0,
+                _ => self.scratch.skip_game = true,
+            },
+            _ => (),
+        }
+    }
+
+    fn end_headers(&mut self) -> Skip {
+        let scratch = &mut self.scratch;
+        if scratch.white.is_empty() || scratch.black.is_empty() {
+            scratch.skip_game = true;
+        }
+        if scratch.white <= scratch.black {
+            self.row = Row {
+                player_a: mem::take(&mut scratch.white),
+                player_b: mem::take(&mut scratch.black),
+                score_a: scratch.score,
+                rating_a: scratch.white_rating.take(),
+                rating_b: scratch.black_rating.take(),
+            };
+        } else {
+            self.row = Row {
+                player_a: mem::take(&mut scratch.black),
+                player_b: mem::take(&mut scratch.white),
+                score_a: 1.0 - scratch.score,
+                rating_a: scratch.black_rating.take(),
+                rating_b: scratch.white_rating.take(),
+            };
+        }
+        // only the headers are needed
+        Skip(true)
+    }
+
+    fn begin_variation(&mut self) -> Skip {
+        Skip(true)
+    }
+
+    fn end_game(&mut self) {}
+}
+
+fn main() -> Result<()> {
+    pgn2csv::<Processor>()?;
+    Ok(())
+}
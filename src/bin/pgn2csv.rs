@@ -0,0 +1,96 @@
+//! Tools for working on processors, rather than a processor itself.
+//! `pgn2csv new my-dataset` starts a processor from the example in
+//! `examples/processor.rs`.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use clap::{Parser, Subcommand};
+
+/// The processor that new ones start from, which is built and tested with the
+/// crate so that it doesn't fall behind its API.
+const PROCESSOR: &str = include_str!("../../examples/processor.rs");
+
+#[derive(Parser)]
+#[command(version, about = "Tools for writing pgn2csv processors")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Create a processor: a new crate, or with --bin a binary in this one
+    New {
+        /// The name of the crate or binary, e.g. my-dataset
+        name: String,
+        /// Write src/bin/<name>.rs in the current crate instead
+        #[arg(long)]
+        bin: bool,
+    },
+}
+
+fn cargo_toml(name: &str) -> String {
+    format!(
+        "[package]
+name = \"{name}\"
+version = \"0.1.0\"
+edition = \"2021\"
+
+[dependencies]
+anyhow = \"1.0\"
+pgn-reader = \"0.19.0\"
+pgn2csv = {{ git = \"https://github.com/S1M0N38/pgn2csv\" }}
+serde = {{ version = \"1\", features = [\"derive\"] }}
+"
+    )
+}
+
+/// Writes `contents` to a new file at `path`, refusing to overwrite one.
+fn create(path: &Path, contents: &str) -> Result<()> {
+    if path.exists() {
+        bail!("{} already exists", path.display());
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, contents).with_context(|| format!("writing {}", path.display()))
+}
+
+fn new(name: &str, bin: bool) -> Result<()> {
+    let valid = name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        bail!("{name} isn't a valid crate name: use letters, digits, - and _");
+    }
+    if bin {
+        let path = PathBuf::from("src/bin").join(format!("{name}.rs"));
+        create(&path, PROCESSOR)?;
+        println!("Created {}", path.display());
+        println!("Run it with: cargo run --release --bin {name} path/to/pgns");
+        println!("If Cargo.toml lists its binaries, add a [[bin]] entry named {name}.");
+        return Ok(());
+    }
+    let dir = Path::new(name);
+    if dir.exists() {
+        bail!("{} already exists", dir.display());
+    }
+    create(&dir.join("Cargo.toml"), &cargo_toml(name))?;
+    create(&dir.join("src/main.rs"), PROCESSOR)?;
+    create(&dir.join(".gitignore"), "/target\n")?;
+    println!("Created the crate {name}");
+    println!("Test it with: cd {name} && cargo test");
+    println!("Run it with: cargo run --release -- path/to/pgns");
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    match Cli::parse().command {
+        Command::New { name, bin } => new(&name, bin),
+    }
+}
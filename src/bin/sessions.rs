@@ -0,0 +1,160 @@
+// Get every lichess game with the players ordered by name, numbered into
+// sessions of rematches between the same two players that started within 30
+// minutes of each other (or `--session-gap`), for tilt and fatigue studies.
+
+use pgn2csv::{
+    headers::{GameDateTime, PgnResult, Rating, Speed, TimeControl},
+    pgn2csv,
+    sessions::Sessions,
+    GameProcessor,
+};
+
+use std::{mem, time::Duration};
+
+use anyhow::Result;
+use pgn_reader::{RawHeader, Skip, Visitor};
+use serde::Serialize;
+
+#[derive(Default, Serialize)]
+struct Row {
+    player_a: String,
+    player_b: String,
+    start: i64,
+    speed: Speed,
+    score_a: f32,
+    rating_a: Option<Rating>,
+    rating_b: Option<Rating>,
+}
+
+#[derive(Default)]
+struct Scratch {
+    white: String,
+    black: String,
+    white_rating: Option<Rating>,
+    black_rating: Option<Rating>,
+    date_time: GameDateTime,
+    // white's score
+    score: f32,
+    skip_game: bool,
+}
+
+impl Scratch {
+    fn reset(&mut self) {
+        *self = Scratch::default();
+    }
+}
+
+#[derive(Default)]
+struct Processor {
+    row: Row,
+    scratch: Scratch,
+}
+
+impl GameProcessor for Processor {
+    type Row = Row;
+
+    fn skip(&self) -> bool {
+        self.scratch.skip_game
+    }
+
+    fn row(&mut self) -> Row {
+        mem::take(&mut self.row)
+    }
+
+    fn description() -> &'static str {
+        "Games numbered into sessions of rematches between the same two players."
+    }
+
+    fn filters() -> Vec<&'static str> {
+        vec![
+            "both players are known",
+            "the UTC date and time, or else the local ones, are known",
+            "the result is decisive or a draw",
+        ]
+    }
+
+    fn sessions() -> Option<Sessions> {
+        Some(Sessions::new(
+            "player_a",
+            "player_b",
+            "start",
+            Duration::from_secs(30 * 60),
+        ))
+    }
+}
+
+impl Visitor for Processor {
+    type Result = ();
+
+    fn begin_game(&mut self) {
+        self.scratch.reset();
+    }
+
+    fn header(&mut self, key: &[u8], value: RawHeader<'_>) {
+        if self.skip() {
+            return;
+        }
+
+        match key {
+            b"White" => self.scratch.white = String::from_utf8_lossy(value.as_bytes()).into_owned(),
+            b"Black" => self.scratch.black = String::from_utf8_lossy(value.as_bytes()).into_owned(),
+            b"WhiteElo" => self.scratch.white_rating = Rating::try_from(value).ok(),
+            b"BlackElo" => self.scratch.black_rating = Rating::try_from(value).ok(),
+            b"UTCDate" | b"UTCTime" | b"Date" | b"Time" => {
+                self.scratch.date_time.header(key, value);
+            }
+            b"TimeControl" if value.as_bytes() == b"-" => {
+                self.row.speed = Speed::Correspondence;
+            }
+            b"TimeControl" => {
+                if let Ok(tc) = TimeControl::try_from(value) {
+                    self.row.speed = Speed::from(&tc);
+                }
+            }
+            b"Result" => match PgnResult::try_from(value) {
+                Ok(PgnResult::WhiteWin) => self.scratch.score = 1.0,
+                Ok(PgnResult::Draw) => self.scratch.score = 0.5,
+                Ok(PgnResult::BlackWin) => self.scratch.score = 0.0,
+                _ => self.scratch.skip_game = true,
+            },
+            _ => (),
+        }
+    }
+
+    fn end_headers(&mut self) -> Skip {
+        let scratch = &mut self.scratch;
+        match scratch.date_time.timestamp() {
+            Some(start) => self.row.start = start,
+            None => scratch.skip_game = true,
+        }
+        if scratch.white.is_empty() || scratch.black.is_empty() {
+            scratch.skip_game = true;
+        }
+        if scratch.white <= scratch.black {
+            self.row.player_a = mem::take(&mut scratch.white);
+            self.row.player_b = mem::take(&mut scratch.black);
+            self.row.score_a = scratch.score;
+            self.row.rating_a = scratch.white_rating.take();
+            self.row.rating_b = scratch.black_rating.take();
+        } else {
+            self.row.player_a = mem::take(&mut scratch.black);
+            self.row.player_b = mem::take(&mut scratch.white);
+            self.row.score_a = 1.0 - scratch.score;
+            self.row.rating_a = scratch.black_rating.take();
+            self.row.rating_b = scratch.white_rating.take();
+        }
+        // only the headers are needed
+        Skip(true)
+    }
+
+    fn begin_variation(&mut self) -> Skip {
+        Skip(true)
+    }
+
+    fn end_game(&mut self) {}
+}
+
+fn main() -> Result<()> {
+    pgn2csv::<Processor>()?;
+    Ok(())
+}
@@ -0,0 +1,171 @@
+// Get every lichess swiss tournament game with its tournament and round, for
+// studies of swiss pairings and standings. Lichess doesn't record rounds, so
+// they are inferred from when the games started (see `SwissRounds`), and are
+// only right if every game of a tournament is in the same PGN.
+
+use pgn2csv::{
+    analyzers::SwissRounds,
+    headers::{
+        Event, EventKind, GameDateTime, PgnResult, Rating, ResultEncoding, Speed, Termination,
+    },
+    pgn2csv, GameProcessor,
+};
+
+use std::mem;
+
+use anyhow::Result;
+use pgn_reader::{RawHeader, Skip, Visitor};
+use serde::Serialize;
+
+#[derive(Default, Serialize)]
+struct Row {
+    tournament_id: String,
+    round: u32,
+    start: i64,
+    speed: Speed,
+    #[serde(serialize_with = "pgn2csv::boolean::serialize")]
+    rated: bool,
+    white_rating: Rating,
+    black_rating: Rating,
+    result: PgnResult,
+    termination: Termination,
+}
+
+#[derive(Default)]
+struct Scratch {
+    white_rating: Option<Rating>,
+    black_rating: Option<Rating>,
+    date_time: GameDateTime,
+    skip_game: bool,
+}
+
+impl Scratch {
+    fn reset(&mut self) {
+        *self = Scratch::default();
+    }
+}
+
+#[derive(Default)]
+struct Processor {
+    row: Row,
+    scratch: Scratch,
+    // kept across the games of a PGN, and cleared for the next
+    rounds: SwissRounds,
+}
+
+impl GameProcessor for Processor {
+    type Row = Row;
+
+    fn skip(&self) -> bool {
+        self.scratch.skip_game
+    }
+
+    fn row(&mut self) -> Row {
+        mem::take(&mut self.row)
+    }
+
+    fn result_encoding() -> ResultEncoding {
+        ResultEncoding::HalfPoints
+    }
+
+    fn needs_moves() -> bool {
+        false
+    }
+
+    fn description() -> &'static str {
+        "Swiss tournament games with their tournament and round, inferred from start times."
+    }
+
+    fn filters() -> Vec<&'static str> {
+        vec![
+            "the game was played in a swiss tournament",
+            "both ratings are known",
+            "the UTC date and time, or else the local ones, are known",
+            "the result is decisive or a draw",
+        ]
+    }
+}
+
+impl Visitor for Processor {
+    type Result = ();
+
+    fn begin_game(&mut self) {
+        self.row = Row::default();
+        self.scratch.reset();
+    }
+
+    fn header(&mut self, key: &[u8], value: RawHeader<'_>) {
+        // skipped swiss games still take part in inferring their round
+        if let b"UTCDate" | b"UTCTime" | b"Date" | b"Time" = key {
+            self.scratch.date_time.header(key, value);
+            return;
+        }
+        if self.skip() {
+            return;
+        }
+
+        match key {
+            b"Event" => match Event::try_from(value) {
+                Ok(Event {
+                    kind: EventKind::Swiss,
+                    tournament_id: Some(id),
+                    rated,
+                    speed,
+                    ..
+                }) => {
+                    self.row.tournament_id = id;
+                    self.row.rated = rated;
+                    self.row.speed = speed;
+                }
+                _ => self.scratch.skip_game = true,
+            },
+            b"WhiteElo" => self.scratch.white_rating = Rating::try_from(value).ok(),
+            b"BlackElo" => self.scratch.black_rating = Rating::try_from(value).ok(),
+            b"Result" => match PgnResult::try_from(value) {
+                Ok(PgnResult::Other) | Err(_) => self.scratch.skip_game = true,
+                Ok(result) => self.row.result = result,
+            },
+            b"Termination" => {
+                if let Ok(termination) = Termination::try_from(value) {
+                    self.row.termination = termination;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn end_headers(&mut self) -> Skip {
+        let scratch = &mut self.scratch;
+        // a game without an Event header isn't known to be from a swiss
+        if self.row.tournament_id.is_empty() {
+            scratch.skip_game = true;
+        }
+        match (scratch.white_rating.take(), scratch.black_rating.take()) {
+            (Some(white), Some(black)) => {
+                self.row.white_rating = white;
+                self.row.black_rating = black;
+            }
+            _ => scratch.skip_game = true,
+        }
+        match scratch.date_time.timestamp() {
+            Some(start) if !self.row.tournament_id.is_empty() => {
+                self.row.start = start;
+                self.row.round = self.rounds.push(&self.row.tournament_id, start);
+            }
+            _ => scratch.skip_game = true,
+        }
+        // only the headers are needed
+        Skip(true)
+    }
+
+    fn begin_variation(&mut self) -> Skip {
+        Skip(true)
+    }
+
+    fn end_game(&mut self) {}
+}
+
+fn main() -> Result<()> {
+    pgn2csv::<Processor>()?;
+    Ok(())
+}
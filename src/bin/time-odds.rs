@@ -1,16 +1,17 @@
 // Get all lichess games where one player started the game with more time than
-// the other. The vast majority of these will be from berserking.
+// the other, or from a position with more material than the other. The vast
+// majority of these will be from berserking.
 
 use pgn2csv::{
+    analyzers::{ClockMonotonicity, Odds, DEFAULT_TOLERANCE},
     comments::Clock,
-    headers::{PgnResult, Rating, Termination, TimeControl},
+    headers::{Event, EventKind, Fen, PgnResult, Rating, ResultEncoding, Termination, TimeControl},
     pgn2csv, GameProcessor,
 };
 
-use std::{env, mem};
+use std::mem;
 
 use anyhow::Result;
-use bstr::ByteSlice;
 use pgn_reader::{RawComment, RawHeader, Skip, Visitor};
 use serde::Serialize;
 
@@ -22,9 +23,11 @@ struct Row {
     black_initial_time: u32,
     initial_time: u32,
     increment: u32,
-    result: u8,
-    termination: u8,
+    result: PgnResult,
+    termination: Termination,
+    #[serde(serialize_with = "pgn2csv::boolean::serialize")]
     tournament: bool,
+    odds: Odds,
 }
 
 #[derive(Default)]
@@ -32,8 +35,8 @@ struct Scratch {
     moves_with_clk: u8,
     white_time_odds: bool,
     black_time_odds: bool,
-    white_prev_time: u32,
-    black_prev_time: u32,
+    material: Option<(u32, u32)>,
+    clocks: ClockMonotonicity,
     skip_game: bool,
 }
 
@@ -42,13 +45,13 @@ impl Scratch {
         self.moves_with_clk = 0;
         self.white_time_odds = false;
         self.black_time_odds = false;
-        self.white_prev_time = 0;
-        self.black_prev_time = 0;
+        self.material = None;
+        self.clocks = ClockMonotonicity::default();
         self.skip_game = false;
     }
 
-    fn white_move(&self) -> bool {
-        self.moves_with_clk % 2 == 0
+    fn material_odds(&self) -> bool {
+        self.material.is_some_and(|(white, black)| white != black)
     }
 }
 
@@ -68,6 +71,25 @@ impl GameProcessor for Processor {
     fn row(&mut self) -> Row {
         mem::take(&mut self.row)
     }
+
+    fn result_encoding() -> ResultEncoding {
+        ResultEncoding::HalfPoints
+    }
+
+    fn description() -> &'static str {
+        "Games where one player started with more time or material than the other, mostly from berserking."
+    }
+
+    fn filters() -> Vec<&'static str> {
+        vec![
+            "both ratings and the time control are known",
+            "the game ended normally or on time",
+            "the result is decisive or a draw",
+            "the players' initial clocks differ, or the game started with unequal material",
+            "neither player gained time in the middle of the game",
+            "both players made a move",
+        ]
+    }
 }
 
 impl Visitor for Processor {
@@ -99,8 +121,14 @@ impl Visitor for Processor {
                     self.scratch.skip_game = true;
                 }
             },
+            b"FEN" => match Fen::try_from(value) {
+                Ok(fen) => self.scratch.material = Some(fen.material()),
+                Err(_) => self.scratch.skip_game = true,
+            },
             b"Event" => {
-                self.row.tournament = value.as_bytes().contains_str("tournament");
+                // players can only berserk in arenas
+                self.row.tournament =
+                    Event::try_from(value).is_ok_and(|event| event.kind == EventKind::Arena);
             }
             b"TimeControl" => match TimeControl::try_from(value) {
                 Ok(tc) => {
@@ -112,25 +140,14 @@ impl Visitor for Processor {
                 }
             },
             b"Termination" => match Termination::try_from(value) {
-                Ok(termination) => match termination {
-                    Termination::Normal => self.row.termination = 0,
-                    Termination::TimeForfeit => self.row.termination = 1,
-                    _ => self.scratch.skip_game = true,
-                },
-                Err(_) => {
-                    self.scratch.skip_game = true;
+                Ok(termination @ (Termination::Normal | Termination::TimeForfeit)) => {
+                    self.row.termination = termination;
                 }
+                _ => self.scratch.skip_game = true,
             },
             b"Result" => match PgnResult::try_from(value) {
-                Ok(result) => match result {
-                    PgnResult::WhiteWin => self.row.result = 2,
-                    PgnResult::Draw => self.row.result = 1,
-                    PgnResult::BlackWin => self.row.result = 0,
-                    PgnResult::Other => self.scratch.skip_game = true,
-                },
-                Err(_) => {
-                    self.scratch.skip_game = true;
-                }
+                Ok(PgnResult::Other) | Err(_) => self.scratch.skip_game = true,
+                Ok(result) => self.row.result = result,
             },
             _ => (),
         }
@@ -141,6 +158,13 @@ impl Visitor for Processor {
             // will we be recording this game?
             Skip(true) // no, so skip past the moves
         } else {
+            // a player who berserked gets no increment, which the clock check
+            // needs to know, whatever order the headers came in
+            let clocks = ClockMonotonicity::new(self.row.increment, DEFAULT_TOLERANCE);
+            self.scratch.clocks = match self.row.tournament {
+                true => clocks.berserk_aware(self.row.initial_time),
+                false => clocks,
+            };
             Skip(false) // maybe, need to check the moves
         }
     }
@@ -153,18 +177,21 @@ impl Visitor for Processor {
         match Clock::try_from(comment) {
             Ok(clock) => {
                 let t = clock.total_seconds();
+                // don't include games where one side got extra time in the
+                // middle of a game (this won't detect all cases, but better
+                // than nothing).
+                let consistent = self.scratch.clocks.push(t);
                 if self.scratch.moves_with_clk == 0 {
                     // white's first move
                     if self.row.initial_time != t {
                         self.scratch.white_time_odds = true;
                     }
                     self.row.white_initial_time = t;
-                    self.scratch.white_prev_time = t;
                 } else if self.scratch.moves_with_clk == 1 {
                     // whether or not either side's initial time is different
                     // from the time control, if the sides are equal to each
                     // other, we don't want the game.
-                    if t == self.row.white_initial_time {
+                    if t == self.row.white_initial_time && !self.scratch.material_odds() {
                         self.scratch.skip_game = true;
                         return;
                     }
@@ -173,26 +200,13 @@ impl Visitor for Processor {
                         self.scratch.black_time_odds = true;
                     }
                     self.row.black_initial_time = t;
-                    self.scratch.black_prev_time = t;
-                } else if !self.scratch.white_time_odds && !self.scratch.black_time_odds {
+                } else if (!self.scratch.white_time_odds
+                    && !self.scratch.black_time_odds
+                    && !self.scratch.material_odds())
+                    || !consistent
+                {
                     self.scratch.skip_game = true;
                     return;
-                } else if self.scratch.white_move() {
-                    // don't include games where one side got extra time in the
-                    // middle of a game (this won't detect all cases, but better
-                    // than nothing). The +1 is to deal with rounding; I'm not
-                    // sure whether/how it is done.
-                    if t > self.scratch.white_prev_time + self.row.increment + 1 {
-                        self.scratch.skip_game = true;
-                        return;
-                    }
-                    self.scratch.white_prev_time = t;
-                } else {
-                    if t > self.scratch.black_prev_time + self.row.increment + 1 {
-                        self.scratch.skip_game = true;
-                        return;
-                    }
-                    self.scratch.black_prev_time = t;
                 }
                 self.scratch.moves_with_clk += 1;
             }
@@ -210,12 +224,16 @@ impl Visitor for Processor {
         // only include games where both players made at least one move.
         if self.scratch.moves_with_clk < 2 {
             self.scratch.skip_game = true;
+            return;
         }
+        self.row.odds = Odds::classify(
+            self.scratch.material,
+            self.row.white_initial_time != self.row.black_initial_time,
+        );
     }
 }
 
 fn main() -> Result<()> {
-    env::set_var("RUST_BACKTRACE", "1");
     pgn2csv::<Processor>()?;
     Ok(())
 }
@@ -0,0 +1,206 @@
+// Get all rated lichess UltraBullet games (under 30 seconds per side, e.g.
+// 15+0), with each player's clock after their first and last moves in tenths
+// of a second, and whether they berserked in arenas.
+
+use pgn2csv::{
+    analyzers::berserked,
+    comments::Clock,
+    headers::{Event, EventKind, PgnResult, Rating, ResultEncoding, Speed, TimeControl},
+    pgn2csv, GameProcessor,
+};
+
+use std::mem;
+
+use anyhow::Result;
+use pgn_reader::{RawComment, RawHeader, Skip, Visitor};
+use serde::Serialize;
+
+#[derive(Default, Serialize)]
+struct Row {
+    white_rating: Rating,
+    black_rating: Rating,
+    initial_time: u32,
+    increment: u32,
+    #[serde(serialize_with = "pgn2csv::boolean::serialize")]
+    arena: bool,
+    #[serde(serialize_with = "pgn2csv::boolean::serialize")]
+    white_berserked: bool,
+    #[serde(serialize_with = "pgn2csv::boolean::serialize")]
+    black_berserked: bool,
+    #[serde(serialize_with = "pgn2csv::comments::serialize_tenths")]
+    white_final_tenths: u32,
+    #[serde(serialize_with = "pgn2csv::comments::serialize_tenths")]
+    black_final_tenths: u32,
+    result: PgnResult,
+}
+
+#[derive(Default)]
+struct Scratch {
+    moves_with_clk: u32,
+    skip_game: bool,
+}
+
+impl Scratch {
+    fn reset(&mut self) {
+        self.moves_with_clk = 0;
+        self.skip_game = false;
+    }
+}
+
+#[derive(Default)]
+struct Processor {
+    row: Row,
+    scratch: Scratch,
+}
+
+impl GameProcessor for Processor {
+    type Row = Row;
+
+    fn skip(&self) -> bool {
+        self.scratch.skip_game
+    }
+
+    fn row(&mut self) -> Row {
+        mem::take(&mut self.row)
+    }
+
+    fn result_encoding() -> ResultEncoding {
+        ResultEncoding::HalfPoints
+    }
+
+    fn description() -> &'static str {
+        "Rated UltraBullet games with berserks and final clocks in tenths of a second."
+    }
+
+    fn filters() -> Vec<&'static str> {
+        vec![
+            "the event is a rated UltraBullet game or arena",
+            "the time control is UltraBullet",
+            "both ratings are known",
+            "the result is decisive or a draw",
+            "both players made a move with a clock comment",
+        ]
+    }
+}
+
+impl Visitor for Processor {
+    type Result = ();
+
+    fn begin_game(&mut self) {
+        self.scratch.reset();
+    }
+
+    fn header(&mut self, key: &[u8], value: RawHeader<'_>) {
+        if self.skip() {
+            return;
+        }
+
+        match key {
+            b"Event" => match Event::try_from(value) {
+                Ok(event) if event.rated && event.speed == Speed::UltraBullet => {
+                    self.row.arena = matches!(event.kind, EventKind::Arena | EventKind::TeamBattle);
+                }
+                _ => self.scratch.skip_game = true,
+            },
+            b"WhiteElo" => match Rating::try_from(value) {
+                Ok(rating) => self.row.white_rating = rating,
+                Err(_) => self.scratch.skip_game = true,
+            },
+            b"BlackElo" => match Rating::try_from(value) {
+                Ok(rating) => self.row.black_rating = rating,
+                Err(_) => self.scratch.skip_game = true,
+            },
+            b"TimeControl" => match TimeControl::try_from(value) {
+                Ok(tc) if Speed::from(&tc) == Speed::UltraBullet => {
+                    self.row.initial_time = tc.initial_time;
+                    self.row.increment = tc.increment;
+                }
+                _ => self.scratch.skip_game = true,
+            },
+            b"Result" => match PgnResult::try_from(value) {
+                Ok(PgnResult::Other) | Err(_) => self.scratch.skip_game = true,
+                Ok(result) => self.row.result = result,
+            },
+            _ => (),
+        }
+    }
+
+    fn end_headers(&mut self) -> Skip {
+        Skip(self.skip())
+    }
+
+    fn comment(&mut self, comment: RawComment<'_>) {
+        if self.skip() {
+            return;
+        }
+
+        match Clock::try_from(comment) {
+            Ok(clock) => {
+                let white = self.scratch.moves_with_clk.is_multiple_of(2);
+                if self.scratch.moves_with_clk < 2 && self.row.arena {
+                    let berserk = berserked(self.row.initial_time, &clock);
+                    if white {
+                        self.row.white_berserked = berserk;
+                    } else {
+                        self.row.black_berserked = berserk;
+                    }
+                }
+                if white {
+                    self.row.white_final_tenths = clock.total_tenths();
+                } else {
+                    self.row.black_final_tenths = clock.total_tenths();
+                }
+                self.scratch.moves_with_clk += 1;
+            }
+            Err(_) => {
+                self.scratch.skip_game = true;
+            }
+        }
+    }
+
+    fn begin_variation(&mut self) -> Skip {
+        Skip(true)
+    }
+
+    fn end_game(&mut self) {
+        // only include games where both players made at least one move.
+        if self.scratch.moves_with_clk < 2 {
+            self.scratch.skip_game = true;
+        }
+    }
+}
+
+fn main() -> Result<()> {
+    pgn2csv::<Processor>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extracts_berserks_and_final_clocks() {
+        let pgn = "\
+[Event \"Rated UltraBullet tournament https://lichess.org/tournament/abcd\"]
+[Result \"1-0\"]
+[WhiteElo \"1800\"]
+[BlackElo \"1700\"]
+[TimeControl \"15+0\"]
+
+1. e4 { [%clk 0:00:07.5] } e5 { [%clk 0:00:15] } 2. Nf3 { [%clk 0:00:06.1] } 1-0
+
+[Event \"Rated Blitz game\"]
+[Result \"0-1\"]
+[WhiteElo \"1800\"]
+[BlackElo \"1700\"]
+[TimeControl \"180+0\"]
+
+1. e4 { [%clk 0:03:00] } e5 { [%clk 0:03:00] } 0-1
+";
+        pgn2csv::assert_rows!(Processor, pgn, ["1800,1700,15,0,true,true,false,61,150,2"]);
+        let (_, skipped, _) = pgn2csv::testing::process_games::<Processor>(pgn);
+        assert_eq!(skipped, 1);
+        pgn2csv::testing::assert_deterministic::<Processor>(pgn);
+    }
+}
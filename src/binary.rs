@@ -0,0 +1,58 @@
+use std::io::{BufWriter, Write};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::sink::{Record, RowSink};
+
+#[derive(Clone, Copy)]
+pub(crate) enum Encoding {
+    #[cfg(feature = "msgpack")]
+    MessagePack,
+    #[cfg(feature = "cbor")]
+    Cbor,
+}
+
+/// Writes rows as a stream of binary-encoded arrays: first the column names,
+/// then the values of each row, in the same order. Leaving the names out of the
+/// rows keeps numeric-heavy rows much smaller than as CSV text.
+pub(crate) struct BinaryWriter {
+    writer: BufWriter<Box<dyn Write>>,
+    encoding: Encoding,
+    wrote_names: bool,
+}
+
+impl BinaryWriter {
+    pub(crate) fn new(writer: Box<dyn Write>, encoding: Encoding) -> Self {
+        BinaryWriter {
+            writer: BufWriter::new(writer),
+            encoding,
+            wrote_names: false,
+        }
+    }
+
+    fn encode(&mut self, value: &impl Serialize) -> Result<()> {
+        match self.encoding {
+            #[cfg(feature = "msgpack")]
+            Encoding::MessagePack => rmp_serde::encode::write(&mut self.writer, value)?,
+            #[cfg(feature = "cbor")]
+            Encoding::Cbor => ciborium::into_writer(value, &mut self.writer)?,
+        }
+        Ok(())
+    }
+}
+
+impl RowSink for BinaryWriter {
+    fn write_row(&mut self, record: &Record) -> Result<()> {
+        if !self.wrote_names {
+            self.encode(&record.keys().collect::<Vec<_>>())?;
+            self.wrote_names = true;
+        }
+        self.encode(&record.values().collect::<Vec<_>>())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,102 @@
+//! Boolean columns, which R, pandas, and Postgres each prefer written
+//! differently, so that they can be read without post-processing the CSVs.
+
+use std::str::FromStr;
+
+use anyhow::{bail, Error, Result};
+use serde::{Serialize, Serializer};
+
+/// How boolean columns are written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BoolEncoding {
+    /// `true` and `false`.
+    #[default]
+    TrueFalse,
+    /// `1` and `0`.
+    OneZero,
+    /// `t` and `f`, as Postgres writes them.
+    TF,
+}
+
+impl FromStr for BoolEncoding {
+    type Err = Error;
+
+    fn from_str(encoding: &str) -> Result<Self> {
+        match encoding {
+            "true-false" => Ok(BoolEncoding::TrueFalse),
+            "1-0" => Ok(BoolEncoding::OneZero),
+            "t-f" => Ok(BoolEncoding::TF),
+            _ => bail!("unknown boolean encoding {encoding}"),
+        }
+    }
+}
+
+/// The name that booleans serialize under, for runs to encode them.
+#[cfg_attr(not(feature = "pipeline"), allow(dead_code))]
+pub(crate) const SERDE_NAME: &str = "pgn2csv::Bool";
+
+impl BoolEncoding {
+    /// Serializes `value` in this encoding.
+    #[cfg_attr(not(feature = "pipeline"), allow(dead_code))]
+    pub(crate) fn serialize<S: Serializer>(
+        self,
+        value: bool,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match self {
+            BoolEncoding::TrueFalse => serializer.serialize_bool(value),
+            BoolEncoding::OneZero => serializer.serialize_u8(u8::from(value)),
+            BoolEncoding::TF => serializer.serialize_str(if value { "t" } else { "f" }),
+        }
+    }
+}
+
+/// Serializes a boolean, for fields of rows like
+/// `#[serde(serialize_with = "pgn2csv::boolean::serialize")] arena: bool`. A
+/// run writes it in its `--bool-encoding`; elsewhere it is a plain boolean.
+///
+/// # Errors
+///
+/// Returns the serializer's error.
+pub fn serialize<S: Serializer>(value: &bool, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_newtype_struct(SERDE_NAME, value)
+}
+
+/// A boolean that a run writes in its encoding, for rows that would rather
+/// not annotate their fields.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Bool(pub bool);
+
+impl From<bool> for Bool {
+    fn from(value: bool) -> Self {
+        Bool(value)
+    }
+}
+
+impl Serialize for Bool {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize(&self.0, serializer)
+    }
+}
+
+#[cfg(all(test, feature = "pipeline"))]
+mod tests {
+    use super::*;
+
+    use serde_json::{json, value::Serializer as JsonSerializer};
+
+    #[test]
+    fn encodes_booleans() {
+        let encode = |encoding: &str, value| {
+            let encoding: BoolEncoding = encoding.parse().unwrap();
+            encoding.serialize(value, JsonSerializer).unwrap()
+        };
+        assert_eq!(encode("true-false", true), json!(true));
+        assert_eq!(encode("1-0", true), json!(1));
+        assert_eq!(encode("1-0", false), json!(0));
+        assert_eq!(encode("t-f", false), json!("f"));
+        assert!("yes-no".parse::<BoolEncoding>().is_err());
+        // outside a run, booleans are plain
+        assert_eq!(serde_json::to_value(Bool(true)).unwrap(), json!(true));
+    }
+}
@@ -0,0 +1,258 @@
+use std::{
+    ffi::OsString,
+    iter,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{ensure, Result};
+use clap::CommandFactory;
+use pgn_reader::Visitor;
+
+use crate::{
+    args::{Args, Cli},
+    run,
+    sink::{RowSink, SinkFactory},
+    source::PgnSource,
+    GameProcessor,
+};
+
+/// A conversion configured from code rather than from the command line, for
+/// embedding the pipeline in other programs: nothing is read from the
+/// process's arguments, and problems are returned as errors rather than
+/// exiting. Build one with `Pgn2Csv::builder()`.
+pub struct Pgn2Csv {
+    args: Args,
+    sources: Vec<Box<dyn PgnSource>>,
+}
+
+impl Pgn2Csv {
+    #[must_use]
+    pub fn builder() -> Pgn2CsvBuilder {
+        Pgn2CsvBuilder::default()
+    }
+
+    /// Converts the PGNs with `P`, as `pgn2csv::<P>()` would.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is an issue with reading or writing files.
+    pub fn run<P>(self) -> Result<()>
+    where
+        P: Visitor + GameProcessor,
+    {
+        run::run::<P>(self.args, self.sources)
+    }
+}
+
+/// Configures a `Pgn2Csv`. Anything that isn't set is as it is when the
+/// option is left out of the command line.
+#[derive(Default)]
+pub struct Pgn2CsvBuilder {
+    pgn_dir: Option<PathBuf>,
+    csv_dir: Option<PathBuf>,
+    output: Option<PathBuf>,
+    globs: Vec<String>,
+    recursive: bool,
+    max_depth: Option<usize>,
+    threads: Option<usize>,
+    serial: bool,
+    flags: Vec<OsString>,
+    config: Option<PathBuf>,
+    sources: Vec<Box<dyn PgnSource>>,
+    sink: Option<Arc<SinkFactory>>,
+}
+
+impl Pgn2CsvBuilder {
+    /// The directory of PGNs, or a single PGN, to convert.
+    #[must_use]
+    pub fn pgn_dir(mut self, dir: impl AsRef<Path>) -> Self {
+        self.pgn_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Where outputs are written, by default the PGN directory.
+    #[must_use]
+    pub fn csv_dir(mut self, dir: impl AsRef<Path>) -> Self {
+        self.csv_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// The file that the output of the only PGN is written to, as `--output`.
+    #[must_use]
+    pub fn output(mut self, path: impl AsRef<Path>) -> Self {
+        self.output = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Converts only the files matching `glob`, or skips them if it starts
+    /// with `!`, as `--glob`. Can be called more than once.
+    #[must_use]
+    pub fn glob(mut self, glob: impl Into<String>) -> Self {
+        self.globs.push(glob.into());
+        self
+    }
+
+    /// Also converts the PGNs in subdirectories, as `--recursive`.
+    #[must_use]
+    pub fn recursive(mut self, recursive: bool) -> Self {
+        self.recursive = recursive;
+        self
+    }
+
+    /// Goes at most `levels` of subdirectories down when `recursive`, as
+    /// `--max-depth`.
+    #[must_use]
+    pub fn max_depth(mut self, levels: usize) -> Self {
+        self.max_depth = Some(levels);
+        self
+    }
+
+    /// The most PGNs to convert at once, by default one per core.
+    #[must_use]
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads);
+        self
+    }
+
+    /// Converts one PGN at a time, on the calling thread, as `--serial`.
+    #[must_use]
+    pub fn serial(mut self, serial: bool) -> Self {
+        self.serial = serial;
+        self
+    }
+
+    /// Any other options, as they are written on the command line, e.g.
+    /// `["--format", "parquet", "--stats"]`.
+    #[must_use]
+    pub fn args<I, T>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString>,
+    {
+        self.flags.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// Reads options from a TOML file, as `--config`. Unlike the binaries, the
+    /// builder doesn't read `pgn2csv.toml` unless it is given here, and takes
+    /// the options of its `[processors.pgn2csv]` table.
+    #[must_use]
+    pub fn config(mut self, path: impl AsRef<Path>) -> Self {
+        self.config = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Also converts `source`, which then doesn't need a PGN directory. Can be
+    /// called more than once.
+    #[must_use]
+    pub fn source(mut self, source: impl PgnSource + 'static) -> Self {
+        self.sources.push(Box::new(source));
+        self
+    }
+
+    /// Writes every output to `sink`, e.g. a `sink::MemorySink`, instead of in
+    /// the `--format`. Clones of it are given every output of the run.
+    #[must_use]
+    pub fn sink<S>(self, sink: S) -> Self
+    where
+        S: RowSink + Clone + Send + Sync + 'static,
+    {
+        self.sink_with(move |_| Ok(Box::new(sink.clone())))
+    }
+
+    /// Writes each output to the sink that `factory` creates for it, given the
+    /// path it would have been written to as a CSV, e.g. `games.csv`.
+    #[must_use]
+    pub fn sink_with(
+        mut self,
+        factory: impl Fn(&Path) -> Result<Box<dyn RowSink>> + Send + Sync + 'static,
+    ) -> Self {
+        self.sink = Some(Arc::new(factory));
+        self
+    }
+
+    /// Checks the configuration.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an option is malformed or options conflict, e.g.
+    /// `threads(0)`, or neither a PGN directory nor a source was given.
+    pub fn build(self) -> Result<Pgn2Csv> {
+        let flags = iter::once(OsString::from("pgn2csv")).chain(self.flags);
+        let mut cli =
+            Cli::parse_with_config(&mut Cli::command(), flags.collect(), self.config.as_deref())?;
+        if self.pgn_dir.is_some() {
+            cli.pgns = self.pgn_dir.into_iter().collect();
+        }
+        if self.csv_dir.is_some() {
+            cli.csv_dir = self.csv_dir;
+        }
+        if self.output.is_some() {
+            cli.output = self.output;
+        }
+        cli.globs.extend(self.globs);
+        cli.recursive |= self.recursive;
+        if self.max_depth.is_some() {
+            ensure!(cli.recursive, "a max depth needs a recursive run");
+            cli.max_depth = self.max_depth;
+        }
+        cli.serial |= self.serial;
+        if let Some(threads) = self.threads {
+            ensure!(threads > 0, "expected a positive number of threads");
+            cli.threads = Some(threads);
+        }
+        ensure!(
+            !(cli.serial && cli.threads.is_some()),
+            "a serial run can't have threads"
+        );
+        let mut args = Args::resolve(cli, !self.sources.is_empty())?;
+        ensure!(
+            !(self.sink.is_some() && args.sort_by.is_some()),
+            "rows written to a sink can't be sorted"
+        );
+        args.sink = self.sink;
+        Ok(Pgn2Csv {
+            args,
+            sources: self.sources,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_without_argv() {
+        let run = Pgn2Csv::builder()
+            .pgn_dir("pgns")
+            .glob("*.pgn.zst")
+            .threads(2)
+            .args(["--format", "csv", "--stats"])
+            .build()
+            .unwrap();
+        assert_eq!(run.args.pgn_dir, Path::new("pgns"));
+        assert_eq!(run.args.csv_dir, Path::new("pgns"));
+        assert_eq!(run.args.globs, ["*.pgn.zst"]);
+        assert_eq!(run.args.threads, Some(2));
+        assert!(run.args.stats);
+        assert!(Pgn2Csv::builder()
+            .pgn_dir("pgns")
+            .sink(crate::sink::MemorySink::default())
+            .args(["--sort-by", "white_elo"])
+            .build()
+            .is_err());
+        assert!(Pgn2Csv::builder().build().is_err());
+        assert!(Pgn2Csv::builder()
+            .pgn_dir("pgns")
+            .threads(0)
+            .build()
+            .is_err());
+        assert!(Pgn2Csv::builder()
+            .pgn_dir("pgns")
+            .args(["--format", "nope"])
+            .build()
+            .is_err());
+    }
+}
@@ -0,0 +1,184 @@
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::Result;
+use arrow_array::{
+    builder::{BooleanBuilder, Float64Builder, Int64Builder, StringBuilder, UInt64Builder},
+    ArrayRef, RecordBatch,
+};
+use arrow_schema::{DataType, Field, Schema};
+use parquet::{arrow::ArrowWriter, basic::Compression, file::properties::WriterProperties};
+use serde_json::Value;
+
+use crate::{
+    huggingface::Dataset,
+    sink::{Record, RowSink},
+};
+
+// rows per record batch, and so at most per row group
+const BATCH: usize = 64 * 1024;
+
+enum Column {
+    Bool(BooleanBuilder),
+    Int(Int64Builder),
+    UInt(UInt64Builder),
+    Float(Float64Builder),
+    Str(StringBuilder),
+}
+
+impl Column {
+    /// Picks the column type from its value in the first row. Values that
+    /// don't fit the type of their column are written as nulls, except in
+    /// string columns, which take the value's text.
+    fn for_value(value: &Value) -> Self {
+        match value {
+            Value::Bool(_) => Column::Bool(BooleanBuilder::new()),
+            Value::Number(n) if n.is_i64() => Column::Int(Int64Builder::new()),
+            Value::Number(n) if n.is_u64() => Column::UInt(UInt64Builder::new()),
+            Value::Number(_) => Column::Float(Float64Builder::new()),
+            _ => Column::Str(StringBuilder::new()),
+        }
+    }
+
+    fn data_type(&self) -> DataType {
+        match self {
+            Column::Bool(_) => DataType::Boolean,
+            Column::Int(_) => DataType::Int64,
+            Column::UInt(_) => DataType::UInt64,
+            Column::Float(_) => DataType::Float64,
+            Column::Str(_) => DataType::Utf8,
+        }
+    }
+
+    /// The name of the column's type in a Hugging Face dataset's features.
+    pub(crate) fn dtype(&self) -> &'static str {
+        match self {
+            Column::Bool(_) => "bool",
+            Column::Int(_) => "int64",
+            Column::UInt(_) => "uint64",
+            Column::Float(_) => "float64",
+            Column::Str(_) => "string",
+        }
+    }
+
+    fn append(&mut self, value: &Value) {
+        match (self, value) {
+            (Column::Bool(b), Value::Bool(v)) => b.append_value(*v),
+            (Column::Bool(b), _) => b.append_null(),
+            (Column::Int(b), Value::Number(n)) => b.append_option(n.as_i64()),
+            (Column::Int(b), _) => b.append_null(),
+            (Column::UInt(b), Value::Number(n)) => b.append_option(n.as_u64()),
+            (Column::UInt(b), _) => b.append_null(),
+            (Column::Float(b), Value::Number(n)) => b.append_option(n.as_f64()),
+            (Column::Float(b), _) => b.append_null(),
+            (Column::Str(b), Value::Null) => b.append_null(),
+            (Column::Str(b), Value::String(s)) => b.append_value(s),
+            (Column::Str(b), _) => b.append_value(value.to_string()),
+        }
+    }
+
+    fn finish(&mut self) -> ArrayRef {
+        match self {
+            Column::Bool(b) => Arc::new(b.finish()),
+            Column::Int(b) => Arc::new(b.finish()),
+            Column::UInt(b) => Arc::new(b.finish()),
+            Column::Float(b) => Arc::new(b.finish()),
+            Column::Str(b) => Arc::new(b.finish()),
+        }
+    }
+}
+
+/// Writes rows to a Snappy-compressed parquet file, with column types taken
+/// from the first row. Nothing is written for a PGN without rows.
+pub(crate) struct ParquetWriter {
+    path: PathBuf,
+    writer: Option<ArrowWriter<File>>,
+    names: Vec<String>,
+    columns: Vec<Column>,
+    buffered: usize,
+    rows: u64,
+    // the dataset this file is a shard of, if any
+    dataset: Option<Arc<Dataset>>,
+}
+
+impl ParquetWriter {
+    pub(crate) fn new(path: &Path, dataset: Option<Arc<Dataset>>) -> Self {
+        ParquetWriter {
+            path: path.to_path_buf(),
+            writer: None,
+            names: Vec::new(),
+            columns: Vec::new(),
+            buffered: 0,
+            rows: 0,
+            dataset,
+        }
+    }
+
+    fn schema(&self) -> Schema {
+        let fields: Vec<Field> = self
+            .names
+            .iter()
+            .zip(&self.columns)
+            .map(|(name, column)| Field::new(name, column.data_type(), true))
+            .collect();
+        Schema::new(fields)
+    }
+
+    fn write_batch(&mut self) -> Result<()> {
+        if self.buffered == 0 {
+            return Ok(());
+        }
+        let schema = Arc::new(self.schema());
+        let columns = self.columns.iter_mut().map(Column::finish).collect();
+        let batch = RecordBatch::try_new(schema, columns)?;
+        if let Some(writer) = &mut self.writer {
+            writer.write(&batch)?;
+        }
+        self.buffered = 0;
+        Ok(())
+    }
+}
+
+// flushing is left to full batches and the end, as a batch per PGN would make
+// row groups too small to read quickly
+impl RowSink for ParquetWriter {
+    fn write_row(&mut self, record: &Record) -> Result<()> {
+        if self.writer.is_none() {
+            self.names = record.keys().cloned().collect();
+            self.columns = record.values().map(Column::for_value).collect();
+            let properties = WriterProperties::builder()
+                .set_compression(Compression::SNAPPY)
+                .build();
+            let file = File::create(&self.path)?;
+            let schema = Arc::new(self.schema());
+            self.writer = Some(ArrowWriter::try_new(file, schema, Some(properties))?);
+        }
+        for (column, value) in self.columns.iter_mut().zip(record.values()) {
+            column.append(value);
+        }
+        self.buffered += 1;
+        self.rows += 1;
+        if self.buffered >= BATCH {
+            self.write_batch()?;
+        }
+        Ok(())
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        self.write_batch()?;
+        if let Some(writer) = self.writer.take() {
+            writer.close()?;
+        }
+        if let Some(dataset) = &self.dataset {
+            let features = self.names.iter().zip(&self.columns);
+            dataset.add_shard(
+                features.map(|(name, column)| (name.clone(), column.dtype())),
+                self.rows,
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,921 @@
+use std::{borrow::Cow, fmt::Write, str::FromStr};
+
+use anyhow::{bail, Error, Result};
+use pgn_reader::{Color, Outcome, RawComment, RawHeader, SanPlus, Suffix};
+use serde_json::Value;
+
+#[cfg(feature = "openings")]
+use crate::openings::{Opening, OpeningBook, OpeningClassifier};
+
+use crate::{
+    analyzers::{
+        flagged_while_winning, move_time_tenths, BerserkDetector, CentipawnLoss, EvalSwings,
+        DECISIVE_ADVANTAGE, MATE_PAWNS,
+    },
+    boolean::BoolEncoding,
+    comments::{Clock, ClockUnit, Eval},
+    encoding::Encodings,
+    headers::{iso_8601, Event, EventKind, GameDateTime, PgnResult, Termination, TimeControl},
+    ratings::{self, RatingStats},
+};
+
+/// A column that the library can add to every row, after the processor's own
+/// columns, so that derived values are computed the same way by every binary.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LibraryColumn {
+    /// `TimeControl::estimated_duration_seconds`, or empty if the game has no
+    /// time control.
+    EstimatedDuration,
+    /// The seconds white spent on their first move, from the clock before the
+    /// increment, which proxies connection lag and premoves. A berserk isn't
+    /// counted as time spent (see `BerserkDetector`). Empty without clocks or
+    /// a time control.
+    WhiteFirstMove,
+    /// The same for black.
+    BlackFirstMove,
+    /// For games lost on time, whether the flagged side had a decisive
+    /// advantage by the last eval, and otherwise empty.
+    FlaggedWhileWinning,
+    /// When the game started as an ISO 8601 UTC timestamp, from `UTCDate` and
+    /// `UTCTime`, or else from `Date` and `Time`, which are assumed to be
+    /// `local_offset` seconds ahead of UTC. Empty if neither is known.
+    StartedAt { local_offset: i32 },
+    /// How many standard deviations white's rating is from the mean rating of
+    /// the game's month and speed in the `--rating-stats`, so that ratings of
+    /// different years can be compared despite rating inflation. Empty
+    /// without a rating, date, or time control, or statistics for them.
+    WhiteEloZ,
+    /// The same for black.
+    BlackEloZ,
+    /// The ECO code of the last opening of the `--openings` book whose
+    /// position the game reached, in whatever move order. Empty if it reached
+    /// none, or started from a `FEN` header.
+    #[cfg(feature = "openings")]
+    OpeningEco,
+    /// The name of the same opening.
+    #[cfg(feature = "openings")]
+    OpeningName,
+    /// The `%eval` after the given ply, e.g. 20 for after black's 10th move,
+    /// in pawns from white's view, with forced mates as 100 pawns (see
+    /// `Eval::pawns`). Empty if that ply has no eval, or the game is shorter.
+    EvalAtPly(u32),
+    /// The largest change in pawns between consecutive evals (see
+    /// `EvalSwings`), or empty if the game has no evals.
+    MaxEvalSwing,
+    /// The number of times the side ahead by the evals changed, or empty if
+    /// the game has no evals.
+    LeadChanges,
+    /// White's average centipawn loss by lichess's definition (see
+    /// `CentipawnLoss`), or empty if none of white's moves has an eval before
+    /// and after it.
+    WhiteAcpl,
+    /// The same for black.
+    BlackAcpl,
+    /// For decisive games with evals, whether the winner was ever behind by
+    /// the given centipawns, by default `DECISIVE_ADVANTAGE`.
+    WinnerWasLost(Option<u32>),
+    /// Whether the game ended before its first move, e.g. because it was
+    /// aborted, or empty if the processor skips the moves.
+    Aborted,
+    /// The mainline in SAN, e.g. `1. e4 e5 2. Nf3`, written as `format` says.
+    /// Empty if the processor skips the moves.
+    MovesSan(MovesFormat),
+    /// Whether the `Result` header agrees with the `Termination` header, with
+    /// the result at the end of the moves, and with a mate on the last move,
+    /// and `PlyCount` with the number of moves. Only the headers are compared
+    /// if the moves aren't read. Empty without a `Result` header.
+    Consistent,
+}
+
+/// How the `moves_san` column writes the mainline, so that sequence models
+/// can be fed the same tokens by every binary.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct MovesFormat {
+    /// The most plies written, or all of them.
+    pub(crate) max_plies: Option<u32>,
+    /// Whether white's moves are preceded by a move number token like `1.`.
+    pub(crate) numbers: bool,
+    /// Whether moves keep their `+` and `#` suffixes.
+    pub(crate) checks: bool,
+    /// What the tokens are separated by.
+    pub(crate) separator: MoveSeparator,
+}
+
+impl Default for MovesFormat {
+    fn default() -> Self {
+        MovesFormat {
+            max_plies: None,
+            numbers: true,
+            checks: true,
+            separator: MoveSeparator::Space,
+        }
+    }
+}
+
+impl MovesFormat {
+    /// Appends the ply-th move to `moves`, unless it is past `max_plies`.
+    fn push(self, moves: &mut String, ply: u32, san_plus: &SanPlus) {
+        if self.max_plies.is_some_and(|max| ply > max) {
+            return;
+        }
+        let separator = match self.separator {
+            MoveSeparator::Space => ' ',
+            MoveSeparator::Comma => ',',
+        };
+        if self.numbers && ply % 2 == 1 {
+            if !moves.is_empty() {
+                moves.push(separator);
+            }
+            let _ = write!(moves, "{}.", ply / 2 + 1);
+        }
+        if !moves.is_empty() {
+            moves.push(separator);
+        }
+        if self.checks {
+            let _ = write!(moves, "{san_plus}");
+        } else {
+            let _ = write!(moves, "{}", san_plus.san);
+        }
+    }
+}
+
+/// What the tokens of the `moves_san` column are separated by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum MoveSeparator {
+    Space,
+    Comma,
+}
+
+impl FromStr for MoveSeparator {
+    type Err = Error;
+
+    fn from_str(separator: &str) -> Result<Self> {
+        match separator {
+            "space" => Ok(MoveSeparator::Space),
+            "comma" => Ok(MoveSeparator::Comma),
+            _ => bail!("unknown move separator {separator}"),
+        }
+    }
+}
+
+impl LibraryColumn {
+    pub(crate) fn name(self) -> Cow<'static, str> {
+        match self {
+            LibraryColumn::EstimatedDuration => "estimated_duration_seconds".into(),
+            LibraryColumn::WhiteFirstMove => "white_first_move_seconds".into(),
+            LibraryColumn::BlackFirstMove => "black_first_move_seconds".into(),
+            LibraryColumn::FlaggedWhileWinning => "flagged_while_winning".into(),
+            LibraryColumn::StartedAt { .. } => "started_at".into(),
+            LibraryColumn::WhiteEloZ => "white_elo_z".into(),
+            LibraryColumn::BlackEloZ => "black_elo_z".into(),
+            #[cfg(feature = "openings")]
+            LibraryColumn::OpeningEco => "opening_eco".into(),
+            #[cfg(feature = "openings")]
+            LibraryColumn::OpeningName => "opening_name".into(),
+            LibraryColumn::EvalAtPly(ply) => format!("{EVAL_AT_PLY}{ply}").into(),
+            LibraryColumn::Aborted => "aborted".into(),
+            LibraryColumn::MovesSan(_) => "moves_san".into(),
+            LibraryColumn::Consistent => "consistent".into(),
+            LibraryColumn::MaxEvalSwing => "max_eval_swing".into(),
+            LibraryColumn::LeadChanges => "lead_changes".into(),
+            LibraryColumn::WhiteAcpl => "white_acpl".into(),
+            LibraryColumn::BlackAcpl => "black_acpl".into(),
+            LibraryColumn::WinnerWasLost(None) => WINNER_WAS_LOST.into(),
+            LibraryColumn::WinnerWasLost(Some(centipawns)) => {
+                format!("{WINNER_WAS_LOST}_{centipawns}").into()
+            }
+        }
+    }
+
+    /// Whether the column is derived from the evals of the moves.
+    fn needs_evals(self) -> bool {
+        matches!(
+            self,
+            LibraryColumn::FlaggedWhileWinning
+                | LibraryColumn::EvalAtPly(_)
+                | LibraryColumn::MaxEvalSwing
+                | LibraryColumn::LeadChanges
+                | LibraryColumn::WhiteAcpl
+                | LibraryColumn::BlackAcpl
+                | LibraryColumn::WinnerWasLost(_)
+        )
+    }
+
+    /// Whether the column is derived from the moves and comments, and not
+    /// only from the headers.
+    fn needs_moves(self) -> bool {
+        !matches!(
+            self,
+            LibraryColumn::EstimatedDuration
+                | LibraryColumn::StartedAt { .. }
+                | LibraryColumn::WhiteEloZ
+                | LibraryColumn::BlackEloZ
+        )
+    }
+
+    /// Whether the column is normalized with the `--rating-stats`.
+    pub(crate) fn needs_rating_stats(self) -> bool {
+        matches!(self, LibraryColumn::WhiteEloZ | LibraryColumn::BlackEloZ)
+    }
+}
+
+impl FromStr for LibraryColumn {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "estimated_duration_seconds" => Ok(LibraryColumn::EstimatedDuration),
+            "white_first_move_seconds" => Ok(LibraryColumn::WhiteFirstMove),
+            "black_first_move_seconds" => Ok(LibraryColumn::BlackFirstMove),
+            "flagged_while_winning" => Ok(LibraryColumn::FlaggedWhileWinning),
+            "started_at" => Ok(LibraryColumn::StartedAt { local_offset: 0 }),
+            "white_elo_z" => Ok(LibraryColumn::WhiteEloZ),
+            "black_elo_z" => Ok(LibraryColumn::BlackEloZ),
+            #[cfg(feature = "openings")]
+            "opening_eco" => Ok(LibraryColumn::OpeningEco),
+            #[cfg(feature = "openings")]
+            "opening_name" => Ok(LibraryColumn::OpeningName),
+            "aborted" => Ok(LibraryColumn::Aborted),
+            "moves_san" => Ok(LibraryColumn::MovesSan(MovesFormat::default())),
+            "consistent" => Ok(LibraryColumn::Consistent),
+            "max_eval_swing" => Ok(LibraryColumn::MaxEvalSwing),
+            "lead_changes" => Ok(LibraryColumn::LeadChanges),
+            "white_acpl" => Ok(LibraryColumn::WhiteAcpl),
+            "black_acpl" => Ok(LibraryColumn::BlackAcpl),
+            WINNER_WAS_LOST => Ok(LibraryColumn::WinnerWasLost(None)),
+            _ => {
+                if let Some(Ok(ply)) = name.strip_prefix(EVAL_AT_PLY).map(str::parse) {
+                    if ply > 0 {
+                        return Ok(LibraryColumn::EvalAtPly(ply));
+                    }
+                }
+                let centipawns = name
+                    .strip_prefix(WINNER_WAS_LOST)
+                    .and_then(|suffix| suffix.strip_prefix('_'));
+                if let Some(Ok(centipawns)) = centipawns.map(str::parse) {
+                    return Ok(LibraryColumn::WinnerWasLost(Some(centipawns)));
+                }
+                bail!("unknown library column {name}")
+            }
+        }
+    }
+}
+
+// the columns of evals after a ply are named e.g. eval_at_ply_20
+const EVAL_AT_PLY: &str = "eval_at_ply_";
+
+// the column of comebacks, optionally followed by a threshold like _500
+const WINNER_WAS_LOST: &str = "winner_was_lost";
+
+/// Collects the headers and moves that the library columns of a game are
+/// derived from.
+pub(crate) struct LibraryColumns<'a> {
+    columns: &'a [LibraryColumn],
+    time_control: Option<TimeControl>,
+    // whether the game was played in an arena, where players can berserk
+    arena: bool,
+    // whether the moves are needed, which saves parsing comments otherwise
+    moves: bool,
+    // whether the game's movetext is read
+    movetext: bool,
+    plies: u32,
+    // the mainline so far, if a moves_san column is written
+    moves_san: Option<(MovesFormat, String)>,
+    // tenths of a second spent on each side's first move
+    first_moves: [Option<u32>; 2],
+    // whether white lost, if either side did
+    white_lost: Option<bool>,
+    time_forfeit: bool,
+    // what the headers and the end of the moves say about how the game ended,
+    // for the consistency check
+    result: Option<PgnResult>,
+    termination: Option<Termination>,
+    ply_count: Option<u32>,
+    // the result at the end of the moves, if there is one, which is None for
+    // `*`
+    outcome: Option<Option<Outcome>>,
+    mated: bool,
+    last_eval: Option<Eval>,
+    // whether the evals are needed, which saves parsing them otherwise
+    evals: bool,
+    // the evals after the plies of eval columns
+    evals_at: Vec<(u32, Option<Eval>)>,
+    swings: EvalSwings,
+    centipawn_loss: CentipawnLoss,
+    date_time: GameDateTime,
+    elos: [Option<u16>; 2],
+    rating_stats: Option<&'a RatingStats>,
+    #[cfg(feature = "openings")]
+    openings: Option<OpeningClassifier<'a>>,
+    encodings: Encodings,
+}
+
+impl<'a> LibraryColumns<'a> {
+    pub(crate) fn new(columns: &'a [LibraryColumn]) -> Self {
+        LibraryColumns {
+            columns,
+            time_control: None,
+            arena: false,
+            moves: columns.iter().any(|column| column.needs_moves()),
+            movetext: false,
+            plies: 0,
+            moves_san: columns.iter().find_map(|&column| match column {
+                LibraryColumn::MovesSan(format) => Some((format, String::new())),
+                _ => None,
+            }),
+            first_moves: [None; 2],
+            white_lost: None,
+            time_forfeit: false,
+            result: None,
+            termination: None,
+            ply_count: None,
+            outcome: None,
+            mated: false,
+            last_eval: None,
+            evals: columns.iter().any(|column| column.needs_evals()),
+            evals_at: columns
+                .iter()
+                .filter_map(|&column| match column {
+                    LibraryColumn::EvalAtPly(ply) => Some((ply, None)),
+                    _ => None,
+                })
+                .collect(),
+            swings: EvalSwings::default(),
+            centipawn_loss: CentipawnLoss::default(),
+            date_time: GameDateTime::default(),
+            elos: [None; 2],
+            rating_stats: None,
+            #[cfg(feature = "openings")]
+            openings: None,
+            encodings: Encodings::default(),
+        }
+    }
+
+    /// Writes the values in the run's `encodings`.
+    pub(crate) fn encode_with(&mut self, encodings: Encodings) {
+        self.encodings = encodings;
+    }
+
+    /// Normalizes the games' ratings with `stats`.
+    pub(crate) fn normalize_ratings(&mut self, stats: &'a RatingStats) {
+        self.rating_stats = Some(stats);
+    }
+
+    /// Classifies the games' openings with `book`.
+    #[cfg(feature = "openings")]
+    pub(crate) fn classify_openings(&mut self, book: &'a OpeningBook) {
+        self.openings = Some(OpeningClassifier::new(book));
+    }
+
+    pub(crate) fn begin_game(&mut self) {
+        self.time_control = None;
+        self.arena = false;
+        self.movetext = false;
+        self.plies = 0;
+        if let Some((_, moves)) = &mut self.moves_san {
+            moves.clear();
+        }
+        self.first_moves = [None; 2];
+        self.white_lost = None;
+        self.time_forfeit = false;
+        self.result = None;
+        self.termination = None;
+        self.ply_count = None;
+        self.outcome = None;
+        self.mated = false;
+        self.last_eval = None;
+        for (_, eval) in &mut self.evals_at {
+            *eval = None;
+        }
+        self.swings = EvalSwings::default();
+        self.centipawn_loss = CentipawnLoss::default();
+        self.date_time.reset();
+        self.elos = [None; 2];
+        #[cfg(feature = "openings")]
+        if let Some(openings) = &mut self.openings {
+            openings.reset();
+        }
+    }
+
+    pub(crate) fn header(&mut self, key: &[u8], value: RawHeader<'_>) {
+        if self.columns.is_empty() {
+            return;
+        }
+        match key {
+            b"TimeControl" => self.time_control = TimeControl::try_from(value).ok(),
+            b"Event" => {
+                self.arena =
+                    Event::try_from(value).is_ok_and(|event| event.kind == EventKind::Arena);
+            }
+            b"Termination" => {
+                self.termination = Termination::try_from(value).ok();
+                self.time_forfeit = self.termination == Some(Termination::TimeForfeit);
+            }
+            b"Result" => {
+                self.result = PgnResult::try_from(value).ok();
+                self.white_lost = match self.result {
+                    Some(PgnResult::WhiteWin) => Some(false),
+                    Some(PgnResult::BlackWin) => Some(true),
+                    _ => None,
+                };
+            }
+            b"PlyCount" => self.ply_count = parse_header(value),
+            b"WhiteElo" => self.elos[0] = parse_header(value),
+            b"BlackElo" => self.elos[1] = parse_header(value),
+            #[cfg(feature = "openings")]
+            b"FEN" => {
+                if let Some(openings) = &mut self.openings {
+                    openings.give_up();
+                }
+            }
+            #[cfg(feature = "openings")]
+            b"Variant" if !value.as_bytes().eq_ignore_ascii_case(b"Standard") => {
+                if let Some(openings) = &mut self.openings {
+                    openings.give_up();
+                }
+            }
+            _ => {
+                self.date_time.header(key, value);
+            }
+        }
+    }
+
+    /// Called once the headers have been read, with whether the movetext will
+    /// be skipped.
+    pub(crate) fn end_headers(&mut self, skip: bool) {
+        self.movetext = !skip;
+    }
+
+    /// Whether some of the columns are derived from the movetext.
+    pub(crate) fn needs_moves(&self) -> bool {
+        self.moves
+    }
+
+    /// Whether the game has no moves, if its movetext was read.
+    pub(crate) fn aborted(&self) -> Option<bool> {
+        self.movetext.then_some(self.plies == 0)
+    }
+
+    /// Called for each move of the mainline.
+    pub(crate) fn san(&mut self, san_plus: &SanPlus) {
+        self.plies += 1;
+        self.mated = san_plus.suffix == Some(Suffix::Checkmate);
+        if let Some((format, moves)) = &mut self.moves_san {
+            format.push(moves, self.plies, san_plus);
+        }
+        #[cfg(feature = "openings")]
+        if let Some(openings) = &mut self.openings {
+            openings.push(&san_plus.to_string());
+        }
+    }
+
+    /// Called for each comment of the mainline.
+    pub(crate) fn comment(&mut self, comment: RawComment<'_>) {
+        if !self.moves {
+            return;
+        }
+        if self.evals {
+            if let Ok(eval) = Eval::try_from(comment) {
+                self.last_eval = Some(eval);
+                self.swings.push(&eval);
+                self.centipawn_loss.push(self.plies, &eval);
+                let plies = self.plies;
+                for (_, at) in self.evals_at.iter_mut().filter(|(ply, _)| *ply == plies) {
+                    *at = Some(eval);
+                }
+            }
+        }
+        // the comment after each side's first move
+        if !(1..=2).contains(&self.plies) {
+            return;
+        }
+        let (Some(tc), Ok(clock)) = (&self.time_control, Clock::try_from(comment)) else {
+            return;
+        };
+        // a player who berserked in an arena started with less time, while
+        // elsewhere a long first move can use half the clock too
+        let white = self.plies == 1;
+        let tenths = match self.arena {
+            true => {
+                BerserkDetector::new(tc.initial_time, tc.increment).first_move_tenths(white, &clock)
+            }
+            false => move_time_tenths(tc.initial_time * 10, &clock, tc.increment),
+        };
+        self.first_moves[usize::from(!white)] = Some(tenths);
+    }
+
+    /// Called with the result at the end of the moves.
+    pub(crate) fn outcome(&mut self, outcome: Option<Outcome>) {
+        self.outcome = Some(outcome);
+    }
+
+    /// Whether what the headers and moves say about how the game ended
+    /// agrees, for the `consistent` column.
+    fn consistent(&self) -> Option<bool> {
+        let result = self.result?;
+        let unfinished = result == PgnResult::Other;
+        if let Some(termination) = self.termination {
+            let unterminated = termination == Termination::Unterminated;
+            if unterminated != unfinished {
+                return Some(false);
+            }
+        }
+        if !self.movetext {
+            return Some(true);
+        }
+        let outcome = self.outcome.map(|outcome| match outcome {
+            Some(Outcome::Decisive {
+                winner: Color::White,
+            }) => PgnResult::WhiteWin,
+            Some(Outcome::Decisive {
+                winner: Color::Black,
+            }) => PgnResult::BlackWin,
+            Some(Outcome::Draw) => PgnResult::Draw,
+            None => PgnResult::Other,
+        });
+        if outcome.is_some_and(|outcome| outcome != result) {
+            return Some(false);
+        }
+        if self.ply_count.is_some_and(|count| count != self.plies) {
+            return Some(false);
+        }
+        if self.mated {
+            // the side that played the last move mated
+            let mate = match self.plies % 2 {
+                1 => PgnResult::WhiteWin,
+                _ => PgnResult::BlackWin,
+            };
+            if result != mate || self.time_forfeit {
+                return Some(false);
+            }
+        }
+        Some(true)
+    }
+
+    /// The z-score of the rating of the given side, 0 for white.
+    fn elo_z(&self, side: usize) -> Value {
+        let (Some(stats), Some(elo)) = (self.rating_stats, self.elos[side]) else {
+            return Value::Null;
+        };
+        ratings::pool(&self.date_time, self.time_control.as_ref())
+            .and_then(|(month, speed)| stats.z_score(elo, month, speed))
+            .map_or(Value::Null, Value::from)
+    }
+
+    fn acpl(&self, white: bool) -> Value {
+        self.centipawn_loss
+            .average(white)
+            .map_or(Value::Null, Value::from)
+    }
+
+    #[cfg(feature = "openings")]
+    fn opening(&self, field: impl Fn(&Opening) -> String) -> Value {
+        self.openings
+            .as_ref()
+            .and_then(OpeningClassifier::opening)
+            .map_or(Value::Null, |opening| field(opening).into())
+    }
+
+    /// The names and values of the columns for the current game.
+    pub(crate) fn values(&self) -> Vec<(Cow<'static, str>, Value)> {
+        self.columns
+            .iter()
+            .map(|&column| {
+                let value = match column {
+                    LibraryColumn::EstimatedDuration => self
+                        .time_control
+                        .as_ref()
+                        .map_or(Value::Null, |tc| tc.estimated_duration_seconds().into()),
+                    LibraryColumn::WhiteFirstMove => {
+                        seconds(self.first_moves[0], self.encodings.clock_unit)
+                    }
+                    LibraryColumn::BlackFirstMove => {
+                        seconds(self.first_moves[1], self.encodings.clock_unit)
+                    }
+                    LibraryColumn::FlaggedWhileWinning => {
+                        match (self.time_forfeit, self.white_lost, &self.last_eval) {
+                            (true, Some(white), Some(eval)) => {
+                                flagged_while_winning(white, eval).into()
+                            }
+                            _ => Value::Null,
+                        }
+                    }
+                    LibraryColumn::StartedAt { local_offset } => self
+                        .date_time
+                        .utc_timestamp(local_offset)
+                        .map_or(Value::Null, |timestamp| iso_8601(timestamp).into()),
+                    LibraryColumn::WhiteEloZ => self.elo_z(0),
+                    LibraryColumn::BlackEloZ => self.elo_z(1),
+                    #[cfg(feature = "openings")]
+                    LibraryColumn::OpeningEco => self.opening(|opening| opening.eco.clone()),
+                    #[cfg(feature = "openings")]
+                    LibraryColumn::OpeningName => {
+                        self.opening(|opening| self.encodings.text.encode(&opening.name))
+                    }
+                    LibraryColumn::EvalAtPly(ply) => self
+                        .evals_at
+                        .iter()
+                        .find(|&&(at, _)| at == ply)
+                        .and_then(|(_, eval)| eval.as_ref())
+                        .map_or(Value::Null, |eval| f64::from(eval.pawns(MATE_PAWNS)).into()),
+                    LibraryColumn::Aborted => self.aborted().map_or(Value::Null, Value::from),
+                    LibraryColumn::MovesSan(_) => match &self.moves_san {
+                        Some((_, moves)) if self.movetext => {
+                            self.encodings.text.encode(moves).into()
+                        }
+                        _ => Value::Null,
+                    },
+                    LibraryColumn::Consistent => self.consistent().map_or(Value::Null, Value::from),
+                    LibraryColumn::MaxEvalSwing if self.swings.has_evals() => {
+                        f64::from(self.swings.max_swing()).into()
+                    }
+                    LibraryColumn::LeadChanges if self.swings.has_evals() => {
+                        self.swings.lead_changes().into()
+                    }
+                    LibraryColumn::WhiteAcpl => self.acpl(true),
+                    LibraryColumn::BlackAcpl => self.acpl(false),
+                    LibraryColumn::WinnerWasLost(centipawns) if self.swings.has_evals() => {
+                        let pawns = centipawns.map_or(DECISIVE_ADVANTAGE, |cp| cp as f32 / 100.0);
+                        self.white_lost.map_or(Value::Null, |white_lost| {
+                            self.swings.was_lost(!white_lost, pawns).into()
+                        })
+                    }
+                    LibraryColumn::MaxEvalSwing
+                    | LibraryColumn::LeadChanges
+                    | LibraryColumn::WinnerWasLost(_) => Value::Null,
+                };
+                (column.name(), encode_bool(value, self.encodings.bool))
+            })
+            .collect()
+    }
+
+    /// Swaps the colors of `values`, as returned by `values`, for a mirrored
+    /// row: the first move times, rating z-scores, and centipawn losses trade
+    /// places and evals change sides. The other columns don't depend on which side is which.
+    pub(crate) fn mirror(&self, values: &mut [(Cow<'static, str>, Value)]) {
+        for (&column, (_, value)) in self.columns.iter().zip(values) {
+            match column {
+                LibraryColumn::WhiteFirstMove => {
+                    *value = seconds(self.first_moves[1], self.encodings.clock_unit)
+                }
+                LibraryColumn::BlackFirstMove => {
+                    *value = seconds(self.first_moves[0], self.encodings.clock_unit)
+                }
+                LibraryColumn::WhiteEloZ => *value = self.elo_z(1),
+                LibraryColumn::BlackEloZ => *value = self.elo_z(0),
+                LibraryColumn::WhiteAcpl => *value = self.acpl(false),
+                LibraryColumn::BlackAcpl => *value = self.acpl(true),
+                LibraryColumn::EvalAtPly(_) => {
+                    if let Some(pawns) = value.as_f64() {
+                        *value = (-pawns).into();
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+/// The `mirrored` column of `--mirror-colors`, which tells mirrored rows
+/// from the rows of the games as played.
+pub(crate) fn mirrored_column(mirrored: bool, encodings: Encodings) -> (Cow<'static, str>, Value) {
+    (
+        "mirrored".into(),
+        encode_bool(mirrored.into(), encodings.bool),
+    )
+}
+
+/// Writes boolean values in the run's `BoolEncoding`, like the processor's
+/// own boolean columns.
+fn encode_bool(value: Value, encoding: BoolEncoding) -> Value {
+    match (value, encoding) {
+        (Value::Bool(value), BoolEncoding::OneZero) => u8::from(value).into(),
+        (Value::Bool(value), BoolEncoding::TF) => if value { "t" } else { "f" }.into(),
+        (value, _) => value,
+    }
+}
+
+/// Parses a numeric header like `PlyCount` or `WhiteElo`, which is None for
+/// e.g. `?`.
+fn parse_header<T: FromStr>(value: RawHeader<'_>) -> Option<T> {
+    std::str::from_utf8(value.as_bytes())
+        .ok()
+        .and_then(|number| number.trim().parse().ok())
+}
+
+/// A duration in tenths of a second, in the run's `unit`, or else in seconds.
+fn seconds(tenths: Option<u32>, unit: Option<ClockUnit>) -> Value {
+    match (tenths, unit) {
+        (None, _) => Value::Null,
+        (Some(tenths), Some(unit)) => unit.from_tenths(tenths).into(),
+        (Some(tenths), None) => (f64::from(tenths) / 10.0).into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_move_times() {
+        let columns = [LibraryColumn::WhiteFirstMove, LibraryColumn::BlackFirstMove];
+        let san = SanPlus::from_ascii(b"e4").unwrap();
+        let mut game = LibraryColumns::new(&columns);
+        game.begin_game();
+        game.header(b"TimeControl", RawHeader(b"60+1"));
+        game.san(&san);
+        game.comment(RawComment(b" [%clk 0:00:59.5] "));
+        game.san(&san);
+        game.comment(RawComment(b" [%clk 0:01:01] "));
+        game.san(&san);
+        game.comment(RawComment(b" [%clk 0:00:30] "));
+        let values = game.values();
+        assert_eq!(
+            values[0],
+            ("white_first_move_seconds".into(), Value::from(1.5))
+        );
+        assert_eq!(
+            values[1],
+            ("black_first_move_seconds".into(), Value::from(0.0))
+        );
+        let mut mirrored = values.clone();
+        game.mirror(&mut mirrored);
+        assert_eq!(mirrored[0].1, values[1].1);
+        assert_eq!(mirrored[1].1, values[0].1);
+        // half the clock is a berserk in arenas, and a long move elsewhere
+        for (event, seconds) in [
+            (
+                b"Rated Bullet tournament https://lichess.org/tournament/abc".as_slice(),
+                0.0,
+            ),
+            (b"Rated Bullet game".as_slice(), 31.0),
+        ] {
+            game.begin_game();
+            game.header(b"Event", RawHeader(event));
+            game.header(b"TimeControl", RawHeader(b"60+1"));
+            game.san(&san);
+            game.comment(RawComment(b" [%clk 0:00:30] "));
+            assert_eq!(game.values()[0].1, Value::from(seconds));
+        }
+    }
+
+    #[test]
+    fn started_at() {
+        let columns = [LibraryColumn::StartedAt { local_offset: 3600 }];
+        let mut game = LibraryColumns::new(&columns);
+        let started_at = |game: &mut LibraryColumns<'_>, headers: &[(&[u8], &[u8])]| {
+            game.begin_game();
+            for &(key, value) in headers {
+                game.header(key, RawHeader(value));
+            }
+            game.values()[0].clone()
+        };
+        // UTC headers are taken as they are, and preferred
+        let utc: [(&[u8], &[u8]); 4] = [
+            (b"Date", b"2024.01.05"),
+            (b"Time", b"10:00:00"),
+            (b"UTCDate", b"2024.01.05"),
+            (b"UTCTime", b"18:04:09"),
+        ];
+        assert_eq!(
+            started_at(&mut game, &utc),
+            ("started_at".into(), Value::from("2024-01-05T18:04:09Z"))
+        );
+        // local ones are an hour ahead, which crosses midnight
+        let local: [(&[u8], &[u8]); 2] = [(b"Date", b"2024.01.01"), (b"Time", b"00:30:00")];
+        assert_eq!(
+            started_at(&mut game, &local).1,
+            Value::from("2023-12-31T23:30:00Z")
+        );
+        let no_time: [(&[u8], &[u8]); 1] = [(b"UTCDate", b"2024.01.05")];
+        assert_eq!(started_at(&mut game, &no_time).1, Value::Null);
+    }
+
+    #[test]
+    fn evals_at_plies() {
+        let columns: Vec<LibraryColumn> = [
+            "eval_at_ply_1",
+            "eval_at_ply_2",
+            "eval_at_ply_30",
+            "white_acpl",
+            "black_acpl",
+        ]
+        .iter()
+        .map(|name| name.parse().unwrap())
+        .collect();
+        let san = SanPlus::from_ascii(b"e4").unwrap();
+        let mut game = LibraryColumns::new(&columns);
+        game.begin_game();
+        game.san(&san);
+        game.comment(RawComment(b" [%eval 0.25] "));
+        game.san(&san);
+        game.comment(RawComment(b" [%eval #-2] "));
+        let values = game.values();
+        assert_eq!(values[0], ("eval_at_ply_1".into(), Value::from(0.25)));
+        assert_eq!(values[1].1, Value::from(-100.0));
+        assert_eq!(values[2].1, Value::Null);
+        // white's move gained 10 centipawns on the starting position, and
+        // black's found a mate
+        assert_eq!(values[3].1, Value::from(0));
+        assert_eq!(values[4].1, Value::from(0));
+        let mut mirrored = values.clone();
+        game.mirror(&mut mirrored);
+        assert_eq!(mirrored[0].1, Value::from(-0.25));
+        assert_eq!(mirrored[2].1, Value::Null);
+        assert!("eval_at_ply_0".parse::<LibraryColumn>().is_err());
+    }
+
+    #[test]
+    fn moves_san() {
+        let moves = |format: MovesFormat| {
+            let columns = [LibraryColumn::MovesSan(format)];
+            let mut game = LibraryColumns::new(&columns);
+            game.begin_game();
+            game.end_headers(false);
+            for san in ["e4", "e5", "Qh5", "Nc6", "Bc4", "Nf6", "Qxf7#"] {
+                game.san(&SanPlus::from_ascii(san.as_bytes()).unwrap());
+            }
+            game.values()[0].1.clone()
+        };
+        assert_eq!(
+            moves(MovesFormat::default()),
+            Value::from("1. e4 e5 2. Qh5 Nc6 3. Bc4 Nf6 4. Qxf7#")
+        );
+        let format = MovesFormat {
+            max_plies: Some(3),
+            ..MovesFormat::default()
+        };
+        assert_eq!(moves(format), Value::from("1. e4 e5 2. Qh5"));
+        let format = MovesFormat {
+            max_plies: None,
+            numbers: false,
+            checks: false,
+            separator: MoveSeparator::Comma,
+        };
+        assert_eq!(moves(format), Value::from("e4,e5,Qh5,Nc6,Bc4,Nf6,Qxf7"));
+    }
+
+    #[test]
+    fn consistent() {
+        let columns = [LibraryColumn::Consistent];
+        let mut game = LibraryColumns::new(&columns);
+        let mut check = |headers: &[(&[u8], &[u8])], moves: &[&str], outcome| {
+            game.begin_game();
+            for &(key, value) in headers {
+                game.header(key, RawHeader(value));
+            }
+            game.end_headers(false);
+            for san in moves {
+                game.san(&SanPlus::from_ascii(san.as_bytes()).unwrap());
+            }
+            game.outcome(outcome);
+            game.values()[0].1.clone()
+        };
+        let white = Some(Outcome::Decisive {
+            winner: Color::White,
+        });
+        let mate = ["e4", "e5", "Qh5", "Nc6", "Bc4", "Nf6", "Qxf7#"];
+        let result: &[(&[u8], &[u8])] = &[(b"Result", b"1-0"), (b"PlyCount", b"7")];
+        assert_eq!(check(result, &mate, white), Value::from(true));
+        // the result at the end of the moves contradicts the header
+        assert_eq!(
+            check(result, &mate, Some(Outcome::Draw)),
+            Value::from(false)
+        );
+        assert_eq!(check(result, &mate[..5], white), Value::from(false));
+        let result: &[(&[u8], &[u8])] = &[(b"Result", b"0-1")];
+        assert_eq!(check(result, &mate, None), Value::from(false));
+        let result: &[(&[u8], &[u8])] = &[(b"Result", b"1-0"), (b"Termination", b"Unterminated")];
+        assert_eq!(check(result, &["e4"], white), Value::from(false));
+        assert_eq!(check(&[], &["e4"], white), Value::Null);
+    }
+
+    #[test]
+    fn flagged_while_winning() {
+        let columns = [LibraryColumn::FlaggedWhileWinning];
+        let san = SanPlus::from_ascii(b"e4").unwrap();
+        let mut game = LibraryColumns::new(&columns);
+        let mut flag = |termination: &[u8], result: &[u8], eval: &[u8]| {
+            game.begin_game();
+            game.header(b"Result", RawHeader(result));
+            game.header(b"Termination", RawHeader(termination));
+            game.san(&san);
+            game.comment(RawComment(eval));
+            game.values()[0].1.clone()
+        };
+        // black flagged while up a rook
+        assert_eq!(
+            flag(b"Time forfeit", b"1-0", b"[%eval -5.2]"),
+            Value::from(true)
+        );
+        assert_eq!(
+            flag(b"Time forfeit", b"1-0", b"[%eval 0.4]"),
+            Value::from(false)
+        );
+        assert_eq!(flag(b"Normal", b"1-0", b"[%eval -5.2]"), Value::Null);
+        assert_eq!(
+            flag(b"Time forfeit", b"0-1", b"[%clk 0:00:01]"),
+            Value::Null
+        );
+    }
+}
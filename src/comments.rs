@@ -1,8 +1,11 @@
-use anyhow::{anyhow, ensure, Error, Result};
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, ensure, Error, Result};
 use bstr::{ByteSlice, Split};
 use bstr_parse::BStrParse;
+use memchr::{memchr, memmem};
 use pgn_reader::RawComment;
-use serde::Serialize;
+use serde::{Serialize, Serializer};
 
 // see https://www.enpassant.dk/chess/palview/enhancedpgn.htm
 // The command string is structured as follows.
@@ -21,13 +24,11 @@ impl<'a> TryFrom<&'a [u8]> for RawCommand<'a> {
     type Error = Error;
 
     fn try_from(value: &'a [u8]) -> Result<Self> {
-        let (name, params) = value
-            .split_once_str(" ")
-            .ok_or_else(|| anyhow!("no space in comment command"))?;
+        let space = memchr(b' ', value).ok_or_else(|| anyhow!("no space in comment command"))?;
 
         Ok(RawCommand {
-            name,
-            params: params.split_str(","),
+            name: &value[..space],
+            params: value[space + 1..].split_str(","),
         })
     }
 }
@@ -39,11 +40,16 @@ pub struct RawCommandIterator<'a> {
 impl<'a> Iterator for RawCommandIterator<'a> {
     type Item = RawCommand<'a>;
     fn next(&mut self) -> Option<RawCommand<'a>> {
-        let start = self.comment.find("[%")?;
-        let end = self.comment[start..].find("]")? + start;
-        let command = &self.comment[start + 2..end];
-        self.comment = &self.comment[end..];
-        command.try_into().ok()
+        loop {
+            let start = memmem::find(self.comment, b"[%")? + 2;
+            let end = memchr(b']', &self.comment[start..])? + start;
+            let command = &self.comment[start..end];
+            self.comment = &self.comment[end + 1..];
+            // skip malformed commands rather than ending the iteration
+            if let Ok(command) = command.try_into() {
+                return Some(command);
+            }
+        }
     }
 }
 
@@ -59,17 +65,24 @@ impl<'a> RawCommands<'a> for RawComment<'a> {
     }
 }
 
+/// A `%clk` command. Clocks under a minute may have tenths of a second, like
+/// `0:00:14.9` in UltraBullet games.
 #[derive(Default, Serialize)]
 pub struct Clock {
     pub hours: u16,
     pub minutes: u8,
     pub seconds: u8,
+    pub tenths: u8,
 }
 
 impl<'a> TryFrom<&'a [u8]> for Clock {
     type Error = Error;
 
     fn try_from(value: &'a [u8]) -> Result<Self> {
+        if let Some(clock) = Clock::parse_fixed(value) {
+            return Ok(clock);
+        }
+
         let mut parts = value.split_str(":");
         let hours = parts
             .next()
@@ -79,17 +92,22 @@ impl<'a> TryFrom<&'a [u8]> for Clock {
             .next()
             .ok_or_else(|| anyhow!("no minutes in clock"))?
             .parse()?;
-        let seconds = parts
-            .next()
-            .ok_or_else(|| anyhow!("no seconds in clock"))?
-            .parse()?;
+        let seconds = parts.next().ok_or_else(|| anyhow!("no seconds in clock"))?;
+        let (seconds, tenths) = match seconds.split_once_str(".") {
+            Some((seconds, tenths)) => {
+                ensure!(tenths.len() == 1, "expected tenths of a second in clock");
+                (seconds, tenths.parse()?)
+            }
+            None => (seconds, 0),
+        };
 
         ensure!(parts.next().is_none(), "too many parts in clock");
 
         Ok(Clock {
             hours,
             minutes,
-            seconds,
+            seconds: seconds.parse()?,
+            tenths,
         })
     }
 }
@@ -121,11 +139,164 @@ impl<'a> TryFrom<RawComment<'a>> for Clock {
     }
 }
 
+/// An `%eval` command, from white's point of view, like `0.17`, `-1.5,23`
+/// (with the search depth), or `#-3` for black mating in three.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum Eval {
+    Pawns(f32),
+    Mate(i16),
+}
+
+impl<'a> TryFrom<RawCommand<'a>> for Eval {
+    type Error = Error;
+
+    fn try_from(value: RawCommand<'a>) -> Result<Self> {
+        let mut params = value.params;
+        // a second param, if any, is the depth
+        let eval = params
+            .next()
+            .ok_or_else(|| anyhow!("no value in eval command"))?;
+        match eval.strip_prefix(b"#") {
+            Some(mate) => Ok(Eval::Mate(mate.parse()?)),
+            None => Ok(Eval::Pawns(eval.parse()?)),
+        }
+    }
+}
+
+impl<'a> TryFrom<RawComment<'a>> for Eval {
+    type Error = Error;
+
+    fn try_from(value: RawComment<'a>) -> Result<Self> {
+        for command in value.raw_commands() {
+            if command.name == b"eval" {
+                return command.try_into();
+            }
+        }
+        Err(anyhow!("no eval command in comment"))
+    }
+}
+
+impl Eval {
+    /// Whether white (or else black) is at least `pawns` ahead, or has a
+    /// forced mate.
+    #[must_use]
+    pub fn favors(&self, white: bool, pawns: f32) -> bool {
+        match *self {
+            Eval::Pawns(eval) if white => eval >= pawns,
+            Eval::Pawns(eval) => -eval >= pawns,
+            Eval::Mate(moves) if white => moves > 0,
+            Eval::Mate(moves) => moves < 0,
+        }
+    }
+
+    /// The eval in pawns, with a forced mate counted as `mate` pawns for the
+    /// side that mates, so that evals fit in one numeric column. Evals beyond
+    /// `mate` are capped at it.
+    #[must_use]
+    pub fn pawns(&self, mate: f32) -> f32 {
+        match *self {
+            Eval::Pawns(eval) => eval.clamp(-mate, mate),
+            Eval::Mate(moves) if moves > 0 => mate,
+            Eval::Mate(_) => -mate,
+        }
+    }
+}
+
+/// Parses two ASCII digits without branching on each one.
+fn two_digits(tens: u8, ones: u8) -> Option<u8> {
+    let (tens, ones) = (tens.wrapping_sub(b'0'), ones.wrapping_sub(b'0'));
+    (tens < 10 && ones < 10).then_some(tens * 10 + ones)
+}
+
 impl Clock {
+    /// Fast path for the `H:MM:SS` layout that lichess always uses. Returns
+    /// `None` for anything else, which is then handled by the general parser.
+    fn parse_fixed(value: &[u8]) -> Option<Clock> {
+        let n = value.len();
+        if !(7..=9).contains(&n) || value[n - 3] != b':' || value[n - 6] != b':' {
+            return None;
+        }
+        let mut hours: u16 = 0;
+        for &digit in &value[..n - 6] {
+            let digit = digit.wrapping_sub(b'0');
+            if digit >= 10 {
+                return None;
+            }
+            hours = hours * 10 + u16::from(digit);
+        }
+        Some(Clock {
+            hours,
+            minutes: two_digits(value[n - 5], value[n - 4])?,
+            seconds: two_digits(value[n - 2], value[n - 1])?,
+            tenths: 0,
+        })
+    }
+
+    /// The time left in whole seconds, rounded down.
     #[must_use]
     pub fn total_seconds(&self) -> u32 {
         u32::from(self.hours) * 3600 + u32::from(self.minutes) * 60 + u32::from(self.seconds)
     }
+
+    #[must_use]
+    pub fn total_tenths(&self) -> u32 {
+        self.total_seconds() * 10 + u32::from(self.tenths)
+    }
+}
+
+/// The unit that every clock-derived column is written in as an integer, so
+/// that datasets mixing speeds neither lose UltraBullet's tenths nor spend
+/// digits on classical games. Without one, each column keeps its own unit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClockUnit {
+    /// Whole seconds, rounded down.
+    Seconds,
+    Deciseconds,
+    Milliseconds,
+}
+
+impl FromStr for ClockUnit {
+    type Err = Error;
+
+    fn from_str(unit: &str) -> Result<Self> {
+        match unit {
+            "seconds" => Ok(ClockUnit::Seconds),
+            "deciseconds" => Ok(ClockUnit::Deciseconds),
+            "milliseconds" => Ok(ClockUnit::Milliseconds),
+            _ => bail!("unknown clock unit {unit}"),
+        }
+    }
+}
+
+/// The name that durations in tenths of a second serialize under, for runs
+/// to write them in their `ClockUnit`.
+#[cfg_attr(not(feature = "pipeline"), allow(dead_code))]
+pub(crate) const TENTHS_SERDE_NAME: &str = "pgn2csv::Tenths";
+
+impl ClockUnit {
+    /// A duration in tenths of a second, like `Clock::total_tenths`, in this
+    /// unit.
+    #[must_use]
+    pub fn from_tenths(self, tenths: u32) -> u64 {
+        let tenths = u64::from(tenths);
+        match self {
+            ClockUnit::Seconds => tenths / 10,
+            ClockUnit::Deciseconds => tenths,
+            ClockUnit::Milliseconds => tenths * 100,
+        }
+    }
+}
+
+/// Serializes a duration in tenths of a second, for fields of rows like
+/// `#[serde(serialize_with = "pgn2csv::comments::serialize_tenths")] final_tenths: u32`.
+/// A run with a `--clock-unit` writes it in that unit; otherwise it is
+/// written as is.
+///
+/// # Errors
+///
+/// Returns the serializer's error.
+pub fn serialize_tenths<S: Serializer>(tenths: &u32, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_newtype_struct(TENTHS_SERDE_NAME, tenths)
 }
 
 #[cfg(test)]
@@ -163,4 +334,46 @@ mod tests {
 
         assert!(iter.next().is_none());
     }
+
+    #[test]
+    fn raw_command_iter_skips_malformed() {
+        let comment = b"[%bogus] [%clk 0:01:00]";
+        let mut iter = RawCommandIterator { comment };
+        assert_eq!(iter.next().unwrap().name, b"clk");
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn clock() {
+        let clock = Clock::try_from(b"1:02:03".as_slice()).unwrap();
+        assert_eq!(clock.total_seconds(), 3723);
+        let clock = Clock::try_from(b"100:00:00".as_slice()).unwrap();
+        assert_eq!(clock.total_seconds(), 360_000);
+        let clock = Clock::try_from(b"0:3:07".as_slice()).unwrap();
+        assert_eq!(clock.total_seconds(), 187);
+        let clock = Clock::try_from(b"0:00:14.9".as_slice()).unwrap();
+        assert_eq!(clock.total_seconds(), 14);
+        assert_eq!(clock.total_tenths(), 149);
+        assert!(Clock::try_from(b"0:00:14.95".as_slice()).is_err());
+        assert!(Clock::try_from(b"0:0a:00".as_slice()).is_err());
+        assert!(Clock::try_from(b"0:00:00:00".as_slice()).is_err());
+        let tenths = Clock::try_from(b"0:00:14.9".as_slice())
+            .unwrap()
+            .total_tenths();
+        assert_eq!(ClockUnit::Seconds.from_tenths(tenths), 14);
+        assert_eq!(ClockUnit::Milliseconds.from_tenths(tenths), 14_900);
+    }
+
+    #[test]
+    fn eval() {
+        let eval = |comment: &[u8]| Eval::try_from(RawComment(comment)).unwrap();
+        assert_eq!(eval(b"[%eval 0.17] [%clk 0:00:30]"), Eval::Pawns(0.17));
+        assert_eq!(eval(b"[%eval -1.5,23]"), Eval::Pawns(-1.5));
+        assert_eq!(eval(b"[%eval #-3]"), Eval::Mate(-3));
+        assert!(eval(b"[%eval #-3]").favors(false, 100.0));
+        assert!(!eval(b"[%eval 2.5]").favors(true, 3.0));
+        assert_eq!(eval(b"[%eval #-3]").pawns(100.0), -100.0);
+        assert_eq!(eval(b"[%eval 153.2]").pawns(100.0), 100.0);
+        assert!(Eval::try_from(RawComment(b"[%clk 0:00:30]".as_slice())).is_err());
+    }
 }
@@ -0,0 +1,102 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use anyhow::{Context, Result};
+use indicatif::{ProgressBar, ProgressStyle};
+
+use crate::log::Log;
+
+/// The number of games expected in each PGN, as in the `counts.txt` that
+/// lichess publishes next to its database dumps: one `<file name> <games>`
+/// line per file.
+#[derive(Default)]
+pub(crate) struct GameCounts(HashMap<String, u64>);
+
+impl GameCounts {
+    fn parse(text: &str) -> Self {
+        let counts = text
+            .lines()
+            .filter_map(|line| {
+                let (name, games) = line.trim().split_once(char::is_whitespace)?;
+                Some((name.to_owned(), games.trim().parse().ok()?))
+            })
+            .collect();
+        GameCounts(counts)
+    }
+
+    /// Reads counts from a file, or with the `download` feature, a URL.
+    // the log is only for the retries of requests
+    #[cfg_attr(not(feature = "download"), allow(unused_variables))]
+    pub(crate) fn load(source: &str, log: &Log) -> Result<Self> {
+        #[cfg(feature = "download")]
+        if source.starts_with("http://") || source.starts_with("https://") {
+            let (response, _permit) = crate::download::call(ureq::get(source), log)?;
+            let text = response.into_string()?;
+            return Ok(Self::parse(&text));
+        }
+        let text = fs::read_to_string(Path::new(source))
+            .with_context(|| format!("reading game counts from {source}"))?;
+        Ok(Self::parse(&text))
+    }
+
+    #[cfg(feature = "download")]
+    pub(crate) fn extend(&mut self, other: GameCounts) {
+        self.0.extend(other.0);
+    }
+
+    /// The expected number of games in the PGN named `name`, which may be
+    /// listed under a different compression than the one it is read from.
+    pub(crate) fn get(&self, name: &str) -> Option<u64> {
+        self.0.get(name).copied().or_else(|| {
+            let stem = strip_compression(name);
+            self.0
+                .iter()
+                .find(|(listed, _)| strip_compression(listed) == stem)
+                .map(|(_, &games)| games)
+        })
+    }
+}
+
+fn strip_compression(name: &str) -> &str {
+    name.strip_suffix(".bz2")
+        .or_else(|| name.strip_suffix(".zst"))
+        .or_else(|| name.strip_suffix(".gz"))
+        .unwrap_or(name)
+}
+
+/// A bar of games read out of the number expected, for a file whose count is
+/// known.
+pub(crate) fn game_bar(name: &str, games: u64) -> Result<ProgressBar> {
+    let pb = ProgressBar::new(games);
+    let template = format!("{{spinner:.green}} {name}: [{{elapsed}}] [{{bar:.cyan/blue}}] {{human_pos}}/{{human_len}} games ({{eta}})");
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template(&template)?
+            .progress_chars("#>-"),
+    );
+    Ok(pb)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_lichess_counts() {
+        let counts = GameCounts::parse(
+            "lichess_db_standard_rated_2013-01.pgn.zst 121332\n\
+             lichess_db_standard_rated_2013-02.pgn.zst 123961\n",
+        );
+        assert_eq!(
+            counts.get("lichess_db_standard_rated_2013-01.pgn.zst"),
+            Some(121_332)
+        );
+        assert_eq!(
+            counts.get("lichess_db_standard_rated_2013-02.pgn"),
+            Some(123_961)
+        );
+        assert_eq!(
+            counts.get("lichess_db_standard_rated_2013-03.pgn.zst"),
+            None
+        );
+    }
+}
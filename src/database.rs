@@ -0,0 +1,135 @@
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{anyhow, Result};
+use duckdb::{params_from_iter, types::Value as DuckValue, Connection};
+use serde_json::Value;
+
+use crate::{
+    output::table_name,
+    sink::{Record, RowSink},
+};
+
+/// The name of the DuckDB database file written to the CSV directory.
+pub(crate) const DATABASE: &str = "pgn2csv.duckdb";
+
+// rows are buffered and appended in batches, since an appender borrows the
+// connection it appends through
+const BATCH: usize = 10_000;
+
+/// The DuckDB database that the rows of every file of a run are appended to,
+/// in a table named after the binary, so that different processors can share
+/// a database. The table is replaced by each run, like the CSVs would be.
+pub(crate) struct Database {
+    connection: Mutex<Connection>,
+    table: String,
+}
+
+impl Database {
+    pub(crate) fn open(csv_dir: &Path) -> Result<Self> {
+        let table = table_name();
+        let connection = Connection::open(csv_dir.join(DATABASE))?;
+        connection.execute_batch(&format!("DROP TABLE IF EXISTS \"{table}\""))?;
+        Ok(Database {
+            connection: Mutex::new(connection),
+            table,
+        })
+    }
+
+    fn connection(&self) -> Result<Connection> {
+        let connection = self
+            .connection
+            .lock()
+            .map_err(|_| anyhow!("database lock poisoned"))?;
+        Ok(connection.try_clone()?)
+    }
+
+    /// Creates the table from the first row written, if no other file has yet.
+    fn create_table(&self, record: &Record) -> Result<()> {
+        let columns: Vec<String> = record
+            .iter()
+            .map(|(name, value)| {
+                let ty = match value {
+                    Value::Bool(_) => "BOOLEAN",
+                    Value::Number(n) if n.is_i64() => "BIGINT",
+                    Value::Number(n) if n.is_u64() => "UBIGINT",
+                    Value::Number(_) => "DOUBLE",
+                    _ => "VARCHAR",
+                };
+                format!("\"{name}\" {ty}")
+            })
+            .collect();
+        let connection = self
+            .connection
+            .lock()
+            .map_err(|_| anyhow!("database lock poisoned"))?;
+        connection.execute_batch(&format!(
+            "CREATE TABLE IF NOT EXISTS \"{}\" ({})",
+            self.table,
+            columns.join(", ")
+        ))?;
+        Ok(())
+    }
+}
+
+fn duck_value(value: &Value) -> DuckValue {
+    match value {
+        Value::Null => DuckValue::Null,
+        Value::Bool(b) => DuckValue::Boolean(*b),
+        Value::Number(n) => match (n.as_i64(), n.as_u64()) {
+            (Some(i), _) => DuckValue::BigInt(i),
+            (None, Some(u)) => DuckValue::UBigInt(u),
+            _ => DuckValue::Double(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        Value::String(s) => DuckValue::Text(s.clone()),
+        _ => DuckValue::Text(value.to_string()),
+    }
+}
+
+/// Appends the rows of one file to the run's table, through its own
+/// connection so that files are appended in parallel.
+pub(crate) struct DatabaseWriter {
+    database: Arc<Database>,
+    connection: Connection,
+    rows: Vec<Vec<DuckValue>>,
+    created: bool,
+}
+
+impl DatabaseWriter {
+    pub(crate) fn new(database: Arc<Database>) -> Result<Self> {
+        Ok(DatabaseWriter {
+            connection: database.connection()?,
+            database,
+            rows: Vec::with_capacity(BATCH),
+            created: false,
+        })
+    }
+}
+
+impl RowSink for DatabaseWriter {
+    fn write_row(&mut self, record: &Record) -> Result<()> {
+        if !self.created {
+            self.database.create_table(record)?;
+            self.created = true;
+        }
+        self.rows.push(record.values().map(duck_value).collect());
+        if self.rows.len() >= BATCH {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+        let mut appender = self.connection.appender(&self.database.table)?;
+        for row in self.rows.drain(..) {
+            appender.append_row(params_from_iter(row))?;
+        }
+        appender.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,52 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashSet},
+    hash::{Hash, Hasher},
+    sync::Mutex,
+};
+
+const SHARDS: usize = 16;
+
+/// The rows written so far across all outputs of a run, kept as 128-bit hashes
+/// rather than the rows themselves. Sharded so that workers writing rows at the
+/// same time rarely wait on each other.
+pub(crate) struct SeenRows {
+    shards: [Mutex<HashSet<u128>>; SHARDS],
+}
+
+impl SeenRows {
+    pub(crate) fn new() -> Self {
+        SeenRows {
+            shards: std::array::from_fn(|_| Mutex::new(HashSet::new())),
+        }
+    }
+
+    /// Records a serialized row, returning whether it hadn't been seen before.
+    pub(crate) fn insert(&self, row: &[u8]) -> bool {
+        let hash = hash(row);
+        let shard = &self.shards[hash as usize % SHARDS];
+        // a poisoned shard only means another worker panicked mid-insert
+        let mut shard = shard.lock().unwrap_or_else(|e| e.into_inner());
+        shard.insert(hash)
+    }
+}
+
+fn hash(row: &[u8]) -> u128 {
+    let mut low = DefaultHasher::new();
+    row.hash(&mut low);
+    let mut high = DefaultHasher::new();
+    (0xffu8, row).hash(&mut high);
+    u128::from(high.finish()) << 64 | u128::from(low.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn suppresses_repeats() {
+        let seen = SeenRows::new();
+        assert!(seen.insert(b"a,1\n"));
+        assert!(seen.insert(b"a,2\n"));
+        assert!(!seen.insert(b"a,1\n"));
+    }
+}
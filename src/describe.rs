@@ -0,0 +1,182 @@
+use std::{env, path::Path};
+
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+    encoding::Encodings,
+    group::AggregateKind,
+    record,
+    uid::{FNV_OFFSET, FNV_PRIME},
+    GameProcessor,
+};
+
+/// What a processor's binary produces, for the people and pipelines that
+/// consume its outputs.
+#[derive(Serialize)]
+pub(crate) struct Description {
+    processor: String,
+    version: &'static str,
+    schema_version: String,
+    description: &'static str,
+    columns: Vec<Column>,
+    filters: Vec<&'static str>,
+}
+
+#[derive(Serialize)]
+struct Column {
+    name: String,
+    #[serde(rename = "type")]
+    kind: &'static str,
+}
+
+/// The name of the running binary, which is the processor's name.
+pub(crate) fn processor_name() -> String {
+    let bin = env::args_os().next().unwrap_or_default();
+    Path::new(&bin)
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn kind(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(n) if n.is_f64() => "number",
+        Value::Number(_) => "integer",
+        Value::String(_) => "string",
+        Value::Array(_) | Value::Object(_) => "json",
+    }
+}
+
+/// Identifies the format of a processor's rows as
+/// `<processor>/<version>/<hash of the columns and their types>`, so that
+/// downstream pipelines can tell when it changed between runs, whether or not
+/// the version was bumped.
+fn schema_version(processor: &str, version: &str, columns: &[Column]) -> String {
+    let mut hash = FNV_OFFSET;
+    for column in columns {
+        for part in [column.name.as_bytes(), column.kind.as_bytes()] {
+            // separators keep e.g. ("ab", "c") and ("a", "bc") apart
+            for &byte in part.iter().chain(&[0]) {
+                hash ^= u64::from(byte);
+                hash = hash.wrapping_mul(FNV_PRIME);
+            }
+        }
+    }
+    format!("{processor}/{version}/{hash:016x}")
+}
+
+/// The line that starts CSVs when the schema version is written into them.
+pub(crate) fn schema_comment(schema_version: &str) -> String {
+    format!("# schema_version: {schema_version}")
+}
+
+impl Description {
+    pub(crate) fn schema_version(&self) -> &str {
+        &self.schema_version
+    }
+}
+
+/// Describes `P`, taking its columns and their types from a default row
+/// written in `encodings`.
+pub(crate) fn describe<P: GameProcessor>(encodings: Encodings) -> Result<Description> {
+    let mut row = record::to_record(&P::Row::default(), encodings)?;
+    if P::sessions().is_some() {
+        for column in ["session_id", "rematch"] {
+            row.insert(column.to_owned(), Value::from(0));
+        }
+    }
+    let mut columns: Vec<Column> = row
+        .iter()
+        .map(|(name, value)| Column {
+            name: name.clone(),
+            kind: kind(value),
+        })
+        .collect();
+    if let Some(group_by) = P::group_by() {
+        // grouped outputs have the key columns, then the aggregates
+        columns = group_by
+            .keys
+            .iter()
+            .map(|&key| {
+                let value = row
+                    .get(key)
+                    .ok_or_else(|| anyhow!("no column named {key} to group by"))?;
+                Ok(Column {
+                    name: key.to_owned(),
+                    kind: kind(value),
+                })
+            })
+            .collect::<Result<_>>()?;
+        columns.extend(group_by.aggregates.iter().map(|aggregate| Column {
+            name: aggregate.name.to_owned(),
+            kind: match aggregate.kind {
+                AggregateKind::Count => "integer",
+                AggregateKind::Sum | AggregateKind::Mean => "number",
+            },
+        }));
+    }
+    let processor = processor_name();
+    Ok(Description {
+        schema_version: schema_version(&processor, P::version(), &columns),
+        processor,
+        version: P::version(),
+        description: P::description(),
+        columns,
+        filters: P::filters(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default, Serialize)]
+    struct Row {
+        white_elo: u16,
+        accuracy: f32,
+        event: String,
+        rated: bool,
+    }
+
+    #[derive(Default)]
+    struct Processor;
+
+    impl GameProcessor for Processor {
+        type Row = Row;
+
+        fn row(&mut self) -> Row {
+            Row::default()
+        }
+
+        fn filters() -> Vec<&'static str> {
+            vec!["rated games only"]
+        }
+    }
+
+    #[test]
+    fn describes_columns() {
+        let description =
+            serde_json::to_value(describe::<Processor>(Encodings::of::<Processor>()).unwrap())
+                .unwrap();
+        assert_eq!(
+            description["columns"],
+            serde_json::json!([
+                {"name": "white_elo", "type": "integer"},
+                {"name": "accuracy", "type": "number"},
+                {"name": "event", "type": "string"},
+                {"name": "rated", "type": "boolean"},
+            ])
+        );
+        assert_eq!(
+            description["filters"],
+            serde_json::json!(["rated games only"])
+        );
+        let schema_version = description["schema_version"].as_str().unwrap();
+        assert!(schema_version.contains("/0.0.0/"));
+    }
+}
@@ -0,0 +1,478 @@
+use std::{
+    collections::{hash_map::RandomState, VecDeque},
+    fs::{self, File, OpenOptions},
+    hash::{BuildHasher, Hasher},
+    io::{self, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Condvar, Mutex, OnceLock},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    log::{Event, Log},
+    source::PgnSource,
+};
+
+// attempts at a download, each resuming where the previous one stopped
+const ATTEMPTS: u32 = 5;
+// attempts at a request that the server turns away for being too many
+const THROTTLED_ATTEMPTS: u32 = 6;
+const HOUR: Duration = Duration::from_secs(60 * 60);
+
+/// Caps on the requests made to servers, shared by every download, stream and
+/// list fetched in a run.
+struct Limiter {
+    per_hour: Option<usize>,
+    concurrent: Option<usize>,
+    state: Mutex<LimiterState>,
+    freed: Condvar,
+}
+
+#[derive(Default)]
+struct LimiterState {
+    in_flight: usize,
+    // when the requests of the last hour were sent, oldest first
+    sent: VecDeque<Instant>,
+}
+
+static LIMITER: OnceLock<Limiter> = OnceLock::new();
+
+/// Limits the requests of the rest of the run to `per_hour` an hour and
+/// `concurrent` open at once. Without limits, requests are only slowed down
+/// when a server answers that there are too many.
+pub(crate) fn limit_requests(per_hour: Option<usize>, concurrent: Option<usize>) {
+    let _ = LIMITER.set(Limiter::new(per_hour, concurrent));
+}
+
+impl Limiter {
+    fn new(per_hour: Option<usize>, concurrent: Option<usize>) -> Self {
+        Limiter {
+            per_hour,
+            concurrent,
+            state: Mutex::default(),
+            freed: Condvar::new(),
+        }
+    }
+
+    fn get() -> &'static Limiter {
+        LIMITER.get_or_init(|| Limiter::new(None, None))
+    }
+
+    /// Waits until a request may be sent under the limits.
+    fn acquire(&'static self) -> Permit {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            let now = Instant::now();
+            while state.sent.front().is_some_and(|&sent| now - sent >= HOUR) {
+                state.sent.pop_front();
+            }
+            if self.concurrent.is_some_and(|max| state.in_flight >= max) {
+                state = self.freed.wait(state).unwrap();
+            } else if self.per_hour.is_some_and(|max| state.sent.len() >= max) {
+                let wait = HOUR - (now - state.sent[0]);
+                state = self.freed.wait_timeout(state, wait).unwrap().0;
+            } else {
+                state.in_flight += 1;
+                if self.per_hour.is_some() {
+                    state.sent.push_back(now);
+                }
+                return Permit(self);
+            }
+        }
+    }
+}
+
+/// A request counting towards the concurrency cap until it is dropped.
+pub(crate) struct Permit(&'static Limiter);
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.0.state.lock().unwrap().in_flight -= 1;
+        self.0.freed.notify_one();
+    }
+}
+
+/// How long to wait before the `attempt`th retry of a throttled request:
+/// doubling from a second, scaled by a random factor between a half and one
+/// and a half so that parallel requests don't retry in lockstep.
+fn backoff(attempt: u32) -> Duration {
+    let jitter = RandomState::new().build_hasher().finish() % 1000;
+    Duration::from_secs(1 << attempt.min(10)).mul_f64(0.5 + jitter as f64 / 1000.0)
+}
+
+/// Sends `request` within the request limits, retrying it after a jittered
+/// backoff, or as long as the server asks, while it answers 429 Too Many
+/// Requests or 503 Service Unavailable. The permit should be kept for as long
+/// as the response is read.
+pub(crate) fn call(
+    request: ureq::Request,
+    log: &Log,
+) -> Result<(ureq::Response, Permit), Box<ureq::Error>> {
+    let mut attempt = 1;
+    loop {
+        let permit = Limiter::get().acquire();
+        match request.clone().call() {
+            Err(ureq::Error::Status(status @ (429 | 503), response))
+                if attempt < THROTTLED_ATTEMPTS =>
+            {
+                drop(permit);
+                let retry_after = response
+                    .header("Retry-After")
+                    .and_then(|secs| secs.trim().parse().ok())
+                    .map(Duration::from_secs);
+                let wait = retry_after.unwrap_or_default() + backoff(attempt);
+                log.emit(&Event::Warning {
+                    message: format!(
+                        "{} answered {status}, retrying in {}s",
+                        request.url(),
+                        wait.as_secs()
+                    ),
+                });
+                thread::sleep(wait);
+                attempt += 1;
+            }
+            result => return result.map(|response| (response, permit)).map_err(Box::new),
+        }
+    }
+}
+
+/// The name that a URL's file is saved under.
+pub(crate) fn file_name(url: &str) -> Result<&str> {
+    url.rsplit('/')
+        .next()
+        .filter(|name| !name.is_empty())
+        .ok_or_else(|| anyhow!("no file name in {url}"))
+}
+
+/// The default checksums list for a URL: lichess publishes `sha256sums.txt`
+/// next to its database dumps.
+pub(crate) fn default_checksums(url: &str) -> Option<String> {
+    let (dir, _) = url.rsplit_once('/')?;
+    dir.contains("database.lichess.org")
+        .then(|| format!("{dir}/sha256sums.txt"))
+}
+
+/// The default game counts for a URL: lichess publishes `counts.txt` next to
+/// its database dumps.
+pub(crate) fn default_counts(url: &str) -> Option<String> {
+    let (dir, _) = url.rsplit_once('/')?;
+    dir.contains("database.lichess.org")
+        .then(|| format!("{dir}/counts.txt"))
+}
+
+/// Fetches a `sha256sum`-style list (`<hex digest>  <file name>` per line) and
+/// returns the digest listed for `name`.
+fn expected_sha256(checksums: &str, name: &str, log: &Log) -> Result<String> {
+    let (response, _permit) = call(ureq::get(checksums), log)?;
+    let list = response.into_string()?;
+    list.lines()
+        .find_map(|line| {
+            let (digest, file) = line.split_once(char::is_whitespace)?;
+            (file.trim_start().trim_start_matches('*') == name).then(|| digest.to_lowercase())
+        })
+        .ok_or_else(|| anyhow!("{name} is not listed in {checksums}"))
+}
+
+fn sha256(path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+fn progress_bar(name: &str, log: &Log) -> Result<ProgressBar> {
+    let pb = ProgressBar::new(0);
+    let template = format!("{{spinner:.green}} Downloading {name}: [{{elapsed}}] [{{bar:.cyan/blue}}] {{bytes}}/{{total_bytes}} ({{eta}})");
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template(&template)?
+            .progress_chars("#>-"),
+    );
+    if log.hides_progress() {
+        pb.set_draw_target(ProgressDrawTarget::hidden());
+    }
+    Ok(pb)
+}
+
+/// Downloads into `part`, resuming from its current length with an HTTP range
+/// request. Returns once the whole file has been received.
+fn fetch(url: &str, part: &Path, pb: &ProgressBar, log: &Log) -> Result<()> {
+    let offset = fs::metadata(part).map_or(0, |m| m.len());
+    let range = ureq::get(url).set("Range", &format!("bytes={offset}-"));
+    let (response, _permit) = match call(range, log) {
+        Ok(response) => response,
+        // the range starts at the end of the file, so it is already complete
+        Err(e) if matches!(*e, ureq::Error::Status(416, _)) => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    let resumed = response.status() == 206;
+    let remaining: Option<u64> = response
+        .header("Content-Length")
+        .and_then(|len| len.parse().ok());
+    let mut file = if resumed {
+        OpenOptions::new().append(true).open(part)?
+    } else {
+        // the server ignored the range, so start over
+        File::create(part)?
+    };
+    let start = if resumed { offset } else { 0 };
+    if let Some(remaining) = remaining {
+        pb.set_length(start + remaining);
+    }
+    pb.set_position(start);
+
+    let mut reader = response.into_reader();
+    let mut received = 0;
+    let mut buf = vec![0; 1 << 16];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        file.write_all(&buf[..n])?;
+        received += n as u64;
+        pb.inc(n as u64);
+    }
+    file.flush()?;
+    if remaining.is_some_and(|remaining| received < remaining) {
+        bail!("connection closed after {received} bytes");
+    }
+    Ok(())
+}
+
+/// Downloads `url` into `dir`, resuming an earlier partial download if there is
+/// one and retrying dropped connections. If a checksums list is given, or one
+/// is published next to the URL, the file is verified against it before it is
+/// processed, so that truncated downloads fail rather than producing short
+/// CSVs. A file that was already downloaded and verified is left as is.
+pub(crate) fn download(
+    url: &str,
+    dir: &Path,
+    checksums: Option<&str>,
+    log: &Log,
+) -> Result<PathBuf> {
+    let name = file_name(url)?;
+    let path = dir.join(name);
+    let checksums = checksums
+        .map(str::to_owned)
+        .or_else(|| default_checksums(url));
+    let expected = checksums
+        .as_deref()
+        .map(|checksums| expected_sha256(checksums, name, log))
+        .transpose()?;
+
+    if path.exists() {
+        match &expected {
+            Some(expected) if sha256(&path)? == *expected => return Ok(path),
+            Some(_) => fs::remove_file(&path)?,
+            None => return Ok(path),
+        }
+    }
+
+    let part = dir.join(format!("{name}.part"));
+    let pb = progress_bar(name, log)?;
+    let mut attempt = 1;
+    while let Err(e) = fetch(url, &part, &pb, log) {
+        if attempt == ATTEMPTS {
+            return Err(e.context(format!("downloading {url} failed {ATTEMPTS} times")));
+        }
+        log.emit(&Event::Warning {
+            message: format!("{e}, resuming {name}"),
+        });
+        thread::sleep(Duration::from_secs(2u64.pow(attempt)));
+        attempt += 1;
+    }
+    pb.finish_and_clear();
+
+    if let Some(expected) = expected {
+        let actual = sha256(&part)?;
+        if actual != expected {
+            fs::remove_file(&part)?;
+            bail!("{name} has SHA-256 {actual}, but {expected} was published");
+        }
+    }
+    fs::rename(&part, &path).with_context(|| format!("moving {name} into place"))?;
+    Ok(path)
+}
+
+/// A directory of complete remote files kept from earlier runs, holding at
+/// most about `max_bytes`. The least recently used files are evicted first.
+pub(crate) struct Cache {
+    dir: PathBuf,
+    max_bytes: u64,
+}
+
+impl Cache {
+    pub(crate) fn new(dir: &Path, max_bytes: u64) -> Result<Self> {
+        fs::create_dir_all(dir)?;
+        Ok(Cache {
+            dir: dir.to_path_buf(),
+            max_bytes,
+        })
+    }
+
+    /// Returns the cached copy of `name`, marking it as recently used.
+    fn get(&self, name: &str) -> Option<File> {
+        let path = self.dir.join(name);
+        let file = File::options().append(true).open(&path).ok()?;
+        let _ = file.set_modified(SystemTime::now());
+        File::open(path).ok()
+    }
+
+    /// Removes the least recently used files until the cache fits, never
+    /// removing the most recent one.
+    fn evict(&self) -> io::Result<()> {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            let is_part = entry.path().extension().is_some_and(|ext| ext == "part");
+            if metadata.is_file() && !is_part {
+                files.push((metadata.modified()?, metadata.len(), entry.path()));
+            }
+        }
+        files.sort();
+        let mut total: u64 = files.iter().map(|(_, len, _)| len).sum();
+        for (_, len, path) in files.iter().rev().skip(1).rev() {
+            if total <= self.max_bytes {
+                break;
+            }
+            fs::remove_file(path)?;
+            total -= len;
+        }
+        Ok(())
+    }
+}
+
+/// Copies the compressed bytes of a remote file into the cache as they are
+/// streamed. The copy only takes the file's name once the stream has been read
+/// to the end, so that an interrupted run doesn't leave a truncated file to be
+/// mistaken for a complete one.
+struct Tee {
+    inner: Box<dyn Read>,
+    file: BufWriter<File>,
+    part: PathBuf,
+    path: PathBuf,
+    cache: Arc<Cache>,
+    done: bool,
+}
+
+impl Read for Tee {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n > 0 {
+            self.file.write_all(&buf[..n])?;
+        } else if !buf.is_empty() && !self.done {
+            self.file.flush()?;
+            fs::rename(&self.part, &self.path)?;
+            self.done = true;
+            self.cache.evict()?;
+        }
+        Ok(n)
+    }
+}
+
+impl Drop for Tee {
+    fn drop(&mut self) {
+        if !self.done {
+            let _ = fs::remove_file(&self.part);
+        }
+    }
+}
+
+/// A response body that holds its request's permit until it is dropped, so
+/// that streams count towards the concurrency cap for as long as they're read.
+struct Permitted<R> {
+    inner: R,
+    _permit: Permit,
+}
+
+impl<R: Read> Read for Permitted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+/// A PGN read over HTTP as it is converted, rather than downloaded first.
+pub(crate) struct Remote {
+    url: String,
+    name: String,
+    cache: Option<Arc<Cache>>,
+    log: Log,
+}
+
+impl Remote {
+    pub(crate) fn new(url: &str, cache: Option<Arc<Cache>>, log: Log) -> Result<Self> {
+        Ok(Remote {
+            url: url.to_owned(),
+            name: file_name(url)?.to_owned(),
+            cache,
+            log,
+        })
+    }
+}
+
+impl PgnSource for Remote {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    /// Opens the compressed bytes of the file, from the cache if it has a
+    /// complete copy, and otherwise from the network, copying them into the
+    /// cache if there is one.
+    fn open(&self) -> Result<Box<dyn Read>> {
+        let name = &self.name;
+        if let Some(file) = self.cache.as_ref().and_then(|cache| cache.get(name)) {
+            return Ok(Box::new(file));
+        }
+        let (response, permit) = call(ureq::get(&self.url), &self.log)?;
+        let inner = Permitted {
+            inner: response.into_reader(),
+            _permit: permit,
+        };
+        let Some(cache) = &self.cache else {
+            return Ok(Box::new(inner));
+        };
+        let part = cache.dir.join(format!("{name}.part"));
+        Ok(Box::new(Tee {
+            inner: Box::new(inner),
+            file: BufWriter::new(File::create(&part)?),
+            part,
+            path: cache.dir.join(name),
+            cache: cache.clone(),
+            done: false,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_is_jittered() {
+        for attempt in 1..5 {
+            let base = Duration::from_secs(1 << attempt);
+            let wait = backoff(attempt);
+            assert!(wait >= base / 2 && wait < base * 3 / 2);
+        }
+    }
+
+    #[test]
+    fn lichess_checksums() {
+        let url = "https://database.lichess.org/standard/lichess_db_standard_rated_2013-01.pgn.zst";
+        assert_eq!(
+            default_checksums(url).as_deref(),
+            Some("https://database.lichess.org/standard/sha256sums.txt")
+        );
+        assert_eq!(default_checksums("https://example.com/games.pgn"), None);
+        assert_eq!(
+            file_name(url).unwrap(),
+            "lichess_db_standard_rated_2013-01.pgn.zst"
+        );
+    }
+}
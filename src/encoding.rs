@@ -0,0 +1,503 @@
+//! The encodings that a run writes its rows' values in, e.g. results as half
+//! points or booleans as `t` and `f`.
+//!
+//! Values with a choice of encodings, like `PgnResult`, serialize as serde
+//! would by default, under a name of their own. `Encoded` wraps the
+//! serializer of each row that a run writes, and writes the values under
+//! those names in the run's encodings instead, so that runs in the same
+//! process, each with encodings of their own, don't affect each other.
+
+use serde::{
+    ser::{
+        Error, SerializeMap, SerializeSeq, SerializeStruct, SerializeStructVariant, SerializeTuple,
+        SerializeTupleStruct, SerializeTupleVariant,
+    },
+    Serialize, Serializer,
+};
+
+use crate::{
+    boolean::{self, BoolEncoding},
+    comments::{self, ClockUnit},
+    headers::{PgnResult, ResultEncoding, Termination, TerminationEncoding},
+    text::{self, TextEncoding},
+    GameProcessor,
+};
+
+/// How the values of a run's rows are written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Encodings {
+    pub(crate) result: ResultEncoding,
+    pub(crate) termination: TerminationEncoding,
+    pub(crate) text: TextEncoding,
+    pub(crate) bool: BoolEncoding,
+    /// The unit of durations in tenths of a second, which are written as is
+    /// without one.
+    pub(crate) clock_unit: Option<ClockUnit>,
+}
+
+impl Encodings {
+    /// The processor's own encodings, which the command line can override.
+    pub(crate) fn of<P: GameProcessor>() -> Self {
+        Encodings {
+            result: P::result_encoding(),
+            termination: P::termination_encoding(),
+            ..Encodings::default()
+        }
+    }
+}
+
+/// A row, or a value of one, serialized in `encodings`.
+pub(crate) struct Encoded<'a, T: ?Sized> {
+    value: &'a T,
+    encodings: Encodings,
+}
+
+impl<'a, T: ?Sized> Encoded<'a, T> {
+    pub(crate) fn new(value: &'a T, encodings: Encodings) -> Self {
+        Encoded { value, encodings }
+    }
+}
+
+impl<T: ?Sized + Serialize> Serialize for Encoded<'_, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.value.serialize(EncodingSerializer {
+            inner: serializer,
+            encodings: self.encodings,
+        })
+    }
+}
+
+fn result<S: Serializer>(
+    encoding: ResultEncoding,
+    result: PgnResult,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let index = match result {
+        PgnResult::WhiteWin => 0,
+        PgnResult::Draw => 1,
+        PgnResult::BlackWin => 2,
+        PgnResult::Other => return serializer.serialize_none(),
+    };
+    match encoding {
+        ResultEncoding::WhiteScore => serializer.serialize_f32([1.0, 0.5, 0.0][index]),
+        ResultEncoding::HalfPoints => serializer.serialize_u8([2, 1, 0][index]),
+        ResultEncoding::Signed => serializer.serialize_i8([1, 0, -1][index]),
+        ResultEncoding::Categorical => serializer.serialize_str(["white", "draw", "black"][index]),
+    }
+}
+
+fn termination<S: Serializer>(
+    encoding: TerminationEncoding,
+    termination: Termination,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match encoding {
+        TerminationEncoding::Codes => serializer.serialize_u8(termination.code()),
+        TerminationEncoding::Names => serializer.serialize_str(termination.name()),
+    }
+}
+
+/// The value that `value` serializes as, to encode it.
+fn capture<T: ?Sized + Serialize, E: Error>(value: &T) -> Result<serde_json::Value, E> {
+    value
+        .serialize(serde_json::value::Serializer)
+        .map_err(E::custom)
+}
+
+/// Forwards everything to the serializer it wraps, except for the values
+/// that it encodes, and wraps the values of compound types in turn.
+struct EncodingSerializer<S> {
+    inner: S,
+    encodings: Encodings,
+}
+
+macro_rules! forward {
+    ($($method:ident($type:ty)),* $(,)?) => {
+        $(
+            fn $method(self, value: $type) -> Result<S::Ok, S::Error> {
+                self.inner.$method(value)
+            }
+        )*
+    };
+}
+
+impl<S: Serializer> Serializer for EncodingSerializer<S> {
+    type Ok = S::Ok;
+    type Error = S::Error;
+    type SerializeSeq = Compound<S::SerializeSeq>;
+    type SerializeTuple = Compound<S::SerializeTuple>;
+    type SerializeTupleStruct = Compound<S::SerializeTupleStruct>;
+    type SerializeTupleVariant = Compound<S::SerializeTupleVariant>;
+    type SerializeMap = Compound<S::SerializeMap>;
+    type SerializeStruct = Compound<S::SerializeStruct>;
+    type SerializeStructVariant = Compound<S::SerializeStructVariant>;
+
+    forward!(
+        serialize_bool(bool),
+        serialize_i8(i8),
+        serialize_i16(i16),
+        serialize_i32(i32),
+        serialize_i64(i64),
+        serialize_i128(i128),
+        serialize_u8(u8),
+        serialize_u16(u16),
+        serialize_u32(u32),
+        serialize_u64(u64),
+        serialize_u128(u128),
+        serialize_f32(f32),
+        serialize_f64(f64),
+        serialize_char(char),
+        serialize_str(&str),
+        serialize_bytes(&[u8]),
+        serialize_unit_struct(&'static str),
+    );
+
+    fn serialize_none(self) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_none()
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<S::Ok, S::Error> {
+        self.inner
+            .serialize_some(&Encoded::new(value, self.encodings))
+    }
+
+    fn serialize_unit(self) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_unit()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+    ) -> Result<S::Ok, S::Error> {
+        match name {
+            PgnResult::SERDE_NAME => match PgnResult::from_variant_index(index) {
+                Some(value) => result(self.encodings.result, value, self.inner),
+                None => self.inner.serialize_unit_variant(name, index, variant),
+            },
+            Termination::SERDE_NAME => match Termination::from_code(index) {
+                Some(value) => termination(self.encodings.termination, value, self.inner),
+                None => self.inner.serialize_unit_variant(name, index, variant),
+            },
+            _ => self.inner.serialize_unit_variant(name, index, variant),
+        }
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<S::Ok, S::Error> {
+        match (name, self.encodings) {
+            (
+                text::SERDE_NAME,
+                Encodings {
+                    text: TextEncoding::Json,
+                    ..
+                },
+            ) => match capture(value)? {
+                serde_json::Value::String(value) => {
+                    self.inner.serialize_str(&TextEncoding::Json.encode(&value))
+                }
+                _ => Err(S::Error::custom("free text must be a string")),
+            },
+            (boolean::SERDE_NAME, Encodings { bool, .. }) => match capture(value)? {
+                serde_json::Value::Bool(value) => bool.serialize(value, self.inner),
+                _ => Err(S::Error::custom("booleans must be bools")),
+            },
+            (
+                comments::TENTHS_SERDE_NAME,
+                Encodings {
+                    clock_unit: Some(unit),
+                    ..
+                },
+            ) => match capture(value)?.as_u64().and_then(|t| u32::try_from(t).ok()) {
+                Some(tenths) => self.inner.serialize_u64(unit.from_tenths(tenths)),
+                None => Err(S::Error::custom("durations must be tenths of a second")),
+            },
+            _ => self
+                .inner
+                .serialize_newtype_struct(name, &Encoded::new(value, self.encodings)),
+        }
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_newtype_variant(
+            name,
+            index,
+            variant,
+            &Encoded::new(value, self.encodings),
+        )
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq, S::Error> {
+        let inner = self.inner.serialize_seq(len)?;
+        Ok(Compound::new(inner, self.encodings))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple, S::Error> {
+        let inner = self.inner.serialize_tuple(len)?;
+        Ok(Compound::new(inner, self.encodings))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleStruct, S::Error> {
+        let inner = self.inner.serialize_tuple_struct(name, len)?;
+        Ok(Compound::new(inner, self.encodings))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleVariant, S::Error> {
+        let inner = self
+            .inner
+            .serialize_tuple_variant(name, index, variant, len)?;
+        Ok(Compound::new(inner, self.encodings))
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap, S::Error> {
+        let inner = self.inner.serialize_map(len)?;
+        Ok(Compound::new(inner, self.encodings))
+    }
+
+    fn serialize_struct(
+        self,
+        name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStruct, S::Error> {
+        let inner = self.inner.serialize_struct(name, len)?;
+        Ok(Compound::new(inner, self.encodings))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStructVariant, S::Error> {
+        let inner = self
+            .inner
+            .serialize_struct_variant(name, index, variant, len)?;
+        Ok(Compound::new(inner, self.encodings))
+    }
+
+    fn is_human_readable(&self) -> bool {
+        self.inner.is_human_readable()
+    }
+}
+
+/// A compound value, such as a row's struct, whose fields are encoded.
+struct Compound<C> {
+    inner: C,
+    encodings: Encodings,
+}
+
+impl<C> Compound<C> {
+    fn new(inner: C, encodings: Encodings) -> Self {
+        Compound { inner, encodings }
+    }
+}
+
+impl<C: SerializeSeq> SerializeSeq for Compound<C> {
+    type Ok = C::Ok;
+    type Error = C::Error;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), C::Error> {
+        self.inner
+            .serialize_element(&Encoded::new(value, self.encodings))
+    }
+
+    fn end(self) -> Result<C::Ok, C::Error> {
+        self.inner.end()
+    }
+}
+
+impl<C: SerializeTuple> SerializeTuple for Compound<C> {
+    type Ok = C::Ok;
+    type Error = C::Error;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), C::Error> {
+        self.inner
+            .serialize_element(&Encoded::new(value, self.encodings))
+    }
+
+    fn end(self) -> Result<C::Ok, C::Error> {
+        self.inner.end()
+    }
+}
+
+impl<C: SerializeTupleStruct> SerializeTupleStruct for Compound<C> {
+    type Ok = C::Ok;
+    type Error = C::Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), C::Error> {
+        self.inner
+            .serialize_field(&Encoded::new(value, self.encodings))
+    }
+
+    fn end(self) -> Result<C::Ok, C::Error> {
+        self.inner.end()
+    }
+}
+
+impl<C: SerializeTupleVariant> SerializeTupleVariant for Compound<C> {
+    type Ok = C::Ok;
+    type Error = C::Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), C::Error> {
+        self.inner
+            .serialize_field(&Encoded::new(value, self.encodings))
+    }
+
+    fn end(self) -> Result<C::Ok, C::Error> {
+        self.inner.end()
+    }
+}
+
+impl<C: SerializeMap> SerializeMap for Compound<C> {
+    type Ok = C::Ok;
+    type Error = C::Error;
+
+    fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> Result<(), C::Error> {
+        self.inner.serialize_key(&Encoded::new(key, self.encodings))
+    }
+
+    fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), C::Error> {
+        self.inner
+            .serialize_value(&Encoded::new(value, self.encodings))
+    }
+
+    fn end(self) -> Result<C::Ok, C::Error> {
+        self.inner.end()
+    }
+}
+
+impl<C: SerializeStruct> SerializeStruct for Compound<C> {
+    type Ok = C::Ok;
+    type Error = C::Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), C::Error> {
+        self.inner
+            .serialize_field(key, &Encoded::new(value, self.encodings))
+    }
+
+    fn skip_field(&mut self, key: &'static str) -> Result<(), C::Error> {
+        self.inner.skip_field(key)
+    }
+
+    fn end(self) -> Result<C::Ok, C::Error> {
+        self.inner.end()
+    }
+}
+
+impl<C: SerializeStructVariant> SerializeStructVariant for Compound<C> {
+    type Ok = C::Ok;
+    type Error = C::Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), C::Error> {
+        self.inner
+            .serialize_field(key, &Encoded::new(value, self.encodings))
+    }
+
+    fn skip_field(&mut self, key: &'static str) -> Result<(), C::Error> {
+        self.inner.skip_field(key)
+    }
+
+    fn end(self) -> Result<C::Ok, C::Error> {
+        self.inner.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize)]
+    struct Row {
+        result: PgnResult,
+        results: Vec<PgnResult>,
+        termination: Option<Termination>,
+        #[serde(serialize_with = "text::serialize")]
+        comment: String,
+        rated: boolean::Bool,
+        #[serde(serialize_with = "comments::serialize_tenths")]
+        clock: u32,
+    }
+
+    #[test]
+    fn encodes_rows() {
+        let row = Row {
+            result: PgnResult::WhiteWin,
+            results: vec![PgnResult::Draw, PgnResult::Other],
+            termination: Some(Termination::TimeForfeit),
+            comment: "a \"sharp\" line".to_owned(),
+            rated: true.into(),
+            clock: 35,
+        };
+        // outside a run, values serialize as serde would by default
+        assert_eq!(
+            serde_json::to_value(&row).unwrap(),
+            serde_json::json!({
+                "result": "WhiteWin",
+                "results": ["Draw", "Other"],
+                "termination": "TimeForfeit",
+                "comment": "a \"sharp\" line",
+                "rated": true,
+                "clock": 35,
+            })
+        );
+        let encodings = Encodings {
+            result: ResultEncoding::HalfPoints,
+            termination: TerminationEncoding::Names,
+            text: TextEncoding::Json,
+            bool: BoolEncoding::TF,
+            clock_unit: Some(ClockUnit::Milliseconds),
+        };
+        assert_eq!(
+            serde_json::to_value(Encoded::new(&row, encodings)).unwrap(),
+            serde_json::json!({
+                "result": 2,
+                "results": [1, null],
+                "termination": "Time forfeit",
+                "comment": "\"a \\\"sharp\\\" line\"",
+                "rated": "t",
+                "clock": 3500,
+            })
+        );
+        let encodings = Encodings::default();
+        assert_eq!(
+            serde_json::to_value(Encoded::new(&row, encodings)).unwrap(),
+            serde_json::json!({
+                "result": 1.0,
+                "results": [0.5, null],
+                "termination": 1,
+                "comment": "a \"sharp\" line",
+                "rated": true,
+                "clock": 35,
+            })
+        );
+    }
+}
@@ -0,0 +1,118 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use anyhow::Error;
+
+use crate::log::{Event, Log};
+
+/// The directory that outputs are written to: the output directory, until it
+/// turns out to be read-only, e.g. because an NFS share or a container volume
+/// was remounted during the run, and from then on the `--fallback-dir`, so
+/// that the rest of the run isn't lost.
+pub(crate) struct OutputDirs {
+    csv_dir: PathBuf,
+    fallback: Option<PathBuf>,
+    redirected: AtomicBool,
+    log: Log,
+}
+
+impl OutputDirs {
+    pub(crate) fn new(csv_dir: &Path, fallback: Option<&Path>, log: Log) -> Self {
+        OutputDirs {
+            csv_dir: csv_dir.to_path_buf(),
+            fallback: fallback.map(Path::to_path_buf),
+            redirected: AtomicBool::new(false),
+            log,
+        }
+    }
+
+    /// Whether outputs are now written to the fallback directory.
+    pub(crate) fn redirected(&self) -> bool {
+        self.redirected.load(Ordering::Relaxed)
+    }
+
+    /// The directory that the run's own files, like the manifest, are written
+    /// to.
+    pub(crate) fn dir(&self) -> &Path {
+        match (&self.fallback, self.redirected()) {
+            (Some(fallback), true) => fallback,
+            _ => &self.csv_dir,
+        }
+    }
+
+    /// Where `output`, a path in the output directory, is written, which is
+    /// the same path in the fallback directory once outputs are redirected.
+    pub(crate) fn path(&self, output: &Path) -> PathBuf {
+        match (&self.fallback, self.redirected()) {
+            (Some(fallback), true) => match output.strip_prefix(&self.csv_dir) {
+                Ok(relative) => fallback.join(relative),
+                Err(_) => output.to_path_buf(),
+            },
+            _ => output.to_path_buf(),
+        }
+    }
+
+    /// Redirects the outputs to the fallback directory if there is one and
+    /// `error` says that the output directory can't be written, returning
+    /// whether the output that failed should be written again.
+    pub(crate) fn redirect(&self, error: &Error) -> bool {
+        let Some(fallback) = &self.fallback else {
+            return false;
+        };
+        if !is_read_only(error) {
+            return false;
+        }
+        if !self.redirected.swap(true, Ordering::Relaxed) {
+            self.log.emit(&Event::Warning {
+                message: format!(
+                    "{} can't be written to ({error:#}), so the remaining outputs are written to {}",
+                    self.csv_dir.display(),
+                    fallback.display()
+                ),
+            });
+        }
+        true
+    }
+}
+
+/// Whether `error` comes from writing to a read-only file system or a
+/// directory without write permission.
+fn is_read_only(error: &Error) -> bool {
+    error.chain().any(|cause| {
+        cause.downcast_ref::<io::Error>().is_some_and(|e| {
+            matches!(
+                e.kind(),
+                io::ErrorKind::ReadOnlyFilesystem | io::ErrorKind::PermissionDenied
+            )
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use anyhow::anyhow;
+
+    #[test]
+    fn redirects_read_only_outputs() {
+        let dirs = OutputDirs::new(
+            Path::new("csvs"),
+            Some(Path::new("/tmp/csvs")),
+            Log::default(),
+        );
+        let output = Path::new("csvs/2024/a.csv");
+        assert_eq!(dirs.path(output), output);
+        assert!(!dirs.redirect(&anyhow!("no rows")));
+        let read_only = Error::new(io::Error::from(io::ErrorKind::ReadOnlyFilesystem));
+        assert!(dirs.redirect(&read_only.context("creating csvs/2024/a.csv")));
+        assert_eq!(dirs.path(output), Path::new("/tmp/csvs/2024/a.csv"));
+        assert_eq!(dirs.dir(), Path::new("/tmp/csvs"));
+        let dirs = OutputDirs::new(Path::new("csvs"), None, Log::default());
+        let denied = Error::new(io::Error::from(io::ErrorKind::PermissionDenied));
+        assert!(!dirs.redirect(&denied));
+    }
+}
@@ -0,0 +1,387 @@
+use std::{
+    borrow::Cow,
+    time::{Duration, Instant},
+};
+
+use pgn_reader::{Nag, Outcome, RawComment, RawHeader, SanPlus, Skip, Visitor};
+use serde_json::Value;
+
+use crate::{
+    columns::{LibraryColumn, LibraryColumns},
+    encoding::Encodings,
+    partition::{Partition, PartitionValue},
+    tournaments::FileTournaments,
+    uid::{GameUid, GameUidBuilder},
+    GameOffsets, GameProcessor,
+};
+
+/// The column that game uids are written in.
+const GAME_UID: &str = "game_uid";
+
+/// Wraps a processor to collect the per-game data that the library provides on
+/// top of it, forwarding every callback to the processor unchanged.
+pub(crate) struct GameVisitor<'a, P> {
+    processor: &'a mut P,
+    file: &'a [u8],
+    index: u64,
+    uid: GameUidBuilder,
+    columns: LibraryColumns<'a>,
+    // how deep in variations that the processor visits the current move is
+    variation_depth: u32,
+    tournaments: Option<FileTournaments>,
+    partition: Option<PartitionValue<'a>>,
+    // the headers of the current game, if they are collected, and its uid
+    headers: Option<Vec<(String, String)>>,
+    game_uid: GameUid,
+    // whether the uid is the first of the library columns
+    write_uid: bool,
+    // whether movetext is read even when the processor skips it, to tell
+    // aborted games apart, and whether the current game's is hidden from it
+    read_movetext: bool,
+    hide_movetext: bool,
+    // whether only headers are read, even if the processor reads movetext
+    headers_only: bool,
+    // the most moves of a game, variations included, that are read, and
+    // how many the current game has had
+    max_plies: Option<u32>,
+    plies: u32,
+    // where the next game is, when games are split
+    offsets: Option<GameOffsets>,
+    // time spent inside the processor's callbacks, when profiling
+    callbacks: Option<Duration>,
+}
+
+impl<'a, P> GameVisitor<'a, P> {
+    pub(crate) fn new(
+        processor: &'a mut P,
+        file: &'a [u8],
+        columns: &'a [LibraryColumn],
+        profile: bool,
+    ) -> Self {
+        GameVisitor {
+            processor,
+            file,
+            index: 0,
+            uid: GameUidBuilder::new(file, 0),
+            columns: LibraryColumns::new(columns),
+            variation_depth: 0,
+            tournaments: None,
+            partition: None,
+            headers: None,
+            game_uid: GameUid::default(),
+            write_uid: false,
+            read_movetext: false,
+            hide_movetext: false,
+            headers_only: false,
+            max_plies: None,
+            plies: 0,
+            offsets: None,
+            callbacks: profile.then_some(Duration::ZERO),
+        }
+    }
+
+    pub(crate) fn processor(&mut self) -> &mut P {
+        self.processor
+    }
+
+    pub(crate) fn set_offsets(&mut self, offsets: GameOffsets) {
+        self.offsets = Some(offsets);
+    }
+
+    /// Classifies the openings of the games read with `book`, for the opening
+    /// library columns.
+    #[cfg(feature = "openings")]
+    pub(crate) fn classify_openings(&mut self, book: &'a crate::openings::OpeningBook) {
+        self.columns.classify_openings(book);
+    }
+
+    /// Writes the library columns in the run's `encodings`.
+    pub(crate) fn encode_with(&mut self, encodings: Encodings) {
+        self.columns.encode_with(encodings);
+    }
+
+    /// Normalizes the ratings of the games read with `stats`, for the rating
+    /// z-score library columns.
+    pub(crate) fn normalize_ratings(&mut self, stats: &'a crate::ratings::RatingStats) {
+        self.columns.normalize_ratings(stats);
+    }
+
+    /// Reads the value of `partition` from every game's headers, for
+    /// partitioned outputs.
+    pub(crate) fn partition_by(&mut self, partition: &'a Partition) {
+        self.partition = Some(PartitionValue::new(partition));
+    }
+
+    /// The current game's partition, if rows are partitioned.
+    pub(crate) fn partition(&self) -> Option<String> {
+        self.partition.as_ref().map(PartitionValue::value)
+    }
+
+    /// Reads the movetext of every game, even when the processor skips it, so
+    /// that `aborted` is known for every game. The processor still doesn't
+    /// see the movetext it skips.
+    pub(crate) fn read_movetext(&mut self) {
+        self.read_movetext = true;
+    }
+
+    /// Skips the movetext of every game, even when the processor reads it,
+    /// so that games are only judged by their headers.
+    pub(crate) fn read_headers_only(&mut self) {
+        self.headers_only = true;
+    }
+
+    /// Stops reading the movetext of games once they have had `max_plies`
+    /// moves, variations included, for `too_long`.
+    pub(crate) fn max_plies(&mut self, max_plies: u32) {
+        self.max_plies = Some(max_plies);
+    }
+
+    /// Whether the last game read had more moves than `max_plies`, so that
+    /// the rest of its movetext wasn't read.
+    pub(crate) fn too_long(&self) -> bool {
+        self.max_plies.is_some_and(|max| self.plies > max)
+    }
+
+    /// Whether the last game read was aborted before a move, if its movetext
+    /// was read.
+    pub(crate) fn aborted(&self) -> Option<bool> {
+        self.columns.aborted()
+    }
+
+    /// Starts collecting the headers of the games read, for the headers table.
+    pub(crate) fn collect_headers(&mut self) {
+        self.headers = Some(Vec::new());
+    }
+
+    /// The uid and headers of the last game read, if headers are collected.
+    pub(crate) fn headers(&self) -> Option<(&GameUid, &[(String, String)])> {
+        let headers = self.headers.as_deref()?;
+        Some((&self.game_uid, headers))
+    }
+
+    /// Starts collecting the tournaments of the games read.
+    pub(crate) fn collect_tournaments(&mut self) {
+        self.tournaments = Some(FileTournaments::default());
+    }
+
+    pub(crate) fn take_tournaments(&mut self) -> Option<FileTournaments> {
+        self.tournaments.take()
+    }
+
+    /// Writes the uid of each game as a `game_uid` column, before the other
+    /// library columns.
+    pub(crate) fn write_game_uid(&mut self) {
+        self.write_uid = true;
+    }
+
+    /// The library columns of the last game read.
+    pub(crate) fn library_columns(&self) -> Vec<(Cow<'static, str>, Value)> {
+        let mut values = self.columns.values();
+        if self.write_uid {
+            values.insert(0, (GAME_UID.into(), self.game_uid.as_str().into()));
+        }
+        values
+    }
+
+    /// Swaps the colors of the library columns, for a mirrored row. The uid
+    /// is the same either way.
+    pub(crate) fn mirror_library_columns(&self, values: &mut [(Cow<'static, str>, Value)]) {
+        self.columns
+            .mirror(&mut values[usize::from(self.write_uid)..]);
+    }
+
+    pub(crate) fn callback_time(&self) -> Duration {
+        self.callbacks.unwrap_or_default()
+    }
+
+    fn forward<T>(&mut self, callback: impl FnOnce(&mut P) -> T) -> T {
+        match &mut self.callbacks {
+            None => callback(self.processor),
+            Some(elapsed) => {
+                let start = Instant::now();
+                let result = callback(self.processor);
+                *elapsed += start.elapsed();
+                result
+            }
+        }
+    }
+}
+
+impl<P> Visitor for GameVisitor<'_, P>
+where
+    P: Visitor + GameProcessor,
+{
+    type Result = P::Result;
+
+    fn begin_game(&mut self) {
+        self.uid = GameUidBuilder::new(self.file, self.index);
+        self.index += 1;
+        self.columns.begin_game();
+        self.variation_depth = 0;
+        self.hide_movetext = false;
+        self.plies = 0;
+        if let Some(headers) = &mut self.headers {
+            headers.clear();
+        }
+        if let Some(tournaments) = &mut self.tournaments {
+            tournaments.begin_game();
+        }
+        if let Some(partition) = &mut self.partition {
+            partition.begin_game();
+        }
+        self.forward(P::begin_game);
+        if let Some(offsets) = self.offsets {
+            self.forward(|p| p.set_game_offsets(offsets));
+        }
+    }
+
+    fn begin_headers(&mut self) {
+        self.forward(P::begin_headers);
+    }
+
+    fn header(&mut self, key: &[u8], value: RawHeader<'_>) {
+        self.uid.header(key, value.as_bytes());
+        self.columns.header(key, value);
+        if let Some(headers) = &mut self.headers {
+            headers.push((
+                String::from_utf8_lossy(key).into_owned(),
+                String::from_utf8_lossy(value.as_bytes()).into_owned(),
+            ));
+        }
+        if let Some(tournaments) = &mut self.tournaments {
+            tournaments.header(key, value);
+        }
+        if let Some(partition) = &mut self.partition {
+            partition.header(key, value);
+        }
+        self.forward(|p| p.header(key, value));
+    }
+
+    fn end_headers(&mut self) -> Skip {
+        self.game_uid = self.uid.finish();
+        self.processor.set_game_uid(self.game_uid.clone());
+        if let Some(tournaments) = &mut self.tournaments {
+            tournaments.end_headers();
+        }
+        let mut skip = self.forward(P::end_headers);
+        if !P::needs_moves() && !self.columns.needs_moves() {
+            skip = Skip(true);
+        }
+        if self.headers_only {
+            self.columns.end_headers(true);
+            return Skip(true);
+        }
+        self.hide_movetext = skip.0 && self.read_movetext;
+        self.columns.end_headers(skip.0 && !self.read_movetext);
+        Skip(skip.0 && !self.read_movetext)
+    }
+
+    fn san(&mut self, san_plus: SanPlus) {
+        self.plies += 1;
+        if self.too_long() {
+            return;
+        }
+        if self.variation_depth == 0 {
+            self.columns.san(&san_plus);
+        }
+        if !self.hide_movetext {
+            self.forward(|p| p.san(san_plus));
+        }
+    }
+
+    fn nag(&mut self, nag: Nag) {
+        if !self.hide_movetext && !self.too_long() {
+            self.forward(|p| p.nag(nag));
+        }
+    }
+
+    fn comment(&mut self, comment: RawComment<'_>) {
+        if self.too_long() {
+            return;
+        }
+        if self.variation_depth == 0 {
+            self.columns.comment(comment);
+        }
+        if !self.hide_movetext {
+            self.forward(|p| p.comment(comment));
+        }
+    }
+
+    fn begin_variation(&mut self) -> Skip {
+        if self.hide_movetext || self.too_long() {
+            return Skip(true);
+        }
+        let skip = self.forward(P::begin_variation);
+        // skipped variations aren't ended either
+        if !skip.0 {
+            self.variation_depth += 1;
+        }
+        skip
+    }
+
+    fn end_variation(&mut self) {
+        self.variation_depth = self.variation_depth.saturating_sub(1);
+        self.forward(P::end_variation);
+    }
+
+    fn outcome(&mut self, outcome: Option<Outcome>) {
+        if self.variation_depth == 0 {
+            self.columns.outcome(outcome);
+        }
+        if !self.hide_movetext && !self.too_long() {
+            self.forward(|p| p.outcome(outcome));
+        }
+    }
+
+    fn end_game(&mut self) -> Self::Result {
+        self.forward(P::end_game)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Processor;
+
+    impl Visitor for Processor {
+        type Result = ();
+
+        fn end_game(&mut self) {}
+    }
+
+    impl GameProcessor for Processor {
+        type Row = ();
+
+        fn row(&mut self) {}
+    }
+
+    #[test]
+    fn writes_game_uids_first() {
+        let columns = [LibraryColumn::WhiteFirstMove, LibraryColumn::BlackFirstMove];
+        let san = SanPlus::from_ascii(b"e4").unwrap();
+        let mut processor = Processor;
+        let mut game = GameVisitor::new(&mut processor, b"a.pgn", &columns, false);
+        game.write_game_uid();
+        game.begin_game();
+        game.begin_headers();
+        game.header(b"Site", RawHeader(b"https://lichess.org/PpwPOZMq"));
+        game.header(b"TimeControl", RawHeader(b"60+1"));
+        game.end_headers();
+        game.san(san.clone());
+        game.comment(RawComment(b" [%clk 0:00:59.5] "));
+        game.san(san.clone());
+        game.comment(RawComment(b" [%clk 0:01:01] "));
+        game.san(san);
+        game.end_game();
+        let values = game.library_columns();
+        assert_eq!(values[0], ("game_uid".into(), Value::from("PpwPOZMq")));
+        assert_eq!(values[1].0, "white_first_move_seconds");
+        let mut mirrored = values.clone();
+        game.mirror_library_columns(&mut mirrored);
+        assert_eq!(mirrored[0], values[0]);
+        assert_eq!(mirrored[1].1, values[2].1);
+        assert_eq!(mirrored[2].1, values[1].1);
+    }
+}
@@ -0,0 +1,251 @@
+#[cfg(feature = "pipeline")]
+use std::{
+    fs::{self, File},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+#[cfg(feature = "pipeline")]
+use anyhow::{anyhow, Result};
+#[cfg(feature = "pipeline")]
+use csv::ByteRecord;
+
+#[cfg(feature = "pipeline")]
+use crate::{sort::SortKey, spill::SpillBuffer};
+
+// only read by the pipeline, which does the grouping
+#[cfg_attr(not(feature = "pipeline"), allow(dead_code))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum AggregateKind {
+    Count,
+    Sum,
+    Mean,
+}
+
+#[cfg_attr(not(feature = "pipeline"), allow(dead_code))]
+#[derive(Clone, Debug)]
+pub(crate) struct Aggregate {
+    pub(crate) name: &'static str,
+    pub(crate) kind: AggregateKind,
+    column: &'static str,
+}
+
+/// Aggregates a processor's rows into one row per distinct combination of the
+/// key columns, for datasets of counts and averages rather than games. Rows
+/// are grouped with an external sort, so the number of groups can exceed
+/// memory, e.g. one per pair of players in a monthly dump.
+///
+/// ```
+/// use pgn2csv::group::GroupBy;
+///
+/// let group_by = GroupBy::new(&["speed", "hour"])
+///     .count("games")
+///     .mean("average_rating", "average_rating");
+/// ```
+#[cfg_attr(not(feature = "pipeline"), allow(dead_code))]
+#[derive(Clone, Debug)]
+pub struct GroupBy {
+    pub(crate) keys: Vec<&'static str>,
+    pub(crate) aggregates: Vec<Aggregate>,
+}
+
+impl GroupBy {
+    /// Groups rows by the given columns, which come first in the output.
+    #[must_use]
+    pub fn new(keys: &[&'static str]) -> Self {
+        GroupBy {
+            keys: keys.to_vec(),
+            aggregates: Vec::new(),
+        }
+    }
+
+    /// Adds a column with the number of rows in each group.
+    #[must_use]
+    pub fn count(self, name: &'static str) -> Self {
+        self.aggregate(name, AggregateKind::Count, "")
+    }
+
+    /// Adds a column with the sum of `column` over each group, ignoring empty
+    /// values.
+    #[must_use]
+    pub fn sum(self, column: &'static str, name: &'static str) -> Self {
+        self.aggregate(name, AggregateKind::Sum, column)
+    }
+
+    /// Adds a column with the mean of `column` over each group, ignoring empty
+    /// values. It is empty for groups without any values.
+    #[must_use]
+    pub fn mean(self, column: &'static str, name: &'static str) -> Self {
+        self.aggregate(name, AggregateKind::Mean, column)
+    }
+
+    fn aggregate(mut self, name: &'static str, kind: AggregateKind, column: &'static str) -> Self {
+        self.aggregates.push(Aggregate { name, kind, column });
+        self
+    }
+}
+
+/// The running totals of one group.
+#[cfg(feature = "pipeline")]
+struct Totals {
+    rows: u64,
+    // the sum and the number of values of each aggregated column
+    sums: Vec<(f64, u64)>,
+}
+
+#[cfg(feature = "pipeline")]
+impl Totals {
+    fn new(aggregates: usize) -> Self {
+        Totals {
+            rows: 0,
+            sums: vec![(0.0, 0); aggregates],
+        }
+    }
+
+    fn add(&mut self, record: &ByteRecord, columns: &[Option<usize>]) {
+        self.rows += 1;
+        for (total, column) in self.sums.iter_mut().zip(columns) {
+            let value = column
+                .and_then(|i| record.get(i))
+                .and_then(|field| std::str::from_utf8(field).ok()?.parse::<f64>().ok());
+            if let Some(value) = value {
+                total.0 += value;
+                total.1 += 1;
+            }
+        }
+    }
+
+    fn fields(&self, aggregates: &[Aggregate]) -> Vec<String> {
+        aggregates
+            .iter()
+            .zip(&self.sums)
+            .map(|(aggregate, &(sum, n))| match aggregate.kind {
+                AggregateKind::Count => self.rows.to_string(),
+                AggregateKind::Sum => sum.to_string(),
+                AggregateKind::Mean if n == 0 => String::new(),
+                AggregateKind::Mean => (sum / n as f64).to_string(),
+            })
+            .collect()
+    }
+}
+
+/// Replaces the CSV at `path` with its rows grouped by `group_by`, ordered by
+/// the key columns, holding at most about `max_bytes` of rows in memory and
+/// spilling the rest to `work_dir`.
+#[cfg(feature = "pipeline")]
+pub(crate) fn group_csv(
+    path: &Path,
+    group_by: &GroupBy,
+    max_bytes: usize,
+    comment: Option<&str>,
+    work_dir: &Path,
+) -> Result<()> {
+    let mut file = BufReader::new(File::open(path)?);
+    if comment.is_some() {
+        file.read_line(&mut String::new())?;
+    }
+    let mut reader = csv::Reader::from_reader(file);
+    let headers = reader.byte_headers()?.clone();
+    if headers.is_empty() {
+        // nothing was written
+        return Ok(());
+    }
+    let keys: Vec<String> = group_by.keys.iter().map(|&key| key.to_owned()).collect();
+    // only identical keys are in the same group
+    let key = SortKey::new(&headers, &keys)?.distinct();
+    let key_columns = keys
+        .iter()
+        .map(|name| headers.iter().position(|h| h == name.as_bytes()))
+        .collect::<Option<Vec<usize>>>()
+        .unwrap_or_default();
+    let columns = group_by
+        .aggregates
+        .iter()
+        .map(|aggregate| match aggregate.kind {
+            AggregateKind::Count => Ok(None),
+            _ => headers
+                .iter()
+                .position(|h| h == aggregate.column.as_bytes())
+                .map(Some)
+                .ok_or_else(|| anyhow!("no column named {} to aggregate", aggregate.column)),
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut buffer = SpillBuffer::new(work_dir, max_bytes, Some(key.clone()));
+    for record in reader.byte_records() {
+        buffer.push(record?)?;
+    }
+
+    let grouped_path = path.with_extension("csv.grouping");
+    let mut file = BufWriter::new(File::create(&grouped_path)?);
+    if let Some(comment) = comment {
+        writeln!(file, "{comment}")?;
+    }
+    let mut writer = csv::Writer::from_writer(file);
+    writer.write_record(
+        group_by
+            .keys
+            .iter()
+            .chain(group_by.aggregates.iter().map(|aggregate| &aggregate.name)),
+    )?;
+    let mut group: Option<(ByteRecord, Totals)> = None;
+    let grouped = |first: &ByteRecord, totals: &Totals| -> ByteRecord {
+        let mut record: ByteRecord = key_columns
+            .iter()
+            .map(|&i| first.get(i).unwrap_or_default())
+            .collect();
+        for field in totals.fields(&group_by.aggregates) {
+            record.push_field(field.as_bytes());
+        }
+        record
+    };
+    for record in buffer.into_records()? {
+        let record = record?;
+        match &mut group {
+            Some((first, totals)) if key.compare(first, &record).is_eq() => {
+                totals.add(&record, &columns);
+            }
+            _ => {
+                if let Some((first, totals)) = &group {
+                    writer.write_byte_record(&grouped(first, totals))?;
+                }
+                let mut totals = Totals::new(columns.len());
+                totals.add(&record, &columns);
+                group = Some((record, totals));
+            }
+        }
+    }
+    if let Some((first, totals)) = &group {
+        writer.write_byte_record(&grouped(first, totals))?;
+    }
+    writer.flush()?;
+    drop(writer);
+    fs::rename(&grouped_path, path)?;
+    Ok(())
+}
+
+#[cfg(all(test, feature = "pipeline"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn groups_rows() {
+        let dir = std::env::temp_dir().join(format!("pgn2csv-group-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("games.csv");
+        fs::write(
+            &path,
+            "speed,hour,rating\nBlitz,3,1500\nBullet,1,\nBlitz,3,1700\nBlitz,1,1000\n",
+        )
+        .unwrap();
+        let group_by = GroupBy::new(&["speed", "hour"])
+            .count("games")
+            .mean("rating", "average_rating");
+        group_csv(&path, &group_by, 1 << 20, None, &dir).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "speed,hour,games,average_rating\nBlitz,1,1,1000\nBlitz,3,2,1600\nBullet,1,1,\n"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
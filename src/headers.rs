@@ -1,10 +1,12 @@
+use std::{fmt, str::FromStr};
+
 use anyhow::{anyhow, Error, Result};
 use bstr::ByteSlice;
 use bstr_parse::BStrParse;
 use pgn_reader::RawHeader;
-use serde::Serialize;
+use serde::{Serialize, Serializer};
 
-#[derive(Default, Serialize)]
+#[derive(Clone, Copy, Default, Serialize)]
 pub struct Rating(u16);
 
 impl TryFrom<RawHeader<'_>> for Rating {
@@ -15,7 +17,85 @@ impl TryFrom<RawHeader<'_>> for Rating {
     }
 }
 
+impl Rating {
+    /// The bucket of `width` Elo points that the rating falls in, with buckets
+    /// starting at multiples of `width`, e.g. `2000-2199` for 2150 with width
+    /// 200.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `width` is zero.
+    #[must_use]
+    pub fn bucket(&self, width: u16) -> RatingBucket {
+        assert!(width > 0, "rating buckets must be at least 1 point wide");
+        RatingBucket {
+            lower: self.0 / width * width,
+            width,
+        }
+    }
+}
+
+/// A range of ratings, serialized as e.g. `2000-2199`, so that every
+/// processor bins ratings the same way. The default bucket is empty and
+/// serialized as an empty string.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RatingBucket {
+    lower: u16,
+    width: u16,
+}
+
+impl RatingBucket {
+    #[must_use]
+    pub fn lower(&self) -> u16 {
+        self.lower
+    }
+
+    /// The highest rating in the bucket.
+    #[must_use]
+    pub fn upper(&self) -> u16 {
+        self.lower.saturating_add(self.width.saturating_sub(1))
+    }
+}
+
+impl fmt::Display for RatingBucket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.width == 0 {
+            return Ok(());
+        }
+        write!(f, "{}-{}", self.lower, self.upper())
+    }
+}
+
+impl Serialize for RatingBucket {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Columns derived from the ratings of both players, from white's point of
+/// view. Copy the fields into your row, since CSV rows can't nest structs.
 #[derive(Default, Serialize)]
+pub struct Matchup {
+    /// White's rating minus black's.
+    pub rating_diff: i32,
+    pub average_rating: u16,
+    /// White's expected score under the Elo model, between 0 and 1.
+    pub expected_score: f64,
+}
+
+impl Matchup {
+    #[must_use]
+    pub fn new(white: &Rating, black: &Rating) -> Self {
+        let rating_diff = i32::from(white.0) - i32::from(black.0);
+        Matchup {
+            rating_diff,
+            average_rating: ((u32::from(white.0) + u32::from(black.0)) / 2) as u16,
+            expected_score: 1.0 / (1.0 + 10f64.powf(-f64::from(rating_diff) / 400.0)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, Serialize)]
 pub struct RatingDiff(i16);
 
 impl TryFrom<RawHeader<'_>> for RatingDiff {
@@ -35,6 +115,18 @@ pub struct TimeControl {
     pub increment: u32,
 }
 
+impl TimeControl {
+    /// The expected length of a game in seconds, assuming it lasts 40 moves:
+    /// the initial time plus 40 increments. Lichess classifies speeds by this,
+    /// so it compares odd time controls consistently. Durations too long for
+    /// a `u32` saturate at `u32::MAX`.
+    #[must_use]
+    pub fn estimated_duration_seconds(&self) -> u32 {
+        self.initial_time
+            .saturating_add(self.increment.saturating_mul(40))
+    }
+}
+
 impl TryFrom<RawHeader<'_>> for TimeControl {
     type Error = Error;
     fn try_from(value: RawHeader<'_>) -> Result<Self> {
@@ -49,16 +141,86 @@ impl TryFrom<RawHeader<'_>> for TimeControl {
     }
 }
 
-/// The variants are the possible values for Termination in lichess PGNs.
-#[derive(Default, Serialize)]
+/// The variants are the possible values for Termination in lichess PGNs. A run
+/// writes it in its [`TerminationEncoding`], as the stable code of each
+/// variant, or as its name in the header; elsewhere it serializes as its
+/// variant's name.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename = "pgn2csv::Termination")]
 pub enum Termination {
     #[default]
-    Normal,
-    TimeForfeit,
-    Abandoned,
-    RulesInfraction,
-    Unterminated,
-    Unknown,
+    Normal = 0,
+    TimeForfeit = 1,
+    Abandoned = 2,
+    RulesInfraction = 3,
+    Unterminated = 4,
+    Unknown = 5,
+}
+
+impl Termination {
+    /// The name that terminations serialize under, for runs to encode them.
+    #[cfg_attr(not(feature = "pipeline"), allow(dead_code))]
+    pub(crate) const SERDE_NAME: &'static str = "pgn2csv::Termination";
+
+    /// The termination whose code is `code`, which is also the index serde
+    /// numbers its variant with.
+    #[cfg_attr(not(feature = "pipeline"), allow(dead_code))]
+    pub(crate) fn from_code(code: u32) -> Option<Self> {
+        match code {
+            0 => Some(Termination::Normal),
+            1 => Some(Termination::TimeForfeit),
+            2 => Some(Termination::Abandoned),
+            3 => Some(Termination::RulesInfraction),
+            4 => Some(Termination::Unterminated),
+            5 => Some(Termination::Unknown),
+            _ => None,
+        }
+    }
+
+    /// The termination's code, which never changes: `0` for `Normal`, `1` for
+    /// `Time forfeit`, `2` for `Abandoned`, `3` for `Rules infraction`, `4`
+    /// for `Unterminated`, and `5` for `Unknown`.
+    #[must_use]
+    pub fn code(self) -> u8 {
+        self as u8
+    }
+
+    /// The termination as it is named in the header.
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Termination::Normal => "Normal",
+            Termination::TimeForfeit => "Time forfeit",
+            Termination::Abandoned => "Abandoned",
+            Termination::RulesInfraction => "Rules infraction",
+            Termination::Unterminated => "Unterminated",
+            Termination::Unknown => "Unknown",
+        }
+    }
+}
+
+/// How terminations are written. Each processor picks a default with
+/// `GameProcessor::termination_encoding`, and `--termination-encoding`
+/// overrides it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TerminationEncoding {
+    /// The stable codes of [`Termination::code`].
+    #[default]
+    Codes,
+    /// The names in the header, e.g. `Time forfeit`.
+    Names,
+}
+
+impl FromStr for TerminationEncoding {
+    type Err = Error;
+
+    fn from_str(encoding: &str) -> Result<Self> {
+        match encoding {
+            "codes" => Ok(TerminationEncoding::Codes),
+            "names" => Ok(TerminationEncoding::Names),
+            _ => Err(anyhow!("unknown termination encoding {encoding}")),
+        }
+    }
 }
 
 impl TryFrom<RawHeader<'_>> for Termination {
@@ -77,7 +239,11 @@ impl TryFrom<RawHeader<'_>> for Termination {
     }
 }
 
-#[derive(Default, Serialize)]
+/// A game's result. A run writes it in its [`ResultEncoding`], or as an empty
+/// field if the game is unfinished; elsewhere it serializes as its variant's
+/// name.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename = "pgn2csv::PgnResult")]
 pub enum PgnResult {
     WhiteWin,
     Draw,
@@ -86,6 +252,64 @@ pub enum PgnResult {
     Other,
 }
 
+impl PgnResult {
+    /// The name that results serialize under, for runs to encode them.
+    #[cfg_attr(not(feature = "pipeline"), allow(dead_code))]
+    pub(crate) const SERDE_NAME: &'static str = "pgn2csv::PgnResult";
+
+    /// The result that serde numbers `index`, in the order of the variants.
+    #[cfg_attr(not(feature = "pipeline"), allow(dead_code))]
+    pub(crate) fn from_variant_index(index: u32) -> Option<Self> {
+        match index {
+            0 => Some(PgnResult::WhiteWin),
+            1 => Some(PgnResult::Draw),
+            2 => Some(PgnResult::BlackWin),
+            3 => Some(PgnResult::Other),
+            _ => None,
+        }
+    }
+
+    /// The result with the colors swapped, for mirrored rows.
+    #[must_use]
+    pub fn swapped(self) -> Self {
+        match self {
+            PgnResult::WhiteWin => PgnResult::BlackWin,
+            PgnResult::BlackWin => PgnResult::WhiteWin,
+            result => result,
+        }
+    }
+}
+
+/// How results are written, so that every binary's are documented and can be
+/// made the same. Each processor picks a default with
+/// `GameProcessor::result_encoding`, and `--result-encoding` overrides it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ResultEncoding {
+    /// White's score: `1` for a white win, `0.5` for a draw, `0` for a loss.
+    #[default]
+    WhiteScore,
+    /// White's score in half points: `2`, `1`, or `0`.
+    HalfPoints,
+    /// `1` for a white win, `0` for a draw, `-1` for a black win.
+    Signed,
+    /// `white`, `draw`, or `black`.
+    Categorical,
+}
+
+impl FromStr for ResultEncoding {
+    type Err = Error;
+
+    fn from_str(encoding: &str) -> Result<Self> {
+        match encoding {
+            "white-score" => Ok(ResultEncoding::WhiteScore),
+            "half-points" => Ok(ResultEncoding::HalfPoints),
+            "signed" => Ok(ResultEncoding::Signed),
+            "categorical" => Ok(ResultEncoding::Categorical),
+            _ => Err(anyhow!("unknown result encoding {encoding}")),
+        }
+    }
+}
+
 impl TryFrom<RawHeader<'_>> for PgnResult {
     type Error = Error;
 
@@ -100,6 +324,743 @@ impl TryFrom<RawHeader<'_>> for PgnResult {
     }
 }
 
+/// The speed categories that lichess names in its Event headers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize)]
+pub enum Speed {
+    UltraBullet,
+    Bullet,
+    Blitz,
+    Rapid,
+    Classical,
+    Correspondence,
+    #[default]
+    Unknown,
+}
+
+impl From<&TimeControl> for Speed {
+    /// Classifies a time control the way lichess does, by its estimated
+    /// duration, e.g. `15+0` is UltraBullet and `2+1` is Bullet.
+    fn from(tc: &TimeControl) -> Self {
+        match tc.estimated_duration_seconds() {
+            0..30 => Speed::UltraBullet,
+            30..180 => Speed::Bullet,
+            180..480 => Speed::Blitz,
+            480..1500 => Speed::Rapid,
+            _ => Speed::Classical,
+        }
+    }
+}
+
+impl Speed {
+    /// The speed's name, as lichess writes it in Event headers.
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Speed::UltraBullet => "UltraBullet",
+            Speed::Bullet => "Bullet",
+            Speed::Blitz => "Blitz",
+            Speed::Rapid => "Rapid",
+            Speed::Classical => "Classical",
+            Speed::Correspondence => "Correspondence",
+            Speed::Unknown => "Unknown",
+        }
+    }
+
+    pub(crate) fn from_name(name: &[u8]) -> Option<Self> {
+        match name {
+            b"UltraBullet" => Some(Speed::UltraBullet),
+            b"Bullet" => Some(Speed::Bullet),
+            b"Blitz" => Some(Speed::Blitz),
+            b"Rapid" => Some(Speed::Rapid),
+            b"Classical" => Some(Speed::Classical),
+            b"Correspondence" => Some(Speed::Correspondence),
+            _ => None,
+        }
+    }
+}
+
+/// Where a game was played, as classified from its Event header.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub enum EventKind {
+    /// An ordinary game, e.g. from a seek or a challenge.
+    Game,
+    Arena,
+    Swiss,
+    /// An arena between teams, which lichess marks with `WhiteTeam` and
+    /// `BlackTeam` headers.
+    TeamBattle,
+    /// Anything that isn't a lichess event, e.g. an OTB tournament.
+    #[default]
+    Other,
+}
+
+/// A classified Event header. Lichess events look like `Rated Blitz game`,
+/// or `Rated Blitz tournament https://lichess.org/tournament/<id>` for arenas
+/// (`swiss` and `/swiss/` for swiss tournaments); other events are classified
+/// as `EventKind::Other`.
+#[derive(Default, Serialize)]
+pub struct Event {
+    /// The event without its URL, e.g. `Rated Blitz tournament`.
+    pub name: String,
+    pub rated: bool,
+    pub speed: Speed,
+    pub kind: EventKind,
+    /// The arena or swiss ID, from the event's URL.
+    pub tournament_id: Option<String>,
+}
+
+impl Event {
+    /// Marks an arena as a team battle. Call this when a game has a
+    /// `WhiteTeam` or `BlackTeam` header, since those come after `Event`.
+    pub fn set_team_battle(&mut self) {
+        if self.kind == EventKind::Arena {
+            self.kind = EventKind::TeamBattle;
+        }
+    }
+}
+
+impl TryFrom<RawHeader<'_>> for Event {
+    type Error = Error;
+
+    fn try_from(header: RawHeader<'_>) -> Result<Self> {
+        let event = header.as_bytes();
+        let (name, url) = match event.rfind(" https://") {
+            Some(i) => (&event[..i], Some(&event[i + 1..])),
+            None => (event, None),
+        };
+        let mut classified = Event {
+            name: String::from_utf8_lossy(name).into_owned(),
+            ..Event::default()
+        };
+        // tournaments are identified by their URL, even if they were given a
+        // name of their own
+        if let Some(url) = url {
+            let id = url.rsplit_str("/").next().filter(|id| !id.is_empty());
+            if url.contains_str("/tournament/") {
+                classified.kind = EventKind::Arena;
+            } else if url.contains_str("/swiss/") {
+                classified.kind = EventKind::Swiss;
+            }
+            if classified.kind != EventKind::Other {
+                classified.tournament_id = id.map(|id| String::from_utf8_lossy(id).into_owned());
+            }
+        }
+        let mut words = name.fields();
+        let (Some(mode), Some(speed), Some(kind)) = (words.next(), words.next(), words.next())
+        else {
+            return Ok(classified);
+        };
+        let rated = match mode {
+            b"Rated" => true,
+            b"Casual" => false,
+            _ => return Ok(classified),
+        };
+        let Some(speed) = Speed::from_name(speed) else {
+            return Ok(classified);
+        };
+        match kind {
+            b"game" => classified.kind = EventKind::Game,
+            b"tournament" | b"swiss" => (),
+            _ => return Ok(classified),
+        }
+        classified.rated = rated;
+        classified.speed = speed;
+        Ok(classified)
+    }
+}
+
+/// A `WhiteTeam` or `BlackTeam` header: the ID of the team a player
+/// represented in a team battle.
+#[derive(Default, Serialize)]
+pub struct Team(String);
+
+impl TryFrom<RawHeader<'_>> for Team {
+    type Error = Error;
+
+    fn try_from(header: RawHeader<'_>) -> Result<Self> {
+        Ok(Team(header.as_bytes().to_str()?.to_owned()))
+    }
+}
+
+impl Team {
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// A Round header: a round number, optionally with sub-rounds like `4.2` for
+/// e.g. the second game of a match in round 4, `-` where rounds don't apply,
+/// or `?` if the round is unknown. Numbered rounds are serialized with each
+/// part zero-padded to 4 digits, e.g. `0004.0002`, so that they sort correctly
+/// both as text and as numbers; the others are serialized as empty strings.
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Round {
+    NotApplicable,
+    #[default]
+    Unknown,
+    Numbered(Vec<u32>),
+}
+
+impl Round {
+    /// The round number, without sub-rounds.
+    #[must_use]
+    pub fn number(&self) -> Option<u32> {
+        match self {
+            Round::Numbered(parts) => parts.first().copied(),
+            _ => None,
+        }
+    }
+
+    /// The sub-round number, e.g. 2 for round `4.2`.
+    #[must_use]
+    pub fn sub_round(&self) -> Option<u32> {
+        match self {
+            Round::Numbered(parts) => parts.get(1).copied(),
+            _ => None,
+        }
+    }
+}
+
+impl TryFrom<RawHeader<'_>> for Round {
+    type Error = Error;
+
+    fn try_from(header: RawHeader<'_>) -> Result<Self> {
+        match header.as_bytes() {
+            b"-" => Ok(Round::NotApplicable),
+            b"?" | b"" => Ok(Round::Unknown),
+            round => {
+                let parts = round
+                    .split_str(".")
+                    .map(|part| part.parse::<u32>())
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| anyhow!("expected a round like 4 or 4.2"))?;
+                Ok(Round::Numbered(parts))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Round {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Round::Numbered(parts) = self else {
+            return Ok(());
+        };
+        for (i, part) in parts.iter().enumerate() {
+            if i > 0 {
+                f.write_str(".")?;
+            }
+            write!(f, "{part:04}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Serialize for Round {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// The text of a free-form header such as `Annotator` or `Source`, kept as
+/// is (with invalid UTF-8 replaced), so that e.g. the provenance of annotated
+/// collections can be carried into the CSV. A run writes it in its
+/// [`TextEncoding`](crate::text::TextEncoding), and it can hold other free text,
+/// like move lists and comments, too.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Text(String);
+
+impl Serialize for Text {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        crate::text::serialize(&self.0, serializer)
+    }
+}
+
+impl From<String> for Text {
+    fn from(text: String) -> Self {
+        Text(text)
+    }
+}
+
+impl From<RawHeader<'_>> for Text {
+    fn from(header: RawHeader<'_>) -> Self {
+        Text(String::from_utf8_lossy(header.as_bytes()).into_owned())
+    }
+}
+
+impl Text {
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// A PGN date like `1992.11.04`, such as a `SourceDate`, in which any part may
+/// be unknown (`??`). It is serialized as much of an ISO date as is known,
+/// e.g. `1992-11-04`, `1992-11`, or `1992`, and as an empty string if the
+/// year is unknown.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PgnDate {
+    pub year: Option<u16>,
+    pub month: Option<u8>,
+    pub day: Option<u8>,
+}
+
+impl TryFrom<RawHeader<'_>> for PgnDate {
+    type Error = Error;
+
+    fn try_from(header: RawHeader<'_>) -> Result<Self> {
+        let mut parts = header.as_bytes().split_str(".");
+        let (Some(year), Some(month), Some(day), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(anyhow!("expected a date like 1992.11.04"));
+        };
+        fn part<T: std::str::FromStr>(part: &[u8]) -> Result<Option<T>> {
+            if part.iter().all(|&b| b == b'?') {
+                return Ok(None);
+            }
+            part.to_str()?
+                .parse()
+                .map(Some)
+                .map_err(|_| anyhow!("expected a date like 1992.11.04"))
+        }
+        Ok(PgnDate {
+            year: part(year)?,
+            month: part(month)?,
+            day: part(day)?,
+        })
+    }
+}
+
+impl PgnDate {
+    /// The number of days since 1970-01-01, if the whole date is known.
+    #[must_use]
+    pub fn days_since_epoch(&self) -> Option<i64> {
+        let (year, month, day) = (
+            i64::from(self.year?),
+            i64::from(self.month?),
+            i64::from(self.day?),
+        );
+        let year = if month <= 2 { year - 1 } else { year };
+        let era = year.div_euclid(400);
+        let year_of_era = year - era * 400;
+        let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        Some(era * 146_097 + day_of_era - 719_468)
+    }
+
+    /// The date `days` days after 1970-01-01.
+    #[must_use]
+    pub fn from_days_since_epoch(days: i64) -> Self {
+        let days = days + 719_468;
+        let era = days.div_euclid(146_097);
+        let day_of_era = days - era * 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+        let month = if shifted_month < 10 {
+            shifted_month + 3
+        } else {
+            shifted_month - 9
+        };
+        let year = year_of_era + era * 400 + i64::from(month <= 2);
+        PgnDate {
+            year: u16::try_from(year).ok(),
+            month: u8::try_from(month).ok(),
+            day: u8::try_from(day).ok(),
+        }
+    }
+
+    /// The day of the week, from 0 for Monday to 6 for Sunday, if the whole
+    /// date is known.
+    #[must_use]
+    pub fn weekday(&self) -> Option<u8> {
+        // 1970-01-01 was a Thursday
+        u8::try_from((self.days_since_epoch()? + 3).rem_euclid(7)).ok()
+    }
+
+    /// Seconds since the Unix epoch at `time` on this date, e.g. for a game's
+    /// `UTCDate` and `UTCTime`, if the whole date is known.
+    #[must_use]
+    pub fn timestamp(&self, time: &PgnTime) -> Option<i64> {
+        Some(self.days_since_epoch()? * 86_400 + i64::from(time.seconds()))
+    }
+}
+
+/// A PGN time like `23:05:59`, such as a `UTCTime`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct PgnTime {
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl TryFrom<RawHeader<'_>> for PgnTime {
+    type Error = Error;
+
+    fn try_from(header: RawHeader<'_>) -> Result<Self> {
+        let error = || anyhow!("expected a time like 23:05:59");
+        let mut parts = header.as_bytes().split_str(":");
+        let (Some(hour), Some(minute), Some(second), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(error());
+        };
+        let time = PgnTime {
+            hour: hour.parse::<u8>().map_err(|_| error())?,
+            minute: minute.parse::<u8>().map_err(|_| error())?,
+            second: second.parse::<u8>().map_err(|_| error())?,
+        };
+        if time.hour > 23 || time.minute > 59 || time.second > 60 {
+            return Err(error());
+        }
+        Ok(time)
+    }
+}
+
+impl PgnTime {
+    /// Seconds since midnight.
+    #[must_use]
+    pub fn seconds(&self) -> u32 {
+        u32::from(self.hour) * 3600 + u32::from(self.minute) * 60 + u32::from(self.second)
+    }
+}
+
+/// Which headers a game's date and time are taken from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DateSource {
+    /// `UTCDate` and `UTCTime`, or `Date` and `Time` when the UTC date is
+    /// missing or unknown, as in most PGNs from outside lichess, which only
+    /// have the local tags.
+    #[default]
+    PreferUtc,
+    /// Only `UTCDate` and `UTCTime`.
+    Utc,
+    /// Only `Date` and `Time`, which are in the local time of wherever the
+    /// game was played.
+    Local,
+}
+
+/// Collects a game's date and time headers, to give its date and time from
+/// whichever of them its `DateSource` prefers. Feed it every header, and
+/// `reset` it when a game begins.
+#[derive(Clone, Debug, Default)]
+pub struct GameDateTime {
+    source: DateSource,
+    utc: (Option<PgnDate>, Option<PgnTime>),
+    local: (Option<PgnDate>, Option<PgnTime>),
+}
+
+impl GameDateTime {
+    #[must_use]
+    pub fn new(source: DateSource) -> Self {
+        GameDateTime {
+            source,
+            ..GameDateTime::default()
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = GameDateTime::new(self.source);
+    }
+
+    /// Records `value` if `key` is a date or time header, returning whether it
+    /// was one. Malformed values are treated as missing.
+    pub fn header(&mut self, key: &[u8], value: RawHeader<'_>) -> bool {
+        match key {
+            b"UTCDate" => self.utc.0 = PgnDate::try_from(value).ok(),
+            b"UTCTime" => self.utc.1 = PgnTime::try_from(value).ok(),
+            b"Date" => self.local.0 = PgnDate::try_from(value).ok(),
+            b"Time" => self.local.1 = PgnTime::try_from(value).ok(),
+            _ => return false,
+        }
+        true
+    }
+
+    /// The date and time headers used, and whether they are the local ones. A
+    /// date and a time are never mixed from different headers, since they can
+    /// be in different time zones.
+    fn chosen(&self) -> ((Option<PgnDate>, Option<PgnTime>), bool) {
+        match self.source {
+            DateSource::Utc => (self.utc, false),
+            DateSource::Local => (self.local, true),
+            DateSource::PreferUtc if self.utc.0.is_some_and(|date| date.year.is_some()) => {
+                (self.utc, false)
+            }
+            DateSource::PreferUtc => (self.local, true),
+        }
+    }
+
+    #[must_use]
+    pub fn date(&self) -> Option<PgnDate> {
+        let ((date, _), _) = self.chosen();
+        date
+    }
+
+    #[must_use]
+    pub fn time(&self) -> Option<PgnTime> {
+        let ((_, time), _) = self.chosen();
+        time
+    }
+
+    /// Seconds since the Unix epoch when the game started (see
+    /// `PgnDate::timestamp`), if its whole date and its time are known.
+    #[must_use]
+    pub fn timestamp(&self) -> Option<i64> {
+        match self.chosen().0 {
+            (Some(date), Some(time)) => date.timestamp(&time),
+            _ => None,
+        }
+    }
+
+    /// Like `timestamp`, but in UTC whichever headers it comes from, assuming
+    /// that `Date` and `Time` are `local_offset` seconds ahead of UTC.
+    #[must_use]
+    pub fn utc_timestamp(&self, local_offset: i32) -> Option<i64> {
+        let timestamp = self.timestamp()?;
+        Some(if self.chosen().1 {
+            timestamp - i64::from(local_offset)
+        } else {
+            timestamp
+        })
+    }
+}
+
+/// Formats seconds since the Unix epoch as an ISO 8601 UTC timestamp, like
+/// `2024-01-05T18:04:09Z`.
+#[must_use]
+pub fn iso_8601(timestamp: i64) -> String {
+    let date = PgnDate::from_days_since_epoch(timestamp.div_euclid(86_400));
+    let seconds = timestamp.rem_euclid(86_400);
+    format!(
+        "{date}T{:02}:{:02}:{:02}Z",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+impl fmt::Display for PgnDate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some(year) = self.year else {
+            return Ok(());
+        };
+        write!(f, "{year:04}")?;
+        let Some(month) = self.month else {
+            return Ok(());
+        };
+        write!(f, "-{month:02}")?;
+        match self.day {
+            Some(day) => write!(f, "-{day:02}"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Serialize for PgnDate {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// The values of the Mode header in the PGN spec.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub enum Mode {
+    /// Over the board.
+    Otb,
+    /// Paper mail.
+    Pm,
+    /// Electronic mail.
+    Em,
+    /// Internet Chess Server, used by e.g. FICS and ICC exports.
+    Ics,
+    /// General telecommunication.
+    Tc,
+    #[default]
+    Unknown,
+}
+
+impl TryFrom<RawHeader<'_>> for Mode {
+    type Error = Error;
+
+    fn try_from(header: RawHeader<'_>) -> Result<Self> {
+        match header.as_bytes() {
+            b"OTB" => Ok(Mode::Otb),
+            b"PM" => Ok(Mode::Pm),
+            b"EM" => Ok(Mode::Em),
+            b"ICS" => Ok(Mode::Ics),
+            b"TC" => Ok(Mode::Tc),
+            _ => Err(anyhow!("unexpected mode")),
+        }
+    }
+}
+
+/// A `WhiteIsComp` or `BlackIsComp` header, with which servers like FICS mark
+/// computer accounts: `Yes` or `No`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct IsComp(bool);
+
+impl TryFrom<RawHeader<'_>> for IsComp {
+    type Error = Error;
+
+    fn try_from(header: RawHeader<'_>) -> Result<Self> {
+        match header.as_bytes() {
+            b"Yes" | b"yes" | b"1" => Ok(IsComp(true)),
+            b"No" | b"no" | b"0" => Ok(IsComp(false)),
+            _ => Err(anyhow!("expected Yes or No")),
+        }
+    }
+}
+
+impl From<IsComp> for bool {
+    fn from(is_comp: IsComp) -> Self {
+        is_comp.0
+    }
+}
+
+/// A `WhiteType` or `BlackType` header, which the PGN spec uses to tell
+/// engines from people.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub enum PlayerType {
+    Human,
+    Program,
+    #[default]
+    Unknown,
+}
+
+impl TryFrom<RawHeader<'_>> for PlayerType {
+    type Error = Error;
+
+    fn try_from(header: RawHeader<'_>) -> Result<Self> {
+        match header.as_bytes() {
+            b"human" => Ok(PlayerType::Human),
+            b"program" => Ok(PlayerType::Program),
+            _ => Err(anyhow!("expected human or program")),
+        }
+    }
+}
+
+/// How a player is marked as something other than a person, normalized from
+/// the headers of different servers: `bot` for lichess's `BOT` title, and
+/// `computer` for `WhiteIsComp`/`BlackIsComp` or a `program` player type.
+/// Serialized in lowercase.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlayerFlag {
+    Bot,
+    Computer,
+    /// Not marked, which doesn't mean that the player is a person.
+    #[default]
+    Unknown,
+}
+
+impl PlayerFlag {
+    /// Combines the flags of two headers, preferring the most specific.
+    fn or(self, other: PlayerFlag) -> PlayerFlag {
+        match (self, other) {
+            (PlayerFlag::Bot, _) | (_, PlayerFlag::Unknown) => self,
+            _ => other,
+        }
+    }
+}
+
+/// Collects the headers that mark the players as bots or computers into a
+/// `PlayerFlag` for each, so that engine games can be left out or studied
+/// the same way whichever server they come from. Feed it every header, and
+/// `reset` it when a game begins.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PlayerFlags {
+    pub white: PlayerFlag,
+    pub black: PlayerFlag,
+}
+
+impl PlayerFlags {
+    pub fn reset(&mut self) {
+        *self = PlayerFlags::default();
+    }
+
+    /// Records `value` if `key` is a header that can flag a player, returning
+    /// whether it was one. Values that don't flag the player are ignored.
+    pub fn header(&mut self, key: &[u8], value: RawHeader<'_>) -> bool {
+        let (player, header) = match key {
+            b"WhiteTitle" | b"WhiteIsComp" | b"WhiteType" => (&mut self.white, &key[5..]),
+            b"BlackTitle" | b"BlackIsComp" | b"BlackType" => (&mut self.black, &key[5..]),
+            _ => return false,
+        };
+        let flag = match header {
+            b"Title" if value.as_bytes().eq_ignore_ascii_case(b"BOT") => PlayerFlag::Bot,
+            b"IsComp" if IsComp::try_from(value).is_ok_and(bool::from) => PlayerFlag::Computer,
+            b"Type" if PlayerType::try_from(value).ok() == Some(PlayerType::Program) => {
+                PlayerFlag::Computer
+            }
+            _ => PlayerFlag::Unknown,
+        };
+        *player = player.or(flag);
+        true
+    }
+
+    /// Whether either player is flagged.
+    #[must_use]
+    pub fn any(&self) -> bool {
+        self.white != PlayerFlag::Unknown || self.black != PlayerFlag::Unknown
+    }
+}
+
+/// A FEN header, for games that start from a custom position, such as
+/// material-odds games. Only the piece placement is checked.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Fen(String);
+
+impl TryFrom<RawHeader<'_>> for Fen {
+    type Error = Error;
+
+    fn try_from(header: RawHeader<'_>) -> Result<Self> {
+        let fen = header.as_bytes().to_str()?;
+        let placement = fen.split(' ').next().unwrap_or_default();
+        if placement.split('/').count() != 8
+            || !placement
+                .bytes()
+                .all(|b| b == b'/' || b.is_ascii_digit() || b"pnbrqkPNBRQK".contains(&b))
+        {
+            return Err(anyhow!("expected a FEN"));
+        }
+        Ok(Fen(fen.to_owned()))
+    }
+}
+
+impl Fen {
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The material of white and black in pawns, counting knights and bishops
+    /// as 3, rooks as 5, and queens as 9, so 39 each in the initial position.
+    #[must_use]
+    pub fn material(&self) -> (u32, u32) {
+        let placement = self.0.split(' ').next().unwrap_or_default();
+        let mut material = (0, 0);
+        for piece in placement.bytes() {
+            let value = match piece.to_ascii_lowercase() {
+                b'p' => 1,
+                b'n' | b'b' => 3,
+                b'r' => 5,
+                b'q' => 9,
+                _ => continue,
+            };
+            if piece.is_ascii_uppercase() {
+                material.0 += value;
+            } else {
+                material.1 += value;
+            }
+        }
+        material
+    }
+}
+
 //#[derive(Default, Serialize)]
 //pub struct Player(String);
 //
@@ -110,3 +1071,176 @@ impl TryFrom<RawHeader<'_>> for PgnResult {
 //        Ok(Player(value.as_bytes().parse::<String>()?))
 //    }
 //}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_ratings() {
+        assert_eq!(Rating(2150).bucket(200).to_string(), "2000-2199");
+        assert_eq!(Rating(2000).bucket(200).to_string(), "2000-2199");
+        assert_eq!(Rating(1999).bucket(100).to_string(), "1900-1999");
+        assert_eq!(RatingBucket::default().to_string(), "");
+    }
+
+    #[test]
+    fn encodes_terminations() {
+        let terminations = [
+            Termination::Normal,
+            Termination::TimeForfeit,
+            Termination::Abandoned,
+            Termination::RulesInfraction,
+            Termination::Unterminated,
+            Termination::Unknown,
+        ];
+        for (code, termination) in terminations.into_iter().enumerate() {
+            assert_eq!(usize::from(termination.code()), code);
+            let header = RawHeader(termination.name().as_bytes());
+            assert_eq!(Termination::try_from(header).unwrap(), termination);
+        }
+    }
+
+    #[test]
+    fn classifies_speeds() {
+        let speed = |tc: &[u8]| Speed::from(&TimeControl::try_from(RawHeader(tc)).unwrap());
+        assert_eq!(speed(b"15+0"), Speed::UltraBullet);
+        assert_eq!(speed(b"30+0"), Speed::Bullet);
+        assert_eq!(speed(b"120+1"), Speed::Bullet);
+        assert_eq!(speed(b"180+0"), Speed::Blitz);
+        assert_eq!(speed(b"600+5"), Speed::Rapid);
+        assert_eq!(speed(b"1800+0"), Speed::Classical);
+        assert_eq!(speed(b"29+0"), Speed::UltraBullet);
+        assert_eq!(speed(b"179+0"), Speed::Bullet);
+        assert_eq!(speed(b"140+1"), Speed::Blitz);
+        assert_eq!(speed(b"479+0"), Speed::Blitz);
+        assert_eq!(speed(b"480+0"), Speed::Rapid);
+        assert_eq!(speed(b"440+1"), Speed::Rapid);
+        assert_eq!(speed(b"4294967295+1"), Speed::Classical);
+        let tc = TimeControl::try_from(RawHeader(b"4294967295+1")).unwrap();
+        assert_eq!(tc.estimated_duration_seconds(), u32::MAX);
+        let tc = TimeControl::try_from(RawHeader(b"0+4294967295")).unwrap();
+        assert_eq!(tc.estimated_duration_seconds(), u32::MAX);
+    }
+
+    #[test]
+    fn classifies_events() {
+        let arena = Event::try_from(RawHeader(
+            b"Rated Blitz tournament https://lichess.org/tournament/abcd1234",
+        ))
+        .unwrap();
+        assert!(arena.rated);
+        assert_eq!(arena.speed, Speed::Blitz);
+        assert_eq!(arena.kind, EventKind::Arena);
+        assert_eq!(arena.tournament_id.as_deref(), Some("abcd1234"));
+        let mut battle = arena;
+        battle.set_team_battle();
+        assert_eq!(battle.kind, EventKind::TeamBattle);
+        let casual = Event::try_from(RawHeader(b"Casual Correspondence game")).unwrap();
+        assert!(!casual.rated);
+        assert_eq!(casual.kind, EventKind::Game);
+        assert_eq!(casual.tournament_id, None);
+        let otb = Event::try_from(RawHeader(b"Tata Steel Masters")).unwrap();
+        assert_eq!(otb.kind, EventKind::Other);
+        let named = Event::try_from(RawHeader(
+            b"Hourly Bullet Arena https://lichess.org/tournament/x1",
+        ))
+        .unwrap();
+        assert_eq!(named.kind, EventKind::Arena);
+        assert_eq!(named.name, "Hourly Bullet Arena");
+        assert_eq!(named.tournament_id.as_deref(), Some("x1"));
+    }
+
+    #[test]
+    fn parses_rounds() {
+        let round = |s: &[u8]| Round::try_from(RawHeader(s)).unwrap();
+        assert_eq!(round(b"4").to_string(), "0004");
+        assert_eq!(round(b"4.2").to_string(), "0004.0002");
+        assert_eq!(round(b"4.2").sub_round(), Some(2));
+        assert!(round(b"4.2") < round(b"4.10"));
+        assert_eq!(round(b"-"), Round::NotApplicable);
+        assert_eq!(round(b"?").to_string(), "");
+        assert!(Round::try_from(RawHeader(b"4a")).is_err());
+    }
+
+    #[test]
+    fn parses_dates() {
+        let date = |s: &[u8]| PgnDate::try_from(RawHeader(s)).unwrap().to_string();
+        assert_eq!(date(b"1992.11.04"), "1992-11-04");
+        assert_eq!(date(b"1992.??.??"), "1992");
+        assert_eq!(date(b"1992.11.??"), "1992-11");
+        assert_eq!(date(b"????.??.??"), "");
+        assert!(PgnDate::try_from(RawHeader(b"1992-11-04")).is_err());
+        let weekday = |s: &[u8]| PgnDate::try_from(RawHeader(s)).unwrap().weekday();
+        assert_eq!(weekday(b"1992.11.04"), Some(2));
+        assert_eq!(weekday(b"2000.01.01"), Some(5));
+        assert_eq!(weekday(b"2024.02.29"), Some(3));
+        assert_eq!(weekday(b"1992.11.??"), None);
+        let time = PgnTime::try_from(RawHeader(b"00:01:05")).unwrap();
+        let date = PgnDate::try_from(RawHeader(b"1970.01.02")).unwrap();
+        assert_eq!(date.timestamp(&time), Some(86_465));
+        assert!(PgnTime::try_from(RawHeader(b"24:00:00")).is_err());
+        assert_eq!(Text::from(RawHeader(b"Kasparov")).as_str(), "Kasparov");
+    }
+
+    #[test]
+    fn falls_back_to_local_dates() {
+        let mut game = GameDateTime::default();
+        game.header(b"UTCDate", RawHeader(b"????.??.??"));
+        game.header(b"Date", RawHeader(b"1970.01.02"));
+        game.header(b"Time", RawHeader(b"00:01:05"));
+        assert_eq!(game.timestamp(), Some(86_465));
+        game.header(b"UTCDate", RawHeader(b"1970.01.01"));
+        // the UTC date is now known, but not the UTC time
+        assert_eq!(game.timestamp(), None);
+        let mut game = GameDateTime::new(DateSource::Utc);
+        game.header(b"Date", RawHeader(b"1970.01.02"));
+        assert_eq!(game.date(), None);
+        let mut game = GameDateTime::default();
+        game.header(b"Date", RawHeader(b"2024.02.29"));
+        game.header(b"Time", RawHeader(b"00:30:00"));
+        assert_eq!(
+            iso_8601(game.utc_timestamp(3600).unwrap()),
+            "2024-02-28T23:30:00Z"
+        );
+    }
+
+    #[test]
+    fn parses_legacy_headers() {
+        assert_eq!(Mode::try_from(RawHeader(b"ICS")).unwrap(), Mode::Ics);
+        assert!(bool::from(IsComp::try_from(RawHeader(b"Yes")).unwrap()));
+        assert!(!bool::from(IsComp::try_from(RawHeader(b"No")).unwrap()));
+        assert_eq!(
+            PlayerType::try_from(RawHeader(b"program")).unwrap(),
+            PlayerType::Program
+        );
+        assert!(Mode::try_from(RawHeader(b"Blitz")).is_err());
+    }
+
+    #[test]
+    fn flags_players() {
+        let mut flags = PlayerFlags::default();
+        flags.header(b"WhiteTitle", RawHeader(b"GM"));
+        flags.header(b"BlackIsComp", RawHeader(b"Yes"));
+        assert_eq!(flags.white, PlayerFlag::Unknown);
+        assert_eq!(flags.black, PlayerFlag::Computer);
+        flags.header(b"BlackTitle", RawHeader(b"BOT"));
+        flags.header(b"BlackType", RawHeader(b"program"));
+        assert_eq!(flags.black, PlayerFlag::Bot);
+        assert!(flags.any());
+        flags.reset();
+        assert!(!flags.any());
+        assert!(!flags.header(b"White", RawHeader(b"BOT")));
+    }
+
+    #[test]
+    fn matchups() {
+        let even = Matchup::new(&Rating(1500), &Rating(1500));
+        assert_eq!(even.rating_diff, 0);
+        assert!((even.expected_score - 0.5).abs() < 1e-9);
+        let favored = Matchup::new(&Rating(1900), &Rating(1500));
+        assert_eq!(favored.rating_diff, 400);
+        assert_eq!(favored.average_rating, 1700);
+        assert!((favored.expected_score - 10.0 / 11.0).abs() < 1e-9);
+    }
+}
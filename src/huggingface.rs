@@ -0,0 +1,109 @@
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::{anyhow, Result};
+use serde_json::json;
+
+/// The directory of a Hugging Face dataset that holds its parquet shards.
+const DATA: &str = "data";
+
+#[derive(Default)]
+struct Shards {
+    // the name and dtype of each column, from the first shard with rows
+    features: Vec<(String, &'static str)>,
+    rows: u64,
+}
+
+/// A dataset in the layout that `datasets.load_dataset` expects: one parquet
+/// shard per PGN under `data/`, all in the `train` split, described by a
+/// README with a dataset card header and by `dataset_infos.json`.
+pub(crate) struct Dataset {
+    shards: Mutex<Shards>,
+}
+
+impl Dataset {
+    pub(crate) fn new(dir: &Path) -> Result<Self> {
+        fs::create_dir_all(dir.join(DATA))?;
+        Ok(Dataset {
+            shards: Mutex::new(Shards::default()),
+        })
+    }
+
+    /// The path of the shard that the rows of the PGN named `pgn` go to.
+    pub(crate) fn shard_path(dir: &Path, pgn: &str) -> PathBuf {
+        let stem = pgn.split('.').next().unwrap_or(pgn);
+        dir.join(DATA).join(format!("train-{stem}.parquet"))
+    }
+
+    pub(crate) fn add_shard(
+        &self,
+        features: impl Iterator<Item = (String, &'static str)>,
+        rows: u64,
+    ) {
+        if let Ok(mut shards) = self.shards.lock() {
+            if shards.features.is_empty() {
+                shards.features = features.collect();
+            }
+            shards.rows += rows;
+        }
+    }
+
+    /// Writes the README and `dataset_infos.json`, once every shard is done.
+    pub(crate) fn write(&self, dir: &Path) -> Result<()> {
+        let shards = self
+            .shards
+            .lock()
+            .map_err(|_| anyhow!("dataset lock poisoned"))?;
+        let name = dir
+            .canonicalize()?
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+
+        let mut readme = BufWriter::new(File::create(dir.join("README.md"))?);
+        writeln!(readme, "---")?;
+        writeln!(readme, "configs:")?;
+        writeln!(readme, "- config_name: default")?;
+        writeln!(readme, "  data_files:")?;
+        writeln!(readme, "  - split: train")?;
+        writeln!(readme, "    path: {DATA}/train-*")?;
+        writeln!(readme, "dataset_info:")?;
+        writeln!(readme, "  features:")?;
+        for (feature, dtype) in &shards.features {
+            writeln!(readme, "  - name: {feature}")?;
+            writeln!(readme, "    dtype: {dtype}")?;
+        }
+        writeln!(readme, "  splits:")?;
+        writeln!(readme, "  - name: train")?;
+        writeln!(readme, "    num_examples: {}", shards.rows)?;
+        writeln!(readme, "---")?;
+        writeln!(readme)?;
+        writeln!(readme, "# {name}")?;
+        writeln!(readme)?;
+        writeln!(readme, "Chess games converted from PGN with pgn2csv.")?;
+        readme.flush()?;
+
+        let features: serde_json::Map<_, _> = shards
+            .features
+            .iter()
+            .map(|(feature, dtype)| (feature.clone(), json!({ "dtype": dtype, "_type": "Value" })))
+            .collect();
+        let infos = json!({
+            "default": {
+                "config_name": "default",
+                "features": features,
+                "splits": {
+                    "train": { "name": "train", "num_examples": shards.rows },
+                },
+            },
+        });
+        let writer = BufWriter::new(File::create(dir.join("dataset_infos.json"))?);
+        serde_json::to_writer_pretty(writer, &infos)?;
+        Ok(())
+    }
+}
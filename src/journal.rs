@@ -0,0 +1,106 @@
+use std::{
+    collections::HashSet,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::{Context, Result};
+
+const JOURNAL: &str = ".pgn2csv.journal";
+
+/// The outputs of an output directory that runs have finished writing, one
+/// path relative to it per line, so that a run that crashed can be resumed without
+/// converting them again. An output is added once it and everything written
+/// after it, e.g. its sorted rows, is complete. A run that doesn't resume
+/// starts the journal over, since it rewrites the outputs.
+pub(crate) struct Journal {
+    dir: PathBuf,
+    path: PathBuf,
+    done: HashSet<PathBuf>,
+    // opened when the first output is added
+    file: Mutex<Option<File>>,
+}
+
+impl Journal {
+    /// Opens the journal of `dir`, reading the outputs it lists if the run
+    /// is resumed, and removing it otherwise.
+    pub(crate) fn open(dir: &Path, resume: bool) -> Result<Self> {
+        let path = dir.join(JOURNAL);
+        let done = match (resume, fs::read_to_string(&path)) {
+            (true, Ok(journal)) => journal.lines().map(PathBuf::from).collect(),
+            (true, Err(e)) if e.kind() == io::ErrorKind::NotFound => HashSet::new(),
+            (true, Err(e)) => return Err(e).with_context(|| format!("reading {}", path.display())),
+            (false, _) => {
+                if path.exists() {
+                    fs::remove_file(&path)?;
+                }
+                HashSet::new()
+            }
+        };
+        Ok(Journal {
+            dir: dir.to_path_buf(),
+            path,
+            done,
+            file: Mutex::new(None),
+        })
+    }
+
+    /// Whether a previous run finished writing `output`.
+    pub(crate) fn is_done(&self, output: &Path) -> bool {
+        self.done.contains(self.relative(output))
+    }
+
+    fn relative<'a>(&self, output: &'a Path) -> &'a Path {
+        output.strip_prefix(&self.dir).unwrap_or(output)
+    }
+
+    /// Adds `output`, once it has been completely written.
+    pub(crate) fn add(&self, output: &Path) -> Result<()> {
+        let mut file = self
+            .file
+            .lock()
+            .map_err(|_| anyhow::anyhow!("journal lock poisoned"))?;
+        if file.is_none() {
+            let opened = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+                .with_context(|| format!("opening {}", self.path.display()))?;
+            *file = Some(opened);
+        }
+        let file = file.as_mut().expect("journal was just opened");
+        writeln!(file, "{}", self.relative(output).display())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resumes_runs() {
+        let dir = std::env::temp_dir().join(format!("pgn2csv-journal-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let journal = Journal::open(&dir, false).unwrap();
+        journal.add(&dir.join("a.csv")).unwrap();
+        drop(journal);
+        assert_eq!(fs::read_to_string(dir.join(JOURNAL)).unwrap(), "a.csv\n");
+        let journal = Journal::open(&dir, true).unwrap();
+        assert!(journal.is_done(&dir.join("a.csv")));
+        assert!(!journal.is_done(&dir.join("b.csv")));
+        journal.add(&dir.join("b.csv")).unwrap();
+        drop(journal);
+        assert!(Journal::open(&dir, true)
+            .unwrap()
+            .is_done(&dir.join("b.csv")));
+        // a run that doesn't resume starts over
+        Journal::open(&dir, false).unwrap();
+        assert!(!Journal::open(&dir, true)
+            .unwrap()
+            .is_done(&dir.join("a.csv")));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,182 +1,237 @@
+pub mod analyzers;
+#[cfg(feature = "pipeline")]
+mod args;
+#[cfg(feature = "pipeline")]
+mod assertions;
+#[cfg(any(feature = "msgpack", feature = "cbor"))]
+mod binary;
+pub mod boolean;
+#[cfg(feature = "pipeline")]
+mod builder;
+#[cfg(feature = "parquet")]
+mod columnar;
+#[cfg(feature = "pipeline")]
+mod columns;
 pub mod comments;
+#[cfg(feature = "pipeline")]
+mod counts;
+#[cfg(feature = "duckdb")]
+mod database;
+#[cfg(feature = "pipeline")]
+mod dedup;
+#[cfg(feature = "pipeline")]
+mod describe;
+#[cfg(feature = "download")]
+mod download;
+#[cfg(feature = "pipeline")]
+mod encoding;
+#[cfg(feature = "pipeline")]
+mod fallback;
+#[cfg(feature = "pipeline")]
+mod game;
+pub mod group;
 pub mod headers;
+#[cfg(feature = "parquet")]
+mod huggingface;
+#[cfg(feature = "pipeline")]
+mod journal;
+#[cfg(feature = "pipeline")]
+mod lock;
+#[cfg(feature = "pipeline")]
+mod log;
+#[cfg(feature = "pipeline")]
+mod manifest;
+#[cfg(feature = "pipeline")]
+mod merge;
+#[cfg(feature = "pipeline")]
+mod normalize;
+#[cfg(feature = "openings")]
+pub mod openings;
+#[cfg(feature = "pipeline")]
+mod output;
+pub mod partition;
+#[cfg(feature = "pipeline")]
+mod preview;
+#[cfg(feature = "pipeline")]
+mod profile;
+#[cfg(feature = "pipeline")]
+mod ratings;
+#[cfg(feature = "pipeline")]
+mod reader;
+#[cfg(feature = "pipeline")]
+mod record;
+#[cfg(feature = "pipeline")]
+mod run;
+#[cfg(feature = "pipeline")]
+mod scan;
+#[cfg(feature = "pipeline")]
+mod segment;
+pub mod sessions;
+#[cfg(feature = "pipeline")]
+pub mod sink;
+#[cfg(feature = "pipeline")]
+mod sort;
+#[cfg(feature = "pipeline")]
+pub mod source;
+#[cfg(feature = "pipeline")]
+mod spill;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+#[cfg(feature = "pipeline")]
+mod stats;
+#[cfg(feature = "pipeline")]
+pub mod testing;
+pub mod text;
+#[cfg(feature = "pipeline")]
+mod tournaments;
+pub mod uid;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+mod uring;
+#[cfg(feature = "pipeline")]
+mod watchdog;
+#[cfg(feature = "pipeline")]
+mod workdir;
+#[cfg(feature = "xlsx")]
+mod xlsx;
 
-use std::{
-    fs::{create_dir, File},
-    io::Read,
-    path::{Path, PathBuf},
-};
-
-use anyhow::Result;
-use bzip2::read::MultiBzDecoder;
-use globwalk::{DirEntry, GlobWalkerBuilder};
-use indicatif::{ParallelProgressIterator, ProgressBar, ProgressStyle};
-use pgn_reader::{BufferedReader, Visitor};
-use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 use serde::Serialize;
-use zstd::stream::read::Decoder as ZstdDecoder;
 
-enum Compression {
-    None,
-    Bzip2,
-    Zstd,
+use crate::{
+    group::GroupBy,
+    headers::{ResultEncoding, TerminationEncoding},
+    partition::Partition,
+    sessions::Sessions,
+    uid::GameUid,
+};
+#[cfg(feature = "pipeline")]
+pub use builder::{Pgn2Csv, Pgn2CsvBuilder};
+#[cfg(feature = "pipeline")]
+pub use run::{pgn2csv, pgn2csv_with_sources};
+
+/// Where a game is in its PGN, in bytes of the decompressed text, from its
+/// first header up to where the next game starts.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct GameOffsets {
+    pub start: u64,
+    pub end: u64,
 }
 
-struct Pgn {
-    path: PathBuf,
-}
+pub trait GameProcessor: Default {
+    type Row: Default + Serialize;
 
-impl From<DirEntry> for Pgn {
-    fn from(dir_entry: DirEntry) -> Self {
-        Pgn {
-            path: dir_entry.into_path(),
-        }
+    fn skip(&self) -> bool {
+        false
     }
-}
 
-impl Pgn {
-    fn csv_path(&self, csv_dir: &Path) -> PathBuf {
-        let mut csv_path = csv_dir.to_path_buf();
-        csv_path.push(self.path.file_name().unwrap_or_default());
-        csv_path.set_extension("csv");
-        csv_path
+    /// Why the current game is skipped, when `skip` returns true. It is
+    /// written above each game that `--dump-skipped` writes out, to find out
+    /// which of several filters rejects the games.
+    fn skip_reason(&self) -> Option<String> {
+        None
     }
 
-    fn compression(&self) -> Compression {
-        match self.path.extension() {
-            Some(ext) => match ext.to_str() {
-                Some("bz2") => Compression::Bzip2,
-                Some("zst") => Compression::Zstd,
-                _ => Compression::None,
-            },
-            None => Compression::None,
-        }
+    fn row(&mut self) -> Self::Row;
+
+    /// The row with the colors swapped: the players, their ratings, and the
+    /// result, and any column derived from them. `--mirror-colors` writes it
+    /// after each row, doubling datasets for models that shouldn't learn a
+    /// color bias. The default can't mirror rows, and the run then refuses
+    /// `--mirror-colors`.
+    fn mirror(_row: &Self::Row) -> Option<Self::Row> {
+        None
     }
 
-    fn reader(&self) -> Result<BufferedReader<Box<dyn Read>>> {
-        let file = File::open(&self.path)?;
-        let reader: Box<dyn Read> = match self.compression() {
-            Compression::None => Box::new(file),
-            Compression::Bzip2 => Box::new(MultiBzDecoder::new(file)),
-            Compression::Zstd => Box::new(ZstdDecoder::new(file)?),
-        };
-        Ok(BufferedReader::new(reader))
+    /// Called once the headers of each game have been read, with a key that
+    /// identifies the game across all outputs of a run. Store it in your row if
+    /// you need to join it against other tables.
+    fn set_game_uid(&mut self, _uid: GameUid) {}
+
+    /// Called before each PGN a processor reads after its first, as processors
+    /// are reused across the PGNs that a worker thread converts rather than
+    /// built for each PGN. The default replaces the processor with a new one.
+    /// Processors with expensive setup, e.g. an opening book or compiled
+    /// regexes, can instead clear only what they gather per PGN.
+    fn reset_for_file(&mut self) {
+        *self = Self::default();
     }
 
-    fn process<P>(&self, processor: &mut P, csv: &mut Csv) -> Result<()>
-    where
-        P: Visitor + GameProcessor,
-    {
-        let mut pgn_reader = self.reader()?;
-        while let Ok(Some(_)) = pgn_reader.read_game(processor) {
-            if processor.skip() {
-                continue;
-            }
-            csv.write_row(processor.row())?;
-        }
-        csv.flush()?;
-        Ok(())
+    /// Returning false tells the library that the processor only reads
+    /// headers, so that the movetext of every game, outcome included, is
+    /// skipped rather than parsed, unless a library column needs it. This
+    /// about doubles the speed of processors that extract metadata, without
+    /// them having to return `Skip(true)` from `end_headers`.
+    fn needs_moves() -> bool {
+        true
     }
-}
 
-fn dir_pgns(dir: &Path) -> Result<Vec<Pgn>> {
-    let exts = ["*.pgn", "*.pgn.bz2", "*.pgn.zst"];
-    let pgns = GlobWalkerBuilder::from_patterns(dir, &exts)
-        .max_depth(1)
-        .build()?
-        .filter_map(Result::ok)
-        .map(Pgn::from)
-        .collect();
-    Ok(pgns)
-}
+    /// Returning true splits each PGN into games before they are parsed, to
+    /// find where each one is, which is a little slower. Their offsets are
+    /// then passed to `set_game_offsets`, and read errors cite them.
+    fn game_offsets() -> bool {
+        false
+    }
 
-struct Csv {
-    writer: csv::Writer<File>,
-}
+    /// Called right after `begin_game` with where the game is in its PGN, if
+    /// `game_offsets` returns true, e.g. to index a PGN by the games' rows.
+    fn set_game_offsets(&mut self, _offsets: GameOffsets) {}
 
-impl Csv {
-    fn new(csv_dir: &Path, pgn: &Pgn) -> Result<Self> {
-        let csv_path = pgn.csv_path(csv_dir);
-        let file = File::create(csv_path)?;
-        let writer = csv::Writer::from_writer(file);
-        Ok(Self { writer })
+    /// Called before `row` for each game that isn't skipped. Returning a key
+    /// sends the row to a separate output for that key, named e.g.
+    /// `games.1400.csv` for key `1400`, instead of `games.csv`. This splits
+    /// outputs by e.g. rating bucket without a separate partitioning job.
+    fn route(&self) -> Option<String> {
+        None
     }
 
-    fn write_row(&mut self, row: impl Serialize) -> Result<()> {
-        self.writer.serialize(row)?;
-        Ok(())
+    /// The version of the processor's rows. Bump it when the meaning of a
+    /// column changes, since that doesn't change the schema version's hash of
+    /// the columns and their types.
+    fn version() -> &'static str {
+        "0.0.0"
     }
 
-    fn flush(&mut self) -> Result<()> {
-        self.writer.flush()?;
-        Ok(())
+    /// A sentence on what the processor extracts, printed by `--describe`.
+    fn description() -> &'static str {
+        ""
     }
-}
-
-pub trait GameProcessor: Default {
-    type Row: Default + Serialize;
 
-    fn skip(&self) -> bool {
-        false
+    /// The criteria a game must meet to get a row, in words, printed by
+    /// `--describe` so that dataset consumers know what was left out.
+    fn filters() -> Vec<&'static str> {
+        Vec::new()
     }
 
-    fn row(&mut self) -> Self::Row;
-}
-
-fn progress_bar(n: usize, message: &str) -> Result<ProgressBar> {
-    let pb = ProgressBar::new(u64::try_from(n)?);
-    let template = format!("{{spinner:.green}} {message}: [{{elapsed}}] [{{bar:.cyan/blue}}] {{human_pos}}/{{human_len}} ({{eta}})");
-    pb.set_style(
-        ProgressStyle::default_bar()
-            .template(&template)?
-            .progress_chars("#>-"),
-    );
-    Ok(pb)
-}
-
-/// Converts PGN files to CSVs. Reads one or two command line arguments: the
-/// path to a directory containing PGN files, and the path to a directory to
-/// write CSV files; if the second argument is not provided, the CSV files will
-/// be written to the same directory as the PGN files. The CSV files will have
-/// the same name as the PGN files, but with the extension replaced with `.csv`.
-/// To customize the data that you collect into the CSVs, you provide the
-/// generic type parameter `P` to the function, which must implement the
-/// `Visitor` and `GameProcessor` traits. See the README for more information.
-///
-/// # Errors
-///
-/// Returns an error if there is an issue with reading or writing files.
-pub fn pgn2csv<P>() -> Result<()>
-where
-    P: Visitor + GameProcessor,
-{
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() != 2 && args.len() != 3 {
-        println!("Usage: {} <pgn dir> [csv dir]", args[0]);
-        std::process::exit(1);
+    /// Returning a grouping writes one row per group, e.g. per hour of the
+    /// day, with counts and averages of the processor's rows, instead of one
+    /// row per game. Grouping is done per PGN, and only for CSV output.
+    fn group_by() -> Option<GroupBy> {
+        None
     }
-    let pgn_dir = Path::new(&args[1]);
-    let csv_dir = if args.len() == 3 {
-        Path::new(&args[2])
-    } else {
-        pgn_dir
-    };
-
-    if !csv_dir.exists() {
-        create_dir(csv_dir)?;
+
+    /// Returning a partition writes the rows of every PGN with the same value
+    /// of it, e.g. the month the game was played in, to one CSV named after
+    /// the value, e.g. `2024-01.csv`, instead of a CSV per PGN, unless a run
+    /// chooses otherwise with `--partition-by`.
+    fn partition_by() -> Option<Partition> {
+        None
     }
 
-    let pgns = dir_pgns(pgn_dir)?;
+    /// Returning sessions orders each output by pair of players and time and
+    /// numbers the sessions of rematches in it, for CSV output. Rows are
+    /// grouped, if they are, after this.
+    fn sessions() -> Option<Sessions> {
+        None
+    }
 
-    let pb = progress_bar(pgns.len(), "Processing PGNs")?;
+    /// How the processor's `PgnResult` columns are written, unless a run
+    /// chooses otherwise with `--result-encoding`.
+    fn result_encoding() -> ResultEncoding {
+        ResultEncoding::WhiteScore
+    }
 
-    pgns.par_iter()
-        .progress_with(pb)
-        .try_for_each(|pgn| -> Result<()> {
-            let mut csv = Csv::new(csv_dir, pgn)?;
-            let mut processor = P::default();
-            pgn.process(&mut processor, &mut csv)?;
-            Ok(())
-        })?;
-    Ok(())
+    /// How the processor's `Termination` columns are written, unless a run
+    /// chooses otherwise with `--termination-encoding`.
+    fn termination_encoding() -> TerminationEncoding {
+        TerminationEncoding::Codes
+    }
 }
@@ -0,0 +1,73 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+
+const LOCK: &str = ".pgn2csv.lock";
+
+/// An advisory lock on an output directory, held for the whole run, so that
+/// two runs (e.g. triggered by cron) can't interleave writes to the same
+/// outputs. It is a file holding the owner's pid, removed when the lock is
+/// dropped. A run that crashes leaves it behind, and `--force` takes it over.
+pub(crate) struct OutputLock {
+    path: PathBuf,
+}
+
+impl OutputLock {
+    /// Locks `dir`, failing if another run holds it, unless `force`.
+    pub(crate) fn acquire(dir: &Path, force: bool) -> Result<Self> {
+        let path = dir.join(LOCK);
+        let mut options = OpenOptions::new();
+        options.write(true);
+        if force {
+            options.create(true).truncate(true);
+        } else {
+            options.create_new(true);
+        }
+        let mut file = match options.open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                let owner = fs::read_to_string(&path).unwrap_or_default();
+                bail!(
+                    "another run (pid {}) is writing to {}; if it isn't running anymore, \
+                     remove {} or pass --force",
+                    owner.trim(),
+                    dir.display(),
+                    path.display()
+                )
+            }
+            Err(e) => return Err(e).with_context(|| format!("locking {}", dir.display())),
+        };
+        writeln!(file, "{}", std::process::id())?;
+        Ok(OutputLock { path })
+    }
+}
+
+impl Drop for OutputLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locks_output_dirs() {
+        let dir = std::env::temp_dir().join(format!("pgn2csv-lock-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let lock = OutputLock::acquire(&dir, false).unwrap();
+        assert!(OutputLock::acquire(&dir, false).is_err());
+        drop(lock);
+        let lock = OutputLock::acquire(&dir, false).unwrap();
+        // a crashed run's lock is taken over
+        std::mem::forget(lock);
+        assert!(OutputLock::acquire(&dir, true).is_ok());
+        assert!(!dir.join(LOCK).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,372 @@
+use std::str::FromStr;
+
+use anyhow::{bail, Error, Result};
+use indicatif::{HumanBytes, HumanCount, MultiProgress, ProgressDrawTarget};
+use serde::Serialize;
+use serde_json::Value;
+
+/// How events are written to stderr.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LogFormat {
+    /// Messages for people, printed above the progress bars.
+    Text,
+    /// One JSON object per event, for log aggregators. Progress bars are
+    /// hidden, so that every line is an event.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = Error;
+
+    fn from_str(format: &str) -> Result<Self> {
+        match format {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => bail!("unknown log format {format}"),
+        }
+    }
+}
+
+/// How much is logged: `--quiet` for warnings only, without progress bars,
+/// and `-v` or `-vv` for more than the default.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Verbosity {
+    Quiet,
+    #[default]
+    Normal,
+    /// Also each file converted, and the totals of the run.
+    Verbose,
+    /// Also each file as it is started.
+    Debug,
+}
+
+impl Verbosity {
+    /// The verbosity of `--quiet`, or of `-v` given `count` times.
+    pub(crate) fn new(quiet: bool, count: u8) -> Self {
+        match (quiet, count) {
+            (true, _) => Verbosity::Quiet,
+            (false, 0) => Verbosity::Normal,
+            (false, 1) => Verbosity::Verbose,
+            (false, _) => Verbosity::Debug,
+        }
+    }
+}
+
+/// How many bytes of the run's PGNs were read through io_uring, and how long
+/// the threads reading them waited for reads to complete in all, which is
+/// how much of the I/O didn't overlap with decompression.
+#[derive(Serialize)]
+pub(crate) struct UringReads {
+    pub(crate) bytes: u64,
+    pub(crate) wait_seconds: f64,
+}
+
+/// Something that happened during a run.
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub(crate) enum Event<'a> {
+    FileStarted {
+        file: &'a str,
+    },
+    FileFinished {
+        file: &'a str,
+        rows: u64,
+        seconds: f64,
+    },
+    Error {
+        file: &'a str,
+        message: String,
+    },
+    Summary {
+        files: usize,
+        rows: u64,
+        seconds: f64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        uring: Option<UringReads>,
+    },
+    Heartbeat {
+        file: &'a str,
+        bytes: u64,
+        games: u64,
+        stalled_seconds: Option<u64>,
+    },
+    Violation {
+        file: &'a str,
+        row: u64,
+        assertion: String,
+        value: &'a Value,
+    },
+    Violations {
+        file: &'a str,
+        assertion: String,
+        violations: u64,
+        rows: u64,
+    },
+    Warning {
+        message: String,
+    },
+    AbortedGames {
+        file: &'a str,
+        games: u64,
+        aborted: u64,
+    },
+    NoRows {
+        file: &'a str,
+        games: u64,
+        skipped: u64,
+        skip_reasons: Vec<(String, u64)>,
+    },
+    /// What `--errors` skipped during the run: each PGN that failed, with
+    /// its error, and how many games couldn't be read.
+    Skipped {
+        files: Vec<(String, String)>,
+        games: u64,
+    },
+}
+
+impl Event<'_> {
+    /// The least verbosity that the event is logged at. Warnings are always
+    /// logged, and the progress of files only when asked for, since the
+    /// progress bars show it otherwise.
+    fn verbosity(&self) -> Verbosity {
+        match self {
+            Event::Error { .. }
+            | Event::Violation { .. }
+            | Event::Violations { .. }
+            | Event::Warning { .. }
+            | Event::NoRows { .. }
+            | Event::Skipped { .. } => Verbosity::Quiet,
+            Event::Heartbeat { .. } | Event::AbortedGames { .. } => Verbosity::Normal,
+            Event::FileFinished { .. } | Event::Summary { .. } => Verbosity::Verbose,
+            Event::FileStarted { .. } => Verbosity::Debug,
+        }
+    }
+
+    /// The event as a message, for those that are shown in text logs. Errors
+    /// are reported when the run stops instead.
+    fn text(&self) -> Option<String> {
+        match self {
+            Event::Error { .. } => None,
+            Event::FileStarted { file } => Some(format!("{file}: started")),
+            Event::FileFinished {
+                file,
+                rows,
+                seconds,
+            } => Some(format!(
+                "{file}: {} rows in {seconds:.1}s",
+                HumanCount(*rows)
+            )),
+            Event::Summary {
+                files,
+                rows,
+                seconds,
+                uring,
+            } => {
+                let mut text = format!(
+                    "converted {files} PGNs into {} rows in {seconds:.1}s",
+                    HumanCount(*rows)
+                );
+                if let Some(reads) = uring {
+                    text += &format!(
+                        "\nread {} through io_uring at {}/s, waiting {:.1}s for reads",
+                        HumanBytes(reads.bytes),
+                        HumanBytes((reads.bytes as f64 / seconds.max(f64::EPSILON)) as u64),
+                        reads.wait_seconds,
+                    );
+                }
+                Some(text)
+            }
+            Event::Heartbeat {
+                file,
+                bytes,
+                games,
+                stalled_seconds,
+            } => {
+                let (bytes, games) = (HumanBytes(*bytes), HumanCount(*games));
+                Some(match stalled_seconds {
+                    Some(secs) => format!(
+                        "{file}: no progress for {secs}s at {bytes} read, {games} games; it may be stuck"
+                    ),
+                    None => format!("{file}: {bytes} read, {games} games"),
+                })
+            }
+            Event::Violation {
+                file,
+                row,
+                assertion,
+                value,
+            } => Some(format!(
+                "{file}: row {row}: `{assertion}` violated by {value}"
+            )),
+            Event::Violations {
+                file,
+                assertion,
+                violations,
+                rows,
+            } => Some(format!(
+                "{file}: `{assertion}` violated by {violations} of {rows} rows"
+            )),
+            Event::Warning { message } => Some(message.clone()),
+            Event::AbortedGames {
+                file,
+                games,
+                aborted,
+            } => Some(format!(
+                "{file}: {aborted} of {games} games ended before their first move"
+            )),
+            Event::NoRows {
+                file,
+                games,
+                skipped,
+                skip_reasons,
+            } => {
+                let (games, skipped) = (HumanCount(*games), HumanCount(*skipped));
+                let mut text = format!(
+                    "WARNING: {file}: no rows from {games} games, of which {skipped} were skipped"
+                );
+                if skip_reasons.is_empty() {
+                    text += "; --dump-skipped shows some of them";
+                }
+                for (reason, games) in skip_reasons {
+                    text += &format!("\n  {}: {reason}", HumanCount(*games));
+                }
+                Some(text)
+            }
+            Event::Skipped { files, games } => {
+                let count = HumanCount(*games);
+                let mut text = match (files.is_empty(), *games) {
+                    (true, _) => {
+                        format!("WARNING: {count} games couldn't be read, and were skipped")
+                    }
+                    (false, 0) => "WARNING: these PGNs failed:".to_owned(),
+                    (false, _) => {
+                        format!("WARNING: {count} games couldn't be read, and these PGNs failed:")
+                    }
+                };
+                for (file, error) in files {
+                    text += &format!("\n  {file}: {error}");
+                }
+                Some(text)
+            }
+        }
+    }
+}
+
+/// How a run logs its events, from its `--log-format`, `--quiet` and `-v`.
+/// Every run carries its own in its `Args`, so that runs embedded in one
+/// program each log as they were asked to. Progress bars drawn through the
+/// run's `bars` are hidden for JSON and quiet logs.
+#[derive(Clone)]
+pub(crate) struct Log {
+    format: LogFormat,
+    verbosity: Verbosity,
+    bars: MultiProgress,
+}
+
+impl Default for Log {
+    fn default() -> Self {
+        Log::new(LogFormat::Text, Verbosity::Normal)
+    }
+}
+
+impl Log {
+    pub(crate) fn new(format: LogFormat, verbosity: Verbosity) -> Self {
+        let log = Log {
+            format,
+            verbosity,
+            bars: MultiProgress::new(),
+        };
+        if log.hides_progress() {
+            log.bars.set_draw_target(ProgressDrawTarget::hidden());
+        }
+        log
+    }
+
+    /// The run's progress bars, above which text logs are printed.
+    pub(crate) fn bars(&self) -> &MultiProgress {
+        &self.bars
+    }
+
+    /// Whether `event` is logged. JSON logs have every event, unless they
+    /// are quiet.
+    fn logs(&self, event: &Event) -> bool {
+        match (self.format, self.verbosity) {
+            (LogFormat::Json, Verbosity::Quiet) | (LogFormat::Text, _) => {
+                event.verbosity() <= self.verbosity
+            }
+            (LogFormat::Json, _) => true,
+        }
+    }
+
+    /// Whether progress bars are hidden, for those not drawn through `bars`.
+    pub(crate) fn hides_progress(&self) -> bool {
+        self.format == LogFormat::Json || self.verbosity == Verbosity::Quiet
+    }
+
+    pub(crate) fn emit(&self, event: &Event) {
+        if !self.logs(event) {
+            return;
+        }
+        match self.format {
+            LogFormat::Json => {
+                if let Ok(line) = serde_json::to_string(event) {
+                    eprintln!("{line}");
+                }
+            }
+            LogFormat::Text => {
+                if let Some(text) = event.text() {
+                    self.bars.suspend(|| eprintln!("{text}"));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_are_tagged() {
+        let event = Event::FileFinished {
+            file: "a.pgn",
+            rows: 2,
+            seconds: 0.5,
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"event":"file_finished","file":"a.pgn","rows":2,"seconds":0.5}"#
+        );
+        assert_eq!(event.text().unwrap(), "a.pgn: 2 rows in 0.5s");
+        assert_eq!(event.verbosity(), Verbosity::Verbose);
+        let log = Log::new(LogFormat::Json, Verbosity::Quiet);
+        assert!(!log.logs(&event));
+        assert!(log.logs(&Event::Warning {
+            message: String::new()
+        }));
+
+        let event = Event::NoRows {
+            file: "a.pgn",
+            games: 20_000,
+            skipped: 20_000,
+            skip_reasons: vec![("not blitz".to_owned(), 15_000)],
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"event":"no_rows","file":"a.pgn","games":20000,"skipped":20000,"skip_reasons":[["not blitz",15000]]}"#
+        );
+        assert_eq!(
+            event.text().unwrap(),
+            "WARNING: a.pgn: no rows from 20,000 games, of which 20,000 were skipped\n  15,000: not blitz"
+        );
+
+        let event = Event::Skipped {
+            files: vec![("b.pgn.zst".to_owned(), "corrupt archive".to_owned())],
+            games: 3,
+        };
+        assert_eq!(
+            event.text().unwrap(),
+            "WARNING: 3 games couldn't be read, and these PGNs failed:\n  b.pgn.zst: corrupt archive"
+        );
+    }
+}
@@ -0,0 +1,65 @@
+use std::{fs::File, io::BufWriter, path::Path, sync::Mutex};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::stats::{ColumnStats, ColumnSummary};
+
+#[derive(Serialize)]
+struct FileEntry {
+    pgn: String,
+    output: String,
+    rows: u64,
+    columns: Vec<ColumnSummary>,
+}
+
+#[derive(Serialize)]
+struct Files<'a> {
+    schema_version: &'a str,
+    files: &'a [FileEntry],
+}
+
+/// Describes the outputs of a run, written as `manifest.json` in the CSV
+/// directory once every file has been converted.
+pub(crate) struct Manifest {
+    schema_version: String,
+    files: Mutex<Vec<FileEntry>>,
+}
+
+impl Manifest {
+    pub(crate) fn new(schema_version: String) -> Self {
+        Manifest {
+            schema_version,
+            files: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub(crate) fn record(&self, pgn: String, output: String, stats: ColumnStats) {
+        let entry = FileEntry {
+            pgn,
+            output,
+            rows: stats.rows(),
+            columns: stats.summarize(),
+        };
+        if let Ok(mut files) = self.files.lock() {
+            files.push(entry);
+        }
+    }
+
+    pub(crate) fn write(&self, csv_dir: &Path) -> Result<()> {
+        let mut files = self
+            .files
+            .lock()
+            .map_err(|_| anyhow::anyhow!("manifest lock poisoned"))?;
+        files.sort_by(|a, b| a.pgn.cmp(&b.pgn));
+        let writer = BufWriter::new(File::create(csv_dir.join("manifest.json"))?);
+        serde_json::to_writer_pretty(
+            writer,
+            &Files {
+                schema_version: &self.schema_version,
+                files: &files,
+            },
+        )?;
+        Ok(())
+    }
+}
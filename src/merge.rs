@@ -0,0 +1,230 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, Write},
+    mem,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, Receiver, SyncSender},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+};
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::{
+    args::STDIO,
+    output::{self, part_path},
+    record::Record,
+    sink::{CsvSink, RowSink, SinkFactory},
+};
+
+// rows are sent to the writer in chunks of this many, so that the channel
+// isn't contended for every row
+const CHUNK_ROWS: usize = 4096;
+
+// how many chunks can wait for the writer before converting waits for it,
+// which bounds the memory of a writer that falls behind
+const QUEUED_CHUNKS: usize = 64;
+
+// a chunk of rows and the CSV they are written to, or None once every row
+// has been sent
+type Chunk = Option<(PathBuf, Vec<Record>)>;
+
+/// CSVs that the rows of several PGNs are written to together: the single
+/// CSV of `--merge`, or the CSVs of the partitions of `--partition-by`. The
+/// outputs of the PGNs, converted in parallel, send their rows over a
+/// channel to a thread of its own that writes them, so that each CSV has one
+/// header and its rows aren't torn. Rows arrive in chunks, so the rows of
+/// different PGNs can be interleaved.
+pub(crate) struct MergedCsvs {
+    chunks: SyncSender<Chunk>,
+    writer: JoinHandle<Result<()>>,
+    // the CSV that every output is merged into, if there is a single one
+    merged: Option<PathBuf>,
+}
+
+impl MergedCsvs {
+    /// Creates the single CSV at `path`, `-` for stdout, starting with
+    /// `comment` if the schema version is written into CSVs.
+    pub(crate) fn create(path: &Path, comment: Option<&str>) -> Result<Self> {
+        // created up front, so that a path that can't be written fails the
+        // run before anything is converted
+        if path != Path::new(STDIO) {
+            File::create(part_path(path))
+                .with_context(|| format!("can't create {}", path.display()))?;
+        }
+        Ok(Self::spawn(Some(path.to_path_buf()), comment))
+    }
+
+    /// Writes each output's rows to its own path, which several outputs can
+    /// share. Files are created when their first row arrives.
+    pub(crate) fn partitioned(comment: Option<&str>) -> Self {
+        Self::spawn(None, comment)
+    }
+
+    fn spawn(merged: Option<PathBuf>, comment: Option<&str>) -> Self {
+        let (chunks, received) = mpsc::sync_channel(QUEUED_CHUNKS);
+        let path = merged.clone();
+        let comment = comment.map(str::to_owned);
+        let writer = thread::spawn(move || {
+            let mut csvs = Csvs {
+                files: HashMap::new(),
+                comment,
+            };
+            // the merged CSV has a header even without rows
+            if let Some(path) = path {
+                csvs.file(&path)?;
+            }
+            csvs.write(&received)
+        });
+        MergedCsvs {
+            chunks,
+            writer,
+            merged,
+        }
+    }
+
+    /// The factory of the outputs of the PGNs, which all send their rows
+    /// here.
+    pub(crate) fn sinks(&self) -> Arc<SinkFactory> {
+        let chunks = self.chunks.clone();
+        let merged = self.merged.clone();
+        Arc::new(move |path| {
+            Ok(Box::new(MergeSink {
+                chunks: chunks.clone(),
+                path: merged.clone().unwrap_or_else(|| path.to_path_buf()),
+                rows: Vec::new(),
+            }))
+        })
+    }
+
+    /// Waits for the writer to write every row sent, once every PGN has been
+    /// converted.
+    pub(crate) fn finish(self) -> Result<()> {
+        // the writer has stopped if it failed, and says why below
+        _ = self.chunks.send(None);
+        self.writer
+            .join()
+            .map_err(|_| anyhow!("the writer of the merged CSV panicked"))?
+    }
+}
+
+/// The CSVs that the writer thread has created.
+struct Csvs {
+    files: HashMap<PathBuf, CsvSink>,
+    comment: Option<String>,
+}
+
+impl Csvs {
+    fn file(&mut self, path: &Path) -> Result<&mut CsvSink> {
+        if !self.files.contains_key(path) {
+            let mut file: Box<dyn Write + Send> = match path == Path::new(STDIO) {
+                true => Box::new(io::stdout()),
+                false => Box::new(
+                    File::create(part_path(path))
+                        .with_context(|| format!("can't create {}", path.display()))?,
+                ),
+            };
+            if let Some(comment) = &self.comment {
+                writeln!(file, "{comment}")?;
+            }
+            self.files.insert(path.to_path_buf(), CsvSink::new(file));
+        }
+        Ok(self.files.get_mut(path).expect("file was just created"))
+    }
+
+    fn write(&mut self, received: &Receiver<Chunk>) -> Result<()> {
+        // the channel is closed without a None if the run failed, whose CSVs
+        // are left incomplete
+        let mut complete = false;
+        while let Ok(chunk) = received.recv() {
+            let Some((path, rows)) = chunk else {
+                complete = true;
+                break;
+            };
+            let csv = self.file(&path)?;
+            for row in &rows {
+                csv.write_row(row)?;
+            }
+        }
+        for csv in self.files.values_mut() {
+            csv.finalize()?;
+        }
+        if complete {
+            for path in self.files.keys().filter(|path| *path != Path::new(STDIO)) {
+                output::publish(path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The output of one PGN, or of one of its partitions, whose rows are sent
+/// to the writer of the merged CSVs.
+struct MergeSink {
+    chunks: SyncSender<Chunk>,
+    path: PathBuf,
+    rows: Vec<Record>,
+}
+
+impl MergeSink {
+    fn send(&mut self) -> Result<()> {
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+        self.chunks
+            .send(Some((self.path.clone(), mem::take(&mut self.rows))))
+            .map_err(|_| anyhow!("the merged CSV can't be written"))
+    }
+}
+
+impl RowSink for MergeSink {
+    fn write_row(&mut self, record: &Record) -> Result<()> {
+        self.rows.push(record.clone());
+        if self.rows.len() >= CHUNK_ROWS {
+            self.send()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.send()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    #[test]
+    fn merges_outputs() {
+        let row = |elo: u32| match json!({"white_elo": elo}) {
+            serde_json::Value::Object(record) => record,
+            _ => unreachable!(),
+        };
+        let path = std::env::temp_dir().join(format!("pgn2csv-merged-{}.csv", std::process::id()));
+        let merged = MergedCsvs::create(&path, None).unwrap();
+        let sinks = merged.sinks();
+        thread::scope(|scope| {
+            for elo in [1500, 1600] {
+                let sinks = &sinks;
+                scope.spawn(move || {
+                    let mut sink = sinks(Path::new("a.csv")).unwrap();
+                    sink.write_row(&row(elo)).unwrap();
+                    sink.finalize().unwrap();
+                });
+            }
+        });
+        merged.finish().unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut lines: Vec<&str> = written.lines().collect();
+        assert_eq!(lines.remove(0), "white_elo");
+        lines.sort_unstable();
+        assert_eq!(lines, ["1500", "1600"]);
+    }
+}
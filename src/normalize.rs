@@ -0,0 +1,168 @@
+use std::{
+    io::{self, Read},
+    str::FromStr,
+};
+
+use anyhow::{bail, Error, Result};
+
+const BOM: &[u8] = b"\xef\xbb\xbf";
+
+/// How much of a PGN's text is rewritten before it is parsed, for PGNs
+/// exported on Windows, which can start with a UTF-8 byte order mark that ends
+/// up in the first header's name, or end lines with a lone CR, which makes the
+/// whole PGN one line.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum Normalization {
+    /// The text is parsed as it is.
+    None,
+    /// A byte order mark at the start is dropped.
+    Bom,
+    /// A byte order mark at the start is dropped, and CRLF and CR line
+    /// endings become LF.
+    #[default]
+    All,
+}
+
+impl FromStr for Normalization {
+    type Err = Error;
+
+    fn from_str(normalization: &str) -> Result<Self> {
+        match normalization {
+            "none" => Ok(Normalization::None),
+            "bom" => Ok(Normalization::Bom),
+            "all" => Ok(Normalization::All),
+            _ => bail!("unknown normalization {normalization}"),
+        }
+    }
+}
+
+/// Normalizes the text read through it, in place in the caller's buffer,
+/// since it only ever drops bytes or replaces them one for one.
+pub(crate) struct Normalize<R> {
+    inner: R,
+    normalization: Normalization,
+    // the first bytes read, until it is known whether they are a BOM
+    head: Option<Vec<u8>>,
+    // whether the last byte read was a CR, so a LF right after it is dropped
+    after_cr: bool,
+}
+
+impl<R: Read> Normalize<R> {
+    pub(crate) fn new(inner: R, normalization: Normalization) -> Self {
+        Normalize {
+            inner,
+            normalization,
+            head: (normalization != Normalization::None).then(Vec::new),
+            after_cr: false,
+        }
+    }
+
+    /// Reads up to the length of a BOM, dropping it if that's what it is.
+    fn read_head(&mut self, mut head: Vec<u8>) -> io::Result<Vec<u8>> {
+        let mut buf = [0; BOM.len()];
+        while head.len() < BOM.len() && BOM.starts_with(&head) {
+            let n = self.inner.read(&mut buf[..BOM.len() - head.len()])?;
+            if n == 0 {
+                break;
+            }
+            head.extend_from_slice(&buf[..n]);
+        }
+        if head == BOM {
+            head.clear();
+        }
+        Ok(head)
+    }
+
+    /// Rewrites line endings in `buf`, returning its new length.
+    fn newlines(&mut self, buf: &mut [u8]) -> usize {
+        let mut len = 0;
+        for i in 0..buf.len() {
+            let byte = buf[i];
+            if byte == b'\n' && self.after_cr {
+                self.after_cr = false;
+                continue;
+            }
+            self.after_cr = byte == b'\r';
+            buf[len] = if self.after_cr { b'\n' } else { byte };
+            len += 1;
+        }
+        len
+    }
+}
+
+impl<R: Read> Read for Normalize<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let n = match self.head.take() {
+                Some(head) => {
+                    let mut head = self.read_head(head)?;
+                    let n = head.len().min(buf.len());
+                    buf[..n].copy_from_slice(&head[..n]);
+                    head.drain(..n);
+                    if !head.is_empty() {
+                        // put back what doesn't fit, to be read next time
+                        self.head = Some(head);
+                    }
+                    if n == 0 {
+                        // an empty PGN, or just a BOM
+                        self.inner.read(buf)?
+                    } else {
+                        n
+                    }
+                }
+                None => self.inner.read(buf)?,
+            };
+            if n == 0 || self.normalization != Normalization::All {
+                return Ok(n);
+            }
+            // a read of only the LF of a CRLF comes out empty, which would
+            // look like the end of the PGN
+            let len = self.newlines(&mut buf[..n]);
+            if len > 0 {
+                return Ok(len);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reads through `Normalize` a few bytes at a time, so that BOMs and CRLFs
+    /// are split across reads.
+    fn normalize(pgn: &[u8], normalization: Normalization) -> Vec<u8> {
+        let mut reader = Normalize::new(pgn, normalization);
+        let mut normalized = Vec::new();
+        let mut buf = [0; 2];
+        loop {
+            let n = reader.read(&mut buf).unwrap();
+            if n == 0 {
+                return normalized;
+            }
+            normalized.extend_from_slice(&buf[..n]);
+        }
+    }
+
+    #[test]
+    fn normalizes_windows_pgns() {
+        let lf = include_bytes!("../tests/pgn/lf.pgn");
+        for pgn in [
+            include_bytes!("../tests/pgn/bom.pgn").as_slice(),
+            include_bytes!("../tests/pgn/crlf.pgn"),
+            include_bytes!("../tests/pgn/cr.pgn"),
+            include_bytes!("../tests/pgn/bom-crlf.pgn"),
+        ] {
+            assert_eq!(normalize(pgn, Normalization::All), lf);
+        }
+        let bom = include_bytes!("../tests/pgn/bom-crlf.pgn");
+        assert_eq!(normalize(bom, Normalization::Bom), bom[BOM.len()..]);
+        assert_eq!(normalize(bom, Normalization::None), bom);
+        // text that starts like a BOM is kept
+        assert_eq!(normalize(b"\xef\xbb[", Normalization::Bom), b"\xef\xbb[");
+        assert_eq!(normalize(b"", Normalization::All), b"");
+    }
+}
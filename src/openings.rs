@@ -0,0 +1,206 @@
+//! Classifies games by the positions they reach rather than by their exact
+//! move order, so that transpositions, e.g. `1. d4 Nf6 2. c4 e6` and
+//! `1. c4 e6 2. d4 Nf6`, get the same opening.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader, Read},
+    path::Path,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use shakmaty::{san::SanPlus, zobrist::Zobrist64, Chess, EnPassantMode, Position};
+
+/// An opening of an `OpeningBook`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Opening {
+    pub eco: String,
+    pub name: String,
+}
+
+/// Openings keyed by the position after their moves, like the TSVs of
+/// lichess's [chess-openings](https://github.com/lichess-org/chess-openings).
+#[derive(Debug, Default)]
+pub struct OpeningBook {
+    // keyed by Zobrist hash
+    positions: HashMap<u64, Opening>,
+    // the most plies of any opening, after which games can't reach another
+    max_plies: u32,
+}
+
+impl OpeningBook {
+    /// Reads the openings of a TSV file (see `read_tsv`).
+    pub fn load(&mut self, path: &Path) -> Result<()> {
+        let file = File::open(path).with_context(|| format!("can't open {}", path.display()))?;
+        self.read_tsv(file)
+            .with_context(|| format!("can't read openings from {}", path.display()))
+    }
+
+    /// Reads openings from a TSV with a header line naming at least `eco`,
+    /// `name`, and `pgn` columns, the last holding the opening's moves like
+    /// `1. e4 e5 2. Nf3`. An opening that reaches the position of one read
+    /// before it doesn't replace it.
+    pub fn read_tsv(&mut self, reader: impl Read) -> Result<()> {
+        let mut lines = BufReader::new(reader).lines();
+        let header = lines.next().ok_or_else(|| anyhow!("no header line"))??;
+        let column = |name| {
+            header
+                .split('\t')
+                .position(|column| column == name)
+                .ok_or_else(|| anyhow!("no {name} column"))
+        };
+        let (eco, name, pgn) = (column("eco")?, column("name")?, column("pgn")?);
+        for (number, line) in lines.enumerate() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('\t').collect();
+            let (Some(&eco), Some(&name), Some(&pgn)) =
+                (fields.get(eco), fields.get(name), fields.get(pgn))
+            else {
+                bail!("line {} has too few columns", number + 2);
+            };
+            let (position, plies) =
+                play(pgn).with_context(|| format!("bad moves on line {}", number + 2))?;
+            self.max_plies = self.max_plies.max(plies);
+            self.positions.entry(hash(&position)).or_insert(Opening {
+                eco: eco.to_owned(),
+                name: name.to_owned(),
+            });
+        }
+        Ok(())
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    /// The opening that ends in `position`, if any.
+    #[must_use]
+    pub fn get(&self, position: &Chess) -> Option<&Opening> {
+        self.positions.get(&hash(position))
+    }
+}
+
+fn hash(position: &Chess) -> u64 {
+    position.zobrist_hash::<Zobrist64>(EnPassantMode::Legal).0
+}
+
+/// Plays moves like `1. e4 e5 2. Nf3` from the starting position, returning
+/// the position reached and the number of plies.
+fn play(moves: &str) -> Result<(Chess, u32)> {
+    let mut position = Chess::default();
+    let mut plies = 0;
+    // move numbers like `1.` or `1...`
+    for san in moves
+        .split_whitespace()
+        .filter(|token| !token.starts_with(|c: char| c.is_ascii_digit()))
+    {
+        let san = SanPlus::from_ascii(san.as_bytes())?;
+        let m = san.san.to_move(&position)?;
+        position.play_unchecked(m);
+        plies += 1;
+    }
+    Ok((position, plies))
+}
+
+/// Follows the moves of a game from the starting position, giving the last
+/// opening of the book that the game reached within the book's longest line,
+/// whatever the order of the moves that reached it. Feed it every move of the
+/// mainline, and `reset` it when a game begins.
+#[derive(Debug)]
+pub struct OpeningClassifier<'a> {
+    book: &'a OpeningBook,
+    position: Chess,
+    plies: u32,
+    opening: Option<&'a Opening>,
+    // whether the game has left the book's reach, or can't be followed
+    done: bool,
+}
+
+impl<'a> OpeningClassifier<'a> {
+    #[must_use]
+    pub fn new(book: &'a OpeningBook) -> Self {
+        OpeningClassifier {
+            book,
+            position: Chess::default(),
+            plies: 0,
+            opening: None,
+            done: false,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = OpeningClassifier::new(self.book);
+    }
+
+    /// Gives up on the game, e.g. because it started from a `FEN` header's
+    /// position or is of a variant, so that it gets no opening.
+    pub fn give_up(&mut self) {
+        self.opening = None;
+        self.done = true;
+    }
+
+    /// Plays the next move, in SAN like `Nf3` or `exd8=Q+`. A move that can't
+    /// be parsed or isn't legal ends the classification, keeping the opening
+    /// reached before it.
+    pub fn push(&mut self, san: &str) {
+        if self.done {
+            return;
+        }
+        let Some(m) = SanPlus::from_ascii(san.as_bytes())
+            .ok()
+            .and_then(|san| san.san.to_move(&self.position).ok())
+        else {
+            self.done = true;
+            return;
+        };
+        self.position.play_unchecked(m);
+        self.plies += 1;
+        if let Some(opening) = self.book.get(&self.position) {
+            self.opening = Some(opening);
+        }
+        self.done = self.plies >= self.book.max_plies;
+    }
+
+    #[must_use]
+    pub fn opening(&self) -> Option<&'a Opening> {
+        self.opening
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_transpositions() {
+        let tsv = "eco\tname\tpgn\n\
+            A40\tQueen's Pawn Game\t1. d4\n\
+            E00\tCatalan Opening\t1. d4 Nf6 2. c4 e6 3. g3\n\
+            A45\tIndian Defense\t1. d4 Nf6\n";
+        let mut book = OpeningBook::default();
+        book.read_tsv(tsv.as_bytes()).unwrap();
+        assert_eq!(book.len(), 3);
+        let mut game = OpeningClassifier::new(&book);
+        for san in ["c4", "e6", "g3", "Nf6", "d4", "d5", "Nf3"] {
+            game.push(san);
+        }
+        assert_eq!(game.opening().unwrap().eco, "E00");
+        game.reset();
+        game.push("d4");
+        game.push("e5");
+        assert_eq!(game.opening().unwrap().name, "Queen's Pawn Game");
+        game.push("Qxe5");
+        game.push("Nf6");
+        assert_eq!(game.opening().unwrap().eco, "A40");
+    }
+}
@@ -0,0 +1,693 @@
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
+
+use anyhow::{bail, Error, Result};
+use serde::Serialize;
+use serde_json::Value;
+
+#[cfg(any(feature = "msgpack", feature = "cbor"))]
+use crate::binary::{BinaryWriter, Encoding};
+#[cfg(feature = "duckdb")]
+use crate::database::{Database, DatabaseWriter};
+#[cfg(feature = "openings")]
+use crate::openings::OpeningBook;
+#[cfg(feature = "sqlite")]
+use crate::sqlite::SqliteWriter;
+#[cfg(feature = "xlsx")]
+use crate::xlsx::XlsxWriter;
+use crate::{
+    args::{Args, STDIO},
+    assertions::{Assertions, FileChecks},
+    columns::LibraryColumn,
+    dedup::SeenRows,
+    describe,
+    encoding::{Encoded, Encodings},
+    log::Log,
+    merge::MergedCsvs,
+    partition::Partition,
+    profile::{Timed, Timer},
+    ratings::RatingStats,
+    reader::UringStats,
+    record::{self, Record},
+    sink::{CsvSink, JsonlSink, RowSink, SinkFactory},
+    stats::ColumnStats,
+    tournaments::Tournaments,
+    uid::GameUid,
+};
+#[cfg(feature = "parquet")]
+use crate::{columnar::ParquetWriter, huggingface::Dataset};
+
+/// The file format that rows are written in.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Format {
+    Csv,
+    Jsonl,
+    #[cfg(feature = "xlsx")]
+    Xlsx,
+    #[cfg(feature = "msgpack")]
+    MessagePack,
+    #[cfg(feature = "cbor")]
+    Cbor,
+    #[cfg(feature = "duckdb")]
+    DuckDb,
+    #[cfg(feature = "sqlite")]
+    Sqlite,
+    #[cfg(feature = "parquet")]
+    Parquet,
+    #[cfg(feature = "parquet")]
+    HuggingFace,
+}
+
+impl Format {
+    pub(crate) fn extension(self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::Jsonl => "jsonl",
+            #[cfg(feature = "xlsx")]
+            Format::Xlsx => "xlsx",
+            #[cfg(feature = "msgpack")]
+            Format::MessagePack => "msgpack",
+            #[cfg(feature = "cbor")]
+            Format::Cbor => "cbor",
+            #[cfg(feature = "duckdb")]
+            Format::DuckDb => "duckdb",
+            #[cfg(feature = "sqlite")]
+            Format::Sqlite => "sqlite",
+            #[cfg(feature = "parquet")]
+            Format::Parquet | Format::HuggingFace => "parquet",
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(format: &str) -> Result<Self> {
+        match format {
+            "csv" => Ok(Format::Csv),
+            "jsonl" => Ok(Format::Jsonl),
+            #[cfg(feature = "xlsx")]
+            "xlsx" => Ok(Format::Xlsx),
+            #[cfg(feature = "msgpack")]
+            "msgpack" => Ok(Format::MessagePack),
+            #[cfg(feature = "cbor")]
+            "cbor" => Ok(Format::Cbor),
+            #[cfg(feature = "duckdb")]
+            "duckdb" => Ok(Format::DuckDb),
+            #[cfg(feature = "sqlite")]
+            "sqlite" => Ok(Format::Sqlite),
+            #[cfg(feature = "parquet")]
+            "parquet" => Ok(Format::Parquet),
+            #[cfg(feature = "parquet")]
+            "huggingface" => Ok(Format::HuggingFace),
+            _ => bail!("unknown output format {format} (formats may need a cargo feature)"),
+        }
+    }
+}
+
+// there is one writer per file, so its size doesn't matter
+#[allow(clippy::large_enum_variant)]
+enum Writer {
+    // CSVs are written straight from the processor's rows, unless records
+    // were built for them anyway
+    Csv(CsvSink),
+    Sink(Box<dyn RowSink>),
+}
+
+/// State shared by the outputs of every file of a run.
+pub(crate) struct Shared {
+    seen: Option<SeenRows>,
+    columns: Vec<LibraryColumn>,
+    pub(crate) tournaments: Option<Tournaments>,
+    header_rows: bool,
+    dump_skipped: Option<usize>,
+    #[cfg(feature = "openings")]
+    openings: Option<OpeningBook>,
+    rating_stats: Option<RatingStats>,
+    /// The line that CSVs start with, if the schema version is written into
+    /// them.
+    pub(crate) schema_comment: Option<String>,
+    #[cfg(feature = "duckdb")]
+    database: Option<Arc<Database>>,
+    #[cfg(feature = "parquet")]
+    pub(crate) dataset: Option<Arc<Dataset>>,
+    sink: Option<Arc<SinkFactory>>,
+    // what rows are partitioned by, and the directory of the partitions
+    partitions: Option<(Partition, PathBuf)>,
+    /// How the values of rows are written.
+    pub(crate) encodings: Encodings,
+    /// The reads of PGNs through io_uring, for the run's summary.
+    pub(crate) uring: Arc<UringStats>,
+}
+
+impl Shared {
+    pub(crate) fn new(args: &Args, schema_version: &str, encodings: Encodings) -> Result<Self> {
+        Ok(Shared {
+            seen: (args.dedup && args.sort_by.is_none()).then(SeenRows::new),
+            columns: args.columns.clone(),
+            tournaments: args.tournaments.then(Tournaments::default),
+            header_rows: args.header_rows,
+            dump_skipped: args.dump_skipped,
+            #[cfg(feature = "openings")]
+            openings: opening_book(args)?,
+            rating_stats: rating_stats(args)?,
+            // only CSVs are written with it
+            schema_comment: args
+                .schema_comment
+                .then(|| describe::schema_comment(schema_version)),
+            #[cfg(feature = "duckdb")]
+            database: match args.format {
+                Format::DuckDb => Some(Arc::new(Database::open(&args.csv_dir)?)),
+                _ => None,
+            },
+            #[cfg(feature = "parquet")]
+            dataset: match args.format {
+                Format::HuggingFace => Some(Arc::new(Dataset::new(&args.csv_dir)?)),
+                _ => None,
+            },
+            sink: args.sink.clone(),
+            partitions: None,
+            encodings,
+            uring: Arc::default(),
+        })
+    }
+
+    /// Sends the rows of every output to `merged` rather than to files of
+    /// their own.
+    pub(crate) fn merge_into(&mut self, merged: &MergedCsvs) {
+        self.sink = Some(merged.sinks());
+    }
+
+    /// Sends the rows of every output to the partition of their game's value
+    /// of `partition`, a CSV in `dir` that `merged` writes.
+    pub(crate) fn partition_into(&mut self, merged: &MergedCsvs, partition: Partition, dir: &Path) {
+        self.merge_into(merged);
+        self.partitions = Some((partition, dir.to_path_buf()));
+    }
+}
+
+/// Loads the `--openings` books, if the opening columns need them.
+#[cfg(feature = "openings")]
+fn opening_book(args: &Args) -> Result<Option<OpeningBook>> {
+    let needed = args.columns.iter().any(|column| {
+        matches!(
+            column,
+            LibraryColumn::OpeningEco | LibraryColumn::OpeningName
+        )
+    });
+    if !needed {
+        return Ok(None);
+    }
+    if args.openings.is_empty() {
+        bail!("the opening library columns need a book of openings from --openings");
+    }
+    let mut book = OpeningBook::default();
+    for path in &args.openings {
+        book.load(path)?;
+    }
+    Ok(Some(book))
+}
+
+/// Loads the `--rating-stats`, if the rating z-score columns need them.
+fn rating_stats(args: &Args) -> Result<Option<RatingStats>> {
+    if !args
+        .columns
+        .iter()
+        .any(|column| column.needs_rating_stats())
+    {
+        return Ok(None);
+    }
+    match &args.rating_stats {
+        Some(path) => RatingStats::load(path).map(Some),
+        None => bail!(
+            "the rating z-score library columns need statistics from --rating-stats, which --write-rating-stats writes"
+        ),
+    }
+}
+
+/// The table that database formats write rows to, named after the binary, e.g.
+/// `time_odds`, so that different binaries can share a database.
+#[cfg(any(feature = "duckdb", feature = "sqlite"))]
+pub(crate) fn table_name() -> String {
+    let bin = std::env::args_os().next().unwrap_or_default();
+    let bin = Path::new(&bin).file_stem().unwrap_or_default();
+    bin.to_string_lossy()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+/// Where the file of an output at `path` is written until it is complete: next
+/// to it, with `.part` added, so that a file at an output's path is always
+/// whole, even after a run that failed or was killed.
+pub(crate) fn part_path(path: &Path) -> PathBuf {
+    let mut part = path.as_os_str().to_owned();
+    part.push(".part");
+    PathBuf::from(part)
+}
+
+/// Moves the complete file of an output from its `part_path` to `path`. Some
+/// formats don't create a file for an output without rows.
+pub(crate) fn publish(path: &Path) -> Result<()> {
+    match fs::rename(part_path(path), path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        renamed => Ok(renamed?),
+    }
+}
+
+fn create(path: &Path, timer: Option<Timer>) -> Result<Box<dyn Write>> {
+    if path == Path::new(STDIO) {
+        return Ok(match timer {
+            Some(timer) => Box::new(Timed::new(io::stdout(), Some(timer))),
+            None => Box::new(io::stdout()),
+        });
+    }
+    let file = File::create(path)?;
+    Ok(match timer {
+        Some(timer) => Box::new(Timed::new(file, Some(timer))),
+        None => Box::new(file),
+    })
+}
+
+impl Writer {
+    fn new(path: &Path, format: Format, timer: Option<Timer>, shared: &Shared) -> Result<Self> {
+        if let Some(sink) = &shared.sink {
+            return Ok(Writer::Sink(sink(path)?));
+        }
+        let sink: Box<dyn RowSink> = match format {
+            Format::Csv => {
+                let mut file = create(path, timer)?;
+                if let Some(comment) = &shared.schema_comment {
+                    writeln!(file, "{comment}")?;
+                }
+                return Ok(Writer::Csv(CsvSink::new(file)));
+            }
+            Format::Jsonl => Box::new(JsonlSink::new(create(path, timer)?)),
+            #[cfg(feature = "xlsx")]
+            Format::Xlsx => Box::new(XlsxWriter::new(path)?),
+            #[cfg(feature = "msgpack")]
+            Format::MessagePack => Box::new(BinaryWriter::new(
+                create(path, timer)?,
+                Encoding::MessagePack,
+            )),
+            #[cfg(feature = "cbor")]
+            Format::Cbor => Box::new(BinaryWriter::new(create(path, timer)?, Encoding::Cbor)),
+            #[cfg(feature = "duckdb")]
+            Format::DuckDb => match &shared.database {
+                Some(database) => Box::new(DatabaseWriter::new(database.clone())?),
+                None => bail!("no database to write to"),
+            },
+            #[cfg(feature = "sqlite")]
+            Format::Sqlite => Box::new(SqliteWriter::new(path)),
+            #[cfg(feature = "parquet")]
+            Format::Parquet | Format::HuggingFace => {
+                Box::new(ParquetWriter::new(path, shared.dataset.clone()))
+            }
+        };
+        Ok(Writer::Sink(sink))
+    }
+
+    /// Writes a row in `encodings`, given as its record if that has already
+    /// been built. Rows with library columns are always given as records,
+    /// since only the record has them.
+    fn write(
+        &mut self,
+        row: impl Serialize,
+        record: Option<Record>,
+        extras: bool,
+        encodings: Encodings,
+    ) -> Result<()> {
+        match self {
+            Writer::Csv(sink) => match record.filter(|_| extras) {
+                Some(record) => sink.write_row(&record),
+                None => sink.serialize(Encoded::new(&row, encodings)),
+            },
+            Writer::Sink(sink) => {
+                let record = record.map_or_else(|| record::to_record(&row, encodings), Ok)?;
+                sink.write_row(&record)
+            }
+        }
+    }
+
+    fn sink(&mut self) -> &mut dyn RowSink {
+        match self {
+            Writer::Csv(sink) => sink,
+            Writer::Sink(sink) => sink.as_mut(),
+        }
+    }
+}
+
+/// Where the rows converted from one PGN are written. Rows that the processor
+/// routes to a key go to a file of their own, named by inserting the key before
+/// the extension, e.g. `games.1400.csv`; the others go to `path`. Files are
+/// created when their first row is written, except that `path` is always
+/// created if no other file was. Partitioned rows go to the file of their
+/// partition instead of `path`, e.g. `2024-01.csv` or `2024-01.1400.csv`.
+pub(crate) struct Output<'a> {
+    path: PathBuf,
+    format: Format,
+    timer: Option<Timer>,
+    shared: &'a Shared,
+    // by partition and route
+    writers: BTreeMap<(Option<String>, Option<String>), Writer>,
+    // the table of every header of every game, if it is written
+    headers: Option<csv::Writer<Box<dyn Write>>>,
+    // the skipped games written out, and how many there are
+    skipped: Option<Box<dyn Write>>,
+    dumped: usize,
+    dedup: Option<Dedup<'a>>,
+    checks: Option<FileChecks<'a>>,
+    stats: Option<ColumnStats>,
+    rows: u64,
+}
+
+/// A header of a game, in the long format of the headers table.
+#[derive(Serialize)]
+struct HeaderRow<'a> {
+    game_uid: &'a GameUid,
+    key: &'a str,
+    #[serde(serialize_with = "crate::text::serialize")]
+    value: &'a str,
+}
+
+/// Drops rows that were already written to any output of the run.
+struct Dedup<'a> {
+    seen: &'a SeenRows,
+    // rows are serialized here first so that they can be looked up
+    scratch: Vec<u8>,
+}
+
+impl<'a> Output<'a> {
+    pub(crate) fn new(
+        path: &Path,
+        format: Format,
+        timer: Option<Timer>,
+        shared: &'a Shared,
+    ) -> Result<Self> {
+        let dedup = shared.seen.as_ref().map(|seen| Dedup {
+            seen,
+            scratch: Vec::new(),
+        });
+        Ok(Self {
+            path: path.to_path_buf(),
+            format,
+            timer,
+            shared,
+            writers: BTreeMap::new(),
+            headers: None,
+            skipped: None,
+            dumped: 0,
+            dedup,
+            checks: None,
+            stats: None,
+            rows: 0,
+        })
+    }
+
+    fn route_path(&self, partition: Option<&str>, route: Option<&str>) -> PathBuf {
+        let extension = self.format.extension();
+        let path = match (partition, &self.shared.partitions) {
+            (Some(partition), Some((_, dir))) => dir.join(format!("{partition}.{extension}")),
+            _ => self.path.clone(),
+        };
+        match route {
+            Some(route) => {
+                let route: String = route
+                    .chars()
+                    .map(|c| if std::path::is_separator(c) { '_' } else { c })
+                    .collect();
+                path.with_extension(format!("{route}.{extension}"))
+            }
+            None => path,
+        }
+    }
+
+    /// Whether the rows are written to files of their own, rather than to
+    /// stdout, a sink, or a database.
+    fn writes_files(&self) -> bool {
+        let files = self.path != Path::new(STDIO) && self.shared.sink.is_none();
+        #[cfg(feature = "duckdb")]
+        let files = files && self.format != Format::DuckDb;
+        files
+    }
+
+    /// The paths of all of the outputs written.
+    fn output_paths(&self) -> impl Iterator<Item = PathBuf> + '_ {
+        self.writers
+            .keys()
+            .map(|(partition, route)| self.route_path(partition.as_deref(), route.as_deref()))
+    }
+
+    /// The paths of all of the files written, where they are until they are
+    /// published.
+    pub(crate) fn paths(&self) -> Vec<PathBuf> {
+        match self.writes_files() {
+            true => self.output_paths().map(|path| part_path(&path)).collect(),
+            false => self.output_paths().collect(),
+        }
+    }
+
+    /// Moves every file written to the output's path, once it is complete,
+    /// rewritten and all.
+    pub(crate) fn publish(&self) -> Result<()> {
+        if self.writes_files() {
+            for path in self.output_paths() {
+                publish(&path)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn writer(&mut self, partition: Option<String>, route: Option<String>) -> Result<&mut Writer> {
+        // every row goes into the same table
+        #[cfg(feature = "duckdb")]
+        let route = route.filter(|_| self.format != Format::DuckDb);
+        let key = (partition, route);
+        if !self.writers.contains_key(&key) {
+            if key.1.is_some() && self.path == Path::new(STDIO) {
+                bail!("rows split into several outputs can't be written to stdout");
+            }
+            let mut path = self.route_path(key.0.as_deref(), key.1.as_deref());
+            if self.writes_files() {
+                path = part_path(&path);
+            }
+            let writer = Writer::new(&path, self.format, self.timer.clone(), self.shared)?;
+            self.writers.insert(key.clone(), writer);
+        }
+        Ok(self
+            .writers
+            .get_mut(&key)
+            .expect("writer was just inserted"))
+    }
+
+    /// What the rows are partitioned by, if they are.
+    pub(crate) fn partition(&self) -> Option<&'a Partition> {
+        self.shared
+            .partitions
+            .as_ref()
+            .map(|(partition, _)| partition)
+    }
+
+    pub(crate) fn check(&mut self, assertions: &'a Assertions, log: &'a Log, name: String) {
+        self.checks = Some(FileChecks::new(assertions, log, name));
+    }
+
+    pub(crate) fn collect_stats(&mut self) {
+        self.stats = Some(ColumnStats::default());
+    }
+
+    /// The number of rows written so far.
+    pub(crate) fn rows(&self) -> u64 {
+        self.rows
+    }
+
+    pub(crate) fn take_stats(&mut self) -> Option<ColumnStats> {
+        self.stats.take()
+    }
+
+    /// Where the tournaments of the file's games are collected, if they are.
+    pub(crate) fn tournaments(&self) -> Option<&'a Tournaments> {
+        self.shared.tournaments.as_ref()
+    }
+
+    /// The book that the openings of the file's games are classified with, if
+    /// they are.
+    #[cfg(feature = "openings")]
+    pub(crate) fn openings(&self) -> Option<&'a OpeningBook> {
+        self.shared.openings.as_ref()
+    }
+
+    /// The statistics that the ratings of the file's games are normalized
+    /// with, if they are.
+    pub(crate) fn rating_stats(&self) -> Option<&'a RatingStats> {
+        self.shared.rating_stats.as_ref()
+    }
+
+    /// Whether every header of every game is written to a table of its own.
+    pub(crate) fn header_rows(&self) -> bool {
+        self.shared.header_rows
+    }
+
+    /// Writes the headers of a game to the output's headers table, named e.g.
+    /// `games.headers.csv` for `games.csv`, with a row for each header. It is
+    /// always a CSV, whatever the format of the rows.
+    pub(crate) fn write_headers(
+        &mut self,
+        uid: &GameUid,
+        headers: &[(String, String)],
+    ) -> Result<()> {
+        if self.headers.is_none() {
+            if self.path == Path::new(STDIO) {
+                bail!("header rows can't be written next to stdout");
+            }
+            #[cfg(feature = "duckdb")]
+            if self.format == Format::DuckDb {
+                bail!("header rows can't be written next to a DuckDB database");
+            }
+            #[cfg(feature = "parquet")]
+            if self.format == Format::HuggingFace {
+                bail!("header rows can't be written into a Hugging Face dataset");
+            }
+            let path = self.path.with_extension("headers.csv");
+            let file = create(&path, self.timer.clone())?;
+            self.headers = Some(csv::Writer::from_writer(file));
+        }
+        let writer = self
+            .headers
+            .as_mut()
+            .expect("headers table was just created");
+        for (key, value) in headers {
+            let row = HeaderRow {
+                game_uid: uid,
+                key,
+                value,
+            };
+            writer.serialize(Encoded::new(&row, self.shared.encodings))?;
+        }
+        Ok(())
+    }
+
+    /// Whether skipped games are written out, so that their text is needed.
+    pub(crate) fn dumps_skipped(&self) -> bool {
+        self.shared.dump_skipped.is_some()
+    }
+
+    /// Writes the text of a skipped game to a PGN next to the output, named
+    /// e.g. `games.skipped.pgn` for `games.csv`, until `--dump-skipped` games
+    /// have been written. Each game is preceded by a `%` escape line, which
+    /// PGN readers ignore, giving why it was skipped.
+    pub(crate) fn dump_skipped(&mut self, text: &[u8], reason: Option<String>) -> Result<()> {
+        if self.dumped >= self.shared.dump_skipped.unwrap_or(0) {
+            return Ok(());
+        }
+        if self.skipped.is_none() {
+            let path = self.path.with_extension("skipped.pgn");
+            self.skipped = Some(Box::new(BufWriter::new(create(&path, None)?)));
+        }
+        let file = self
+            .skipped
+            .as_mut()
+            .expect("skipped games file was just created");
+        let reason = reason.as_deref().unwrap_or("by the processor");
+        writeln!(file, "% skipped {}", reason.replace('\n', " "))?;
+        file.write_all(text.trim_ascii_end())?;
+        file.write_all(b"\n\n")?;
+        self.dumped += 1;
+        Ok(())
+    }
+
+    /// How the values of rows are written.
+    pub(crate) fn encodings(&self) -> Encodings {
+        self.shared.encodings
+    }
+
+    /// Where reads of PGNs through io_uring are counted.
+    pub(crate) fn uring_stats(&self) -> &'a Arc<UringStats> {
+        &self.shared.uring
+    }
+
+    /// The library columns added to every row.
+    pub(crate) fn library_columns(&self) -> &[LibraryColumn] {
+        &self.shared.columns
+    }
+
+    pub(crate) fn write_row(
+        &mut self,
+        partition: Option<String>,
+        route: Option<String>,
+        row: impl Serialize,
+        extras: Vec<(Cow<'static, str>, Value)>,
+    ) -> Result<()> {
+        let encodings = self.shared.encodings;
+        if let Some(dedup) = &mut self.dedup {
+            dedup.scratch.clear();
+            let mut scratch = csv::WriterBuilder::new()
+                .has_headers(false)
+                .from_writer(&mut dedup.scratch);
+            scratch.serialize(Encoded::new(&row, encodings))?;
+            scratch.flush()?;
+            drop(scratch);
+            if !dedup.seen.insert(&dedup.scratch) {
+                return Ok(());
+            }
+        }
+        let has_extras = !extras.is_empty();
+        let record = if self.checks.is_some() || self.stats.is_some() || has_extras {
+            let mut record = record::to_record(&row, encodings)?;
+            for (name, value) in extras {
+                record.insert(name.into_owned(), value);
+            }
+            Some(record)
+        } else {
+            None
+        };
+        if let (Some(checks), Some(record)) = (&mut self.checks, &record) {
+            if !checks.check(record)? {
+                return Ok(());
+            }
+        }
+        if let (Some(stats), Some(record)) = (&mut self.stats, &record) {
+            stats.add(record);
+        }
+        self.writer(partition, route)?
+            .write(row, record, has_extras, encodings)?;
+        self.rows += 1;
+        Ok(())
+    }
+
+    /// Writes out the rows buffered so far, once all rows of a PGN have been
+    /// written.
+    pub(crate) fn flush(&mut self) -> Result<()> {
+        for writer in self.writers.values_mut() {
+            writer.sink().flush()?;
+        }
+        Ok(())
+    }
+
+    /// Writes out anything still buffered, once all rows have been written.
+    pub(crate) fn finish(&mut self) -> Result<()> {
+        if let Some(checks) = &self.checks {
+            checks.finish();
+        }
+        if self.writers.is_empty() {
+            self.writer(None, None)?;
+        }
+        for writer in self.writers.values_mut() {
+            writer.sink().finalize()?;
+        }
+        if let Some(headers) = &mut self.headers {
+            headers.flush()?;
+        }
+        if let Some(skipped) = &mut self.skipped {
+            skipped.flush()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,148 @@
+use std::str::FromStr;
+
+use anyhow::{bail, Error, Result};
+use pgn_reader::RawHeader;
+
+use crate::headers::{GameDateTime, Speed, TimeControl};
+
+/// The file name of the partition of games without the partition's value.
+pub(crate) const UNKNOWN: &str = "unknown";
+
+/// A value of each game's headers to partition a run's rows by: the rows of
+/// every PGN with the same value are written to one file named after it, in
+/// the output directory, e.g. `2024-01.csv` and `2024-02.csv` for `Month`.
+/// Games without the value go to `unknown.csv`.
+///
+/// ```
+/// use pgn2csv::partition::Partition;
+///
+/// assert_eq!("month".parse::<Partition>().unwrap(), Partition::Month);
+/// assert_eq!(
+///     "Event".parse::<Partition>().unwrap(),
+///     Partition::Header("Event".to_owned())
+/// );
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Partition {
+    /// The month the game was played in, like `2024-01`, from `UTCDate`, or
+    /// else `Date`.
+    Month,
+    /// The speed of the game's time control, like `blitz` (see `Speed`).
+    Speed,
+    /// The value of a header, e.g. `Event`.
+    Header(String),
+}
+
+impl FromStr for Partition {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "month" => Ok(Partition::Month),
+            "speed" => Ok(Partition::Speed),
+            "" => bail!("expected month, speed, or a header to partition by"),
+            header => Ok(Partition::Header(header.to_owned())),
+        }
+    }
+}
+
+/// Reads the value of a `Partition` from the headers of each game.
+// only used by the pipeline, which writes the partitions
+#[cfg_attr(not(feature = "pipeline"), allow(dead_code))]
+pub(crate) struct PartitionValue<'a> {
+    partition: &'a Partition,
+    date_time: GameDateTime,
+    time_control: Option<TimeControl>,
+    header: Option<String>,
+}
+
+#[cfg_attr(not(feature = "pipeline"), allow(dead_code))]
+impl<'a> PartitionValue<'a> {
+    pub(crate) fn new(partition: &'a Partition) -> Self {
+        PartitionValue {
+            partition,
+            date_time: GameDateTime::default(),
+            time_control: None,
+            header: None,
+        }
+    }
+
+    pub(crate) fn begin_game(&mut self) {
+        self.date_time.reset();
+        self.time_control = None;
+        self.header = None;
+    }
+
+    pub(crate) fn header(&mut self, key: &[u8], value: RawHeader<'_>) {
+        match self.partition {
+            Partition::Month => {
+                self.date_time.header(key, value);
+            }
+            Partition::Speed if key == b"TimeControl" => {
+                self.time_control = TimeControl::try_from(value).ok();
+            }
+            Partition::Header(header) if key == header.as_bytes() => {
+                self.header = Some(String::from_utf8_lossy(value.as_bytes()).into_owned());
+            }
+            _ => (),
+        }
+    }
+
+    /// The current game's value, as a file name without an extension.
+    pub(crate) fn value(&self) -> String {
+        let value = match self.partition {
+            Partition::Month => self
+                .date_time
+                .date()
+                .and_then(|date| Some(format!("{:04}-{:02}", date.year?, date.month?))),
+            Partition::Speed => self
+                .time_control
+                .as_ref()
+                .map(|tc| Speed::from(tc).name().to_lowercase()),
+            Partition::Header(_) => self.header.clone(),
+        };
+        match value {
+            Some(value) if !value.is_empty() && value != "?" => value
+                .chars()
+                .map(|c| {
+                    if std::path::is_separator(c) || c.is_control() {
+                        '_'
+                    } else {
+                        c
+                    }
+                })
+                .collect(),
+            _ => UNKNOWN.to_owned(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_partition_values() {
+        let value = |partition: &str, headers: &[(&[u8], &[u8])]| {
+            let partition = partition.parse().unwrap();
+            let mut value = PartitionValue::new(&partition);
+            value.begin_game();
+            for &(key, header) in headers {
+                value.header(key, RawHeader(header));
+            }
+            value.value()
+        };
+        let headers: &[(&[u8], &[u8])] = &[
+            (b"Event", b"Rated Blitz game"),
+            (b"UTCDate", b"2024.01.05"),
+            (b"TimeControl", b"180+2"),
+            (b"Site", b"https://lichess.org/abcdefgh"),
+        ];
+        assert_eq!(value("month", headers), "2024-01");
+        assert_eq!(value("speed", headers), "blitz");
+        assert_eq!(value("Event", headers), "Rated Blitz game");
+        assert_eq!(value("Site", headers), "https:__lichess.org_abcdefgh");
+        assert_eq!(value("month", &[(b"UTCDate", b"????.??.??")]), UNKNOWN);
+        assert_eq!(value("White", headers), UNKNOWN);
+    }
+}
@@ -0,0 +1,123 @@
+use serde_json::Value;
+
+use crate::record::{self, Record};
+
+// cells are cut to this many characters, so that a long column doesn't push
+// the others off the screen
+const MAX_WIDTH: usize = 30;
+
+/// The type of a column, from its values in the previewed rows, as it would
+/// be taken by the typed formats: `int`, `float`, `bool`, or `string`, with a
+/// `?` if some rows have no value. Columns with no values at all are `null`.
+fn column_type<'a>(values: impl Iterator<Item = &'a Value>) -> String {
+    let (mut ty, mut nullable) = (None, false);
+    for value in values {
+        let this = match value {
+            _ if record::is_null(value) => {
+                nullable = true;
+                continue;
+            }
+            Value::Bool(_) => "bool",
+            Value::Number(n) if n.is_f64() => "float",
+            Value::Number(_) => "int",
+            _ => "string",
+        };
+        ty = Some(match (ty, this) {
+            (None, this) => this,
+            (Some(ty), this) if ty == this => ty,
+            (Some("int" | "float"), "int" | "float") => "float",
+            _ => "string",
+        });
+    }
+    match ty {
+        Some(ty) if nullable => format!("{ty}?"),
+        Some(ty) => ty.to_owned(),
+        None => "null".to_owned(),
+    }
+}
+
+fn cell(value: &str) -> String {
+    if value.chars().count() <= MAX_WIDTH {
+        return value.to_owned();
+    }
+    let mut cut: String = value.chars().take(MAX_WIDTH - 1).collect();
+    cut.push('…');
+    cut
+}
+
+/// Lays out rows as an aligned table for the terminal: a line of column
+/// names, a line of their types, and a line per row, with numbers aligned
+/// right.
+pub(crate) fn table(rows: &[Record]) -> String {
+    let Some(first) = rows.first() else {
+        return String::new();
+    };
+    let columns: Vec<(String, String, Vec<String>)> = first
+        .keys()
+        .map(|name| {
+            let values = rows.iter().filter_map(|row| row.get(name));
+            let ty = column_type(values.clone());
+            let cells = values.map(|value| cell(&record::field(value))).collect();
+            (cell(name), ty, cells)
+        })
+        .collect();
+    let widths: Vec<usize> = columns
+        .iter()
+        .map(|(name, ty, cells)| {
+            let cells = cells.iter().map(|cell| cell.chars().count());
+            cells
+                .chain([name.chars().count(), ty.len()])
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    let line = |cells: &mut dyn Iterator<Item = (&str, bool)>| {
+        let cells: Vec<String> = cells
+            .zip(&widths)
+            .map(|((cell, right), &width)| match right {
+                true => format!("{cell:>width$}"),
+                false => format!("{cell:<width$}"),
+            })
+            .collect();
+        cells.join("  ").trim_end().to_owned() + "\n"
+    };
+    let mut table = line(&mut columns.iter().map(|(name, ..)| (name.as_str(), false)));
+    table += &line(&mut columns.iter().map(|(_, ty, _)| (ty.as_str(), false)));
+    for i in 0..rows.len() {
+        table += &line(&mut columns.iter().map(|(_, ty, cells)| {
+            let numeric = ty.starts_with("int") || ty.starts_with("float");
+            (cells.get(i).map_or("", String::as_str), numeric)
+        }));
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    #[test]
+    fn lays_out_tables() {
+        let rows: Vec<Record> = [
+            json!({"white": "a", "white_elo": 1500, "eval": 0.5, "rated": true}),
+            json!({"white": "bob", "white_elo": 900, "eval": 1, "rated": null}),
+        ]
+        .into_iter()
+        .filter_map(|row| match row {
+            Value::Object(row) => Some(row),
+            _ => None,
+        })
+        .collect();
+        assert_eq!(
+            table(&rows),
+            "\
+white   white_elo  eval   rated
+string  int        float  bool?
+a            1500    0.5  true
+bob           900      1
+"
+        );
+    }
+}
@@ -0,0 +1,196 @@
+use std::{
+    cell::Cell,
+    fs::File,
+    io::{self, BufWriter, Read, Write},
+    path::Path,
+    rc::Rc,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use serde::Serialize;
+
+/// A running total of time, shared between a timed reader or writer and the
+/// loop that processes a file.
+#[derive(Clone, Default)]
+pub(crate) struct Timer(Rc<Cell<Duration>>);
+
+impl Timer {
+    fn add(&self, elapsed: Duration) {
+        self.0.set(self.0.get() + elapsed);
+    }
+
+    fn get(&self) -> Duration {
+        self.0.get()
+    }
+}
+
+/// A reader or writer that adds the time spent in its calls to a `Timer`, if
+/// it is being profiled.
+pub(crate) struct Timed<T> {
+    inner: T,
+    timer: Option<Timer>,
+}
+
+impl<T> Timed<T> {
+    pub(crate) fn new(inner: T, timer: Option<Timer>) -> Self {
+        Timed { inner, timer }
+    }
+
+    fn time<U>(&mut self, call: impl FnOnce(&mut T) -> U) -> U {
+        let Some(timer) = &self.timer else {
+            return call(&mut self.inner);
+        };
+        let start = Instant::now();
+        let result = call(&mut self.inner);
+        timer.add(start.elapsed());
+        result
+    }
+}
+
+impl<R: Read> Read for Timed<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.time(|inner| inner.read(buf))
+    }
+}
+
+impl<W: Write> Write for Timed<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.time(|inner| inner.write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.time(Write::flush)
+    }
+}
+
+/// The timings collected while converting one PGN.
+#[derive(Default)]
+pub(crate) struct FileTimers {
+    /// Reading and decompressing the PGN.
+    pub(crate) decompress: Timer,
+    /// Writing serialized rows to the CSV file.
+    pub(crate) write: Timer,
+    /// Inside the processor's `Visitor` callbacks.
+    pub(crate) callbacks: Duration,
+    /// Inside `read_game`, which includes decompression and callbacks.
+    pub(crate) read_games: Duration,
+    /// Inside `write_row`, which includes writing.
+    pub(crate) write_rows: Duration,
+}
+
+impl FileTimers {
+    fn stages(&self) -> [(&'static str, Duration); 5] {
+        let decompress = self.decompress.get();
+        let write = self.write.get();
+        [
+            ("decompress", decompress),
+            (
+                "parse",
+                self.read_games
+                    .saturating_sub(decompress)
+                    .saturating_sub(self.callbacks),
+            ),
+            ("processor callbacks", self.callbacks),
+            ("serialize", self.write_rows.saturating_sub(write)),
+            ("write", write),
+        ]
+    }
+}
+
+struct FileProfile {
+    name: String,
+    thread: usize,
+    start: Duration,
+    total: Duration,
+    stages: [(&'static str, Duration); 5],
+}
+
+/// Collects per-file stage timings across all workers and writes them as a
+/// Chrome trace (viewable in e.g. `chrome://tracing` or Perfetto).
+pub(crate) struct Profiler {
+    start: Instant,
+    files: Mutex<Vec<FileProfile>>,
+}
+
+#[derive(Serialize)]
+struct TraceEvent<'a> {
+    name: &'a str,
+    cat: &'static str,
+    ph: &'static str,
+    ts: f64,
+    dur: f64,
+    pid: u32,
+    tid: usize,
+}
+
+#[derive(Serialize)]
+struct Trace<'a> {
+    #[serde(rename = "traceEvents")]
+    trace_events: Vec<TraceEvent<'a>>,
+}
+
+fn micros(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1e6
+}
+
+impl Profiler {
+    pub(crate) fn new() -> Self {
+        Profiler {
+            start: Instant::now(),
+            files: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub(crate) fn record(&self, name: String, start: Instant, timers: &FileTimers) {
+        let profile = FileProfile {
+            name,
+            thread: rayon::current_thread_index().unwrap_or(0),
+            start: start.duration_since(self.start),
+            total: start.elapsed(),
+            stages: timers.stages(),
+        };
+        if let Ok(mut files) = self.files.lock() {
+            files.push(profile);
+        }
+    }
+
+    /// Writes one event per file, containing one event per stage. Stages are
+    /// totals over the whole file, so they are laid out back to back inside the
+    /// file's event rather than at the times they actually ran.
+    pub(crate) fn write(&self, path: &Path) -> Result<()> {
+        let files = self
+            .files
+            .lock()
+            .map_err(|_| anyhow::anyhow!("profiler lock poisoned"))?;
+        let mut trace_events = Vec::new();
+        for file in files.iter() {
+            trace_events.push(TraceEvent {
+                name: &file.name,
+                cat: "file",
+                ph: "X",
+                ts: micros(file.start),
+                dur: micros(file.total),
+                pid: 0,
+                tid: file.thread,
+            });
+            let mut ts = micros(file.start);
+            for (name, duration) in file.stages {
+                trace_events.push(TraceEvent {
+                    name,
+                    cat: "stage",
+                    ph: "X",
+                    ts,
+                    dur: micros(duration),
+                    pid: 0,
+                    tid: file.thread,
+                });
+                ts += micros(duration);
+            }
+        }
+        let writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer(writer, &Trace { trace_events })?;
+        Ok(())
+    }
+}
@@ -0,0 +1,235 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, Read},
+    path::Path,
+};
+
+use anyhow::{anyhow, Context, Result};
+use bstr_parse::BStrParse;
+use pgn_reader::{BufferedReader, RawHeader, Skip, Visitor};
+use serde::{Deserialize, Serialize};
+
+use crate::headers::{GameDateTime, Speed, TimeControl};
+
+/// The month and speed that ratings are normalized within, since lichess
+/// ratings drift over the years and differ between speeds.
+type Pool = ((u16, u8), Speed);
+
+/// The mean and spread of the ratings of a pool, gathered with Welford's
+/// algorithm so that a pass over the PGNs doesn't keep the ratings.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Moments {
+    count: u64,
+    mean: f64,
+    // the sum of squared differences from the mean
+    m2: f64,
+}
+
+impl Moments {
+    fn add(&mut self, rating: f64) {
+        self.count += 1;
+        let delta = rating - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (rating - self.mean);
+    }
+
+    /// Combines the moments of two sets of ratings, e.g. of two PGNs read in
+    /// parallel.
+    fn merge(&mut self, other: Moments) {
+        let count = self.count + other.count;
+        if count == 0 {
+            return;
+        }
+        let delta = other.mean - self.mean;
+        self.mean += delta * other.count as f64 / count as f64;
+        self.m2 += other.m2 + delta * delta * (self.count * other.count) as f64 / count as f64;
+        self.count = count;
+    }
+
+    fn std(&self) -> f64 {
+        match self.count {
+            0 => 0.0,
+            count => (self.m2 / count as f64).sqrt(),
+        }
+    }
+}
+
+/// A line of a statistics file.
+#[derive(Serialize, Deserialize)]
+struct PoolRow {
+    /// e.g. `2024-01`
+    month: String,
+    speed: String,
+    ratings: u64,
+    mean: f64,
+    std: f64,
+}
+
+/// The mean and standard deviation of the ratings of each month and speed,
+/// for the `white_elo_z` and `black_elo_z` library columns. They are
+/// gathered in a first pass with `--write-rating-stats`, and read back from
+/// its CSV with `--rating-stats`.
+#[derive(Default)]
+pub(crate) struct RatingStats {
+    pools: HashMap<Pool, Moments>,
+}
+
+impl RatingStats {
+    /// Reads the statistics that `write` wrote to `path`.
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("can't open {}", path.display()))?;
+        Self::read_csv(file)
+            .with_context(|| format!("can't read rating statistics from {}", path.display()))
+    }
+
+    fn read_csv(reader: impl Read) -> Result<Self> {
+        let mut stats = RatingStats::default();
+        for row in csv::Reader::from_reader(reader).deserialize() {
+            let row: PoolRow = row?;
+            let month = row
+                .month
+                .split_once('-')
+                .and_then(|(year, month)| Some((year.parse().ok()?, month.parse().ok()?)))
+                .ok_or_else(|| anyhow!("expected a month like 2024-01, not {}", row.month))?;
+            let speed = Speed::from_name(row.speed.as_bytes())
+                .ok_or_else(|| anyhow!("unknown speed {}", row.speed))?;
+            // only the count, mean, and spread are written, and the sum of
+            // squares is rebuilt from them
+            let moments = Moments {
+                count: row.ratings,
+                mean: row.mean,
+                m2: row.std * row.std * row.ratings as f64,
+            };
+            stats.pools.insert((month, speed), moments);
+        }
+        Ok(stats)
+    }
+
+    /// Writes the statistics as a CSV with `month`, `speed`, `ratings`,
+    /// `mean`, and `std` columns, ordered by month and speed.
+    pub(crate) fn write(&self, path: &Path) -> Result<()> {
+        let mut pools: Vec<_> = self.pools.iter().collect();
+        pools.sort_by_key(|&(&(month, speed), _)| (month, speed.name()));
+        let mut csv = csv::Writer::from_path(path)
+            .with_context(|| format!("can't write {}", path.display()))?;
+        for (&((year, month), speed), moments) in pools {
+            csv.serialize(PoolRow {
+                month: format!("{year:04}-{month:02}"),
+                speed: speed.name().to_owned(),
+                ratings: moments.count,
+                mean: moments.mean,
+                std: moments.std(),
+            })?;
+        }
+        csv.flush()?;
+        Ok(())
+    }
+
+    /// Gathers the ratings of every game of a PGN, from their headers.
+    pub(crate) fn read_pgn(reader: impl Read) -> io::Result<Self> {
+        let mut visitor = GameRatings {
+            stats: RatingStats::default(),
+            date_time: GameDateTime::default(),
+            time_control: None,
+            elos: [None; 2],
+        };
+        let mut reader = BufferedReader::new(reader);
+        while reader.read_game(&mut visitor)?.is_some() {}
+        Ok(visitor.stats)
+    }
+
+    pub(crate) fn merge(&mut self, other: RatingStats) {
+        for (pool, moments) in other.pools {
+            self.pools.entry(pool).or_default().merge(moments);
+        }
+    }
+
+    /// How many standard deviations `rating` is from the mean of the
+    /// ratings of its month and speed, if they are known and spread out.
+    pub(crate) fn z_score(&self, rating: u16, month: (u16, u8), speed: Speed) -> Option<f64> {
+        let moments = self.pools.get(&(month, speed))?;
+        let std = moments.std();
+        (std > 0.0).then(|| (f64::from(rating) - moments.mean) / std)
+    }
+}
+
+/// The month and speed of a game, from its date and time control headers, if
+/// both are known.
+pub(crate) fn pool(date_time: &GameDateTime, time_control: Option<&TimeControl>) -> Option<Pool> {
+    let date = date_time.date()?;
+    Some(((date.year?, date.month?), Speed::from(time_control?)))
+}
+
+/// Reads the headers of each game into `stats`.
+struct GameRatings {
+    stats: RatingStats,
+    date_time: GameDateTime,
+    time_control: Option<TimeControl>,
+    elos: [Option<u16>; 2],
+}
+
+impl Visitor for GameRatings {
+    type Result = ();
+
+    fn begin_game(&mut self) {
+        self.date_time.reset();
+        self.time_control = None;
+        self.elos = [None; 2];
+    }
+
+    fn header(&mut self, key: &[u8], value: RawHeader<'_>) {
+        match key {
+            b"WhiteElo" => self.elos[0] = value.as_bytes().parse().ok(),
+            b"BlackElo" => self.elos[1] = value.as_bytes().parse().ok(),
+            b"TimeControl" => self.time_control = TimeControl::try_from(value).ok(),
+            _ => {
+                self.date_time.header(key, value);
+            }
+        }
+    }
+
+    fn end_headers(&mut self) -> Skip {
+        Skip(true)
+    }
+
+    fn end_game(&mut self) {
+        let Some(pool) = pool(&self.date_time, self.time_control.as_ref()) else {
+            return;
+        };
+        let moments = self.stats.pools.entry(pool).or_default();
+        for elo in self.elos.into_iter().flatten() {
+            moments.add(f64::from(elo));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_ratings() {
+        let pgn = |month: &str, elos: [u16; 2]| {
+            format!(
+                "[UTCDate \"2024.{month}.05\"]\n[TimeControl \"180+0\"]\n[WhiteElo \"{}\"]\n[BlackElo \"{}\"]\n\n1. e4 *\n\n",
+                elos[0], elos[1]
+            )
+        };
+        let mut stats = RatingStats::read_pgn(pgn("01", [1400, 1600]).as_bytes()).unwrap();
+        let february = pgn("02", [2000, 2000]) + &pgn("01", [1500, 1500]);
+        stats.merge(RatingStats::read_pgn(february.as_bytes()).unwrap());
+        let z = stats.z_score(1600, (2024, 1), Speed::Blitz).unwrap();
+        assert!((z - 1.0 / 0.5f64.sqrt()).abs() < 1e-9);
+        // every rating of February is the same
+        assert_eq!(stats.z_score(2000, (2024, 2), Speed::Blitz), None);
+        assert_eq!(stats.z_score(1600, (2024, 1), Speed::Rapid), None);
+
+        let path = std::env::temp_dir().join(format!("pgn2csv-ratings-{}.csv", std::process::id()));
+        stats.write(&path).unwrap();
+        let read = RatingStats::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let read = read.z_score(1600, (2024, 1), Speed::Blitz).unwrap();
+        assert!((read - z).abs() < 1e-9);
+    }
+}
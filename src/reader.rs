@@ -0,0 +1,243 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::Result;
+use bzip2::read::MultiBzDecoder;
+use flate2::read::MultiGzDecoder;
+use zstd::stream::read::Decoder as ZstdDecoder;
+
+use crate::log::UringReads;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+use crate::uring::UringReader;
+
+/// The bytes of a PGN as they are stored. Each kind of source is a variant
+/// rather than a boxed `Read`, so that reads are dispatched statically and
+/// sources that can seek do.
+pub(crate) enum Source {
+    File(File),
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    Uring(Box<UringReader>),
+    /// An uncompressed PGN mapped into memory, so that `BufferedReader` is fed
+    /// from a slice rather than by read syscalls.
+    #[cfg(feature = "mmap")]
+    Mmap(io::Cursor<memmap2::Mmap>),
+    /// A PGN from any other `PgnSource`, e.g. streamed over the network or
+    /// read from stdin, which is slow enough that dynamic dispatch doesn't
+    /// matter.
+    Dyn(Box<dyn Read>),
+}
+
+impl Source {
+    /// Reads a local file, through io_uring if it is enabled and the kernel
+    /// supports it, adding its reads to `stats`.
+    pub(crate) fn file(file: File, stats: Option<Arc<UringStats>>) -> Self {
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        if let Ok(ring) = io_uring::IoUring::new(2) {
+            return Source::Uring(Box::new(UringReader::new(file, ring, stats)));
+        }
+        #[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+        let _ = stats;
+        Source::File(file)
+    }
+
+    /// Reads a local uncompressed PGN, from a memory map if it is enabled.
+    #[cfg(not(feature = "mmap"))]
+    pub(crate) fn plain_file(file: File, stats: Option<Arc<UringStats>>) -> Result<Self> {
+        Ok(Source::file(file, stats))
+    }
+
+    /// Reads a local uncompressed PGN, from a memory map if it is enabled.
+    #[cfg(feature = "mmap")]
+    pub(crate) fn plain_file(file: File, _stats: Option<Arc<UringStats>>) -> Result<Self> {
+        // SAFETY: the map is only read, and we assume PGNs aren't truncated or
+        // modified by other processes while they are being converted.
+        let mmap = unsafe { memmap2::Mmap::map(&file)? };
+        #[cfg(unix)]
+        mmap.advise(memmap2::Advice::Sequential)?;
+        Ok(Source::Mmap(io::Cursor::new(mmap)))
+    }
+
+    /// Reads a PGN from any other source, finding out from its first bytes
+    /// how it is compressed. They are read again after that.
+    pub(crate) fn sniff(mut reader: Box<dyn Read>) -> io::Result<(Self, Compression)> {
+        let mut magic = Vec::with_capacity(4);
+        (&mut reader).take(4).read_to_end(&mut magic)?;
+        let compression = Compression::from_magic(&magic);
+        let reader = Box::new(io::Cursor::new(magic).chain(reader));
+        Ok((Source::Dyn(reader), compression))
+    }
+}
+
+impl Read for Source {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Source::File(file) => file.read(buf),
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            Source::Uring(reader) => reader.read(buf),
+            #[cfg(feature = "mmap")]
+            Source::Mmap(cursor) => cursor.read(buf),
+            Source::Dyn(reader) => reader.read(buf),
+        }
+    }
+}
+
+impl Seek for Source {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Source::File(file) => file.seek(pos),
+            #[cfg(feature = "mmap")]
+            Source::Mmap(cursor) => cursor.seek(pos),
+            #[allow(unreachable_patterns)]
+            _ => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "this PGN source can't seek",
+            )),
+        }
+    }
+}
+
+/// The reads of local files through io_uring during a run, whose throughput
+/// the run's summary reports.
+#[derive(Default)]
+pub(crate) struct UringStats {
+    bytes: AtomicU64,
+    wait_nanos: AtomicU64,
+}
+
+impl UringStats {
+    /// Counts a read of `bytes` that took `wait` to complete.
+    #[cfg_attr(not(all(target_os = "linux", feature = "io-uring")), allow(dead_code))]
+    pub(crate) fn add(&self, bytes: u64, wait: Duration) {
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        let nanos = u64::try_from(wait.as_nanos()).unwrap_or(u64::MAX);
+        self.wait_nanos.fetch_add(nanos, Ordering::Relaxed);
+    }
+
+    /// What was read, if anything was.
+    pub(crate) fn reads(&self) -> Option<UringReads> {
+        let bytes = self.bytes.load(Ordering::Relaxed);
+        (bytes > 0).then(|| UringReads {
+            bytes,
+            wait_seconds: Duration::from_nanos(self.wait_nanos.load(Ordering::Relaxed))
+                .as_secs_f64(),
+        })
+    }
+}
+
+/// How a PGN is compressed, from its extension.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Compression {
+    None,
+    Bzip2,
+    Zstd,
+    Gzip,
+}
+
+impl Compression {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            Compression::Bzip2 => "bzip2",
+            Compression::Zstd => "zstd",
+            Compression::Gzip => "gzip",
+        }
+    }
+
+    pub(crate) fn from_extension(ext: Option<&str>) -> Self {
+        match ext {
+            Some("bz2") => Compression::Bzip2,
+            Some("zst") => Compression::Zstd,
+            Some("gz") => Compression::Gzip,
+            _ => Compression::None,
+        }
+    }
+
+    /// The compression of a PGN from its first bytes, for PGNs whose name
+    /// doesn't tell, e.g. one piped into stdin.
+    pub(crate) fn from_magic(bytes: &[u8]) -> Self {
+        match bytes {
+            [0x42, 0x5a, 0x68, ..] => Compression::Bzip2,
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Compression::Zstd,
+            [0x1f, 0x8b, ..] => Compression::Gzip,
+            _ => Compression::None,
+        }
+    }
+}
+
+/// The decompressed bytes of a PGN.
+pub(crate) enum PgnReader {
+    Plain(Source),
+    Bzip2(MultiBzDecoder<Source>),
+    Zstd(ZstdDecoder<'static, BufReader<Source>>),
+    Gzip(MultiGzDecoder<Source>),
+}
+
+impl PgnReader {
+    pub(crate) fn new(source: Source, compression: Compression) -> Result<Self> {
+        Ok(match compression {
+            Compression::None => PgnReader::Plain(source),
+            Compression::Bzip2 => PgnReader::Bzip2(MultiBzDecoder::new(source)),
+            Compression::Zstd => PgnReader::Zstd(ZstdDecoder::new(source)?),
+            Compression::Gzip => PgnReader::Gzip(MultiGzDecoder::new(source)),
+        })
+    }
+}
+
+impl Read for PgnReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            PgnReader::Plain(source) => source.read(buf),
+            PgnReader::Bzip2(decoder) => decoder.read(buf),
+            PgnReader::Zstd(decoder) => decoder.read(buf),
+            PgnReader::Gzip(decoder) => decoder.read(buf),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    #[test]
+    fn decompresses_gzip() {
+        let pgn = b"[Event \"Rated Blitz game\"]\n\n1. e4 e5 1-0\n";
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        encoder.write_all(pgn).unwrap();
+        let path = std::env::temp_dir().join(format!("pgn2csv-{}.pgn.gz", std::process::id()));
+        std::fs::write(&path, encoder.finish().unwrap()).unwrap();
+        let source = Source::file(File::open(&path).unwrap(), None);
+        let mut reader = PgnReader::new(source, Compression::Gzip).unwrap();
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).unwrap();
+        assert_eq!(read, pgn);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn sniffs_compression() {
+        let pgn = b"[Event \"Rated Blitz game\"]\n\n1. e4 e5 1-0\n";
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        encoder.write_all(pgn).unwrap();
+        let gzip = encoder.finish().unwrap();
+        for (bytes, expected) in [(gzip, Compression::Gzip), (pgn.to_vec(), Compression::None)] {
+            let (source, compression) = Source::sniff(Box::new(io::Cursor::new(bytes))).unwrap();
+            assert_eq!(compression, expected);
+            let mut read = Vec::new();
+            PgnReader::new(source, compression)
+                .unwrap()
+                .read_to_end(&mut read)
+                .unwrap();
+            assert_eq!(read, pgn);
+        }
+        assert_eq!(Compression::from_magic(b"[E"), Compression::None);
+    }
+}
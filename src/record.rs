@@ -0,0 +1,34 @@
+use anyhow::{bail, Result};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::encoding::{Encoded, Encodings};
+
+/// A row as named, typed values in column order, for the outputs and checks
+/// that need more than the row's CSV text.
+pub type Record = Map<String, Value>;
+
+pub(crate) fn to_record(row: &impl Serialize, encodings: Encodings) -> Result<Record> {
+    match serde_json::to_value(Encoded::new(row, encodings))? {
+        Value::Object(record) => Ok(record),
+        _ => bail!("rows must serialize as structs or maps to be inspected"),
+    }
+}
+
+/// A value as a CSV field.
+pub(crate) fn field(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        _ => value.to_string(),
+    }
+}
+
+/// Whether a value is missing, which in a CSV is the same as being empty.
+pub(crate) fn is_null(value: &Value) -> bool {
+    match value {
+        Value::Null => true,
+        Value::String(s) => s.is_empty(),
+        _ => false,
+    }
+}
@@ -0,0 +1,1396 @@
+use std::{
+    collections::HashMap,
+    fs::{create_dir, create_dir_all, File},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+use ignore::{overrides::OverrideBuilder, WalkBuilder, WalkState};
+use indicatif::{ProgressBar, ProgressIterator, ProgressStyle};
+use pgn_reader::Visitor;
+use rayon::{
+    iter::{IntoParallelRefIterator, ParallelIterator},
+    ThreadPoolBuilder,
+};
+
+#[cfg(feature = "duckdb")]
+use crate::database;
+#[cfg(feature = "download")]
+use crate::download;
+#[cfg(feature = "parquet")]
+use crate::huggingface;
+use crate::{
+    args::{AbortedGames, Args, ErrorPolicy, OnExisting, STDIO},
+    columns,
+    counts::{self, GameCounts},
+    describe,
+    encoding::Encoded,
+    fallback::OutputDirs,
+    game::GameVisitor,
+    group,
+    journal::Journal,
+    lock::OutputLock,
+    log::Event,
+    manifest::Manifest,
+    merge::MergedCsvs,
+    normalize::{Normalization, Normalize},
+    output::{Format, Output, Shared},
+    preview,
+    profile::{FileTimers, Profiler, Timed, Timer},
+    ratings::RatingStats,
+    reader::{Compression, PgnReader, Source, UringStats},
+    record::{self, Record},
+    scan::{self, FileScan},
+    segment::GameReader,
+    sessions, sort,
+    source::{FileSource, PgnSource, StdinSource},
+    watchdog::{Counted, Watch, Watchdog},
+    workdir::WorkDir,
+    GameProcessor,
+};
+
+// the name of the files listing PGNs to leave out, in `.gitignore` syntax
+const IGNORE_FILE: &str = ".pgnignore";
+pub(crate) const EXTENSIONS: [&str; 4] = [".pgn", ".pgn.bz2", ".pgn.zst", ".pgn.gz"];
+#[cfg(feature = "archives")]
+const ARCHIVE_EXTENSION: &str = ".tar";
+// PGNs of at least this many games that give no rows are warned about, since
+// a filter that matches nothing is likelier than a PGN without a wanted game
+const SUSPICIOUS_GAMES: u64 = 10_000;
+
+struct Pgn {
+    source: Box<dyn PgnSource>,
+    // the directory of the PGN relative to the PGN directory, whose structure
+    // is kept in the output directory
+    subdir: PathBuf,
+}
+
+impl Pgn {
+    fn new(source: impl PgnSource + 'static, subdir: PathBuf) -> Self {
+        Pgn {
+            source: Box::new(source),
+            subdir,
+        }
+    }
+
+    fn name(&self) -> String {
+        self.source.name()
+    }
+
+    // the PGN's path relative to the PGN directory
+    fn relative_path(&self) -> PathBuf {
+        self.subdir.join(self.name())
+    }
+
+    fn output_path(&self, csv_dir: &Path, format: Format) -> PathBuf {
+        output_path(csv_dir, &self.relative_path(), format)
+    }
+
+    /// The size of the PGN as it is stored, if it is known.
+    fn size(&self) -> Option<u64> {
+        self.source.size_hint()
+    }
+
+    fn compression(&self) -> Compression {
+        let name = self.name();
+        Compression::from_extension(Path::new(&name).extension().and_then(|ext| ext.to_str()))
+    }
+
+    fn reader(
+        &self,
+        timer: Option<Timer>,
+        watch: Option<&Watch>,
+        uring: Option<&Arc<UringStats>>,
+        normalization: Normalization,
+    ) -> Result<Normalize<Timed<Counted<PgnReader>>>> {
+        let compression = self.compression();
+        // a PGN whose name doesn't say it is compressed, e.g. stdin, may be
+        let (source, compression) = match self.source(compression, uring)? {
+            Source::Dyn(reader) if compression == Compression::None => Source::sniff(reader)?,
+            source => (source, compression),
+        };
+        let reader = PgnReader::new(source, compression)?;
+        let reader = Counted::new(reader, watch.map(|watch| watch.progress.clone()));
+        Ok(Normalize::new(Timed::new(reader, timer), normalization))
+    }
+
+    /// Reads the PGN's bytes as they are stored, from local files directly,
+    /// counting reads through io_uring into `uring`.
+    fn source(&self, compression: Compression, uring: Option<&Arc<UringStats>>) -> Result<Source> {
+        let Some(path) = self.source.local_path() else {
+            return Ok(Source::Dyn(self.source.open()?));
+        };
+        let file = File::open(path)?;
+        match compression {
+            Compression::None => Source::plain_file(file, uring.cloned()),
+            _ => Ok(Source::file(file, uring.cloned())),
+        }
+    }
+
+    /// Gathers the ratings of the PGN's games for `--write-rating-stats`.
+    fn rating_stats(&self, args: &Args) -> Result<RatingStats> {
+        let reader = self.reader(None, None, None, args.normalization)?;
+        RatingStats::read_pgn(reader)
+            .with_context(|| format!("can't read the ratings of {}", self.name()))
+    }
+
+    /// Reads the first `rows` rows of the PGN, with their library columns,
+    /// without writing them.
+    fn preview<P>(&self, rows: usize, args: &Args) -> Result<Vec<Record>>
+    where
+        P: Visitor + GameProcessor,
+    {
+        let reader = self.reader(None, None, None, args.normalization)?;
+        let mut pgn_reader = GameReader::new(reader, P::game_offsets(), args.tolerant);
+        let file = self.name();
+        let mut processor = P::default();
+        let mut game = GameVisitor::new(&mut processor, file.as_bytes(), &args.columns, false);
+        let encodings = args.encodings::<P>();
+        game.encode_with(encodings);
+        if args.game_uid {
+            game.write_game_uid();
+        }
+        let mut records = Vec::new();
+        while records.len() < rows && pgn_reader.read_game(&mut game)? {
+            if game.processor().skip() {
+                continue;
+            }
+            let extras = game.library_columns();
+            let mut record = record::to_record(&game.processor().row(), encodings)?;
+            for (name, value) in extras {
+                record.insert(name.into_owned(), value);
+            }
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    /// Reads the headers of the PGN's games, to find out how many of them
+    /// the processor keeps by their headers, and how large their rows are as
+    /// CSV when built from their headers alone.
+    fn scan<P>(&self, args: &Args) -> Result<FileScan>
+    where
+        P: Visitor + GameProcessor,
+    {
+        let reader = self.reader(None, None, None, args.normalization)?;
+        let mut pgn_reader = GameReader::new(reader, false, args.tolerant);
+        let file = self.name();
+        let mut processor = P::default();
+        let mut game = GameVisitor::new(&mut processor, file.as_bytes(), &[], false);
+        game.read_headers_only();
+        let encodings = args.encodings::<P>();
+        let mut csv = csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_writer(Vec::new());
+        let (mut games, mut matched) = (0, 0);
+        while pgn_reader.read_game(&mut game)? {
+            games += 1;
+            if game.processor().skip() {
+                continue;
+            }
+            matched += 1;
+            csv.serialize(Encoded::new(&game.processor().row(), encodings))?;
+        }
+        csv.flush()?;
+        Ok(FileScan {
+            name: self.relative_path().display().to_string(),
+            games,
+            matched,
+            bytes: csv.get_ref().len() as u64,
+        })
+    }
+
+    /// Converts the PGN's games into `output`, returning how many games were
+    /// skipped for `--errors skip-game`. A PGN that can't be read to its end
+    /// fails like any other error.
+    fn process<P>(
+        &self,
+        processor: &mut P,
+        output: &mut Output,
+        games: Option<&ProgressBar>,
+        watch: Option<&Watch>,
+        timers: Option<&mut FileTimers>,
+        args: &Args,
+    ) -> Result<u64>
+    where
+        P: Visitor + GameProcessor,
+    {
+        let profiling = timers.is_some();
+        let timer = timers.as_deref().map(|t| t.decompress.clone());
+        let reader = self.reader(timer, watch, Some(output.uring_stats()), args.normalization)?;
+        let dump = output.dumps_skipped();
+        let skip_errors = args.errors == ErrorPolicy::SkipGame;
+        let split = P::game_offsets() || dump || args.max_game_bytes.is_some() || skip_errors;
+        let mut pgn_reader = GameReader::new(reader, split, args.tolerant);
+        if dump {
+            pgn_reader.keep_text();
+        }
+        if skip_errors {
+            pgn_reader.skip_errors();
+        }
+        if let Some(max_bytes) = args.max_game_bytes {
+            pgn_reader.max_game_bytes(max_bytes);
+        }
+        let file = self.name();
+        let columns = output.library_columns().to_vec();
+        let mut game = GameVisitor::new(processor, file.as_bytes(), &columns, profiling);
+        game.encode_with(output.encodings());
+        if args.game_uid {
+            game.write_game_uid();
+        }
+        if output.tournaments().is_some() {
+            game.collect_tournaments();
+        }
+        if output.header_rows() {
+            game.collect_headers();
+        }
+        if args.aborted.is_some() {
+            game.read_movetext();
+        }
+        if let Some(max_plies) = args.max_plies {
+            game.max_plies(max_plies);
+        }
+        #[cfg(feature = "openings")]
+        if let Some(book) = output.openings() {
+            game.classify_openings(book);
+        }
+        if let Some(stats) = output.rating_stats() {
+            game.normalize_ratings(stats);
+        }
+        if let Some(partition) = output.partition() {
+            game.partition_by(partition);
+        }
+        let mut too_long = 0;
+        let mut read_games = Duration::ZERO;
+        let mut write_rows = Duration::ZERO;
+        let (mut games_read, mut aborted, mut skipped) = (0, 0, 0);
+        let mut skip_reasons = HashMap::new();
+        let rows = output.rows();
+        loop {
+            let start = profiling.then(Instant::now);
+            match pgn_reader.read_game(&mut game) {
+                Ok(true) => (),
+                Ok(false) => break,
+                Err(e) => {
+                    let at = pgn_reader
+                        .offset()
+                        .map(|offset| format!(" at byte {offset}"))
+                        .unwrap_or_default();
+                    return Err(e).context(format!("can't read {file}{at}"));
+                }
+            }
+            if let Some(start) = start {
+                read_games += start.elapsed();
+            }
+            if let Some(games) = games {
+                games.inc(1);
+            }
+            if let Some(watch) = watch {
+                watch.progress.add_game();
+            }
+            games_read += 1;
+            if let Some((uid, headers)) = game.headers() {
+                output.write_headers(uid, headers)?;
+            }
+            if game.too_long() {
+                too_long += 1;
+                continue;
+            }
+            let was_aborted = game.aborted() == Some(true);
+            if was_aborted {
+                aborted += 1;
+            }
+            let keep = was_aborted && args.aborted == Some(AbortedGames::Keep);
+            if game.processor().skip() && !keep {
+                skipped += 1;
+                let reason = game.processor().skip_reason();
+                if let Some(reason) = &reason {
+                    *skip_reasons.entry(reason.clone()).or_insert(0) += 1;
+                }
+                if let Some(text) = pgn_reader.text() {
+                    output.dump_skipped(text, reason)?;
+                }
+                continue;
+            }
+            let start = profiling.then(Instant::now);
+            let mut extras = game.library_columns();
+            let processor = game.processor();
+            let route = processor.route();
+            let row = processor.row();
+            let partition = game.partition();
+            // a row that can't be mirrored is still marked as not mirrored
+            let mirrored = match args.mirror_colors {
+                true => P::mirror(&row).map(|mirrored| {
+                    let mut mirrored_extras = extras.clone();
+                    game.mirror_library_columns(&mut mirrored_extras);
+                    mirrored_extras.push(columns::mirrored_column(true, output.encodings()));
+                    (mirrored, mirrored_extras)
+                }),
+                false => None,
+            };
+            if args.mirror_colors {
+                extras.push(columns::mirrored_column(false, output.encodings()));
+            }
+            output.write_row(partition.clone(), route.clone(), row, extras)?;
+            if let Some((mirrored, mirrored_extras)) = mirrored {
+                output.write_row(partition, route, mirrored, mirrored_extras)?;
+            }
+            if let Some(start) = start {
+                write_rows += start.elapsed();
+            }
+            if args
+                .limit
+                .is_some_and(|limit| output.rows() - rows >= limit)
+            {
+                break;
+            }
+        }
+        if args.aborted.is_some() {
+            args.log.emit(&Event::AbortedGames {
+                file: &file,
+                games: games_read,
+                aborted,
+            });
+        }
+        let mut guarded = Vec::new();
+        if let (Some(max), 1..) = (args.max_plies, too_long) {
+            guarded.push(format!("{too_long} games of more than {max} moves"));
+        }
+        if let (Some(max), oversized @ 1..) = (args.max_game_bytes, pgn_reader.oversized()) {
+            guarded.push(format!("{oversized} games of more than {max} bytes"));
+        }
+        if pgn_reader.skipped() > 0 {
+            guarded.push(format!(
+                "{} games that couldn't be read",
+                pgn_reader.skipped()
+            ));
+        }
+        if !guarded.is_empty() {
+            args.log.emit(&Event::Warning {
+                message: format!("{file}: skipped {}", guarded.join(" and ")),
+            });
+        }
+        if games_read >= SUSPICIOUS_GAMES && output.rows() == rows {
+            let mut skip_reasons: Vec<(String, u64)> = skip_reasons.into_iter().collect();
+            skip_reasons.sort_by(|(a, m), (b, n)| n.cmp(m).then(a.cmp(b)));
+            skip_reasons.truncate(3);
+            args.log.emit(&Event::NoRows {
+                file: &file,
+                games: games_read,
+                skipped,
+                skip_reasons,
+            });
+        }
+        if let (Some(tournaments), Some(file)) = (output.tournaments(), game.take_tournaments()) {
+            tournaments.add(file);
+        }
+        if let Some(timers) = timers {
+            timers.read_games += read_games;
+            timers.write_rows += write_rows;
+            timers.callbacks += game.callback_time();
+        }
+        Ok(pgn_reader.skipped())
+    }
+}
+
+/// The path of the output named `name`, relative to the output directory.
+fn output_path(csv_dir: &Path, name: &Path, format: Format) -> PathBuf {
+    #[cfg(feature = "duckdb")]
+    if format == Format::DuckDb {
+        // the rows of every file go into the same database
+        return csv_dir.join(database::DATABASE);
+    }
+    #[cfg(feature = "parquet")]
+    if format == Format::HuggingFace {
+        let name = name.file_name().unwrap_or_default().to_string_lossy();
+        return huggingface::Dataset::shard_path(csv_dir, &name);
+    }
+    let mut output_path = csv_dir.join(name);
+    output_path.set_extension(format.extension());
+    output_path
+}
+
+/// PGNs that are converted one after another into the same output.
+struct Batch<'a> {
+    name: String,
+    path: PathBuf,
+    format: Format,
+    pgns: Vec<&'a Pgn>,
+}
+
+impl<'a> Batch<'a> {
+    /// The batch of `pgn` alone, in the format that `--format-for` gives it.
+    fn single(pgn: &'a Pgn, args: &Args) -> Self {
+        let format = args.format_of(&pgn.relative_path(), pgn.size());
+        Batch {
+            name: pgn.name(),
+            path: pgn.output_path(&args.csv_dir, format),
+            format,
+            pgns: vec![pgn],
+        }
+    }
+}
+
+/// Groups the PGNs into the batches that they are converted in. Without
+/// `--batch`, every PGN is converted on its own. With it, PGNs smaller than
+/// its size are packed into batches of at most about that many bytes, which
+/// are written to outputs named `batch-000000` and so on, so that the cost of
+/// setting up an output isn't paid for every tiny PGN. PGNs that
+/// `--format-for` gives another format than `--format` are never packed.
+fn batches<'a>(pgns: &'a [Pgn], args: &Args) -> Vec<Batch<'a>> {
+    let Some(max_bytes) = args.batch else {
+        return pgns.iter().map(|pgn| Batch::single(pgn, args)).collect();
+    };
+    let (csv_dir, format) = (args.csv_dir.as_path(), args.format);
+    let mut batches = Vec::new();
+    let mut small = Vec::new();
+    let mut bytes = 0;
+    let flush = |small: &mut Vec<&'a Pgn>, batches: &mut Vec<Batch<'a>>| {
+        if !small.is_empty() {
+            let name = format!("batch-{:06}", batches.len());
+            batches.push(Batch {
+                path: output_path(csv_dir, Path::new(&name), format),
+                name,
+                format,
+                pgns: std::mem::take(small),
+            });
+        }
+    };
+    for pgn in pgns {
+        match pgn.size() {
+            Some(size)
+                if size < max_bytes as u64
+                    && args.format_of(&pgn.relative_path(), Some(size)) == format =>
+            {
+                if bytes + size > max_bytes as u64 {
+                    flush(&mut small, &mut batches);
+                    bytes = 0;
+                }
+                small.push(pgn);
+                bytes += size;
+            }
+            _ => batches.push(Batch::single(pgn, args)),
+        }
+    }
+    flush(&mut small, &mut batches);
+    batches
+}
+
+/// Lays out what a run would do, for `--dry-run`: each PGN, how it is
+/// compressed, and the output it is written to. PGNs that aren't local files
+/// and aren't named as compressed have their compression found once they are
+/// read.
+fn plan(batches: &[Batch]) -> String {
+    let header = ["pgn", "compression", "output"].map(str::to_owned);
+    let lines: Vec<[String; 3]> = batches
+        .iter()
+        .flat_map(|batch| {
+            batch.pgns.iter().map(|pgn| {
+                let compression = match (pgn.compression(), pgn.source.local_path()) {
+                    (Compression::None, None) => "when read",
+                    (compression, _) => compression.name(),
+                };
+                [
+                    pgn.relative_path().display().to_string(),
+                    compression.to_owned(),
+                    batch.path.display().to_string(),
+                ]
+            })
+        })
+        .collect();
+    let width = |i: usize| {
+        let widths = lines
+            .iter()
+            .chain([&header])
+            .map(|line| line[i].chars().count());
+        widths.max().unwrap_or(0)
+    };
+    let (pgn, compression) = (width(0), width(1));
+    let mut plan = String::new();
+    for [name, kind, output] in [&header].into_iter().chain(&lines) {
+        plan += &format!("{name:<pgn$}  {kind:<compression$}  {output}\n");
+    }
+    plan
+}
+
+/// Finds the PGNs in `dir`, and with `recursive` in its subdirectories too,
+/// leaving out those matched by a `.pgnignore` file. `globs` override which
+/// files are PGNs, as for `--glob`. Directories are listed in parallel, for PGN
+/// directories holding a file per game.
+fn dir_pgns(dir: &Path, args: &Args) -> Result<Vec<Pgn>> {
+    let mut overrides = OverrideBuilder::new(dir);
+    for glob in &args.globs {
+        overrides.add(glob)?;
+    }
+    let overrides = overrides.build()?;
+    // the PGNs directly in the directory are at depth 1
+    let max_depth = match (args.recursive, args.max_depth) {
+        (false, _) => Some(1),
+        (true, max_depth) => max_depth.map(|levels| levels + 1),
+    };
+    // files that matched a glob are converted whatever their extension
+    let whitelisted = overrides.num_whitelists() > 0;
+    let pgns = Mutex::new(Vec::new());
+    WalkBuilder::new(dir)
+        .standard_filters(false)
+        .add_custom_ignore_filename(IGNORE_FILE)
+        .overrides(overrides)
+        .max_depth(max_depth)
+        .build_parallel()
+        .run(|| {
+            Box::new(|entry| {
+                let Ok(entry) = entry else {
+                    return WalkState::Continue;
+                };
+                if !entry.file_type().is_some_and(|t| t.is_file()) {
+                    return WalkState::Continue;
+                }
+                let name = entry.file_name().to_string_lossy();
+                let is_pgn = whitelisted || EXTENSIONS.iter().any(|ext| name.ends_with(ext));
+                #[cfg(feature = "archives")]
+                let is_archive = !is_pgn && name.ends_with(ARCHIVE_EXTENSION);
+                let path = entry.into_path();
+                let subdir = path
+                    .parent()
+                    .and_then(|parent| parent.strip_prefix(dir).ok())
+                    .unwrap_or(Path::new(""))
+                    .to_path_buf();
+                if is_pgn {
+                    pgns.lock()
+                        .unwrap()
+                        .push(Pgn::new(FileSource::new(path), subdir));
+                    return WalkState::Continue;
+                }
+                #[cfg(feature = "archives")]
+                if is_archive {
+                    // an archive's PGNs are written to a directory named after it
+                    let stem = path.file_name().unwrap_or_default().to_string_lossy();
+                    let archive_dir = subdir.join(stem.trim_end_matches(ARCHIVE_EXTENSION));
+                    match crate::source::tar_entries(&path, &EXTENSIONS) {
+                        Ok(entries) => {
+                            pgns.lock()
+                                .unwrap()
+                                .extend(entries.into_iter().map(|entry| {
+                                    let subdir = archive_dir.join(entry.dir());
+                                    Pgn::new(entry, subdir)
+                                }))
+                        }
+                        Err(e) => args.log.emit(&Event::Warning {
+                            message: format!("skipping the archive {}: {e}", path.display()),
+                        }),
+                    }
+                }
+                WalkState::Continue
+            })
+        });
+    let mut pgns = pgns.into_inner().unwrap();
+    pgns.sort_by_key(Pgn::relative_path);
+    Ok(pgns)
+}
+
+fn progress_bar(n: usize, message: &str) -> Result<ProgressBar> {
+    let pb = ProgressBar::new(u64::try_from(n)?);
+    let template = format!("{{spinner:.green}} {message}: [{{elapsed}}] [{{bar:.cyan/blue}}] {{human_pos}}/{{human_len}} ({{eta}})");
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template(&template)?
+            .progress_chars("#>-"),
+    );
+    Ok(pb)
+}
+
+/// Converts PGN files to CSVs. Reads one or two command line arguments: the
+/// path to a directory containing PGN files, and the path to a directory to
+/// write CSV files; if the second argument is not provided, the CSV files will
+/// be written to the same directory as the PGN files. The CSV files will have
+/// the same name as the PGN files, but with the extension replaced with `.csv`.
+/// Options for other output formats, sorting, deduplication, statistics,
+/// assertions, and profiling are listed by `--help`, with `P::description()`
+/// as the binary's description, and described in the README. Malformed
+/// arguments print the usage and exit the process; to embed the pipeline in a
+/// program of your own, configure it with `Pgn2Csv::builder()` instead. To
+/// customize the data that you collect into the CSVs, you provide the generic
+/// type parameter `P` to the function, which must implement the `Visitor` and
+/// `GameProcessor` traits. See the README for more information.
+///
+/// # Errors
+///
+/// Returns an error if there is an issue with reading or writing files.
+pub fn pgn2csv<P>() -> Result<()>
+where
+    P: Visitor + GameProcessor,
+{
+    pgn2csv_with_sources::<P>(Vec::new())
+}
+
+/// Like `pgn2csv`, but also converts `sources`, e.g. PGNs from a database or
+/// an archive format of your own, along with the PGNs given on the command
+/// line. Their outputs are written to the CSV directory, named after them.
+///
+/// # Errors
+///
+/// Returns an error if there is an issue with reading or writing files.
+pub fn pgn2csv_with_sources<P>(sources: Vec<Box<dyn PgnSource>>) -> Result<()>
+where
+    P: Visitor + GameProcessor,
+{
+    run::<P>(Args::parse(P::description()), sources)
+}
+
+/// Converts the PGNs that `args` point to, and `sources`.
+pub(crate) fn run<P>(args: Args, sources: Vec<Box<dyn PgnSource>>) -> Result<()>
+where
+    P: Visitor + GameProcessor,
+{
+    let run_start = Instant::now();
+    if args.describe {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&describe::describe::<P>(args.encodings::<P>())?)?
+        );
+        return Ok(());
+    }
+    // rows written to a sink of the program's own can't be rewritten
+    let csv = args.only_csv() && args.sink.is_none();
+    let group_by = P::group_by();
+    if group_by.is_some() && !csv {
+        bail!("this binary groups its rows, which only works with CSV output");
+    }
+    let sessions = match (P::sessions(), args.session_gap) {
+        (Some(sessions), Some(gap)) => Some(sessions.with_max_gap(gap)),
+        (sessions, _) => sessions,
+    };
+    if sessions.is_some() && !csv {
+        bail!("this binary numbers sessions, which only works with CSV output");
+    }
+    if args.merge.is_some() && (group_by.is_some() || sessions.is_some()) {
+        bail!("this binary rewrites its outputs, so they can't be merged");
+    }
+    let partition = args.partition_by.clone().or_else(P::partition_by);
+    if partition.is_some() {
+        if !csv || args.output.is_some() {
+            bail!("partitioned rows are written to CSVs in the output directory");
+        }
+        if args.merge.is_some() || args.sort_by.is_some() || args.resume {
+            bail!("partitioned rows can't be merged, sorted, or resumed");
+        }
+        // which partitions a run writes is only known once it has read them
+        if args.on_existing != OnExisting::Overwrite {
+            bail!("partitioned outputs are always overwritten, so --on-existing can't be given");
+        }
+        if group_by.is_some() || sessions.is_some() {
+            bail!("this binary rewrites its outputs, so they can't be partitioned");
+        }
+    }
+    if args.mirror_colors && P::mirror(&P::Row::default()).is_none() {
+        bail!("this binary can't swap the colors of its rows, which --mirror-colors needs");
+    }
+    let log = &args.log;
+    let bars = log.bars();
+    let pgn_dir = args.pgn_dir.as_path();
+    let csv_dir = args.csv_dir.as_path();
+
+    #[cfg(feature = "download")]
+    download::limit_requests(args.requests_per_hour, args.max_connections);
+    #[cfg(feature = "download")]
+    if args.dry_run {
+        for url in &args.downloads {
+            println!("would download {url}");
+        }
+    } else if !args.downloads.is_empty() {
+        if !pgn_dir.exists() {
+            create_dir(pgn_dir)?;
+        }
+        for url in &args.downloads {
+            download::download(url, pgn_dir, args.checksums.as_deref(), log)?;
+        }
+    }
+
+    let mut pgns = if pgn_dir.as_os_str().is_empty() {
+        // only the PGN files given one by one and the sources given from code
+        // are converted
+        Vec::new()
+    } else if pgn_dir == Path::new(STDIO) {
+        vec![Pgn::new(StdinSource, PathBuf::new())]
+    } else {
+        dir_pgns(pgn_dir, &args)?
+    };
+    for path in &args.pgn_files {
+        let pgn = Pgn::new(FileSource::new(path.clone()), PathBuf::new());
+        if pgns.iter().any(|other| other.name() == pgn.name()) {
+            bail!(
+                "there are several PGNs named {}, whose outputs would overwrite each other",
+                pgn.name()
+            );
+        }
+        pgns.push(pgn);
+    }
+    #[cfg(feature = "download")]
+    {
+        let cache = match &args.cache_dir {
+            Some(dir) => Some(std::sync::Arc::new(download::Cache::new(
+                dir,
+                args.cache_size,
+            )?)),
+            None => None,
+        };
+        for url in &args.streams {
+            let remote = download::Remote::new(url, cache.clone(), log.clone())?;
+            pgns.push(Pgn::new(remote, PathBuf::new()));
+        }
+    }
+    pgns.extend(sources.into_iter().map(|source| Pgn {
+        source,
+        subdir: PathBuf::new(),
+    }));
+    if let Some(rows) = args.preview {
+        let Some(pgn) = pgns.first() else {
+            bail!("there are no PGNs to preview");
+        };
+        print!("{}", preview::table(&pgn.preview::<P>(rows, &args)?));
+        return Ok(());
+    }
+    if let Some(path) = &args.write_rating_stats {
+        let read = |pgn: &Pgn| pgn.rating_stats(&args);
+        let all: Vec<RatingStats> = match args.serial {
+            true => pgns.iter().map(read).collect::<Result<_>>()?,
+            false => pgns.par_iter().map(read).collect::<Result<_>>()?,
+        };
+        let mut stats = RatingStats::default();
+        for pgn_stats in all {
+            stats.merge(pgn_stats);
+        }
+        return stats.write(path);
+    }
+
+    #[allow(unused_mut)]
+    let mut counts = match &args.game_counts {
+        Some(source) => GameCounts::load(source, log)?,
+        None => GameCounts::default(),
+    };
+    #[cfg(feature = "download")]
+    if args.game_counts.is_none() {
+        let mut sources: Vec<String> = args
+            .downloads
+            .iter()
+            .chain(&args.streams)
+            .filter_map(|url| download::default_counts(url))
+            .collect();
+        sources.dedup();
+        for source in sources {
+            match GameCounts::load(&source, log) {
+                Ok(listed) => counts.extend(listed),
+                Err(e) => log.emit(&Event::Warning {
+                    message: format!("no game counts from {source}: {e}"),
+                }),
+            }
+        }
+    }
+
+    let mut batches = batches(&pgns, &args);
+    if let Some(output) = &args.output {
+        if pgns.len() != 1 {
+            bail!(
+                "--output needs exactly one PGN, but {} were found",
+                pgns.len()
+            );
+        }
+        let to_stdout = output == Path::new(STDIO);
+        if to_stdout && (group_by.is_some() || sessions.is_some()) {
+            bail!("this binary rewrites its outputs, so they can't be written to stdout");
+        }
+        batches[0].path = output.clone();
+    }
+    // taken once every argument has been checked, so that a rejected run
+    // leaves nothing behind; a dry run doesn't write anything
+    let _lock = match args.dry_run {
+        true => None,
+        false => {
+            if !csv_dir.exists() {
+                create_dir(csv_dir)?;
+            }
+            Some(OutputLock::acquire(csv_dir, args.force)?)
+        }
+    };
+    match args.on_existing {
+        OnExisting::Overwrite => (),
+        OnExisting::Skip if args.merge.as_ref().is_some_and(|merge| merge.exists()) => {
+            log.emit(&Event::Warning {
+                message: "left out every PGN, since the merged output exists".to_owned(),
+            });
+            return Ok(());
+        }
+        OnExisting::Error if args.merge.as_ref().is_some_and(|merge| merge.exists()) => {
+            bail!("the merged output already exists; pass --on-existing overwrite");
+        }
+        OnExisting::Skip => {
+            let before = batches.len();
+            batches.retain(|batch| !batch.path.exists());
+            let skipped = before - batches.len();
+            if skipped > 0 {
+                log.emit(&Event::Warning {
+                    message: format!("left out {skipped} of {before} PGNs, whose outputs exist"),
+                });
+            }
+        }
+        OnExisting::Error => {
+            if let Some(batch) = batches.iter().find(|batch| batch.path.exists()) {
+                bail!(
+                    "{} already exists; pass --on-existing overwrite or skip",
+                    batch.path.display()
+                );
+            }
+        }
+    }
+    // a dry run that doesn't resume leaves the journal as it is, and so do
+    // merged and partitioned runs, which don't write the PGNs' own outputs
+    let own_outputs = args.merge.is_none() && partition.is_none();
+    let journal = match (!args.dry_run || args.resume) && own_outputs {
+        true => Some(Journal::open(csv_dir, args.resume)?),
+        false => None,
+    };
+    if let (Some(journal), true) = (&journal, args.resume) {
+        let before = batches.len();
+        batches.retain(|batch| !journal.is_done(&batch.path));
+        let done = before - batches.len();
+        if done > 0 {
+            log.emit(&Event::Warning {
+                message: format!("resuming: {done} of {before} PGNs were already converted"),
+            });
+        }
+    }
+    if args.dry_run {
+        print!("{}", plan(&batches));
+        return Ok(());
+    }
+    if args.scan {
+        let scan = |pgn: &&Pgn| pgn.scan::<P>(&args);
+        let pgns: Vec<&Pgn> = batches
+            .iter()
+            .flat_map(|batch| &batch.pgns)
+            .copied()
+            .collect();
+        let scans: Vec<FileScan> = match args.serial {
+            true => pgns.iter().map(scan).collect::<Result<_>>()?,
+            false => pgns.par_iter().map(scan).collect::<Result<_>>()?,
+        };
+        eprint!("{}", scan::report(&scans));
+        if !scan::confirm(args.yes)? {
+            return Ok(());
+        }
+    }
+    let pb = bars.add(progress_bar(batches.len(), "Processing PGNs")?);
+    let watchdog = args
+        .heartbeat
+        .map(|interval| Watchdog::start(interval, log.clone()));
+
+    let profiler = args.profile.as_ref().map(|_| Profiler::new());
+    let encodings = args.encodings::<P>();
+    let schema_version = describe::describe::<P>(encodings)?
+        .schema_version()
+        .to_owned();
+    let mut shared = Shared::new(&args, &schema_version, encodings)?;
+    let comment = shared.schema_comment.clone();
+    let merged = match (&args.merge, partition) {
+        (Some(path), _) => {
+            let merged = MergedCsvs::create(path, comment.as_deref())?;
+            shared.merge_into(&merged);
+            Some(merged)
+        }
+        (None, Some(partition)) => {
+            let merged = MergedCsvs::partitioned(comment.as_deref());
+            shared.partition_into(&merged, partition, csv_dir);
+            Some(merged)
+        }
+        (None, None) => None,
+    };
+    let manifest = args.stats.then(|| Manifest::new(schema_version.clone()));
+
+    let work_dir = WorkDir::create(csv_dir)?;
+    let dirs = OutputDirs::new(csv_dir, args.fallback_dir.as_deref(), log.clone());
+    let rows = AtomicU64::new(0);
+    // what --errors skipped
+    let failed_files = Mutex::new(Vec::new());
+    let failed_games = AtomicU64::new(0);
+    // each worker reuses a processor across the files it converts
+    let convert = |batch: &Batch, path: &Path, processor: &mut Option<P>| -> Result<u64> {
+        let start = Instant::now();
+        let mut timers = profiler.as_ref().map(|_| FileTimers::default());
+        let timer = timers.as_ref().map(|t| t.write.clone());
+        if let Some(dir) = path.parent() {
+            create_dir_all(dir)?;
+        }
+        let mut output = Output::new(path, batch.format, timer, &shared)?;
+        if let Some(assertions) = &args.assertions {
+            output.check(assertions, log, batch.name.clone());
+        }
+        if manifest.is_some() {
+            output.collect_stats();
+        }
+        for pgn in &batch.pgns {
+            let games = match counts.get(&pgn.name()) {
+                Some(expected) => Some(bars.add(counts::game_bar(&pgn.name(), expected)?)),
+                None => None,
+            };
+            let watch = watchdog.as_ref().map(|w| w.watch(pgn.name()));
+            let processor = match processor {
+                Some(processor) => {
+                    processor.reset_for_file();
+                    processor
+                }
+                None => processor.insert(P::default()),
+            };
+            let skipped = pgn.process(
+                processor,
+                &mut output,
+                games.as_ref(),
+                watch.as_ref(),
+                timers.as_mut(),
+                &args,
+            )?;
+            failed_games.fetch_add(skipped, Ordering::Relaxed);
+            output.flush()?;
+            if let Some(games) = games {
+                games.finish_and_clear();
+            }
+        }
+        output.finish()?;
+        if let (Some(manifest), Some(stats)) = (&manifest, output.take_stats()) {
+            let name = path.file_name().unwrap_or_default();
+            manifest.record(
+                batch.name.clone(),
+                name.to_string_lossy().into_owned(),
+                stats,
+            );
+        }
+        if let Some(sessions) = &sessions {
+            for path in output.paths() {
+                sessions::sessions_csv(
+                    &path,
+                    sessions,
+                    args.max_memory,
+                    shared.schema_comment.as_deref(),
+                    work_dir.path(),
+                )?;
+            }
+        }
+        if let Some(group_by) = &group_by {
+            for path in output.paths() {
+                group::group_csv(
+                    &path,
+                    group_by,
+                    args.max_memory,
+                    shared.schema_comment.as_deref(),
+                    work_dir.path(),
+                )?;
+            }
+        }
+        if let Some(columns) = &args.sort_by {
+            for path in output.paths() {
+                sort::sort_csv(
+                    &path,
+                    columns,
+                    args.dedup,
+                    args.max_memory,
+                    shared.schema_comment.as_deref(),
+                    work_dir.path(),
+                )?;
+            }
+        }
+        output.publish()?;
+        if let (Some(profiler), Some(timers)) = (&profiler, &timers) {
+            profiler.record(batch.name.clone(), start, timers);
+        }
+        Ok(output.rows())
+    };
+    let convert_logged = |processor: &mut Option<P>, batch: &Batch| -> Result<()> {
+        let file = &batch.name;
+        log.emit(&Event::FileStarted { file });
+        let start = Instant::now();
+        let path = dirs.path(&batch.path);
+        let mut converted = convert(batch, &path, processor);
+        if let Err(e) = &converted {
+            // the output is written again from the start in the fallback dir
+            if path == batch.path && dirs.redirect(e) {
+                converted = convert(batch, &dirs.path(&batch.path), processor);
+            }
+        }
+        match converted {
+            Ok(written) => {
+                if let Some(journal) = &journal {
+                    // the journal is in the output dir, which can't be
+                    // written once outputs are redirected
+                    match journal.add(&batch.path) {
+                        Err(_) if dirs.redirected() => (),
+                        added => added?,
+                    }
+                }
+                rows.fetch_add(written, Ordering::Relaxed);
+                log.emit(&Event::FileFinished {
+                    file,
+                    rows: written,
+                    seconds: start.elapsed().as_secs_f64(),
+                });
+                Ok(())
+            }
+            Err(e) => {
+                let message = format!("{e:#}");
+                log.emit(&Event::Error {
+                    file,
+                    message: message.clone(),
+                });
+                if args.errors == ErrorPolicy::Abort {
+                    return Err(e);
+                }
+                // the output is left incomplete as a .part file, and isn't
+                // journaled, so that a resumed run converts the PGN again
+                if let Ok(mut failed) = failed_files.lock() {
+                    failed.push((file.clone(), message));
+                }
+                Ok(())
+            }
+        }
+    };
+    let converted = if args.serial {
+        let mut processor = None;
+        batches
+            .iter()
+            .progress_with(pb)
+            .try_for_each(|batch| convert_logged(&mut processor, batch))
+    } else {
+        // every worker thread converts the next batch until none are left,
+        // with a processor of its own, rather than one per job that rayon
+        // splits the batches into
+        let next = AtomicUsize::new(0);
+        let stop = AtomicBool::new(false);
+        let convert_all = || {
+            let converted = rayon::broadcast(|_| {
+                let mut processor = None;
+                while !stop.load(Ordering::Relaxed) {
+                    let Some(batch) = batches.get(next.fetch_add(1, Ordering::Relaxed)) else {
+                        break;
+                    };
+                    let converted = convert_logged(&mut processor, batch);
+                    pb.inc(1);
+                    if converted.is_err() {
+                        stop.store(true, Ordering::Relaxed);
+                        return converted;
+                    }
+                }
+                Ok(())
+            })
+            .into_iter()
+            .collect::<Result<()>>();
+            if converted.is_ok() {
+                pb.finish_using_style();
+            }
+            converted
+        };
+        // a pool of the run's own, so that embedding programs can run
+        // conversions with different thread counts
+        match args.threads {
+            Some(threads) => ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()?
+                .install(convert_all),
+            None => convert_all(),
+        }
+    };
+    match converted {
+        // a read-only output dir can't be cleaned up
+        Ok(()) if dirs.redirected() => _ = work_dir.remove(),
+        Ok(()) => work_dir.remove()?,
+        Err(e) => return Err(work_dir.keep(e)),
+    }
+    if let Some(merged) = merged {
+        merged.finish()?;
+    }
+    let csv_dir = dirs.dir();
+
+    if let (Some(profiler), Some(path)) = (&profiler, &args.profile) {
+        profiler.write(path)?;
+    }
+    if let Some(manifest) = &manifest {
+        manifest.write(csv_dir)?;
+    }
+    if let Some(tournaments) = &shared.tournaments {
+        tournaments.write(csv_dir)?;
+    }
+    #[cfg(feature = "parquet")]
+    if let Some(dataset) = &shared.dataset {
+        dataset.write(csv_dir)?;
+    }
+    let failed_files = failed_files.into_inner().unwrap_or_default();
+    let failed_games = failed_games.into_inner();
+    if !failed_files.is_empty() || failed_games > 0 {
+        log.emit(&Event::Skipped {
+            files: failed_files,
+            games: failed_games,
+        });
+    }
+    log.emit(&Event::Summary {
+        files: pgns.len(),
+        rows: rows.into_inner(),
+        seconds: run_start.elapsed().as_secs_f64(),
+        uring: shared.uring.reads(),
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    use clap::Parser;
+    use flate2::{write::GzEncoder, Compression};
+    use serde::Serialize;
+
+    use crate::{args::Cli, Pgn2Csv};
+
+    #[test]
+    fn globs_narrow_discovery() {
+        let dir = std::env::temp_dir().join(format!("pgn2csv-globs-{}", std::process::id()));
+        for name in [
+            "lichess_db_standard_rated_2023-12.pgn.zst",
+            "lichess_db_standard_rated_2024-01.pgn.zst",
+            "lichess_db_standard_rated_2024-02.pgn.zst",
+            "2024/lichess_db_standard_rated_2024-03.pgn.zst",
+            "notes.txt",
+        ] {
+            let path = dir.join(name);
+            create_dir_all(path.parent().unwrap()).unwrap();
+            File::create(path).unwrap();
+        }
+        let found = |flags: &[&str]| {
+            let cli = Cli::try_parse_from(["blitz", dir.to_str().unwrap()].iter().chain(flags));
+            let args = Args::resolve(cli.unwrap(), false).unwrap();
+            let pgns = dir_pgns(&dir, &args).unwrap();
+            pgns.iter().map(Pgn::relative_path).collect::<Vec<_>>()
+        };
+        assert_eq!(found(&[]).len(), 3);
+        let glob = "lichess_db_standard_rated_2024-*.pgn.zst";
+        assert_eq!(
+            found(&["--glob", glob]),
+            [
+                PathBuf::from("lichess_db_standard_rated_2024-01.pgn.zst"),
+                PathBuf::from("lichess_db_standard_rated_2024-02.pgn.zst"),
+            ]
+        );
+        assert_eq!(found(&["--glob", glob, "--recursive"]).len(), 3);
+        assert_eq!(found(&["--glob", "!*2024-01*"]).len(), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn small_pgns_are_batched() {
+        let dir = std::env::temp_dir().join(format!("pgn2csv-batches-{}", std::process::id()));
+        create_dir_all(&dir).unwrap();
+        for (name, size) in [
+            ("a.pgn", 400),
+            ("b.pgn", 400),
+            ("big.pgn", 2000),
+            ("c.pgn", 400),
+            ("d.pgn", 400),
+            ("e.pgn", 400),
+        ] {
+            std::fs::write(dir.join(name), vec![b'\n'; size]).unwrap();
+        }
+        let batched = |flags: &[&str]| {
+            let cli = Cli::try_parse_from(["blitz", dir.to_str().unwrap()].iter().chain(flags));
+            let args = Args::resolve(cli.unwrap(), false).unwrap();
+            let pgns = dir_pgns(&dir, &args).unwrap();
+            batches(&pgns, &args)
+                .iter()
+                .map(|batch| {
+                    let names = batch.pgns.iter().map(|pgn| pgn.name()).collect();
+                    (batch.name.clone(), names)
+                })
+                .collect::<Vec<(String, Vec<String>)>>()
+        };
+        assert_eq!(batched(&[]).len(), 6);
+        let owned = |name: &str, pgns: &[&str]| {
+            let pgns = pgns.iter().map(|pgn| pgn.to_string()).collect();
+            (name.to_owned(), pgns)
+        };
+        assert_eq!(
+            batched(&["--batch", "1k", "--format-for", "e.pgn=jsonl"]),
+            [
+                owned("big.pgn", &["big.pgn"]),
+                owned("batch-000001", &["a.pgn", "b.pgn"]),
+                owned("e.pgn", &["e.pgn"]),
+                owned("batch-000003", &["c.pgn", "d.pgn"]),
+            ]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[derive(Default, Serialize)]
+    struct Row {
+        game: u8,
+    }
+
+    #[derive(Default)]
+    struct Processor;
+
+    impl Visitor for Processor {
+        type Result = ();
+
+        fn end_game(&mut self) {}
+    }
+
+    impl GameProcessor for Processor {
+        type Row = Row;
+
+        fn row(&mut self) -> Row {
+            Row::default()
+        }
+    }
+
+    #[test]
+    fn truncated_pgns_fail() {
+        let dir = std::env::temp_dir().join(format!("pgn2csv-truncated-{}", std::process::id()));
+        let (pgn_dir, csv_dir) = (dir.join("pgns"), dir.join("csvs"));
+        create_dir_all(&pgn_dir).unwrap();
+        let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+        for i in 0..1000 {
+            write!(gz, "[Event \"{i}\"]\n\n1. e4 e5 {{ game {i} }} 1-0\n\n").unwrap();
+        }
+        let gz = gz.finish().unwrap();
+        std::fs::write(pgn_dir.join("truncated.pgn.gz"), &gz[..gz.len() / 2]).unwrap();
+        let run = |errors: &str| {
+            Pgn2Csv::builder()
+                .pgn_dir(&pgn_dir)
+                .csv_dir(&csv_dir)
+                .serial(true)
+                .args(["--errors", errors, "--on-existing", "skip"])
+                .build()
+                .unwrap()
+                .run::<Processor>()
+        };
+        let error = run("abort").unwrap_err();
+        assert!(format!("{error:#}").contains("can't read truncated.pgn.gz"));
+        // the incomplete output isn't taken as done, so the PGN is converted
+        // again
+        assert!(!csv_dir.join("truncated.pgn.csv").exists());
+        assert!(run("abort").is_err());
+        assert!(run("skip-file").is_ok());
+        assert!(run("skip-game").is_ok());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn limit_stops_each_pgn() {
+        let dir = std::env::temp_dir().join(format!("pgn2csv-limit-{}", std::process::id()));
+        let (pgn_dir, csv_dir) = (dir.join("pgns"), dir.join("csvs"));
+        create_dir_all(&pgn_dir).unwrap();
+        let games: String = (0..5)
+            .map(|i| format!("[Event \"{i}\"]\n\n1. e4 e5 1-0\n\n"))
+            .collect();
+        for name in ["a.pgn", "b.pgn"] {
+            std::fs::write(pgn_dir.join(name), &games).unwrap();
+        }
+        Pgn2Csv::builder()
+            .pgn_dir(&pgn_dir)
+            .csv_dir(&csv_dir)
+            .serial(true)
+            .args(["--limit", "2"])
+            .build()
+            .unwrap()
+            .run::<Processor>()
+            .unwrap();
+        for name in ["a.csv", "b.csv"] {
+            let csv = std::fs::read_to_string(csv_dir.join(name)).unwrap();
+            // the header and the first two games
+            assert_eq!(csv.lines().count(), 3, "{name}");
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    static PROCESSORS: AtomicUsize = AtomicUsize::new(0);
+
+    struct Counted;
+
+    impl Default for Counted {
+        fn default() -> Self {
+            PROCESSORS.fetch_add(1, Ordering::Relaxed);
+            Counted
+        }
+    }
+
+    impl Visitor for Counted {
+        type Result = ();
+
+        fn end_game(&mut self) {}
+    }
+
+    impl GameProcessor for Counted {
+        type Row = Row;
+
+        fn reset_for_file(&mut self) {}
+
+        fn row(&mut self) -> Row {
+            Row::default()
+        }
+    }
+
+    #[test]
+    fn processors_are_reused_per_worker() {
+        let dir = std::env::temp_dir().join(format!("pgn2csv-workers-{}", std::process::id()));
+        let (pgn_dir, csv_dir) = (dir.join("pgns"), dir.join("csvs"));
+        create_dir_all(&pgn_dir).unwrap();
+        for i in 0..50 {
+            let pgn = format!("[Event \"{i}\"]\n\n1. e4 e5 1-0\n\n");
+            std::fs::write(pgn_dir.join(format!("{i}.pgn")), pgn).unwrap();
+        }
+        Pgn2Csv::builder()
+            .pgn_dir(&pgn_dir)
+            .csv_dir(&csv_dir)
+            .threads(2)
+            .build()
+            .unwrap()
+            .run::<Counted>()
+            .unwrap();
+        // one for each worker, besides the one the run describes its rows with
+        assert!(PROCESSORS.load(Ordering::Relaxed) <= 3);
+        assert_eq!(std::fs::read_dir(&csv_dir).unwrap().count(), 51);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn merged_and_partitioned_runs_are_not_journaled() {
+        let dir = std::env::temp_dir().join(format!("pgn2csv-journaled-{}", std::process::id()));
+        let (pgn_dir, csv_dir) = (dir.join("pgns"), dir.join("csvs"));
+        create_dir_all(&pgn_dir).unwrap();
+        for name in ["a.pgn", "b.pgn"] {
+            std::fs::write(pgn_dir.join(name), "[Event \"x\"]\n\n1. e4 e5 1-0\n\n").unwrap();
+        }
+        let run = |args: &[&str]| {
+            Pgn2Csv::builder()
+                .pgn_dir(&pgn_dir)
+                .csv_dir(&csv_dir)
+                .serial(true)
+                .args(args)
+                .build()
+                .unwrap()
+                .run::<Processor>()
+                .unwrap();
+        };
+        let merged = dir.join("games.csv");
+        run(&["--merge", merged.to_str().unwrap()]);
+        assert!(merged.exists());
+        run(&["--partition-by", "Event"]);
+        assert!(csv_dir.join("x.csv").exists());
+        // a resumed run still writes the PGNs' own outputs
+        run(&["--resume"]);
+        assert!(csv_dir.join("a.csv").exists());
+        assert!(csv_dir.join("b.csv").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
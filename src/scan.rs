@@ -0,0 +1,88 @@
+use std::io::{self, BufRead, IsTerminal, Write};
+
+use anyhow::{bail, Result};
+use indicatif::{HumanBytes, HumanCount};
+
+/// What a quick read of a PGN's headers found: how many of its games the
+/// processor would keep, going by their headers alone, and how large their
+/// rows would be as CSV.
+pub(crate) struct FileScan {
+    pub(crate) name: String,
+    pub(crate) games: u64,
+    pub(crate) matched: u64,
+    pub(crate) bytes: u64,
+}
+
+/// Lays out the scans of the PGNs as a table, with a line of totals.
+pub(crate) fn report(scans: &[FileScan]) -> String {
+    let total = FileScan {
+        name: "total".to_owned(),
+        games: scans.iter().map(|scan| scan.games).sum(),
+        matched: scans.iter().map(|scan| scan.matched).sum(),
+        bytes: scans.iter().map(|scan| scan.bytes).sum(),
+    };
+    let width = scans
+        .iter()
+        .chain([&total])
+        .map(|scan| scan.name.chars().count())
+        .max()
+        .unwrap_or(0);
+    let mut report = format!(
+        "{:<width$}  {:>12}  {:>12}  {:>6}  {:>10}\n",
+        "pgn", "games", "matched", "rate", "csv size"
+    );
+    for scan in scans.iter().chain([&total]) {
+        let rate = match scan.games {
+            0 => 0.0,
+            games => 100.0 * scan.matched as f64 / games as f64,
+        };
+        report += &format!(
+            "{:<width$}  {:>12}  {:>12}  {:>5.1}%  {:>10}\n",
+            scan.name,
+            HumanCount(scan.games).to_string(),
+            HumanCount(scan.matched).to_string(),
+            rate,
+            HumanBytes(scan.bytes).to_string(),
+        );
+    }
+    report
+}
+
+/// Asks on the terminal whether to go on with the run, unless `yes` already
+/// says so.
+pub(crate) fn confirm(yes: bool) -> Result<bool> {
+    if yes {
+        return Ok(true);
+    }
+    if !io::stdin().is_terminal() {
+        bail!("there is no terminal to confirm the run on, so pass --yes to run it");
+    }
+    eprint!("Convert them? [y/N] ");
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_match_rates() {
+        let scans = [FileScan {
+            name: "a.pgn".to_owned(),
+            games: 2000,
+            matched: 500,
+            bytes: 2048,
+        }];
+        assert_eq!(
+            report(&scans),
+            "\
+pgn           games       matched    rate    csv size
+a.pgn         2,000           500   25.0%    2.00 KiB
+total         2,000           500   25.0%    2.00 KiB
+"
+        );
+    }
+}
@@ -0,0 +1,420 @@
+use std::io::{self, BufRead, Cursor};
+
+use pgn_reader::{BufferedReader, Visitor};
+
+use crate::{game::GameVisitor, GameOffsets, GameProcessor};
+
+/// The bytes of one game, and where they are in the decompressed PGN.
+pub(crate) struct Segment {
+    pub(crate) offsets: GameOffsets,
+    pub(crate) bytes: Vec<u8>,
+    // whether the game was larger than the splitter keeps, so that only its
+    // start is in `bytes`
+    pub(crate) oversized: bool,
+}
+
+/// Splits a PGN into games by counting the bytes read through it, since
+/// `BufferedReader` reads ahead and doesn't tell where a game starts. A game
+/// starts at a header line (one starting with `[`) that follows movetext, and
+/// runs up to where the next one starts, so it includes the blank lines after
+/// it. Brace comments are skipped, so that `[` at the start of a line inside a
+/// comment doesn't split a game.
+///
+/// Scraped PGNs can also have games that follow another's movetext on the
+/// same line, or have no movetext at all. A tolerant splitter also starts a
+/// game at an `[Event ` tag in the middle of a line of movetext, and at an
+/// `[Event ` header when the game already has one, which `BufferedReader`
+/// would otherwise read as part of the game before.
+///
+/// After an error reading the stream, the splitter can be resynchronized to
+/// go on with the next line that starts with an `[Event ` header.
+pub(crate) struct GameSplitter<R> {
+    reader: R,
+    tolerant: bool,
+    // offset of the next byte to be read
+    offset: u64,
+    // the line, or rest of a line, that starts the next game, and its offset
+    pending: Option<(u64, Vec<u8>)>,
+    // the most bytes of a game that are kept
+    max_bytes: Option<usize>,
+    // whether lines are skipped up to the next `[Event ` header
+    resyncing: bool,
+    // the offset of the last error, which the stream has to get past to be
+    // resynchronized again
+    failed_at: Option<u64>,
+}
+
+impl<R: BufRead> GameSplitter<R> {
+    pub(crate) fn new(reader: R, tolerant: bool) -> Self {
+        GameSplitter {
+            reader,
+            tolerant,
+            offset: 0,
+            pending: None,
+            max_bytes: None,
+            resyncing: false,
+            failed_at: None,
+        }
+    }
+
+    /// Keeps at most `max_bytes` of each game, marking larger games as
+    /// oversized, so that a pathological game isn't read into memory.
+    pub(crate) fn max_bytes(&mut self, max_bytes: usize) {
+        self.max_bytes = Some(max_bytes);
+    }
+
+    /// Skips up to the next line that starts with an `[Event ` header, after
+    /// `next_game` returned an error, dropping the game it was reading.
+    /// Returns false, leaving the splitter as it is, if the stream hasn't got
+    /// past the last error, so that a stream that keeps failing isn't retried
+    /// forever.
+    pub(crate) fn resync(&mut self) -> bool {
+        if self.failed_at == Some(self.offset) {
+            return false;
+        }
+        self.failed_at = Some(self.offset);
+        self.pending = None;
+        self.resyncing = true;
+        true
+    }
+
+    pub(crate) fn next_game(&mut self) -> io::Result<Option<Segment>> {
+        let mut start = None;
+        let mut bytes = Vec::new();
+        let mut len = 0;
+        let mut oversized = false;
+        let max_bytes = self.max_bytes;
+        let mut keep = |bytes: &mut Vec<u8>, line: &[u8]| {
+            len += line.len() as u64;
+            oversized |= max_bytes.is_some_and(|max| bytes.len() + line.len() > max);
+            if !oversized {
+                bytes.extend_from_slice(line);
+            }
+        };
+        let mut movetext = false;
+        let mut event = false;
+        let mut in_comment = false;
+        loop {
+            let (line_start, mut line) = match self.pending.take() {
+                Some(pending) => pending,
+                None => {
+                    let mut line = Vec::new();
+                    let read = self.reader.read_until(b'\n', &mut line);
+                    let line_start = self.offset;
+                    // what was read before an error is consumed too
+                    self.offset += line.len() as u64;
+                    if read? == 0 {
+                        break;
+                    }
+                    if self.resyncing {
+                        if !line.trim_ascii_start().starts_with(EVENT) {
+                            continue;
+                        }
+                        self.resyncing = false;
+                    }
+                    (line_start, line)
+                }
+            };
+            let text = line.trim_ascii_start();
+            let indent = line.len() - text.len();
+            if !in_comment && text.starts_with(b"[") {
+                let event_header = text.starts_with(EVENT);
+                if movetext || (self.tolerant && event && event_header) {
+                    self.pending = Some((line_start, line));
+                    break;
+                }
+                event |= event_header;
+            } else if !text.is_empty() && !text.starts_with(b"%") {
+                movetext = true;
+                let next_event;
+                (in_comment, next_event) = scan_movetext(text, in_comment);
+                if let Some(at) = next_event.filter(|_| self.tolerant) {
+                    let rest = line.split_off(indent + at);
+                    self.pending = Some((line_start + line.len() as u64, rest));
+                    start.get_or_insert(line_start);
+                    keep(&mut bytes, &line);
+                    break;
+                }
+            }
+            start.get_or_insert(line_start);
+            keep(&mut bytes, &line);
+        }
+        Ok(start.map(|start| Segment {
+            offsets: GameOffsets {
+                start,
+                end: start + len,
+            },
+            bytes,
+            oversized,
+        }))
+    }
+}
+
+const EVENT: &[u8] = b"[Event ";
+
+/// Whether a line of movetext ends inside a brace comment, given whether it
+/// starts inside one, and where an `[Event ` tag outside comments starts in
+/// it, if one does.
+fn scan_movetext(line: &[u8], mut in_comment: bool) -> (bool, Option<usize>) {
+    for (i, &byte) in line.iter().enumerate() {
+        match (in_comment, byte) {
+            (true, b'}') => in_comment = false,
+            (false, b'{') => in_comment = true,
+            (false, b'[') if line[i..].starts_with(EVENT) => return (false, Some(i)),
+            // the rest of the line is a comment
+            (false, b';') => break,
+            _ => (),
+        }
+    }
+    (in_comment, None)
+}
+
+/// Reads the games of a PGN into a visitor, either straight from the stream,
+/// or one split off game at a time so that the processor knows where each one
+/// is.
+pub(crate) enum GameReader<R> {
+    Buffered(BufferedReader<R>),
+    Split {
+        splitter: Box<GameSplitter<io::BufReader<R>>>,
+        current: Option<(GameOffsets, BufferedReader<Cursor<Vec<u8>>>)>,
+        // the text of the current game, if it is kept
+        text: Option<Vec<u8>>,
+        // how many games were too large to be parsed
+        oversized: u64,
+        // how many games that couldn't be read were skipped, if they are
+        skipped: Option<u64>,
+    },
+}
+
+impl<R: io::Read> GameReader<R> {
+    /// Reads games straight from the stream, unless their offsets are needed
+    /// or the PGN is to be split tolerantly (see `GameSplitter`).
+    pub(crate) fn new(reader: R, offsets: bool, tolerant: bool) -> Self {
+        if offsets || tolerant {
+            GameReader::Split {
+                splitter: Box::new(GameSplitter::new(io::BufReader::new(reader), tolerant)),
+                current: None,
+                text: None,
+                oversized: 0,
+                skipped: None,
+            }
+        } else {
+            GameReader::Buffered(BufferedReader::new(reader))
+        }
+    }
+
+    /// Reads the next game, returning whether there was one.
+    pub(crate) fn read_game<P>(&mut self, game: &mut GameVisitor<'_, P>) -> io::Result<bool>
+    where
+        P: Visitor + GameProcessor,
+    {
+        let (splitter, current, text, oversized, skipped) = match self {
+            GameReader::Buffered(reader) => return Ok(reader.read_game(game)?.is_some()),
+            GameReader::Split {
+                splitter,
+                current,
+                text,
+                oversized,
+                skipped,
+            } => (splitter, current, text, oversized, skipped),
+        };
+        loop {
+            if let Some((offsets, reader)) = current {
+                game.set_offsets(*offsets);
+                // a segment can hold more than one game, e.g. if one had no
+                // headers, and they all get its offsets
+                match (reader.read_game(game), skipped.as_mut()) {
+                    (Ok(Some(_)), _) => return Ok(true),
+                    (Ok(None), _) => (),
+                    // the rest of the segment is given up on
+                    (Err(_), Some(skipped)) => *skipped += 1,
+                    (Err(e), None) => return Err(e),
+                }
+                *current = None;
+            }
+            let segment = match (splitter.next_game(), skipped.as_mut()) {
+                (Ok(segment), _) => segment,
+                // the game being split off is given up on, and so is the
+                // stream up to the next game
+                (Err(_), Some(skipped)) if splitter.resync() => {
+                    *skipped += 1;
+                    continue;
+                }
+                (Err(e), _) => return Err(e),
+            };
+            match segment {
+                Some(segment) if segment.oversized => {
+                    *current = None;
+                    *oversized += 1;
+                }
+                Some(segment) => {
+                    if let Some(text) = text {
+                        text.clone_from(&segment.bytes);
+                    }
+                    *current = Some((segment.offsets, BufferedReader::new_cursor(segment.bytes)));
+                }
+                None => return Ok(false),
+            }
+        }
+    }
+
+    /// Skips the games larger than `max_bytes` without parsing them, if
+    /// games are split, counting them for `oversized`.
+    pub(crate) fn max_game_bytes(&mut self, max_bytes: usize) {
+        if let GameReader::Split { splitter, .. } = self {
+            splitter.max_bytes(max_bytes);
+        }
+    }
+
+    /// How many games were skipped for being larger than `max_game_bytes`.
+    pub(crate) fn oversized(&self) -> u64 {
+        match self {
+            GameReader::Split { oversized, .. } => *oversized,
+            GameReader::Buffered(_) => 0,
+        }
+    }
+
+    /// Skips the games that can't be read, if games are split, counting them
+    /// for `skipped`, rather than failing. After an error reading the stream
+    /// itself, e.g. from decompressing it, the game being read is skipped and
+    /// reading goes on at the next `[Event ` header; the error is returned if
+    /// the stream fails again without getting any further.
+    pub(crate) fn skip_errors(&mut self) {
+        if let GameReader::Split { skipped, .. } = self {
+            *skipped = Some(0);
+        }
+    }
+
+    /// How many games were skipped because they couldn't be read.
+    pub(crate) fn skipped(&self) -> u64 {
+        match self {
+            GameReader::Split { skipped, .. } => skipped.unwrap_or(0),
+            GameReader::Buffered(_) => 0,
+        }
+    }
+
+    /// Keeps the text of each game read, if games are split, for `text`.
+    pub(crate) fn keep_text(&mut self) {
+        if let GameReader::Split { text, .. } = self {
+            *text = Some(Vec::new());
+        }
+    }
+
+    /// The text of the last game read, if it is kept. It holds every game of
+    /// a split off game that turned out to hold more than one.
+    pub(crate) fn text(&self) -> Option<&[u8]> {
+        match self {
+            GameReader::Split { text, .. } => text.as_deref(),
+            GameReader::Buffered(_) => None,
+        }
+    }
+
+    /// Where the game being read starts, if games are split.
+    pub(crate) fn offset(&self) -> Option<u64> {
+        match self {
+            GameReader::Buffered(_) => None,
+            GameReader::Split {
+                splitter, current, ..
+            } => Some(
+                current
+                    .as_ref()
+                    .map_or(splitter.offset, |(offsets, _)| offsets.start),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_games() {
+        let pgn = b"[Event \"a\"]\n\n1. e4 { a comment\n[not a header] } e5 1-0\n\n[Event \"b\"]\n[Site \"?\"]\n\n1. d4 0-1\n";
+        let mut splitter = GameSplitter::new(pgn.as_slice(), false);
+        let first = splitter.next_game().unwrap().unwrap();
+        let second = splitter.next_game().unwrap().unwrap();
+        assert!(splitter.next_game().unwrap().is_none());
+        assert_eq!(first.offsets.start, 0);
+        assert_eq!(first.offsets.end, second.offsets.start);
+        assert_eq!(second.offsets.end, pgn.len() as u64);
+        assert!(second.bytes.starts_with(b"[Event \"b\"]"));
+    }
+
+    #[test]
+    fn splits_crammed_games() {
+        let pgn = b"[Event \"a\"]\n1. e4 {[Event in a comment]} 1-0 [Event \"b\"]\n[Event \"c\"]\n\n1. d4 0-1\n";
+        let games = |tolerant| {
+            let mut splitter = GameSplitter::new(pgn.as_slice(), tolerant);
+            let mut games = Vec::new();
+            while let Some(segment) = splitter.next_game().unwrap() {
+                games.push(segment.bytes);
+            }
+            games
+        };
+        assert_eq!(games(false).len(), 2);
+        let games = games(true);
+        assert_eq!(games.len(), 3);
+        assert_eq!(
+            games[0],
+            b"[Event \"a\"]\n1. e4 {[Event in a comment]} 1-0 "
+        );
+        assert_eq!(games[1], b"[Event \"b\"]\n");
+        assert!(games[2].starts_with(b"[Event \"c\"]"));
+    }
+
+    #[test]
+    fn marks_oversized_games() {
+        let pgn = b"[Event \"a\"]\n\n1. e4 e5 2. Nf3 Nc6 1-0\n\n[Event \"b\"]\n\n1. d4 0-1\n";
+        let mut splitter = GameSplitter::new(pgn.as_slice(), false);
+        splitter.max_bytes(30);
+        let first = splitter.next_game().unwrap().unwrap();
+        let second = splitter.next_game().unwrap().unwrap();
+        assert!(first.oversized && !second.oversized);
+        assert_eq!(first.bytes, b"[Event \"a\"]\n\n");
+        assert_eq!(first.offsets.end, second.offsets.start);
+    }
+
+    /// Reads its chunks in turn, failing for the ones that are `None`.
+    struct Flaky(Vec<Option<&'static [u8]>>);
+
+    impl io::Read for Flaky {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() {
+                return Ok(0);
+            }
+            match self.0.remove(0) {
+                Some(chunk) => {
+                    buf[..chunk.len()].copy_from_slice(chunk);
+                    Ok(chunk.len())
+                }
+                None => Err(io::Error::other("corrupt")),
+            }
+        }
+    }
+
+    #[test]
+    fn resyncs_after_errors() {
+        let flaky = Flaky(vec![
+            Some(b"[Event \"a\"]\n\n1. e4 1-0\n\n[Event \"b\"]\n\n1. d4"),
+            None,
+            Some(b" d5 2. c4 0-1\n\n[Site \"?\"]\n"),
+            Some(b"[Event \"c\"]\n\n1. c4 1/2-1/2\n"),
+            Some(b"[Event \"d\"]\n"),
+            None,
+            None,
+        ]);
+        let mut splitter = GameSplitter::new(io::BufReader::new(flaky), false);
+        let first = splitter.next_game().unwrap().unwrap();
+        assert!(first.bytes.starts_with(b"[Event \"a\"]"));
+        assert!(splitter.next_game().is_err());
+        assert!(splitter.resync());
+        let third = splitter.next_game().unwrap().unwrap();
+        assert_eq!(third.bytes, b"[Event \"c\"]\n\n1. c4 1/2-1/2\n");
+        assert!(splitter.next_game().is_err());
+        assert!(splitter.resync());
+        // the stream fails again without getting any further
+        assert!(splitter.next_game().is_err());
+        assert!(!splitter.resync());
+    }
+}
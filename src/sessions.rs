@@ -0,0 +1,178 @@
+use std::time::Duration;
+#[cfg(feature = "pipeline")]
+use std::{
+    fs::{self, File},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+#[cfg(feature = "pipeline")]
+use anyhow::{anyhow, Result};
+
+#[cfg(feature = "pipeline")]
+use crate::{analyzers::SessionTracker, sort::SortKey, spill::SpillBuffer};
+
+/// Numbers the sessions of rematches between the same two players, for tilt
+/// and fatigue studies. Each output is ordered by the two player columns and
+/// then the time column, which holds seconds since the epoch, and gets a
+/// `session_id` column and a `rematch` column counting the session's earlier
+/// games. A game is a rematch if it started within `max_gap` of the pair's
+/// previous game.
+///
+/// The player columns are taken as they are, so to count games with either
+/// color together, put the players in a fixed order, e.g. by name.
+// only read by the pipeline, which numbers the sessions
+#[cfg_attr(not(feature = "pipeline"), allow(dead_code))]
+#[derive(Clone, Debug)]
+pub struct Sessions {
+    pub(crate) players: [&'static str; 2],
+    pub(crate) time: &'static str,
+    pub(crate) max_gap: Duration,
+}
+
+impl Sessions {
+    #[must_use]
+    pub fn new(
+        player_a: &'static str,
+        player_b: &'static str,
+        time: &'static str,
+        max_gap: Duration,
+    ) -> Self {
+        Sessions {
+            players: [player_a, player_b],
+            time,
+            max_gap,
+        }
+    }
+
+    /// The same sessions with a different gap, e.g. from `--session-gap`.
+    #[cfg(feature = "pipeline")]
+    #[must_use]
+    pub(crate) fn with_max_gap(self, max_gap: Duration) -> Self {
+        Sessions { max_gap, ..self }
+    }
+}
+
+/// Replaces the CSV at `path` with its rows in session order, with session
+/// columns added, holding at most about `max_bytes` of rows in memory and
+/// spilling the rest to `work_dir`.
+#[cfg(feature = "pipeline")]
+pub(crate) fn sessions_csv(
+    path: &Path,
+    sessions: &Sessions,
+    max_bytes: usize,
+    comment: Option<&str>,
+    work_dir: &Path,
+) -> Result<()> {
+    let mut file = BufReader::new(File::open(path)?);
+    if comment.is_some() {
+        file.read_line(&mut String::new())?;
+    }
+    let mut reader = csv::Reader::from_reader(file);
+    let mut headers = reader.byte_headers()?.clone();
+    if headers.is_empty() {
+        // nothing was written
+        return Ok(());
+    }
+    let column = |name: &str| {
+        headers
+            .iter()
+            .position(|h| h == name.as_bytes())
+            .ok_or_else(|| anyhow!("no column named {name} to find sessions by"))
+    };
+    let players = [column(sessions.players[0])?, column(sessions.players[1])?];
+    let time = column(sessions.time)?;
+    let columns: Vec<String> = sessions
+        .players
+        .iter()
+        .chain([&sessions.time])
+        .map(|&name| name.to_owned())
+        .collect();
+    let key = SortKey::new(&headers, &columns)?.distinct();
+
+    let mut buffer = SpillBuffer::new(work_dir, max_bytes, Some(key));
+    for record in reader.byte_records() {
+        buffer.push(record?)?;
+    }
+
+    let sessions_path = path.with_extension("csv.sessions");
+    let mut file = BufWriter::new(File::create(&sessions_path)?);
+    if let Some(comment) = comment {
+        writeln!(file, "{comment}")?;
+    }
+    let mut writer = csv::Writer::from_writer(file);
+    headers.push_field(b"session_id");
+    headers.push_field(b"rematch");
+    writer.write_byte_record(&headers)?;
+    let max_gap = i64::try_from(sessions.max_gap.as_secs())?;
+    let mut tracker = SessionTracker::new(max_gap);
+    let mut pair = Vec::new();
+    for record in buffer.into_records()? {
+        let mut record = record?;
+        pair.clear();
+        for (i, &player) in players.iter().enumerate() {
+            if i > 0 {
+                // a separator that can't be in a name keeps e.g. ("ab", "c")
+                // and ("a", "bc") apart
+                pair.push(0);
+            }
+            pair.extend_from_slice(record.get(player).unwrap_or_default());
+        }
+        let timestamp = record
+            .get(time)
+            .and_then(|field| std::str::from_utf8(field).ok()?.parse::<i64>().ok());
+        match timestamp {
+            Some(timestamp) => {
+                let (session, rematch) = tracker.push(&pair, timestamp);
+                record.push_field(session.to_string().as_bytes());
+                record.push_field(rematch.to_string().as_bytes());
+            }
+            // games without a start time aren't in any session
+            None => {
+                record.push_field(b"");
+                record.push_field(b"");
+            }
+        }
+        writer.write_byte_record(&record)?;
+    }
+    writer.flush()?;
+    drop(writer);
+    fs::rename(&sessions_path, path)?;
+    Ok(())
+}
+
+#[cfg(all(test, feature = "pipeline"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_sessions() {
+        let dir = std::env::temp_dir().join(format!("pgn2csv-sessions-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("games.csv");
+        fs::write(
+            &path,
+            "# pgn2csv\nwhite,black,start\na,b,5000\nc,d,50\na,b,\na,b,0\na,b,300\n",
+        )
+        .unwrap();
+        let sessions = Sessions::new("white", "black", "start", Duration::from_secs(600));
+        sessions_csv(&path, &sessions, 1 << 20, Some("# pgn2csv"), &dir).unwrap();
+        // games without a start time come last, in no session
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "# pgn2csv\nwhite,black,start,session_id,rematch\n\
+             a,b,0,1,0\na,b,300,1,1\na,b,5000,2,0\na,b,,,\nc,d,50,3,0\n"
+        );
+        // a longer gap joins the first three games
+        let sessions = sessions.with_max_gap(Duration::from_secs(5000));
+        fs::write(&path, "white,black,start\na,b,5000\na,b,0\na,b,300\n").unwrap();
+        sessions_csv(&path, &sessions, 1 << 20, None, &dir).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "white,black,start,session_id,rematch\na,b,0,1,0\na,b,300,1,1\na,b,5000,1,2\n"
+        );
+        let sessions = Sessions::new("white", "black", "utc", Duration::from_secs(600));
+        assert!(sessions_csv(&path, &sessions, 1 << 20, None, &dir).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,185 @@
+//! Where the rows of a run are written. Every output format is a `RowSink`,
+//! so that the pipeline writes CSVs, parquet files, and databases the same
+//! way, and other formats can be added by implementing the trait.
+
+use std::{
+    io::{BufWriter, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+use serde::Serialize;
+
+pub use crate::record::Record;
+
+/// Writes the rows converted from one PGN, or from one batch of them, to one
+/// output.
+pub trait RowSink {
+    /// Writes a row, as its named values in column order. The rows of an
+    /// output all have the same columns.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the row can't be written.
+    fn write_row(&mut self, record: &Record) -> Result<()>;
+
+    /// Writes out the rows buffered so far. It is called once all rows of
+    /// each PGN of the output have been written.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the rows can't be written.
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Completes the output, once every row has been written. It is called
+    /// once, even if no rows were written.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the output can't be completed.
+    fn finalize(&mut self) -> Result<()> {
+        self.flush()
+    }
+}
+
+/// Creates the sink for the output at a path, which is named as a CSV would
+/// be, e.g. `games.csv`, or `games.1400.csv` for rows routed to `1400`.
+pub type SinkFactory = dyn Fn(&Path) -> Result<Box<dyn RowSink>> + Send + Sync;
+
+/// Writes rows as CSV, with a header row.
+pub(crate) struct CsvSink {
+    writer: csv::Writer<Box<dyn Write>>,
+    // whether headers were written for rows given as records
+    headers: bool,
+}
+
+impl CsvSink {
+    pub(crate) fn new(writer: Box<dyn Write>) -> Self {
+        CsvSink {
+            writer: csv::Writer::from_writer(writer),
+            headers: false,
+        }
+    }
+
+    /// Writes a row straight from the processor's type, without building its
+    /// record, which is most of the work of writing it.
+    pub(crate) fn serialize(&mut self, row: impl Serialize) -> Result<()> {
+        self.writer.serialize(row)?;
+        Ok(())
+    }
+}
+
+impl RowSink for CsvSink {
+    fn write_row(&mut self, record: &Record) -> Result<()> {
+        if !self.headers {
+            self.writer.write_record(record.keys())?;
+            self.headers = true;
+        }
+        self.writer
+            .write_record(record.values().map(crate::record::field))?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Writes rows as JSON Lines: an object per row, with the values typed as
+/// they are in the row rather than as text.
+pub(crate) struct JsonlSink {
+    writer: BufWriter<Box<dyn Write>>,
+}
+
+impl JsonlSink {
+    pub(crate) fn new(writer: Box<dyn Write>) -> Self {
+        JsonlSink {
+            writer: BufWriter::new(writer),
+        }
+    }
+}
+
+impl RowSink for JsonlSink {
+    fn write_row(&mut self, record: &Record) -> Result<()> {
+        serde_json::to_writer(&mut self.writer, record)?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Collects rows in memory, for programs that use the rows straight away
+/// rather than reading them back from files. Clones share their rows, so a
+/// single sink collects every output of a run:
+///
+/// ```ignore
+/// let rows = MemorySink::default();
+/// Pgn2Csv::builder()
+///     .pgn_dir("pgns")
+///     .sink(rows.clone())
+///     .build()?
+///     .run::<MyProcessor>()?;
+/// println!("{} rows", rows.rows().len());
+/// ```
+#[derive(Clone, Default)]
+pub struct MemorySink {
+    rows: Arc<Mutex<Vec<Record>>>,
+}
+
+impl MemorySink {
+    /// The rows written so far, in the order they were written in.
+    #[must_use]
+    pub fn rows(&self) -> Vec<Record> {
+        self.rows
+            .lock()
+            .map(|rows| rows.clone())
+            .unwrap_or_default()
+    }
+}
+
+impl RowSink for MemorySink {
+    fn write_row(&mut self, record: &Record) -> Result<()> {
+        self.rows
+            .lock()
+            .map_err(|_| anyhow::anyhow!("memory sink lock poisoned"))?
+            .push(record.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    #[test]
+    fn writes_rows() {
+        let record = match json!({"white_elo": 1500, "site": "a,b"}) {
+            serde_json::Value::Object(record) => record,
+            _ => unreachable!(),
+        };
+        let path = std::env::temp_dir().join(format!("pgn2csv-{}.jsonl", std::process::id()));
+        let mut jsonl = JsonlSink::new(Box::new(std::fs::File::create(&path).unwrap()));
+        jsonl.write_row(&record).unwrap();
+        jsonl.finalize().unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(written, "{\"white_elo\":1500,\"site\":\"a,b\"}\n");
+        std::fs::remove_file(&path).unwrap();
+
+        let memory = MemorySink::default();
+        let mut sink: Box<dyn RowSink> = Box::new(memory.clone());
+        sink.write_row(&record).unwrap();
+        sink.write_row(&record).unwrap();
+        sink.finalize().unwrap();
+        assert_eq!(memory.rows(), [record.clone(), record]);
+    }
+}
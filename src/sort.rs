@@ -0,0 +1,182 @@
+use std::{
+    cmp::Ordering,
+    fs::{self, File},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::{anyhow, Result};
+use csv::ByteRecord;
+
+use crate::spill::SpillBuffer;
+
+/// The columns to order CSV records by. Fields that both parse as numbers are
+/// compared numerically, and anything else byte-wise. An exact key breaks ties
+/// by comparing whole records byte-wise, so that only identical records compare
+/// equal. A distinct key tells apart fields that are equal as numbers but not
+/// as text, like `7` and `07`, so that only identical keys compare equal.
+#[derive(Clone)]
+pub(crate) struct SortKey {
+    columns: Vec<usize>,
+    exact: bool,
+    distinct: bool,
+}
+
+impl SortKey {
+    pub(crate) fn new(headers: &ByteRecord, names: &[String]) -> Result<Self> {
+        let columns = names
+            .iter()
+            .map(|name| {
+                headers
+                    .iter()
+                    .position(|header| header == name.as_bytes())
+                    .ok_or_else(|| anyhow!("no column named {name} to sort by"))
+            })
+            .collect::<Result<_>>()?;
+        Ok(SortKey {
+            columns,
+            exact: false,
+            distinct: false,
+        })
+    }
+
+    pub(crate) fn exact(self) -> Self {
+        SortKey {
+            exact: true,
+            ..self
+        }
+    }
+
+    pub(crate) fn distinct(self) -> Self {
+        SortKey {
+            distinct: true,
+            ..self
+        }
+    }
+
+    pub(crate) fn compare(&self, a: &ByteRecord, b: &ByteRecord) -> Ordering {
+        self.columns
+            .iter()
+            .map(|&i| {
+                let (a, b) = (a.get(i).unwrap_or_default(), b.get(i).unwrap_or_default());
+                let ordering = compare_fields(a, b);
+                if self.distinct {
+                    ordering.then_with(|| a.cmp(b))
+                } else {
+                    ordering
+                }
+            })
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or_else(|| {
+                if self.exact {
+                    a.iter().cmp(b.iter())
+                } else {
+                    Ordering::Equal
+                }
+            })
+    }
+}
+
+/// The value of a field that holds a finite number, since `f64` also parses
+/// e.g. `NaN` and `inf`.
+fn number(field: &[u8]) -> Option<f64> {
+    let number: f64 = std::str::from_utf8(field).ok()?.parse().ok()?;
+    number.is_finite().then_some(number)
+}
+
+/// Orders numbers numerically, before the fields that aren't numbers, which
+/// are ordered by their bytes, so that a column mixing the two, e.g. with
+/// empty fields, is still totally ordered.
+fn compare_fields(a: &[u8], b: &[u8]) -> Ordering {
+    match (number(a), number(b)) {
+        (Some(a), Some(b)) => a.total_cmp(&b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => a.cmp(b),
+    }
+}
+
+/// Sorts the CSV at `path` in place by the given columns, with an external
+/// merge sort that holds at most about `max_bytes` of records in memory. With
+/// `dedup`, identical records end up next to each other and all but the first
+/// are dropped. Spilled runs go in `work_dir`.
+pub(crate) fn sort_csv(
+    path: &Path,
+    columns: &[String],
+    dedup: bool,
+    max_bytes: usize,
+    comment: Option<&str>,
+    work_dir: &Path,
+) -> Result<()> {
+    let mut file = BufReader::new(File::open(path)?);
+    if comment.is_some() {
+        file.read_line(&mut String::new())?;
+    }
+    let mut reader = csv::Reader::from_reader(file);
+    let headers = reader.byte_headers()?.clone();
+    if headers.is_empty() {
+        // nothing was written
+        return Ok(());
+    }
+    let mut key = SortKey::new(&headers, columns)?;
+    if dedup {
+        key = key.exact();
+    }
+    let mut buffer = SpillBuffer::new(work_dir, max_bytes, Some(key));
+    for record in reader.byte_records() {
+        buffer.push(record?)?;
+    }
+
+    let sorted_path = path.with_extension("csv.sorting");
+    let mut file = BufWriter::new(File::create(&sorted_path)?);
+    if let Some(comment) = comment {
+        writeln!(file, "{comment}")?;
+    }
+    let mut writer = csv::Writer::from_writer(file);
+    writer.write_byte_record(&headers)?;
+    let mut previous: Option<ByteRecord> = None;
+    for record in buffer.into_records()? {
+        let record = record?;
+        if dedup && previous.as_ref() == Some(&record) {
+            continue;
+        }
+        writer.write_byte_record(&record)?;
+        previous = Some(record);
+    }
+    writer.flush()?;
+    drop(writer);
+    fs::rename(&sorted_path, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compares_numbers_numerically() {
+        let headers = ByteRecord::from(vec!["date", "elo"]);
+        let key = SortKey::new(&headers, &["date".to_string(), "elo".to_string()]).unwrap();
+        let a = ByteRecord::from(vec!["2023.01.01", "900"]);
+        let b = ByteRecord::from(vec!["2023.01.01", "1500"]);
+        let c = ByteRecord::from(vec!["2023.01.02", "100"]);
+        assert!(key.compare(&a, &b).is_lt());
+        assert!(key.compare(&b, &c).is_lt());
+        assert!(SortKey::new(&headers, &["nope".to_string()]).is_err());
+        let padded = ByteRecord::from(vec!["2023.01.01", "0900"]);
+        assert!(key.compare(&a, &padded).is_eq());
+        assert!(key.distinct().compare(&a, &padded).is_ne());
+    }
+
+    #[test]
+    fn orders_fields_totally() {
+        assert_eq!(number(b"NaN"), None);
+        assert_eq!(number(b"inf"), None);
+        assert_eq!(number(b"-infinity"), None);
+        assert_eq!(number(b"-0.5"), Some(-0.5));
+        let mut fields = [&b"NaN"[..], b"10", b"", b"1a", b"2", b"-inf"];
+        fields.sort_by(|a, b| compare_fields(a, b));
+        assert_eq!(fields, [&b"2"[..], b"10", b"", b"-inf", b"1a", b"NaN"]);
+        assert!(compare_fields(b"NaN", b"NaN").is_eq());
+    }
+}
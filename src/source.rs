@@ -0,0 +1,186 @@
+//! Where the PGNs of a run are read from. The pipeline only sees
+//! `PgnSource`s, so that files, stdin, URLs, and archive entries are converted
+//! the same way, and other inputs can be added by implementing the trait.
+
+use std::{
+    fs::{self, File},
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+
+/// A PGN to convert, as it is stored: compressed or not, as its name's
+/// extension says, or else as its first bytes say.
+pub trait PgnSource: Send + Sync {
+    /// The PGN's file name, e.g. `lichess_db_standard_rated_2013-01.pgn.zst`,
+    /// whose extension gives its compression and which names its output.
+    fn name(&self) -> String;
+
+    /// Opens the PGN's bytes as they are stored. It is called once per run,
+    /// from the thread that converts the PGN, and once more before that with
+    /// `--scan`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the PGN can't be read.
+    fn open(&self) -> Result<Box<dyn Read>>;
+
+    /// The size of the PGN as it is stored, if it is known up front, which
+    /// `--batch` uses to pack small PGNs together.
+    fn size_hint(&self) -> Option<u64> {
+        None
+    }
+
+    /// A local file holding exactly the PGN's bytes, if there is one, which is
+    /// then read through a memory map or io_uring where they are enabled
+    /// rather than through `open`.
+    fn local_path(&self) -> Option<&Path> {
+        None
+    }
+}
+
+/// A PGN in a local file.
+pub(crate) struct FileSource {
+    path: PathBuf,
+}
+
+impl FileSource {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        FileSource { path }
+    }
+}
+
+impl PgnSource for FileSource {
+    fn name(&self) -> String {
+        self.path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn open(&self) -> Result<Box<dyn Read>> {
+        Ok(Box::new(File::open(&self.path)?))
+    }
+
+    fn size_hint(&self) -> Option<u64> {
+        fs::metadata(&self.path).ok().map(|metadata| metadata.len())
+    }
+
+    fn local_path(&self) -> Option<&Path> {
+        Some(&self.path)
+    }
+}
+
+/// A PGN read from stdin, named `stdin.pgn`, whose compression is found from
+/// its first bytes.
+pub(crate) struct StdinSource;
+
+impl PgnSource for StdinSource {
+    fn name(&self) -> String {
+        "stdin.pgn".to_owned()
+    }
+
+    fn open(&self) -> Result<Box<dyn Read>> {
+        Ok(Box::new(io::stdin()))
+    }
+}
+
+/// A PGN stored in an uncompressed tar archive, read in place rather than
+/// extracted first.
+#[cfg(feature = "archives")]
+pub(crate) struct TarEntry {
+    archive: PathBuf,
+    // the entry's path within the archive
+    path: PathBuf,
+    // where the entry's bytes start in the archive, and how many there are
+    offset: u64,
+    size: u64,
+}
+
+#[cfg(feature = "archives")]
+impl TarEntry {
+    /// The entry's directory within the archive.
+    pub(crate) fn dir(&self) -> &Path {
+        self.path.parent().unwrap_or(Path::new(""))
+    }
+}
+
+#[cfg(feature = "archives")]
+impl PgnSource for TarEntry {
+    fn name(&self) -> String {
+        self.path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn open(&self) -> Result<Box<dyn Read>> {
+        use std::io::{Seek, SeekFrom};
+
+        let mut file = File::open(&self.archive)?;
+        file.seek(SeekFrom::Start(self.offset))?;
+        Ok(Box::new(io::BufReader::new(file.take(self.size))))
+    }
+
+    fn size_hint(&self) -> Option<u64> {
+        Some(self.size)
+    }
+}
+
+/// The entries of the tar archive at `path` whose names end in one of
+/// `extensions`.
+#[cfg(feature = "archives")]
+pub(crate) fn tar_entries(path: &Path, extensions: &[&str]) -> Result<Vec<TarEntry>> {
+    let mut archive = tar::Archive::new(File::open(path)?);
+    let mut entries = Vec::new();
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let name = entry.path()?.into_owned();
+        let is_pgn = extensions
+            .iter()
+            .any(|ext| name.to_string_lossy().ends_with(ext));
+        if is_pgn && entry.header().entry_type().is_file() {
+            entries.push(TarEntry {
+                archive: path.to_path_buf(),
+                path: name,
+                offset: entry.raw_file_position(),
+                size: entry.size(),
+            });
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(all(test, feature = "archives"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_tar_entries() {
+        let pgn = b"[Event \"Rated Blitz game\"]\n\n1. e4 e5 1-0\n";
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(pgn.len() as u64);
+        header.set_cksum();
+        builder
+            .append_data(&mut header.clone(), "2024/a.pgn", pgn.as_slice())
+            .unwrap();
+        builder
+            .append_data(&mut header, "README.txt", pgn.as_slice())
+            .unwrap();
+        let path = std::env::temp_dir().join(format!("pgn2csv-{}.tar", std::process::id()));
+        fs::write(&path, builder.into_inner().unwrap()).unwrap();
+
+        let entries = tar_entries(&path, &[".pgn"]).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].name(), "a.pgn");
+        assert_eq!(entries[0].dir(), Path::new("2024"));
+        let mut read = Vec::new();
+        entries[0].open().unwrap().read_to_end(&mut read).unwrap();
+        assert_eq!(read, pgn);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,221 @@
+use std::{
+    fs::{self, File},
+    mem,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use anyhow::Result;
+use csv::{ByteRecord, Reader, ReaderBuilder, WriterBuilder};
+
+use crate::sort::SortKey;
+
+// rough per-record cost of a `ByteRecord` on top of its field bytes
+const RECORD_OVERHEAD: usize = 64;
+
+static SPILLS: AtomicUsize = AtomicUsize::new(0);
+
+/// Buffers CSV records in memory, spilling them to temporary files in `dir`
+/// (the run's `WorkDir`) whenever they take up more than `max_bytes`, so that
+/// outputs which have to be held back before writing (to be ordered or merged)
+/// don't run out of memory on very large inputs. With a sort key, each spilled run is sorted
+/// and the records come back out merged into order; without one they come back
+/// in the order they were pushed.
+pub(crate) struct SpillBuffer {
+    dir: PathBuf,
+    max_bytes: usize,
+    key: Option<SortKey>,
+    bytes: usize,
+    records: Vec<ByteRecord>,
+    runs: Vec<PathBuf>,
+}
+
+impl SpillBuffer {
+    pub(crate) fn new(dir: &Path, max_bytes: usize, key: Option<SortKey>) -> Self {
+        SpillBuffer {
+            dir: dir.to_path_buf(),
+            max_bytes,
+            key,
+            bytes: 0,
+            records: Vec::new(),
+            runs: Vec::new(),
+        }
+    }
+
+    pub(crate) fn push(&mut self, record: ByteRecord) -> Result<()> {
+        self.bytes += record.as_slice().len() + RECORD_OVERHEAD;
+        self.records.push(record);
+        if self.bytes > self.max_bytes {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    fn sort(&mut self) {
+        if let Some(key) = &self.key {
+            // stable, so that equal records stay in the order they were pushed
+            self.records.sort_by(|a, b| key.compare(a, b));
+        }
+    }
+
+    fn spill(&mut self) -> Result<()> {
+        self.sort();
+        let path = self.dir.join(format!(
+            "spill-{}.csv",
+            SPILLS.fetch_add(1, Ordering::Relaxed)
+        ));
+        let mut writer = WriterBuilder::new().has_headers(false).from_path(&path)?;
+        // record the run first so that it is cleaned up even if writing fails
+        self.runs.push(path);
+        for record in self.records.drain(..) {
+            writer.write_byte_record(&record)?;
+        }
+        writer.flush()?;
+        self.bytes = 0;
+        Ok(())
+    }
+
+    /// Returns all of the buffered records. Spilled runs are removed once the
+    /// returned iterator is dropped.
+    pub(crate) fn into_records(mut self) -> Result<Records> {
+        self.sort();
+        let mut sources = Vec::new();
+        for run in &self.runs {
+            sources.push(Source::Run(run_reader(run)?));
+        }
+        sources.push(Source::Memory(mem::take(&mut self.records).into_iter()));
+        let mut heads = Vec::with_capacity(sources.len());
+        for source in &mut sources {
+            heads.push(source.next()?);
+        }
+        Ok(Records {
+            key: self.key.take(),
+            sources,
+            heads,
+            runs: mem::take(&mut self.runs),
+        })
+    }
+}
+
+impl Drop for SpillBuffer {
+    fn drop(&mut self) {
+        for run in &self.runs {
+            let _ = fs::remove_file(run);
+        }
+    }
+}
+
+fn run_reader(path: &Path) -> Result<Reader<File>> {
+    Ok(ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_path(path)?)
+}
+
+enum Source {
+    Run(Reader<File>),
+    Memory(std::vec::IntoIter<ByteRecord>),
+}
+
+impl Source {
+    fn next(&mut self) -> Result<Option<ByteRecord>> {
+        match self {
+            Source::Run(reader) => {
+                let mut record = ByteRecord::new();
+                Ok(reader.read_byte_record(&mut record)?.then_some(record))
+            }
+            Source::Memory(records) => Ok(records.next()),
+        }
+    }
+}
+
+/// Merges the spilled runs and the records still in memory. Runs hold earlier
+/// records than memory does, so without a sort key this is a concatenation.
+pub(crate) struct Records {
+    key: Option<SortKey>,
+    sources: Vec<Source>,
+    heads: Vec<Option<ByteRecord>>,
+    runs: Vec<PathBuf>,
+}
+
+impl Records {
+    fn next_record(&mut self) -> Result<Option<ByteRecord>> {
+        let mut next: Option<usize> = None;
+        for (i, head) in self.heads.iter().enumerate() {
+            let Some(record) = head else {
+                continue;
+            };
+            next = match (next, &self.key) {
+                (None, _) => Some(i),
+                (Some(_), None) => break,
+                (Some(j), Some(key)) => match &self.heads[j] {
+                    // ties go to the earlier source, keeping the merge stable
+                    Some(best) if key.compare(record, best).is_lt() => Some(i),
+                    _ => Some(j),
+                },
+            };
+        }
+        let Some(i) = next else {
+            return Ok(None);
+        };
+        let record = self.heads[i].take();
+        self.heads[i] = self.sources[i].next()?;
+        Ok(record)
+    }
+}
+
+impl Iterator for Records {
+    type Item = Result<ByteRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_record().transpose()
+    }
+}
+
+impl Drop for Records {
+    fn drop(&mut self) {
+        for run in &self.runs {
+            let _ = fs::remove_file(run);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(i: usize) -> ByteRecord {
+        ByteRecord::from(vec![i.to_string(), "x".to_string()])
+    }
+
+    fn firsts(records: Records) -> Vec<String> {
+        records
+            .map(|r| String::from_utf8_lossy(&r.unwrap()[0]).into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn spills_and_preserves_order() {
+        let mut buffer = SpillBuffer::new(&std::env::temp_dir(), 4 * RECORD_OVERHEAD, None);
+        for i in 0..20 {
+            buffer.push(record(i)).unwrap();
+        }
+        assert!(buffer.runs.len() >= 4);
+        let runs = buffer.runs.clone();
+        let expected: Vec<String> = (0..20).map(|i| i.to_string()).collect();
+        assert_eq!(firsts(buffer.into_records().unwrap()), expected);
+        assert!(runs.iter().all(|run| !run.exists()));
+    }
+
+    #[test]
+    fn spills_and_sorts() {
+        let headers = ByteRecord::from(vec!["n", "x"]);
+        let key = SortKey::new(&headers, &["n".to_string()]).unwrap();
+        let mut buffer = SpillBuffer::new(&std::env::temp_dir(), 4 * RECORD_OVERHEAD, Some(key));
+        for i in 0..20 {
+            buffer.push(record(i * 7 % 20)).unwrap();
+        }
+        let expected: Vec<String> = (0..20).map(|i| i.to_string()).collect();
+        assert_eq!(firsts(buffer.into_records().unwrap()), expected);
+    }
+}
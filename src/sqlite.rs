@@ -0,0 +1,137 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use rusqlite::{params_from_iter, types::Value as SqlValue, Connection};
+use serde_json::Value;
+
+use crate::{
+    output::table_name,
+    sink::{Record, RowSink},
+};
+
+/// Writes rows to a SQLite database per PGN, in a table named after the
+/// binary, with column types taken from the first row. Rows are inserted in a
+/// transaction per PGN, which is what makes inserting them one at a time fast.
+/// Nothing is written for a PGN without rows.
+pub(crate) struct SqliteWriter {
+    path: PathBuf,
+    // opened when the first row is written
+    connection: Option<Connection>,
+    insert: String,
+}
+
+impl SqliteWriter {
+    pub(crate) fn new(path: &Path) -> Self {
+        SqliteWriter {
+            path: path.to_path_buf(),
+            connection: None,
+            insert: String::new(),
+        }
+    }
+
+    /// Replaces the database with one holding an empty table for rows like
+    /// `record`.
+    fn open(&mut self, record: &Record) -> Result<Connection> {
+        if self.path.exists() {
+            fs::remove_file(&self.path)?;
+        }
+        let connection = Connection::open(&self.path)?;
+        let table = table_name();
+        let columns: Vec<String> = record
+            .iter()
+            .map(|(name, value)| {
+                let ty = match value {
+                    Value::Bool(_) => "INTEGER",
+                    Value::Number(n) if n.is_i64() => "INTEGER",
+                    Value::Number(_) => "REAL",
+                    _ => "TEXT",
+                };
+                format!("\"{name}\" {ty}")
+            })
+            .collect();
+        connection.execute_batch(&format!(
+            "CREATE TABLE \"{table}\" ({}); BEGIN",
+            columns.join(", ")
+        ))?;
+        let placeholders = vec!["?"; record.len()].join(", ");
+        self.insert = format!("INSERT INTO \"{table}\" VALUES ({placeholders})");
+        Ok(connection)
+    }
+}
+
+fn sql_value(value: &Value) -> SqlValue {
+    match value {
+        Value::Null => SqlValue::Null,
+        Value::Bool(b) => SqlValue::Integer(i64::from(*b)),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => SqlValue::Integer(i),
+            None => SqlValue::Real(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        Value::String(s) => SqlValue::Text(s.clone()),
+        _ => SqlValue::Text(value.to_string()),
+    }
+}
+
+impl RowSink for SqliteWriter {
+    fn write_row(&mut self, record: &Record) -> Result<()> {
+        if self.connection.is_none() {
+            self.connection = Some(self.open(record)?);
+        }
+        let connection = self.connection.as_ref().expect("database was just opened");
+        connection
+            .prepare_cached(&self.insert)?
+            .execute(params_from_iter(record.values().map(sql_value)))?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if let Some(connection) = &self.connection {
+            connection.execute_batch("COMMIT; BEGIN")?;
+        }
+        Ok(())
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        if let Some(connection) = self.connection.take() {
+            connection.execute_batch("COMMIT")?;
+            connection.close().map_err(|(_, e)| e)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    #[test]
+    fn writes_tables() {
+        let path = std::env::temp_dir().join(format!("pgn2csv-{}.sqlite", std::process::id()));
+        let mut writer = SqliteWriter::new(&path);
+        for elo in [1500, 1600] {
+            let record = match json!({"white_elo": elo, "rated": true, "site": null}) {
+                Value::Object(record) => record,
+                _ => unreachable!(),
+            };
+            writer.write_row(&record).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let connection = Connection::open(&path).unwrap();
+        let sum: i64 = connection
+            .query_row(
+                &format!("SELECT sum(white_elo) FROM \"{}\"", table_name()),
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(sum, 3100);
+        drop(connection);
+        fs::remove_file(&path).unwrap();
+    }
+}
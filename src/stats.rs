@@ -0,0 +1,187 @@
+use std::{
+    cmp::Ordering,
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::record::{is_null, Record};
+
+// 2^12 registers, for a standard error of about 1.6%
+const PRECISION: u32 = 12;
+const REGISTERS: usize = 1 << PRECISION;
+
+/// A HyperLogLog sketch, estimating the number of distinct values added to it
+/// in constant memory.
+struct HyperLogLog {
+    registers: Box<[u8; REGISTERS]>,
+}
+
+impl HyperLogLog {
+    fn new() -> Self {
+        HyperLogLog {
+            registers: Box::new([0; REGISTERS]),
+        }
+    }
+
+    fn add(&mut self, value: impl Hash) {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        let hash = hasher.finish();
+        let register = (hash >> (64 - PRECISION)) as usize;
+        // the sentinel bit caps the rank if the remaining bits are all zero
+        let rest = hash << PRECISION | 1 << (PRECISION - 1);
+        let rank = rest.leading_zeros() as u8 + 1;
+        self.registers[register] = self.registers[register].max(rank);
+    }
+
+    fn estimate(&self) -> u64 {
+        let m = REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|&rank| 2f64.powi(-i32::from(rank)))
+            .sum();
+        let estimate = alpha * m * m / sum;
+        let zeros = self.registers.iter().filter(|&&rank| rank == 0).count();
+        if estimate <= 2.5 * m && zeros > 0 {
+            // linear counting is more accurate for small cardinalities
+            (m * (m / zeros as f64).ln()).round() as u64
+        } else {
+            estimate.round() as u64
+        }
+    }
+}
+
+/// Orders numbers numerically and strings lexicographically, with numbers
+/// before strings.
+fn compare(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::String(a), Value::String(b)) => a.cmp(b),
+        (Value::String(_), _) => Ordering::Greater,
+        (_, Value::String(_)) => Ordering::Less,
+        _ => number(a).partial_cmp(&number(b)).unwrap_or(Ordering::Equal),
+    }
+}
+
+fn number(value: &Value) -> f64 {
+    match value {
+        Value::Bool(b) => f64::from(u8::from(*b)),
+        Value::Number(n) => n.as_f64().unwrap_or(f64::NAN),
+        _ => f64::NAN,
+    }
+}
+
+struct Column {
+    name: String,
+    min: Option<Value>,
+    max: Option<Value>,
+    nulls: u64,
+    distinct: HyperLogLog,
+}
+
+impl Column {
+    fn new(name: String) -> Self {
+        Column {
+            name,
+            min: None,
+            max: None,
+            nulls: 0,
+            distinct: HyperLogLog::new(),
+        }
+    }
+
+    fn add(&mut self, value: &Value) {
+        if is_null(value) {
+            self.nulls += 1;
+            return;
+        }
+        match value {
+            Value::String(s) => self.distinct.add(s),
+            _ => self.distinct.add(value.to_string()),
+        }
+        if self
+            .min
+            .as_ref()
+            .is_none_or(|min| compare(value, min).is_lt())
+        {
+            self.min = Some(value.clone());
+        }
+        if self
+            .max
+            .as_ref()
+            .is_none_or(|max| compare(value, max).is_gt())
+        {
+            self.max = Some(value.clone());
+        }
+    }
+}
+
+/// The statistics of one column of an output, as written to the manifest.
+#[derive(Serialize)]
+pub(crate) struct ColumnSummary {
+    name: String,
+    min: Option<Value>,
+    max: Option<Value>,
+    nulls: u64,
+    distinct: u64,
+}
+
+/// Collects min, max, null count, and an estimate of the number of distinct
+/// values for every column of an output as its rows are written. Empty strings
+/// count as nulls.
+#[derive(Default)]
+pub(crate) struct ColumnStats {
+    rows: u64,
+    columns: Vec<Column>,
+}
+
+impl ColumnStats {
+    pub(crate) fn add(&mut self, record: &Record) {
+        if self.columns.is_empty() {
+            self.columns = record.keys().cloned().map(Column::new).collect();
+        }
+        self.rows += 1;
+        for (column, value) in self.columns.iter_mut().zip(record.values()) {
+            column.add(value);
+        }
+    }
+
+    pub(crate) fn rows(&self) -> u64 {
+        self.rows
+    }
+
+    pub(crate) fn summarize(self) -> Vec<ColumnSummary> {
+        self.columns
+            .into_iter()
+            .map(|column| ColumnSummary {
+                distinct: column.distinct.estimate(),
+                name: column.name,
+                min: column.min,
+                max: column.max,
+                nulls: column.nulls,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hyperloglog_estimates() {
+        for n in [10, 1_000, 100_000] {
+            let mut hll = HyperLogLog::new();
+            for i in 0..n {
+                hll.add(i);
+                hll.add(i);
+            }
+            let error = (hll.estimate() as f64 - n as f64).abs() / n as f64;
+            assert!(error < 0.05, "{n}: {}", hll.estimate());
+        }
+    }
+}
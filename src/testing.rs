@@ -0,0 +1,202 @@
+//! Helpers for testing processors against small PGNs, so that a binary's rows
+//! can be checked by golden tests rather than by eyeballing its CSVs.
+//!
+//! ```ignore
+//! #[cfg(test)]
+//! mod tests {
+//!     use super::*;
+//!
+//!     #[test]
+//!     fn extracts_blitz_games() {
+//!         pgn2csv::assert_rows!(
+//!             Processor,
+//!             "[Event \"Rated Blitz game\"]\n[WhiteElo \"1500\"]\n\n1. e4 1-0\n",
+//!             ["1500,2"],
+//!         );
+//!     }
+//! }
+//! ```
+
+use std::{
+    env,
+    ffi::OsStr,
+    fs,
+    io::Read,
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use pgn_reader::Visitor;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+    encoding::{Encoded, Encodings},
+    game::GameVisitor,
+    normalize::{Normalization, Normalize},
+    segment::{GameReader, GameSplitter},
+    sink::MemorySink,
+    GameProcessor, Pgn2Csv,
+};
+
+/// The name that games read by `process_games` are given, e.g. for their
+/// `GameUid`s.
+pub const FILE_NAME: &str = "test.pgn";
+
+/// Reads the games of `pgn` through a processor as a run would, with the
+/// default options, returning the rows of the games that weren't skipped, the
+/// number of games that were, and any error that stopped the reading.
+pub fn process_games<P>(pgn: &str) -> (Vec<P::Row>, usize, Vec<String>)
+where
+    P: Visitor + GameProcessor,
+{
+    let mut processor = P::default();
+    let mut game = GameVisitor::new(&mut processor, FILE_NAME.as_bytes(), &[], false);
+    let reader = Normalize::new(pgn.as_bytes(), Normalization::default());
+    read_games(&mut game, GameReader::new(reader, P::game_offsets(), false))
+}
+
+fn read_games<P, R>(
+    game: &mut GameVisitor<'_, P>,
+    mut reader: GameReader<R>,
+) -> (Vec<P::Row>, usize, Vec<String>)
+where
+    P: Visitor + GameProcessor,
+    R: Read,
+{
+    let mut rows = Vec::new();
+    let mut skipped = 0;
+    let mut errors = Vec::new();
+    loop {
+        match reader.read_game(game) {
+            Ok(true) => (),
+            Ok(false) => break,
+            Err(e) => {
+                errors.push(e.to_string());
+                break;
+            }
+        }
+        let processor = game.processor();
+        if processor.skip() {
+            skipped += 1;
+        } else {
+            rows.push(processor.row());
+        }
+    }
+    (rows, skipped, errors)
+}
+
+/// The rows of `pgn` as CSV records without a header, as they would be
+/// written to the processor's CSV.
+///
+/// # Panics
+///
+/// If reading `pgn` fails, or a row can't be written as CSV.
+pub fn csv_rows<P>(pgn: &str) -> Vec<String>
+where
+    P: Visitor + GameProcessor,
+{
+    let (rows, _, errors) = process_games::<P>(pgn);
+    assert!(errors.is_empty(), "failed to read the PGN: {errors:?}");
+    let encodings = Encodings::of::<P>();
+    rows.iter().map(|row| csv_record(row, encodings)).collect()
+}
+
+/// `row` as a CSV record, in the processor's encodings.
+fn csv_record(row: &impl Serialize, encodings: Encodings) -> String {
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(Vec::new());
+    writer
+        .serialize(Encoded::new(row, encodings))
+        .expect("row can't be written as CSV");
+    let record = writer.into_inner().expect("row can't be written as CSV");
+    String::from_utf8_lossy(&record).trim_end().to_owned()
+}
+
+/// Asserts that a processor writes the same rows for `pgn`, in some order,
+/// whether a run converts its games one after the other or in parallel, to a
+/// sink or merged into one CSV. Each game is converted as a PGN of its own, so
+/// that the parallel runs spread them over worker threads, which reuse their
+/// processors across PGNs. Rows that differ point at state that leaks from
+/// one game or PGN into the next, at the processor depending on the order of
+/// games, or at a race in writing the rows.
+///
+/// # Panics
+///
+/// If the rows differ, or a run fails.
+pub fn assert_deterministic<P>(pgn: &str)
+where
+    P: Visitor + GameProcessor,
+{
+    static RUNS: AtomicUsize = AtomicUsize::new(0);
+    let run = RUNS.fetch_add(1, Ordering::Relaxed);
+    let dir = env::temp_dir().join(format!("pgn2csv-deterministic-{}-{run}", process::id()));
+    let pgn_dir = dir.join("pgns");
+    fs::create_dir_all(&pgn_dir).expect("can't create the PGN directory");
+    let mut splitter = GameSplitter::new(pgn.as_bytes(), false);
+    let mut games = 0;
+    while let Some(game) = splitter.next_game().expect("failed to split the PGN") {
+        let path = pgn_dir.join(format!("{games:06}.pgn"));
+        fs::write(path, &game.bytes).expect("can't write a game's PGN");
+        games += 1;
+    }
+    let builder = |threads: usize| {
+        let builder = Pgn2Csv::builder()
+            .pgn_dir(&pgn_dir)
+            .csv_dir(dir.join(format!("csvs-{threads}")))
+            .args(["--quiet"]);
+        match threads {
+            1 => builder.serial(true),
+            threads => builder.threads(threads),
+        }
+    };
+    let sunk = |threads: usize| {
+        let sink = MemorySink::default();
+        builder(threads)
+            .sink(sink.clone())
+            .build()
+            .and_then(Pgn2Csv::run::<P>)
+            .expect("the run failed");
+        let mut rows: Vec<String> = sink
+            .rows()
+            .iter()
+            .map(|row| Value::from(row.clone()).to_string())
+            .collect();
+        rows.sort();
+        rows
+    };
+    let merged = |threads: usize| {
+        let path = dir.join(format!("merged-{threads}.csv"));
+        builder(threads)
+            .args([OsStr::new("--merge"), path.as_os_str()])
+            .build()
+            .and_then(Pgn2Csv::run::<P>)
+            .expect("the merged run failed");
+        let csv = fs::read_to_string(&path).expect("can't read the merged CSV");
+        let mut rows: Vec<String> = csv.lines().skip(1).map(str::to_owned).collect();
+        rows.sort();
+        rows
+    };
+    assert_eq!(
+        sunk(1),
+        sunk(4),
+        "rows differ between serial and parallel runs"
+    );
+    assert_eq!(
+        merged(1),
+        merged(4),
+        "merged rows differ between serial and parallel runs"
+    );
+    fs::remove_dir_all(&dir).expect("can't remove the runs' directory");
+}
+
+/// Asserts that a processor turns a PGN into the given rows, written as CSV
+/// records without a header, e.g. `["1500,1620,true"]`. Skipped games have no
+/// row.
+#[macro_export]
+macro_rules! assert_rows {
+    ($processor:ty, $pgn:expr, $rows:expr $(,)?) => {
+        assert_eq!($crate::testing::csv_rows::<$processor>($pgn), $rows)
+    };
+}
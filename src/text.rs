@@ -0,0 +1,114 @@
+//! Free-text columns, such as move lists, comments, and free-form headers,
+//! which may hold commas, quotes, and line breaks.
+//!
+//! Every output format quotes such text correctly: CSV fields holding commas,
+//! quotes, or line breaks are quoted, with quotes doubled. Some tools still
+//! split rows on line breaks, though, so free text can instead be written as a
+//! JSON string literal, which is always a single line.
+
+use std::{fmt::Write, str::FromStr};
+
+use anyhow::{bail, Error, Result};
+use serde::Serializer;
+
+/// How free-text columns are written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TextEncoding {
+    /// As is, quoted by the output format where needed.
+    #[default]
+    Plain,
+    /// As a JSON string literal, e.g. `"1. e4 {a \"sharp\" line}\n"`.
+    Json,
+}
+
+impl FromStr for TextEncoding {
+    type Err = Error;
+
+    fn from_str(encoding: &str) -> Result<Self> {
+        match encoding {
+            "plain" => Ok(TextEncoding::Plain),
+            "json" => Ok(TextEncoding::Json),
+            _ => bail!("unknown text encoding {encoding}"),
+        }
+    }
+}
+
+/// The name that free text serializes under, for runs to encode it.
+#[cfg_attr(not(feature = "pipeline"), allow(dead_code))]
+pub(crate) const SERDE_NAME: &str = "pgn2csv::Text";
+
+impl TextEncoding {
+    /// `text` as it is written in this encoding.
+    #[must_use]
+    pub fn encode(self, text: &str) -> String {
+        match self {
+            TextEncoding::Plain => text.to_owned(),
+            TextEncoding::Json => json_string(text),
+        }
+    }
+}
+
+/// Serializes free text, for fields of rows like
+/// `#[serde(serialize_with = "pgn2csv::text::serialize")] moves: String`. A
+/// run writes it in its `--text-encoding`; elsewhere it is written as is.
+///
+/// # Errors
+///
+/// Returns the serializer's error.
+pub fn serialize<S: Serializer>(text: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_newtype_struct(SERDE_NAME, text)
+}
+
+fn json_string(text: &str) -> String {
+    let mut json = String::with_capacity(text.len() + 2);
+    json.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c < ' ' => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_json_strings() {
+        let comment = "1. e4 {a \"sharp\", C:\\ line}\r\n\u{1}";
+        assert_eq!(TextEncoding::Plain.encode(comment), comment);
+        assert_eq!(
+            TextEncoding::Json.encode(comment),
+            r#""1. e4 {a \"sharp\", C:\\ line}\r\n\u0001""#
+        );
+    }
+
+    #[cfg(feature = "pipeline")]
+    #[test]
+    fn round_trips_through_csv() {
+        #[derive(serde::Serialize)]
+        struct Row<'a> {
+            ply: u32,
+            #[serde(serialize_with = "serialize")]
+            comment: &'a str,
+        }
+
+        let comment = "a, \"quoted\"\ncomment";
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        writer.serialize(Row { ply: 1, comment }).unwrap();
+        let csv = writer.into_inner().unwrap();
+        let mut reader = csv::Reader::from_reader(csv.as_slice());
+        let record = reader.records().next().unwrap().unwrap();
+        assert_eq!(&record[1], comment);
+    }
+}
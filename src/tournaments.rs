@@ -0,0 +1,162 @@
+use std::{collections::BTreeMap, path::Path, sync::Mutex};
+
+use anyhow::{anyhow, Result};
+use pgn_reader::RawHeader;
+use serde::Serialize;
+
+use crate::headers::{Event, EventKind};
+
+/// The name of the dimension table of tournaments in the output directory.
+pub(crate) const TOURNAMENTS: &str = "tournaments.csv";
+
+/// A row of the tournaments table.
+#[derive(Clone, Serialize)]
+struct Tournament {
+    tournament_id: String,
+    kind: EventKind,
+    name: String,
+    games: u64,
+    first_date: String,
+    last_date: String,
+}
+
+impl Tournament {
+    fn merge(&mut self, other: &Tournament) {
+        self.games += other.games;
+        if other.first_date < self.first_date {
+            self.first_date.clone_from(&other.first_date);
+        }
+        if other.last_date > self.last_date {
+            self.last_date.clone_from(&other.last_date);
+        }
+    }
+}
+
+/// Collects the tournaments of the games of one file, from their Event and
+/// date headers.
+#[derive(Default)]
+pub(crate) struct FileTournaments {
+    tournaments: BTreeMap<String, Tournament>,
+    event: Option<Event>,
+    date: Option<String>,
+    utc_date: Option<String>,
+}
+
+impl FileTournaments {
+    pub(crate) fn begin_game(&mut self) {
+        self.event = None;
+        self.date = None;
+        self.utc_date = None;
+    }
+
+    pub(crate) fn header(&mut self, key: &[u8], value: RawHeader<'_>) {
+        match key {
+            b"Event" => self.event = Event::try_from(value).ok(),
+            b"Date" => self.date = Some(String::from_utf8_lossy(value.as_bytes()).into_owned()),
+            b"UTCDate" => {
+                self.utc_date = Some(String::from_utf8_lossy(value.as_bytes()).into_owned());
+            }
+            b"WhiteTeam" | b"BlackTeam" => {
+                if let Some(event) = &mut self.event {
+                    event.set_team_battle();
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Counts the game, once all of its headers have been read.
+    pub(crate) fn end_headers(&mut self) {
+        let Some(Event {
+            name,
+            kind,
+            tournament_id: Some(id),
+            ..
+        }) = self.event.take()
+        else {
+            return;
+        };
+        let date = self
+            .utc_date
+            .take()
+            .or(self.date.take())
+            .unwrap_or_default();
+        let game = Tournament {
+            tournament_id: id.clone(),
+            kind,
+            name,
+            games: 1,
+            first_date: date.clone(),
+            last_date: date,
+        };
+        match self.tournaments.get_mut(&id) {
+            Some(tournament) => tournament.merge(&game),
+            None => {
+                self.tournaments.insert(id, game);
+            }
+        }
+    }
+}
+
+/// The distinct tournaments seen in all files of a run, written as a table to
+/// join game rows against.
+#[derive(Default)]
+pub(crate) struct Tournaments {
+    tournaments: Mutex<BTreeMap<String, Tournament>>,
+}
+
+impl Tournaments {
+    pub(crate) fn add(&self, file: FileTournaments) {
+        let Ok(mut tournaments) = self.tournaments.lock() else {
+            return;
+        };
+        for (id, tournament) in file.tournaments {
+            match tournaments.get_mut(&id) {
+                Some(seen) => seen.merge(&tournament),
+                None => {
+                    tournaments.insert(id, tournament);
+                }
+            }
+        }
+    }
+
+    pub(crate) fn write(&self, csv_dir: &Path) -> Result<()> {
+        let tournaments = self
+            .tournaments
+            .lock()
+            .map_err(|_| anyhow!("tournaments lock poisoned"))?;
+        let mut writer = csv::Writer::from_path(csv_dir.join(TOURNAMENTS))?;
+        for tournament in tournaments.values() {
+            writer.serialize(tournament)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_games_and_dates() {
+        let mut file = FileTournaments::default();
+        for date in [b"2024.01.02".as_slice(), b"2024.01.01", b"2024.01.03"] {
+            file.begin_game();
+            file.header(
+                b"Event",
+                RawHeader(b"Rated Blitz tournament https://lichess.org/tournament/abc"),
+            );
+            file.header(b"UTCDate", RawHeader(date));
+            file.end_headers();
+        }
+        file.begin_game();
+        file.header(b"Event", RawHeader(b"Rated Blitz game"));
+        file.end_headers();
+        let tournament = &file.tournaments["abc"];
+        assert_eq!(file.tournaments.len(), 1);
+        assert_eq!(tournament.games, 3);
+        assert_eq!(tournament.first_date, "2024.01.01");
+        assert_eq!(tournament.last_date, "2024.01.03");
+    }
+}
@@ -0,0 +1,108 @@
+// the builder is only used by the pipeline, which computes the uids
+#![cfg_attr(not(feature = "pipeline"), allow(dead_code))]
+
+use std::fmt;
+
+use serde::Serialize;
+
+pub(crate) const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+pub(crate) const FNV_PRIME: u64 = 0x0100_0000_01b3;
+const LICHESS_SITE: &[u8] = b"https://lichess.org/";
+
+/// A stable surrogate key for a game, so that rows from several tables
+/// exported from the same PGNs (e.g. games, moves, clocks) can be joined. For
+/// lichess games this is the game ID taken from the `Site` header; otherwise it
+/// is a hex-encoded 64-bit FNV-1a hash of the PGN file name, the index of the
+/// game within the file, and all of the game's headers.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize)]
+pub struct GameUid(String);
+
+impl GameUid {
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for GameUid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Accumulates the parts of a game that make up its `GameUid`.
+pub(crate) struct GameUidBuilder {
+    hash: u64,
+    lichess_id: Option<String>,
+}
+
+impl GameUidBuilder {
+    pub(crate) fn new(file: &[u8], index: u64) -> Self {
+        let mut builder = GameUidBuilder {
+            hash: FNV_OFFSET,
+            lichess_id: None,
+        };
+        builder.write(file);
+        builder.write(&index.to_le_bytes());
+        builder
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.hash ^= u64::from(byte);
+            self.hash = self.hash.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    pub(crate) fn header(&mut self, key: &[u8], value: &[u8]) {
+        // separators keep e.g. ("ab", "c") and ("a", "bc") apart
+        self.write(key);
+        self.write(&[0]);
+        self.write(value);
+        self.write(&[0]);
+        if key == b"Site" {
+            self.lichess_id = lichess_id(value).map(|id| String::from_utf8_lossy(id).into_owned());
+        }
+    }
+
+    pub(crate) fn finish(&self) -> GameUid {
+        match &self.lichess_id {
+            Some(id) => GameUid(id.clone()),
+            None => GameUid(format!("{:016x}", self.hash)),
+        }
+    }
+}
+
+fn lichess_id(site: &[u8]) -> Option<&[u8]> {
+    let id = site.strip_prefix(LICHESS_SITE)?;
+    (id.len() == 8 && id.iter().all(u8::is_ascii_alphanumeric)).then_some(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lichess_site() {
+        let mut builder = GameUidBuilder::new(b"a.pgn", 0);
+        builder.header(b"Site", b"https://lichess.org/PpwPOZMq");
+        assert_eq!(builder.finish().as_str(), "PpwPOZMq");
+
+        let mut builder = GameUidBuilder::new(b"a.pgn", 0);
+        builder.header(b"Site", b"https://lichess.org/tournament/yc1WW2Ox");
+        assert_eq!(builder.finish().as_str().len(), 16);
+    }
+
+    #[test]
+    fn hashed() {
+        let uid = |file: &[u8], index, value: &[u8]| {
+            let mut builder = GameUidBuilder::new(file, index);
+            builder.header(b"Event", value);
+            builder.finish()
+        };
+        assert_eq!(uid(b"a.pgn", 0, b"x"), uid(b"a.pgn", 0, b"x"));
+        assert_ne!(uid(b"a.pgn", 0, b"x"), uid(b"a.pgn", 1, b"x"));
+        assert_ne!(uid(b"a.pgn", 0, b"x"), uid(b"b.pgn", 0, b"x"));
+        assert_ne!(uid(b"a.pgn", 0, b"x"), uid(b"a.pgn", 0, b"y"));
+    }
+}
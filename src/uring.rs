@@ -0,0 +1,149 @@
+use std::{
+    fs::File,
+    io::{self, Read},
+    os::fd::AsRawFd,
+    sync::Arc,
+    time::Instant,
+};
+
+use io_uring::{opcode, types, IoUring};
+
+use crate::reader::UringStats;
+
+const BLOCK_SIZE: usize = 1 << 20;
+
+/// Reads a file through io_uring, keeping the read of the next block in flight
+/// while the current one is consumed, so that I/O overlaps with decompression.
+pub(crate) struct UringReader {
+    ring: IoUring,
+    file: File,
+    // file offset of the next block to read
+    offset: u64,
+    blocks: [Vec<u8>; 2],
+    current: usize,
+    pos: usize,
+    len: usize,
+    in_flight: bool,
+    eof: bool,
+    // where the run's reads through io_uring are counted
+    stats: Option<Arc<UringStats>>,
+}
+
+impl UringReader {
+    pub(crate) fn new(file: File, ring: IoUring, stats: Option<Arc<UringStats>>) -> Self {
+        UringReader {
+            ring,
+            file,
+            offset: 0,
+            blocks: [vec![0; BLOCK_SIZE], vec![0; BLOCK_SIZE]],
+            current: 0,
+            pos: 0,
+            len: 0,
+            in_flight: false,
+            eof: false,
+            stats,
+        }
+    }
+
+    fn submit(&mut self, block: usize) -> io::Result<()> {
+        let buf = &mut self.blocks[block];
+        let entry = opcode::Read::new(
+            types::Fd(self.file.as_raw_fd()),
+            buf.as_mut_ptr(),
+            u32::try_from(buf.len()).map_err(io::Error::other)?,
+        )
+        .offset(self.offset)
+        .build();
+        // SAFETY: the buffer and the file outlive the read, since we always wait
+        // for it to complete before touching the buffer again or dropping self.
+        unsafe {
+            self.ring
+                .submission()
+                .push(&entry)
+                .map_err(|_| io::Error::other("io_uring submission queue is full"))?;
+        }
+        self.ring.submit()?;
+        self.in_flight = true;
+        Ok(())
+    }
+
+    fn wait(&mut self) -> io::Result<usize> {
+        let start = Instant::now();
+        self.ring.submit_and_wait(1)?;
+        let cqe = self
+            .ring
+            .completion()
+            .next()
+            .ok_or_else(|| io::Error::other("io_uring completion queue is empty"))?;
+        self.in_flight = false;
+        let n = usize::try_from(cqe.result())
+            .map_err(|_| io::Error::from_raw_os_error(-cqe.result()))?;
+        self.offset += n as u64;
+        if let Some(stats) = &self.stats {
+            stats.add(n as u64, start.elapsed());
+        }
+        Ok(n)
+    }
+}
+
+impl Read for UringReader {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.len {
+            if self.eof {
+                return Ok(0);
+            }
+            if !self.in_flight {
+                self.submit(1 - self.current)?;
+            }
+            let n = self.wait()?;
+            self.current = 1 - self.current;
+            self.pos = 0;
+            self.len = n;
+            if n == 0 {
+                self.eof = true;
+                return Ok(0);
+            }
+            // read ahead into the block we just finished with
+            self.submit(1 - self.current)?;
+        }
+        let n = out.len().min(self.len - self.pos);
+        out[..n].copy_from_slice(&self.blocks[self.current][self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+impl Drop for UringReader {
+    fn drop(&mut self) {
+        if self.in_flight {
+            let _ = self.wait();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    #[test]
+    fn reads_whole_file() {
+        let Ok(ring) = IoUring::new(2) else {
+            return; // io_uring unavailable, e.g. in some containers
+        };
+        let path = std::env::temp_dir().join(format!("pgn2csv-uring-{}", std::process::id()));
+        let data: Vec<u8> = (0..BLOCK_SIZE * 2 + 12345)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        File::create(&path).unwrap().write_all(&data).unwrap();
+
+        let stats = Arc::new(UringStats::default());
+        let mut reader = UringReader::new(File::open(&path).unwrap(), ring, Some(stats.clone()));
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(read == data);
+        assert_eq!(stats.reads().unwrap().bytes, data.len() as u64);
+    }
+}
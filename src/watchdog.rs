@@ -0,0 +1,146 @@
+use std::{
+    collections::HashMap,
+    io::{self, Read},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::log::{Event, Log};
+
+/// How far the conversion of one file has got.
+#[derive(Default)]
+pub(crate) struct FileProgress {
+    bytes: AtomicU64,
+    games: AtomicU64,
+}
+
+impl FileProgress {
+    pub(crate) fn add_game(&self) {
+        self.games.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn get(&self) -> (u64, u64) {
+        (
+            self.bytes.load(Ordering::Relaxed),
+            self.games.load(Ordering::Relaxed),
+        )
+    }
+}
+
+/// A reader that counts the bytes read through it into a `FileProgress`, if
+/// the file is watched.
+pub(crate) struct Counted<R> {
+    inner: R,
+    progress: Option<Arc<FileProgress>>,
+}
+
+impl<R> Counted<R> {
+    pub(crate) fn new(inner: R, progress: Option<Arc<FileProgress>>) -> Self {
+        Counted { inner, progress }
+    }
+}
+
+impl<R: Read> Read for Counted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if let Some(progress) = &self.progress {
+            progress.bytes.fetch_add(n as u64, Ordering::Relaxed);
+        }
+        Ok(n)
+    }
+}
+
+type Files = Arc<Mutex<HashMap<String, Arc<FileProgress>>>>;
+
+/// Logs the progress of every file being converted every `interval`, and flags
+/// files that haven't advanced since the previous heartbeat, so that a slow
+/// file (e.g. a long zstd stretch with heavy filtering) can be told apart from
+/// a stuck one.
+pub(crate) struct Watchdog {
+    files: Files,
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Watchdog {
+    pub(crate) fn start(interval: Duration, log: Log) -> Self {
+        let files = Files::default();
+        let (stop, stopped) = mpsc::channel();
+        let thread = thread::spawn({
+            let files = files.clone();
+            move || {
+                let mut last: HashMap<String, ((u64, u64), Instant)> = HashMap::new();
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    let files = files.lock().expect("no panics while locked");
+                    last.retain(|name, _| files.contains_key(name));
+                    let mut names: Vec<&String> = files.keys().collect();
+                    names.sort();
+                    for name in names {
+                        let now = files[name].get();
+                        let (bytes, games) = now;
+                        let since = match last.get(name) {
+                            Some(&(before, since)) if before == now => since,
+                            _ => Instant::now(),
+                        };
+                        last.insert(name.clone(), (now, since));
+                        let stalled = since.elapsed();
+                        log.emit(&Event::Heartbeat {
+                            file: name,
+                            bytes,
+                            games,
+                            stalled_seconds: (stalled >= interval).then_some(stalled.as_secs()),
+                        });
+                    }
+                }
+            }
+        });
+        Watchdog {
+            files,
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+
+    /// Starts watching a file, until the returned guard is dropped.
+    pub(crate) fn watch(&self, name: String) -> Watch {
+        let progress = Arc::new(FileProgress::default());
+        self.files
+            .lock()
+            .expect("no panics while locked")
+            .insert(name.clone(), progress.clone());
+        Watch {
+            files: self.files.clone(),
+            name,
+            progress,
+        }
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// A file being watched.
+pub(crate) struct Watch {
+    files: Files,
+    name: String,
+    pub(crate) progress: Arc<FileProgress>,
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        if let Ok(mut files) = self.files.lock() {
+            files.remove(&self.name);
+        }
+    }
+}
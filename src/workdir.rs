@@ -0,0 +1,87 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Error, Result};
+
+/// A directory of the run's own for intermediate files, such as the runs that
+/// sorting spills, so that concurrent runs into the same output directory
+/// don't trample each other's. It is removed once the run succeeds, and kept
+/// for inspection if it fails or crashes, which the next run doesn't mind, as
+/// it gets a directory of its own.
+pub(crate) struct WorkDir {
+    path: PathBuf,
+}
+
+impl WorkDir {
+    /// Creates a uniquely named directory in `parent`.
+    pub(crate) fn create(parent: &Path) -> Result<Self> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .subsec_nanos();
+        let mut attempt = 0;
+        loop {
+            let name = format!(".pgn2csv-run-{}-{nanos:x}-{attempt}", std::process::id());
+            let path = parent.join(name);
+            match fs::create_dir(&path) {
+                Ok(()) => return Ok(WorkDir { path }),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => attempt += 1,
+                Err(e) => {
+                    return Err(e)
+                        .with_context(|| format!("creating a work dir in {}", parent.display()))
+                }
+            }
+        }
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Removes the directory and whatever is left in it, once the run has
+    /// succeeded.
+    pub(crate) fn remove(self) -> Result<()> {
+        fs::remove_dir_all(&self.path)
+            .with_context(|| format!("removing the work dir {}", self.path.display()))
+    }
+
+    /// Keeps the directory after the run failed with `error`, and points to it
+    /// in the error. An empty directory has nothing to inspect, and is removed.
+    pub(crate) fn keep(self, error: Error) -> Error {
+        if fs::remove_dir(&self.path).is_ok() {
+            return error;
+        }
+        error.context(format!(
+            "the run's intermediate files were kept in {}",
+            self.path.display()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use anyhow::anyhow;
+
+    #[test]
+    fn work_dirs_are_unique() {
+        let parent = std::env::temp_dir();
+        let a = WorkDir::create(&parent).unwrap();
+        let b = WorkDir::create(&parent).unwrap();
+        assert_ne!(a.path(), b.path());
+        let path = a.path().to_path_buf();
+        a.remove().unwrap();
+        assert!(!path.exists());
+
+        let path = b.path().to_path_buf();
+        fs::write(path.join("spill-0.csv"), "a,b\n").unwrap();
+        let error = b.keep(anyhow!("disk full"));
+        assert!(format!("{error:#}").contains(&*path.to_string_lossy()));
+        assert!(path.exists());
+        fs::remove_dir_all(path).unwrap();
+    }
+}
@@ -0,0 +1,73 @@
+use std::{
+    mem,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use rust_xlsxwriter::{Format, Workbook, Worksheet};
+use serde_json::Value;
+
+use crate::sink::{Record, RowSink};
+
+/// Writes rows to a single worksheet with a bold, frozen header row. Numbers
+/// and booleans are written as such rather than as text, so that they can be
+/// used in formulas straight away. The whole sheet is kept in memory until the
+/// workbook is saved, which suits the small extractions Excel can open anyway.
+pub(crate) struct XlsxWriter {
+    path: PathBuf,
+    worksheet: Worksheet,
+    // the number of rows written, not counting the header
+    rows: u32,
+}
+
+impl XlsxWriter {
+    pub(crate) fn new(path: &Path) -> Result<Self> {
+        let mut worksheet = Worksheet::new();
+        worksheet.set_freeze_panes(1, 0)?;
+        Ok(XlsxWriter {
+            path: path.to_path_buf(),
+            worksheet,
+            rows: 0,
+        })
+    }
+}
+
+impl RowSink for XlsxWriter {
+    fn write_row(&mut self, record: &Record) -> Result<()> {
+        if self.rows == 0 {
+            let bold = Format::new().set_bold();
+            for (col, name) in (0..).zip(record.keys()) {
+                self.worksheet
+                    .write_string_with_format(0, col, name, &bold)?;
+            }
+        }
+        self.rows += 1;
+        let row = self.rows;
+        for (col, value) in (0..).zip(record.values()) {
+            match value {
+                Value::Null => {}
+                Value::Bool(b) => {
+                    self.worksheet.write_boolean(row, col, *b)?;
+                }
+                Value::Number(n) => {
+                    self.worksheet
+                        .write_number(row, col, n.as_f64().unwrap_or(f64::NAN))?;
+                }
+                Value::String(s) => {
+                    self.worksheet.write_string(row, col, s)?;
+                }
+                _ => {
+                    self.worksheet.write_string(row, col, value.to_string())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        let mut workbook = Workbook::new();
+        workbook.push_worksheet(mem::replace(&mut self.worksheet, Worksheet::new()));
+        workbook.save(&self.path)?;
+        Ok(())
+    }
+}